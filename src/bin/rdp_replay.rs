@@ -0,0 +1,121 @@
+#![deny(trivial_casts, trivial_numeric_casts)]
+
+extern crate rustendo64;
+
+use rustendo64::n64::{read_trace, replay_trace};
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::process;
+
+// Zlib stored blocks hold at most this many bytes
+const MAX_STORED_BLOCK: usize = 0xffff;
+
+// Replays an RDP trace through the software renderer and writes the final
+// color image as a PNG. The image is the same for any number of render
+// threads.
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() != 2 && args.len() != 3 {
+        println!("Usage: rdp_replay <trace> <output.png> [render threads]");
+        process::exit(1);
+    }
+    let threads = match args.get(2) {
+        Some(count) => count.parse().unwrap_or_else(|_| fail("Invalid render thread count")),
+        None => 1,
+    };
+
+    let batches = read_trace(&args[0]).unwrap_or_else(|e| fail(&format!("Unable to read trace: {}", e)));
+
+    let (width, height, rgba) = replay_trace(&batches, threads);
+    if width == 0 || height == 0 {
+        fail("The trace doesn't draw to a color image");
+    }
+
+    write_png(&args[1], width, height, &rgba).unwrap_or_else(|e| fail(&format!("Unable to write PNG: {}", e)));
+    println!("Replayed {} batches, wrote {}x{} image to {}", batches.len(), width, height, args[1]);
+}
+
+fn fail(message: &str) -> ! {
+    println!("{}", message);
+    process::exit(1);
+}
+
+// Writes an 8-bit RGB PNG. The alpha channel of the color image holds
+// coverage, so it is left out. The image data is stored uncompressed.
+fn write_png(path: &str, width: u32, height: u32, rgba: &[u8]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&be_u32(width));
+    header.extend_from_slice(&be_u32(height));
+    // Bit depth 8, color type RGB, default compression, filter and no
+    // interlacing
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(&mut writer, b"IHDR", &header)?;
+
+    // Every line starts with filter type 0
+    let mut raw = Vec::with_capacity((height * (width * 3 + 1)) as usize);
+    for line in rgba.chunks((width * 4) as usize) {
+        raw.push(0);
+        for pixel in line.chunks(4) {
+            raw.extend_from_slice(&pixel[..3]);
+        }
+    }
+    write_chunk(&mut writer, b"IDAT", &zlib_stored(&raw))?;
+    write_chunk(&mut writer, b"IEND", &[])?;
+    writer.flush()
+}
+
+fn write_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&be_u32(data.len() as u32))?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+
+    let mut crc = crc32_update(0xffff_ffff, kind);
+    crc = crc32_update(crc, data);
+    writer.write_all(&be_u32(!crc))
+}
+
+// A zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let length = block.len() as u16;
+        out.push(if last { 1 } else { 0 });
+        out.extend_from_slice(&[length as u8, (length >> 8) as u8, !length as u8, (!length >> 8) as u8]);
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&be_u32(adler32(data)));
+    out
+}
+
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if (crc & 1) != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn be_u32(value: u32) -> [u8; 4] {
+    [(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]
+}
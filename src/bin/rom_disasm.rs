@@ -0,0 +1,173 @@
+#![deny(trivial_casts, trivial_numeric_casts)]
+
+extern crate byteorder;
+extern crate rustendo64;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use rustendo64::disasm::{self, RegisterNames};
+use rustendo64::n64::cart::{self, CartHeader, GAME_CODE_START};
+use rustendo64::n64::mem_map;
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::process;
+
+// Disassembled when no range is given on the command line
+const DEFAULT_LENGTH: usize = 0x400;
+
+// IPL3 is executed from SP DMEM right after the header
+const BOOT_CODE_ADDR: u32 = 0xa400_0000;
+
+fn main() {
+    let mut names = RegisterNames::Abi;
+    let mut args = Vec::new();
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--numeric" => names = RegisterNames::Numeric,
+            _ => args.push(arg),
+        }
+    }
+
+    if args.is_empty() || args.len() > 3 {
+        println!("Usage: rom_disasm [--numeric] <cart image> [start offset [end offset]]");
+        println!("Without a range the code at the entry point is disassembled.");
+        process::exit(1);
+    }
+
+    let image = read_bin(&args[0]);
+    let image = cart::to_big_endian(image).unwrap_or_else(|e| fail(&e));
+    let header = CartHeader::parse(&image).unwrap_or_else(|e| fail(&e));
+    println!("{:?}\n", header);
+
+    let start = args.get(1).map(|s| parse_offset(s)).unwrap_or(GAME_CODE_START);
+    let end = args.get(2).map(|s| parse_offset(s)).unwrap_or(start + DEFAULT_LENGTH);
+    if start > end || end > image.len() {
+        fail(&format!("Invalid range {:#x}..{:#x} for a {:#x} byte image", start, end, image.len()));
+    }
+
+    let mut tracker = RegisterTracker::new();
+    for offset in (start & !0b11..end).step_by(4) {
+        let addr = offset_to_addr(&header, offset);
+        let word = BigEndian::read_u32(&image[offset..]);
+        let text = disasm::disassemble_vr4300(word, addr as i32 as u64, names);
+
+        match tracker.step(word) {
+            Some(reg_name) => println!("{:08X}: {:08X}  {:<40} ; {}", addr, word, text, reg_name),
+            None => println!("{:08X}: {:08X}  {}", addr, word, text),
+        }
+    }
+}
+
+fn offset_to_addr(header: &CartHeader, offset: usize) -> u32 {
+    if offset < GAME_CODE_START {
+        BOOT_CODE_ADDR + offset as u32
+    } else {
+        header.entry_point.wrapping_add((offset - GAME_CODE_START) as u32)
+    }
+}
+
+// Follows the values built up by LUI followed by ORI/ADDIU or a load/store
+// offset, and reports when they refer to a hardware register
+struct RegisterTracker {
+    values: [Option<u32>; 32],
+    reset_after_delay_slot: bool,
+}
+
+impl RegisterTracker {
+    fn new() -> RegisterTracker {
+        RegisterTracker {
+            values: [None; 32],
+            reset_after_delay_slot: false,
+        }
+    }
+
+    fn step(&mut self, word: u32) -> Option<&'static str> {
+        if self.reset_after_delay_slot {
+            self.values = [None; 32];
+            self.reset_after_delay_slot = false;
+        }
+
+        let op = word >> 26;
+        let rs = ((word >> 21) & 0b11111) as usize;
+        let rt = ((word >> 16) & 0b11111) as usize;
+        let rd = ((word >> 11) & 0b11111) as usize;
+        let imm = word & 0xffff;
+        let simm = imm as i16 as u32;
+
+        let (accessed_addr, written_reg, written_value) = match op {
+            // J and JR/JALR end a block, JAL clobbers ra
+            0b000010 => {
+                self.reset_after_delay_slot = true;
+                (None, None, None)
+            }
+            0b000011 => (None, Some(31), None),
+            0b000000 => {
+                if (word & 0b111110) == 0b001000 {
+                    self.reset_after_delay_slot = true;
+                }
+                (None, Some(rd), None)
+            }
+
+            0b001001 => {
+                let value = self.values[rs].map(|v| v.wrapping_add(simm));
+                (value, Some(rt), value)
+            }
+            0b001101 => {
+                let value = self.values[rs].map(|v| v | imm);
+                (value, Some(rt), value)
+            }
+            0b001111 => (None, Some(rt), Some(imm << 16)),
+            0b001000 | 0b001010..=0b001100 | 0b001110 | 0b011000..=0b011011 => (None, Some(rt), None),
+
+            // Loads
+            0b100000..=0b100111 | 0b110000 | 0b110100 | 0b110111 => {
+                (self.values[rs].map(|v| v.wrapping_add(simm)), Some(rt), None)
+            }
+            // Stores
+            0b101000..=0b101110 | 0b110001 | 0b110101 | 0b111001 | 0b111101 | 0b111111 => {
+                (self.values[rs].map(|v| v.wrapping_add(simm)), None, None)
+            }
+            0b111000 | 0b111100 => (self.values[rs].map(|v| v.wrapping_add(simm)), Some(rt), None),
+
+            // MFC0/DMFC0/MFC1/DMFC1/CFC1
+            0b010000 | 0b010001 if rs <= 0b00010 => (None, Some(rt), None),
+
+            _ => (None, None, None),
+        };
+
+        if let Some(reg) = written_reg {
+            if reg != 0 {
+                self.values[reg] = written_value;
+            }
+        }
+
+        accessed_addr.and_then(|addr| match addr {
+            // Only kseg0 and kseg1 map directly onto physical addresses
+            0x8000_0000..=0xbfff_ffff => mem_map::register_name(addr & 0x1fff_ffff),
+            _ => None,
+        })
+    }
+}
+
+fn parse_offset(s: &str) -> usize {
+    let result = if s.starts_with("0x") || s.starts_with("0X") {
+        usize::from_str_radix(&s[2..], 16)
+    } else {
+        s.parse()
+    };
+    result.unwrap_or_else(|_| fail(&format!("Invalid offset: {}", s)))
+}
+
+fn read_bin(path: &str) -> Box<[u8]> {
+    let mut file = File::open(path).unwrap_or_else(|e| fail(&format!("Could not open {}: {}", path, e)));
+    let mut file_buf = Vec::new();
+    file.read_to_end(&mut file_buf).unwrap();
+    file_buf.into_boxed_slice()
+}
+
+fn fail(message: &str) -> ! {
+    println!("{}", message);
+    process::exit(1);
+}
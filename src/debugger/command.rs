@@ -1,14 +1,29 @@
 use std::borrow::Cow;
 use std::str::{self, FromStr};
 
-use nom::{IResult, eof, space, digit};
+use nom::{IResult, eof, space, digit, hex_digit};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Processor {
+    Cpu,
+    Rsp,
+}
+
+#[derive(Debug, Clone)]
 pub enum Command {
     Step(usize),
     Run,
     Memdump(Option<usize>, usize),
+    Disassemble(Option<usize>, usize),
     CpuInfo,
+    RspTasks,
+    SelectProcessor(Processor),
+    Breakpoint(Option<usize>),
+    RspInfo,
+    VectorInfo,
+    Dmemdump(Option<usize>, usize),
+    RdpTrace(Option<String>),
+    CpuTrace(Option<String>),
     Exit,
     Repeat,
 }
@@ -28,10 +43,20 @@ named!(
     command<Command>,
     chain!(
         c: alt_complete!(
+            // Longer commands go first so they aren't taken for an alias
+            rdptrace |
+            cputrace |
+            rsptasks |
+            rspinfo |
+            dmemdump |
             step |
             run |
             memdump |
+            disassemble |
             cpuinfo |
+            select_processor |
+            breakpoint |
+            vectorinfo |
             exit |
             repeat) ~
             eof,
@@ -54,10 +79,18 @@ named!(
     memdump<Command>,
     chain!(
         alt_complete!(tag!("memdump") | tag!("m")) ~
-            address: opt!(preceded!(space, usize_parser)) ~
+            address: opt!(preceded!(space, address_parser)) ~
             size: opt!(preceded!(space, usize_parser)),
         || Command::Memdump(address, size.unwrap_or(256))));
 
+named!(
+    disassemble<Command>,
+    chain!(
+        alt_complete!(tag!("disasm") | tag!("d")) ~
+            address: opt!(preceded!(space, address_parser)) ~
+            count: opt!(preceded!(space, usize_parser)),
+        || Command::Disassemble(address, count.unwrap_or(16))));
+
 named!(
     exit<Command>,
     map!(
@@ -70,6 +103,66 @@ named!(
         alt_complete!(tag!("cpuinfo") | tag!("i")),
         |_| Command::CpuInfo));
 
+named!(
+    rsptasks<Command>,
+    map!(
+        alt_complete!(tag!("rsptasks") | tag!("t")),
+        |_| Command::RspTasks));
+
+named!(
+    select_processor<Command>,
+    chain!(
+        alt_complete!(tag!("proc") | tag!("p")) ~
+            space ~
+            processor: alt_complete!(
+                map!(tag!("cpu"), |_| Processor::Cpu) |
+                map!(tag!("rsp"), |_| Processor::Rsp)),
+        || Command::SelectProcessor(processor)));
+
+named!(
+    breakpoint<Command>,
+    chain!(
+        alt_complete!(tag!("break") | tag!("b")) ~
+            address: opt!(preceded!(space, address_parser)),
+        || Command::Breakpoint(address)));
+
+named!(
+    rspinfo<Command>,
+    map!(
+        alt_complete!(tag!("rspinfo") | tag!("ri")),
+        |_| Command::RspInfo));
+
+named!(
+    vectorinfo<Command>,
+    map!(
+        alt_complete!(tag!("vuinfo") | tag!("v")),
+        |_| Command::VectorInfo));
+
+named!(
+    dmemdump<Command>,
+    chain!(
+        alt_complete!(tag!("dmem") | tag!("dm")) ~
+            offset: opt!(preceded!(space, address_parser)) ~
+            size: opt!(preceded!(space, usize_parser)),
+        || Command::Dmemdump(offset, size.unwrap_or(256))));
+
+// Starts recording RDP commands to a file, or stops without one
+named!(
+    rdptrace<Command>,
+    chain!(
+        tag!("rdptrace") ~
+            path: opt!(preceded!(space, map_res!(is_not!(" \t"), str::from_utf8))),
+        || Command::RdpTrace(path.map(String::from))));
+
+// Starts writing the disassembly of every CPU instruction executed to a
+// file, or stops without one
+named!(
+    cputrace<Command>,
+    chain!(
+        tag!("cputrace") ~
+            path: opt!(preceded!(space, map_res!(is_not!(" \t"), str::from_utf8))),
+        || Command::CpuTrace(path.map(String::from))));
+
 named!(
     repeat<Command>,
     value!(Command::Repeat));
@@ -81,3 +174,16 @@ named!(
             digit,
             str::from_utf8),
         FromStr::from_str));
+
+// Addresses are decimal, or hexadecimal with a 0x prefix
+named!(
+    address_parser<usize>,
+    alt_complete!(
+        preceded!(
+            tag!("0x"),
+            map_res!(
+                map_res!(
+                    hex_digit,
+                    str::from_utf8),
+                |s| usize::from_str_radix(s, 16))) |
+        usize_parser));
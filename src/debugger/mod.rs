@@ -1,16 +1,18 @@
 mod command;
 
-use std::io::{stdin, stdout};
+use std::fs::File;
+use std::io::{stdin, stdout, BufWriter};
 use std::io::prelude::*;
 use std::borrow::Cow;
-use n64::cpu::Instruction;
-use n64::cpu::opcode::Opcode::*;
+use std::collections::HashSet;
+use byteorder::{BigEndian, ByteOrder};
+use disasm::{self, RegisterNames};
 use n64::mem_map;
 use n64::mem_map::Addr::*;
 use n64::N64;
 use n64::sinks::{VideoFrame};
 use middleware::MostRecentFrameSink;
-use self::command::Command;
+use self::command::{Command, Processor};
 
 use minifb::{WindowOptions, Window, Key, KeyRepeat, Scale};
 
@@ -19,6 +21,12 @@ pub struct Debugger {
 
     last_command: Option<Command>,
 
+    // Processor that step and disasm work on
+    processor: Processor,
+    rsp_breakpoints: HashSet<u32>,
+    // Disassembly of the CPU instructions executed, while recording
+    cpu_trace: Option<BufWriter<File>>,
+
     window: Option<Window>,
     window_size: (u32, u32),
 }
@@ -30,6 +38,10 @@ impl Debugger {
 
             last_command: None,
 
+            processor: Processor::Cpu,
+            rsp_breakpoints: HashSet::new(),
+            cpu_trace: None,
+
             window: None,
             window_size: (0, 0),
         }
@@ -39,10 +51,13 @@ impl Debugger {
         // Run the emulator when pressing enter
         self.last_command = Some(Command::Run);
         loop {
-            print!("r64> ");
+            match self.processor {
+                Processor::Cpu => print!("r64> "),
+                Processor::Rsp => print!("r64 (rsp)> "),
+            }
             stdout().flush().unwrap();
 
-            let command = match (read_stdin().parse(), self.last_command) {
+            let command = match (read_stdin().parse(), self.last_command.clone()) {
                 (Ok(Command::Repeat), Some(c)) => Ok(c),
                 (Ok(Command::Repeat), None) => Err("No last command".into()),
                 (Ok(c), _) => Ok(c),
@@ -53,7 +68,16 @@ impl Debugger {
                 Ok(Command::Step(count)) => self.step(count),
                 Ok(Command::Run) => self.execute_run(),
                 Ok(Command::Memdump(addr, count)) => self.memdump(addr, count),
+                Ok(Command::Disassemble(addr, count)) => self.disassemble(addr, count),
                 Ok(Command::CpuInfo) => self.cpuinfo(),
+                Ok(Command::RspTasks) => self.rsptasks(),
+                Ok(Command::SelectProcessor(processor)) => self.processor = processor,
+                Ok(Command::Breakpoint(addr)) => self.breakpoint(addr),
+                Ok(Command::RspInfo) => self.rspinfo(),
+                Ok(Command::VectorInfo) => self.vectorinfo(),
+                Ok(Command::Dmemdump(offset, size)) => self.dmemdump(offset, size),
+                Ok(Command::RdpTrace(ref path)) => self.rdptrace(path.as_ref().map(|path| path.as_str())),
+                Ok(Command::CpuTrace(ref path)) => self.cputrace(path.as_ref().map(|path| path.as_str())),
                 Ok(Command::Exit) => break,
                 Ok(Command::Repeat) => unreachable!(),
                 Err(ref e) => println!("{}", e),
@@ -64,17 +88,16 @@ impl Debugger {
     }
 
     pub fn step(&mut self, count: usize) {
+        if self.processor == Processor::Rsp {
+            return self.step_rsp(count);
+        }
+
         for _ in 0..count {
             let current_pc = self.n64.cpu().current_pc_phys();
-            let instr = Instruction(self.n64.interconnect().read_word_debug(current_pc as u32).unwrap());
+            let word = self.n64.interconnect().read_word_debug(current_pc as u32).unwrap();
 
             print!("{:018X}: ", current_pc);
-
-            match instr.opcode() {
-                Special => print!("{:?} (Special)", instr.special_op()),
-                RegImm => print!("{:?} (RegImm)", instr.reg_imm_op()),
-                _ => print!("{:?}", instr),
-            }
+            print!("{}", disasm::disassemble_vr4300(word, self.n64.cpu().current_pc_virt(), RegisterNames::Abi));
 
             if self.n64.cpu().will_execute_from_delay_slot() {
                 println!(" (DELAY)");
@@ -82,20 +105,67 @@ impl Debugger {
                 println!("");
             }
 
+            self.trace_cpu();
             let mut frame_sink = MostRecentFrameSink::new();
             self.n64.step(&mut frame_sink);
             self.display_frame(frame_sink);
         }
     }
 
+    pub fn step_rsp(&mut self, count: usize) {
+        for _ in 0..count {
+            if self.n64.interconnect().rsp_regs().is_halted() {
+                println!("RSP is halted");
+                return;
+            }
+
+            let pc = self.n64.rsp().current_pc(self.n64.interconnect().rsp_regs());
+            let word = self.n64.interconnect().rsp_regs().read_imem(pc);
+
+            print!("{:03X}: ", pc);
+            print!("{}", disasm::disassemble_rsp(word, pc, RegisterNames::Abi));
+
+            if self.n64.rsp().will_execute_from_delay_slot() {
+                println!(" (DELAY)");
+            } else {
+                println!("");
+            }
+
+            self.n64.step_rsp();
+        }
+    }
+
     pub fn execute_run(&mut self) {
+        // Only stop when the RSP arrives at a breakpoint, so running again
+        // continues past the one it stopped at
+        let mut resume_pc = self.rsp_pc();
         loop {
-            let current_pc = self.n64.cpu().current_pc_phys();
-            let instr = Instruction(self.n64.interconnect().read_word_debug(current_pc as u32).unwrap());
-
+            self.trace_cpu();
             let mut frame_sink = MostRecentFrameSink::new();
-            self.n64.step(&mut frame_sink);
+            let stopped_at = {
+                let breakpoints = &self.rsp_breakpoints;
+                self.n64.step_until_rsp(&mut frame_sink, |pc| {
+                    let resuming = resume_pc.take() == Some(pc);
+                    !resuming && breakpoints.contains(&pc)
+                })
+            };
             self.display_frame(frame_sink);
+
+            if let Some(pc) = stopped_at {
+                println!("RSP breakpoint hit at {:03X}", pc);
+                self.processor = Processor::Rsp;
+                return;
+            }
+        }
+    }
+
+    // None while the RSP is halted
+    fn rsp_pc(&self) -> Option<u32> {
+        let regs = self.n64.interconnect().rsp_regs();
+        if regs.is_halted() {
+            None
+        } else {
+            Some(self.n64.rsp().current_pc(regs))
         }
     }
 
@@ -131,9 +201,144 @@ impl Debugger {
         }
     }
 
+    pub fn disassemble(&mut self, addr: Option<usize>, count: usize) {
+        let current_pc = match self.processor {
+            Processor::Cpu => self.n64.cpu().current_pc_phys() as u32,
+            Processor::Rsp => mem_map::SP_IMEM_START + self.n64.rsp().current_pc(self.n64.interconnect().rsp_regs()),
+        };
+        let start_addr = addr.map(|addr| addr as u32).unwrap_or(current_pc);
+
+        for i in 0..(count as u32) {
+            let addr = start_addr + i * 4;
+            match self.n64.interconnect().read_word_debug(addr) {
+                Some(word) => {
+                    let text = match mem_map::map_addr(addr) {
+                        SpImem(offset) => disasm::disassemble_rsp(word, offset, RegisterNames::Abi),
+                        // Physical addresses are shown in kseg0 so jump targets come out right
+                        _ => disasm::disassemble_vr4300(word, 0xffff_ffff_8000_0000 | addr as u64, RegisterNames::Abi),
+                    };
+                    println!("{:08X}: {:08X}  {}", addr, word, text);
+                }
+                None => println!("{:08X}: ????????", addr),
+            }
+        }
+    }
+
     pub fn cpuinfo(&mut self) {
         println!("{:?}", self.n64.cpu());
     }
+
+    // Toggles a breakpoint at an IMEM offset, or lists them without one
+    pub fn breakpoint(&mut self, addr: Option<usize>) {
+        match addr {
+            Some(addr) => {
+                let offset = addr as u32 & 0x0ffc;
+                if self.rsp_breakpoints.remove(&offset) {
+                    println!("Removed RSP breakpoint at {:03X}", offset);
+                } else {
+                    self.rsp_breakpoints.insert(offset);
+                    println!("Added RSP breakpoint at {:03X}", offset);
+                }
+            }
+            None => {
+                let mut breakpoints: Vec<_> = self.rsp_breakpoints.iter().collect();
+                breakpoints.sort();
+                for offset in breakpoints {
+                    println!("RSP breakpoint at {:03X}", offset);
+                }
+            }
+        }
+    }
+
+    pub fn rspinfo(&mut self) {
+        let regs = self.n64.interconnect().rsp_regs();
+        println!("RSP PC: {:03X} ({})", regs.pc(), if regs.is_halted() { "halted" } else { "running" });
+        println!("{:?}", self.n64.rsp());
+    }
+
+    pub fn vectorinfo(&mut self) {
+        println!("{:?}", self.n64.rsp().vu());
+    }
+
+    pub fn dmemdump(&mut self, offset: Option<usize>, size: usize) {
+        let start = offset.unwrap_or(0) as u32 & 0x0ffc;
+        let dmem = self.n64.interconnect().rsp_regs().dmem();
+
+        for i in 0..(size as u32 / 4) {
+            let offset = (start + i * 4) & 0x0ffc;
+            if (i % 8) == 0 {
+                print!("\n{:03X}: ", offset);
+            }
+            print!("{:08X} ", BigEndian::read_u32(&dmem[offset as usize..]));
+        }
+        println!("");
+    }
+
+    pub fn rdptrace(&mut self, path: Option<&str>) {
+        let rdp = self.n64.rdp();
+        match path {
+            Some(path) => match rdp.start_trace(path) {
+                Ok(()) => println!("Recording RDP trace to {}", path),
+                Err(e) => println!("Unable to create RDP trace: {}", e),
+            },
+            None if rdp.is_tracing() => match rdp.stop_trace() {
+                Ok(()) => println!("Stopped RDP trace"),
+                Err(e) => println!("Unable to finish RDP trace: {}", e),
+            },
+            None => println!("No RDP trace is being recorded"),
+        }
+    }
+
+    pub fn cputrace(&mut self, path: Option<&str>) {
+        match path {
+            Some(path) => match File::create(path) {
+                Ok(file) => {
+                    self.cpu_trace = Some(BufWriter::new(file));
+                    println!("Recording CPU trace to {}", path);
+                }
+                Err(e) => println!("Unable to create CPU trace: {}", e),
+            },
+            None => match self.cpu_trace.take() {
+                Some(mut trace) => match trace.flush() {
+                    Ok(()) => println!("Stopped CPU trace"),
+                    Err(e) => println!("Unable to finish CPU trace: {}", e),
+                },
+                None => println!("No CPU trace is being recorded"),
+            },
+        }
+    }
+
+    // Writes the instruction the CPU executes next to the CPU trace
+    fn trace_cpu(&mut self) {
+        let result = match self.cpu_trace.as_mut() {
+            Some(trace) => {
+                let pc = self.n64.cpu().current_pc_virt();
+                match self.n64.interconnect().read_word_debug(self.n64.cpu().current_pc_phys() as u32) {
+                    Some(word) => {
+                        let text = disasm::disassemble_vr4300(word, pc, RegisterNames::Abi);
+                        writeln!(trace, "{:016X}: {:08X}  {}", pc, word, text)
+                    }
+                    None => writeln!(trace, "{:016X}: ????????", pc),
+                }
+            }
+            None => return,
+        };
+
+        if let Err(e) = result {
+            println!("WARNING: Stopped CPU trace: {}", e);
+            self.cpu_trace = None;
+        }
+    }
+
+    pub fn rsptasks(&mut self) {
+        let tasks = self.n64.rsp().hle().tasks();
+        if tasks.is_empty() {
+            println!("No RSP tasks started yet");
+        }
+        for (i, task) in tasks.iter().enumerate() {
+            println!("{:3}: {}", i, task);
+        }
+    }
 }
 
 fn read_stdin() -> String {
@@ -0,0 +1,116 @@
+mod rsp;
+mod vr4300;
+
+pub use self::rsp::disassemble as disassemble_rsp;
+pub use self::vr4300::disassemble as disassemble_vr4300;
+
+#[derive(Debug, Clone, Copy)]
+pub enum RegisterNames {
+    Numeric,
+    Abi,
+}
+
+const GPR_ABI_NAMES: [&'static str; 32] = [
+    "zero", "at", "v0", "v1", "a0", "a1", "a2", "a3", "t0", "t1", "t2", "t3", "t4", "t5", "t6", "t7", "s0", "s1", "s2", "s3",
+    "s4", "s5", "s6", "s7", "t8", "t9", "k0", "k1", "gp", "sp", "s8", "ra",
+];
+
+fn gpr(index: u32, names: RegisterNames) -> String {
+    match names {
+        RegisterNames::Numeric => format!("${}", index),
+        RegisterNames::Abi => format!("${}", GPR_ABI_NAMES[index as usize]),
+    }
+}
+
+fn signed_hex(value: i32) -> String {
+    if value < 0 {
+        format!("-{:#x}", -(value as i64))
+    } else {
+        format!("{:#x}", value)
+    }
+}
+
+fn invalid(word: u32) -> String {
+    format!(".word {:#010x}", word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vr4300_words_are_disassembled() {
+        let pc = 0xffff_ffff_8000_1000;
+        let table = [
+            (0x0000_0000, "nop"),
+            // Special
+            (0x0085_1021, "addu $v0, $a0, $a1"),
+            (0x0009_4100, "sll $t0, $t1, 4"),
+            (0x0003_103c, "dsll32 $v0, $v1, 0"),
+            (0x0085_0018, "mult $a0, $a1"),
+            (0x03e0_0008, "jr $ra"),
+            (0x0320_f809, "jalr $t9"),
+            (0x0000_000d, "break"),
+            // RegImm
+            (0x0481_0004, "bgez $a0, 0x80001014"),
+            (0x0410_ffff, "bltzal $zero, 0x80001000"),
+            (0x050c_ffff, "teqi $t0, -0x1"),
+            // Branches and jumps
+            (0x1109_fffe, "beq $t0, $t1, 0x80000ffc"),
+            (0x5509_0001, "bnel $t0, $t1, 0x80001008"),
+            (0x0810_0000, "j 0x80400000"),
+            (0x0c10_0000, "jal 0x80400000"),
+            // Immediates and memory
+            (0x27bd_ffe0, "addiu $sp, $sp, -0x20"),
+            (0x3c01_8000, "lui $at, 0x8000"),
+            (0x8fbf_0014, "lw $ra, 0x14($sp)"),
+            // COP0
+            (0x4008_6000, "mfc0 $t0, $Status"),
+            (0x4080_4800, "mtc0 $zero, $Count"),
+            (0x4200_0018, "eret"),
+            // COP1
+            (0x4604_1000, "add.s $f0, $f2, $f4"),
+            (0x4680_1021, "cvt.d.w $f0, $f2"),
+            (0x4624_103c, "c.lt.d $f2, $f4"),
+            (0x4488_6000, "mtc1 $t0, $f12"),
+            (0x4501_0001, "bc1t 0x80001008"),
+            (0xc484_0008, "lwc1 $f4, 0x8($a0)"),
+            (0x4c00_0000, ".word 0x4c000000"),
+        ];
+        for &(word, expected) in &table {
+            assert_eq!(disassemble_vr4300(word, pc, RegisterNames::Abi), expected, "{:08x}", word);
+        }
+    }
+
+    #[test]
+    fn rsp_words_are_disassembled() {
+        let table = [
+            // Vector ops with the element suffixes
+            (0x4a00_0010, 0x100, "vadd $v0, $v0, $v0"),
+            (0x4a65_2187, 0x100, "vmudh $v6, $v4, $v5[1q]"),
+            (0x4ac3_1040, 0x100, "vmulf $v1, $v2, $v3[2h]"),
+            (0x4ba1_10c8, 0x100, "vmacf $v3, $v2, $v1[5]"),
+            (0x4aa3_1070, 0x100, "vrcp $v1[2], $v3[5]"),
+            (0x4a00_0037, 0x100, "vnop"),
+            // Vector loads and stores with scaled offsets
+            (0xc881_2001, 0x100, "lqv $v1[0], 0x10($a0)"),
+            (0xe802_1c7f, 0x100, "sdv $v2[8], -0x8($zero)"),
+            (0x4808_0a00, 0x100, "mfc2 $t0, $v1[4]"),
+            (0x4848_0800, 0x100, "cfc2 $t0, $vcc"),
+            (0x4008_3000, 0x100, "mfc0 $t0, $SP_DMA_BUSY"),
+            // Targets wrap within IMEM
+            (0x1000_0001, 0xffc, "beq $zero, $zero, 0x004"),
+            (0x0511_fffe, 0x000, "bgezal $t0, 0xffc"),
+            (0x0800_0410, 0x100, "j 0x040"),
+        ];
+        for &(word, pc, expected) in &table {
+            assert_eq!(disassemble_rsp(word, pc, RegisterNames::Abi), expected, "{:08x}", word);
+        }
+    }
+
+    #[test]
+    fn registers_can_be_numbered() {
+        assert_eq!(disassemble_vr4300(0x0085_1021, 0, RegisterNames::Numeric), "addu $2, $4, $5");
+        assert_eq!(disassemble_rsp(0xc881_2001, 0, RegisterNames::Numeric), "lqv $v1[0], 0x10($4)");
+    }
+}
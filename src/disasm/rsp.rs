@@ -0,0 +1,259 @@
+use super::{gpr, invalid, signed_hex, RegisterNames};
+
+const OPCODES: [&'static str; 64] = [
+    "", "", "j", "jal", "beq", "bne", "blez", "bgtz",
+    "addi", "addiu", "slti", "sltiu", "andi", "ori", "xori", "lui",
+    "", "", "", "", "", "", "", "",
+    "", "", "", "", "", "", "", "",
+    "lb", "lh", "", "lw", "lbu", "lhu", "", "lwu",
+    "sb", "sh", "", "sw", "", "", "", "",
+    "", "", "", "", "", "", "", "",
+    "", "", "", "", "", "", "", "",
+];
+
+const SPECIAL_OPCODES: [&'static str; 64] = [
+    "sll", "", "srl", "sra", "sllv", "", "srlv", "srav",
+    "jr", "jalr", "", "", "", "break", "", "",
+    "", "", "", "", "", "", "", "",
+    "", "", "", "", "", "", "", "",
+    "add", "addu", "sub", "subu", "and", "or", "xor", "nor",
+    "", "", "slt", "sltu", "", "", "", "",
+    "", "", "", "", "", "", "", "",
+    "", "", "", "", "", "", "", "",
+];
+
+const VECTOR_OPCODES: [&'static str; 64] = [
+    "vmulf", "vmulu", "vrndp", "vmulq", "vmudl", "vmudm", "vmudn", "vmudh",
+    "vmacf", "vmacu", "vrndn", "vmacq", "vmadl", "vmadm", "vmadn", "vmadh",
+    "vadd", "vsub", "", "vabs", "vaddc", "vsubc", "", "",
+    "", "", "", "", "", "vsar", "", "",
+    "vlt", "veq", "vne", "vge", "vcl", "vch", "vcr", "vmrg",
+    "vand", "vnand", "vor", "vnor", "vxor", "vnxor", "", "",
+    "vrcp", "vrcpl", "vrcph", "vmov", "vrsq", "vrsql", "vrsqh", "vnop",
+    "", "", "", "", "", "", "", "",
+];
+
+// Indexed by the rd field of LWC2/SWC2
+const VECTOR_LOADS: [&'static str; 12] = [
+    "lbv", "lsv", "llv", "ldv", "lqv", "lrv", "lpv", "luv", "lhv", "lfv", "", "ltv",
+];
+const VECTOR_STORES: [&'static str; 12] = [
+    "sbv", "ssv", "slv", "sdv", "sqv", "srv", "spv", "suv", "shv", "sfv", "swv", "stv",
+];
+// The 7-bit offset is scaled by the access size of each vector load/store
+const VECTOR_OFFSET_SHIFT: [u32; 12] = [0, 1, 2, 3, 4, 4, 3, 3, 4, 4, 4, 4];
+
+const COP0_REG_NAMES: [&'static str; 16] = [
+    "SP_MEM_ADDR", "SP_DRAM_ADDR", "SP_RD_LEN", "SP_WR_LEN", "SP_STATUS", "SP_DMA_FULL", "SP_DMA_BUSY", "SP_SEMAPHORE",
+    "DPC_START", "DPC_END", "DPC_CURRENT", "DPC_STATUS", "DPC_CLOCK", "DPC_BUFBUSY", "DPC_PIPEBUSY", "DPC_TMEM",
+];
+
+// Renders a single RSP instruction word located at IMEM offset `pc`.
+// The RSP program counter is 12 bits wide, so branch and jump targets wrap
+// within IMEM.
+pub fn disassemble(word: u32, pc: u32, names: RegisterNames) -> String {
+    let instr = RspInstruction {
+        word: word,
+        pc: pc,
+        names: names,
+    };
+    instr.render().unwrap_or_else(|| invalid(word))
+}
+
+struct RspInstruction {
+    word: u32,
+    pc: u32,
+    names: RegisterNames,
+}
+
+impl RspInstruction {
+    fn op(&self) -> usize {
+        (self.word >> 26) as usize
+    }
+
+    fn rs(&self) -> u32 {
+        (self.word >> 21) & 0b11111
+    }
+
+    fn rt(&self) -> u32 {
+        (self.word >> 16) & 0b11111
+    }
+
+    fn rd(&self) -> u32 {
+        (self.word >> 11) & 0b11111
+    }
+
+    fn sa(&self) -> u32 {
+        (self.word >> 6) & 0b11111
+    }
+
+    fn funct(&self) -> usize {
+        (self.word & 0b111111) as usize
+    }
+
+    fn imm(&self) -> u32 {
+        self.word & 0xffff
+    }
+
+    fn simm(&self) -> i32 {
+        self.imm() as i16 as i32
+    }
+
+    fn gpr(&self, index: u32) -> String {
+        gpr(index, self.names)
+    }
+
+    fn branch_target(&self) -> String {
+        let target = self.pc.wrapping_add(4).wrapping_add((self.simm() << 2) as u32) & 0x0ffc;
+        format!("{:#05x}", target)
+    }
+
+    fn jump_target(&self) -> String {
+        format!("{:#05x}", (self.word << 2) & 0x0ffc)
+    }
+
+    fn render(&self) -> Option<String> {
+        let mnemonic = OPCODES[self.op()];
+        let text = match self.op() {
+            0b000000 => return self.render_special(),
+            0b000001 => return self.render_reg_imm(),
+            0b010000 => return self.render_cop0(),
+            0b010010 => return self.render_cop2(),
+            0b110010 => return self.render_vector_memory(&VECTOR_LOADS),
+            0b111010 => return self.render_vector_memory(&VECTOR_STORES),
+
+            _ if mnemonic.is_empty() => return None,
+
+            0b000010 | 0b000011 => format!("{} {}", mnemonic, self.jump_target()),
+            0b000100 | 0b000101 => {
+                format!("{} {}, {}, {}", mnemonic, self.gpr(self.rs()), self.gpr(self.rt()), self.branch_target())
+            }
+            0b000110 | 0b000111 => format!("{} {}, {}", mnemonic, self.gpr(self.rs()), self.branch_target()),
+            0b001000..=0b001011 => {
+                format!("{} {}, {}, {}", mnemonic, self.gpr(self.rt()), self.gpr(self.rs()), signed_hex(self.simm()))
+            }
+            0b001100..=0b001110 => {
+                format!("{} {}, {}, {:#x}", mnemonic, self.gpr(self.rt()), self.gpr(self.rs()), self.imm())
+            }
+            0b001111 => format!("{} {}, {:#x}", mnemonic, self.gpr(self.rt()), self.imm()),
+            _ => {
+                format!("{} {}, {}({})", mnemonic, self.gpr(self.rt()), signed_hex(self.simm()), self.gpr(self.rs()))
+            }
+        };
+        Some(text)
+    }
+
+    fn render_special(&self) -> Option<String> {
+        if self.word == 0 {
+            return Some("nop".into());
+        }
+
+        let mnemonic = SPECIAL_OPCODES[self.funct()];
+        let text = match self.funct() {
+            _ if mnemonic.is_empty() => return None,
+            0b000000 | 0b000010 | 0b000011 => {
+                format!("{} {}, {}, {}", mnemonic, self.gpr(self.rd()), self.gpr(self.rt()), self.sa())
+            }
+            0b000100..=0b000111 => {
+                format!("{} {}, {}, {}", mnemonic, self.gpr(self.rd()), self.gpr(self.rt()), self.gpr(self.rs()))
+            }
+            0b001000 => format!("{} {}", mnemonic, self.gpr(self.rs())),
+            0b001001 => format!("{} {}, {}", mnemonic, self.gpr(self.rd()), self.gpr(self.rs())),
+            0b001101 => mnemonic.into(),
+            _ => format!("{} {}, {}, {}", mnemonic, self.gpr(self.rd()), self.gpr(self.rs()), self.gpr(self.rt())),
+        };
+        Some(text)
+    }
+
+    fn render_reg_imm(&self) -> Option<String> {
+        let mnemonic = match self.rt() {
+            0b00000 => "bltz",
+            0b00001 => "bgez",
+            0b10000 => "bltzal",
+            0b10001 => "bgezal",
+            _ => return None,
+        };
+        Some(format!("{} {}, {}", mnemonic, self.gpr(self.rs()), self.branch_target()))
+    }
+
+    fn render_cop0(&self) -> Option<String> {
+        let mnemonic = match self.rs() {
+            0b00000 => "mfc0",
+            0b00100 => "mtc0",
+            _ => return None,
+        };
+        let reg = COP0_REG_NAMES[(self.rd() & 0b1111) as usize];
+        Some(format!("{} {}, ${}", mnemonic, self.gpr(self.rt()), reg))
+    }
+
+    fn render_cop2(&self) -> Option<String> {
+        if (self.word & (1 << 25)) != 0 {
+            return self.render_vector_op();
+        }
+
+        let element = (self.word >> 7) & 0b1111;
+        let text = match self.rs() {
+            0b00000 => format!("mfc2 {}, $v{}[{}]", self.gpr(self.rt()), self.rd(), element),
+            0b00100 => format!("mtc2 {}, $v{}[{}]", self.gpr(self.rt()), self.rd(), element),
+            0b00010 | 0b00110 => {
+                let mnemonic = if self.rs() == 0b00010 { "cfc2" } else { "ctc2" };
+                let reg = match self.rd() & 0b11 {
+                    0 => "$vco",
+                    1 => "$vcc",
+                    _ => "$vce",
+                };
+                format!("{} {}, {}", mnemonic, self.gpr(self.rt()), reg)
+            }
+            _ => return None,
+        };
+        Some(text)
+    }
+
+    fn render_vector_op(&self) -> Option<String> {
+        let mnemonic = VECTOR_OPCODES[self.funct()];
+        if mnemonic.is_empty() {
+            return None;
+        }
+
+        let element = (self.word >> 21) & 0b1111;
+        let vt = self.rt();
+        let vs = self.rd();
+        let vd = self.sa();
+
+        let text = match self.funct() {
+            0b110111 => mnemonic.into(),
+            // Single lane ops encode the destination lane in the vs field
+            0b110000..=0b110110 => format!("{} $v{}[{}], $v{}[{}]", mnemonic, vd, vs & 0b111, vt, element & 0b111),
+            _ => format!("{} $v{}, $v{}, $v{}{}", mnemonic, vd, vs, vt, element_suffix(element)),
+        };
+        Some(text)
+    }
+
+    fn render_vector_memory(&self, mnemonics: &[&'static str; 12]) -> Option<String> {
+        let op = self.rd() as usize;
+        if op >= mnemonics.len() || mnemonics[op].is_empty() {
+            return None;
+        }
+
+        let element = (self.word >> 7) & 0b1111;
+        // Sign extend the 7-bit offset before scaling it
+        let offset = (((self.word & 0x7f) << 25) as i32 >> 25) << VECTOR_OFFSET_SHIFT[op];
+        Some(format!(
+            "{} $v{}[{}], {}({})",
+            mnemonics[op],
+            self.rt(),
+            element,
+            signed_hex(offset),
+            self.gpr(self.rs())
+        ))
+    }
+}
+
+fn element_suffix(element: u32) -> String {
+    match element {
+        0b0000 | 0b0001 => String::new(),
+        0b0010 | 0b0011 => format!("[{}q]", element & 0b1),
+        0b0100..=0b0111 => format!("[{}h]", element & 0b11),
+        _ => format!("[{}]", element & 0b111),
+    }
+}
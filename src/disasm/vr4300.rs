@@ -0,0 +1,285 @@
+use super::{gpr, invalid, signed_hex, RegisterNames};
+
+const OPCODES: [&'static str; 64] = [
+    "", "", "j", "jal", "beq", "bne", "blez", "bgtz",
+    "addi", "addiu", "slti", "sltiu", "andi", "ori", "xori", "lui",
+    "", "", "", "", "beql", "bnel", "blezl", "bgtzl",
+    "daddi", "daddiu", "ldl", "ldr", "", "", "", "",
+    "lb", "lh", "lwl", "lw", "lbu", "lhu", "lwr", "lwu",
+    "sb", "sh", "swl", "sw", "sdl", "sdr", "swr", "cache",
+    "ll", "lwc1", "lwc2", "", "lld", "ldc1", "ldc2", "ld",
+    "sc", "swc1", "swc2", "", "scd", "sdc1", "sdc2", "sd",
+];
+
+const SPECIAL_OPCODES: [&'static str; 64] = [
+    "sll", "", "srl", "sra", "sllv", "", "srlv", "srav",
+    "jr", "jalr", "", "", "syscall", "break", "", "sync",
+    "mfhi", "mthi", "mflo", "mtlo", "dsllv", "", "dsrlv", "dsrav",
+    "mult", "multu", "div", "divu", "dmult", "dmultu", "ddiv", "ddivu",
+    "add", "addu", "sub", "subu", "and", "or", "xor", "nor",
+    "", "", "slt", "sltu", "dadd", "daddu", "dsub", "dsubu",
+    "tge", "tgeu", "tlt", "tltu", "teq", "", "tne", "",
+    "dsll", "", "dsrl", "dsra", "dsll32", "", "dsrl32", "dsra32",
+];
+
+const REG_IMM_OPCODES: [&'static str; 32] = [
+    "bltz", "bgez", "bltzl", "bgezl", "", "", "", "",
+    "tgei", "tgeiu", "tlti", "tltiu", "teqi", "", "tnei", "",
+    "bltzal", "bgezal", "bltzall", "bgezall", "", "", "", "",
+    "", "", "", "", "", "", "", "",
+];
+
+const COP1_OPCODES: [&'static str; 64] = [
+    "add", "sub", "mul", "div", "sqrt", "abs", "mov", "neg",
+    "round.l", "trunc.l", "ceil.l", "floor.l", "round.w", "trunc.w", "ceil.w", "floor.w",
+    "", "", "", "", "", "", "", "",
+    "", "", "", "", "", "", "", "",
+    "cvt.s", "cvt.d", "", "", "cvt.w", "cvt.l", "", "",
+    "", "", "", "", "", "", "", "",
+    "c.f", "c.un", "c.eq", "c.ueq", "c.olt", "c.ult", "c.ole", "c.ule",
+    "c.sf", "c.ngle", "c.seq", "c.ngl", "c.lt", "c.nge", "c.le", "c.ngt",
+];
+
+const COP0_REG_NAMES: [&'static str; 32] = [
+    "Index", "Random", "EntryLo0", "EntryLo1", "Context", "PageMask", "Wired", "7",
+    "BadVAddr", "Count", "EntryHi", "Compare", "Status", "Cause", "EPC", "PRId",
+    "Config", "LLAddr", "WatchLo", "WatchHi", "XContext", "21", "22", "23",
+    "24", "25", "PErr", "CacheErr", "TagLo", "TagHi", "ErrorEPC", "31",
+];
+
+// Renders a single VR4300 instruction word located at `pc` as assembly text.
+// Branch and jump targets are resolved to absolute addresses.
+pub fn disassemble(word: u32, pc: u64, names: RegisterNames) -> String {
+    let instr = Vr4300Instruction {
+        word: word,
+        pc: pc,
+        names: names,
+    };
+    instr.render().unwrap_or_else(|| invalid(word))
+}
+
+// Sign extended 32-bit addresses are shown in their short form
+fn address(addr: u64) -> String {
+    if addr == addr as i32 as u64 {
+        format!("{:#x}", addr as u32)
+    } else {
+        format!("{:#x}", addr)
+    }
+}
+
+struct Vr4300Instruction {
+    word: u32,
+    pc: u64,
+    names: RegisterNames,
+}
+
+impl Vr4300Instruction {
+    fn op(&self) -> usize {
+        (self.word >> 26) as usize
+    }
+
+    fn rs(&self) -> u32 {
+        (self.word >> 21) & 0b11111
+    }
+
+    fn rt(&self) -> u32 {
+        (self.word >> 16) & 0b11111
+    }
+
+    fn rd(&self) -> u32 {
+        (self.word >> 11) & 0b11111
+    }
+
+    fn sa(&self) -> u32 {
+        (self.word >> 6) & 0b11111
+    }
+
+    fn funct(&self) -> usize {
+        (self.word & 0b111111) as usize
+    }
+
+    fn imm(&self) -> u32 {
+        self.word & 0xffff
+    }
+
+    fn simm(&self) -> i32 {
+        self.imm() as i16 as i32
+    }
+
+    fn gpr(&self, index: u32) -> String {
+        gpr(index, self.names)
+    }
+
+    fn branch_target(&self) -> String {
+        let offset = (self.simm() as i64 as u64) << 2;
+        address(self.pc.wrapping_add(4).wrapping_add(offset))
+    }
+
+    fn jump_target(&self) -> String {
+        let delay_slot_pc = self.pc.wrapping_add(4);
+        let target = (delay_slot_pc & 0xffff_ffff_f000_0000) | (((self.word & 0x03ff_ffff) as u64) << 2);
+        address(target)
+    }
+
+    fn mem_operand(&self) -> String {
+        format!("{}({})", signed_hex(self.simm()), self.gpr(self.rs()))
+    }
+
+    fn render(&self) -> Option<String> {
+        let mnemonic = OPCODES[self.op()];
+        let text = match self.op() {
+            0b000000 => return self.render_special(),
+            0b000001 => return self.render_reg_imm(),
+            0b010000 => return self.render_cop0(),
+            0b010001 => return self.render_cop1(),
+            0b010010 => format!("cop2 {:#x}", self.word & 0x01ff_ffff),
+
+            _ if mnemonic.is_empty() => return None,
+
+            0b000010 | 0b000011 => format!("{} {}", mnemonic, self.jump_target()),
+
+            0b000100 | 0b000101 | 0b010100 | 0b010101 => {
+                format!("{} {}, {}, {}", mnemonic, self.gpr(self.rs()), self.gpr(self.rt()), self.branch_target())
+            }
+            0b000110 | 0b000111 | 0b010110 | 0b010111 => {
+                format!("{} {}, {}", mnemonic, self.gpr(self.rs()), self.branch_target())
+            }
+
+            0b001000..=0b001011 | 0b011000 | 0b011001 => {
+                format!("{} {}, {}, {}", mnemonic, self.gpr(self.rt()), self.gpr(self.rs()), signed_hex(self.simm()))
+            }
+            0b001100..=0b001110 => {
+                format!("{} {}, {}, {:#x}", mnemonic, self.gpr(self.rt()), self.gpr(self.rs()), self.imm())
+            }
+            0b001111 => format!("{} {}, {:#x}", mnemonic, self.gpr(self.rt()), self.imm()),
+
+            0b101111 => format!("{} {:#x}, {}", mnemonic, self.rt(), self.mem_operand()),
+
+            0b110001 | 0b110101 | 0b111001 | 0b111101 => {
+                format!("{} $f{}, {}", mnemonic, self.rt(), self.mem_operand())
+            }
+            0b110010 | 0b110110 | 0b111010 | 0b111110 => {
+                format!("{} ${}, {}", mnemonic, self.rt(), self.mem_operand())
+            }
+
+            _ => format!("{} {}, {}", mnemonic, self.gpr(self.rt()), self.mem_operand()),
+        };
+        Some(text)
+    }
+
+    fn render_special(&self) -> Option<String> {
+        if self.word == 0 {
+            return Some("nop".into());
+        }
+
+        let mnemonic = SPECIAL_OPCODES[self.funct()];
+        if mnemonic.is_empty() {
+            return None;
+        }
+
+        let text = match self.funct() {
+            0b000000 | 0b000010 | 0b000011 | 0b111000..=0b111111 => {
+                format!("{} {}, {}, {}", mnemonic, self.gpr(self.rd()), self.gpr(self.rt()), self.sa())
+            }
+            0b000100..=0b000111 | 0b010100..=0b010111 => {
+                format!("{} {}, {}, {}", mnemonic, self.gpr(self.rd()), self.gpr(self.rt()), self.gpr(self.rs()))
+            }
+            0b001000 => format!("{} {}", mnemonic, self.gpr(self.rs())),
+            0b001001 => {
+                if self.rd() == 31 {
+                    format!("{} {}", mnemonic, self.gpr(self.rs()))
+                } else {
+                    format!("{} {}, {}", mnemonic, self.gpr(self.rd()), self.gpr(self.rs()))
+                }
+            }
+            0b001100 | 0b001101 => {
+                let code = (self.word >> 6) & 0x000f_ffff;
+                if code == 0 {
+                    mnemonic.into()
+                } else {
+                    format!("{} {:#x}", mnemonic, code)
+                }
+            }
+            0b001111 => mnemonic.into(),
+            0b010000 | 0b010010 => format!("{} {}", mnemonic, self.gpr(self.rd())),
+            0b010001 | 0b010011 => format!("{} {}", mnemonic, self.gpr(self.rs())),
+            0b011000..=0b011111 | 0b110000..=0b110110 => {
+                format!("{} {}, {}", mnemonic, self.gpr(self.rs()), self.gpr(self.rt()))
+            }
+            _ => format!("{} {}, {}, {}", mnemonic, self.gpr(self.rd()), self.gpr(self.rs()), self.gpr(self.rt())),
+        };
+        Some(text)
+    }
+
+    fn render_reg_imm(&self) -> Option<String> {
+        let mnemonic = REG_IMM_OPCODES[self.rt() as usize];
+        let text = match self.rt() {
+            _ if mnemonic.is_empty() => return None,
+            0b01000..=0b01110 => format!("{} {}, {}", mnemonic, self.gpr(self.rs()), signed_hex(self.simm())),
+            _ => format!("{} {}, {}", mnemonic, self.gpr(self.rs()), self.branch_target()),
+        };
+        Some(text)
+    }
+
+    fn render_cop0(&self) -> Option<String> {
+        let mnemonic = match self.rs() {
+            0b00000 => "mfc0",
+            0b00001 => "dmfc0",
+            0b00100 => "mtc0",
+            0b00101 => "dmtc0",
+            0b10000..=0b11111 => {
+                return match self.funct() {
+                    0b000001 => Some("tlbr".into()),
+                    0b000010 => Some("tlbwi".into()),
+                    0b000110 => Some("tlbwr".into()),
+                    0b001000 => Some("tlbp".into()),
+                    0b011000 => Some("eret".into()),
+                    _ => None,
+                };
+            }
+            _ => return None,
+        };
+        Some(format!("{} {}, ${}", mnemonic, self.gpr(self.rt()), COP0_REG_NAMES[self.rd() as usize]))
+    }
+
+    fn render_cop1(&self) -> Option<String> {
+        let text = match self.rs() {
+            0b00000 => format!("mfc1 {}, $f{}", self.gpr(self.rt()), self.rd()),
+            0b00001 => format!("dmfc1 {}, $f{}", self.gpr(self.rt()), self.rd()),
+            0b00010 => format!("cfc1 {}, $fcr{}", self.gpr(self.rt()), self.rd()),
+            0b00100 => format!("mtc1 {}, $f{}", self.gpr(self.rt()), self.rd()),
+            0b00101 => format!("dmtc1 {}, $f{}", self.gpr(self.rt()), self.rd()),
+            0b00110 => format!("ctc1 {}, $fcr{}", self.gpr(self.rt()), self.rd()),
+            0b01000 => {
+                let mnemonic = match self.rt() {
+                    0b00000 => "bc1f",
+                    0b00001 => "bc1t",
+                    0b00010 => "bc1fl",
+                    0b00011 => "bc1tl",
+                    _ => return None,
+                };
+                format!("{} {}", mnemonic, self.branch_target())
+            }
+            0b10000 | 0b10001 | 0b10100 | 0b10101 => {
+                let fmt = match self.rs() {
+                    0b10000 => "s",
+                    0b10001 => "d",
+                    0b10100 => "w",
+                    _ => "l",
+                };
+                let mnemonic = COP1_OPCODES[self.funct()];
+                // fs lives in the rd field, ft in rt and fd in sa
+                match self.funct() {
+                    _ if mnemonic.is_empty() => return None,
+                    0b000000..=0b000011 => {
+                        format!("{}.{} $f{}, $f{}, $f{}", mnemonic, fmt, self.sa(), self.rd(), self.rt())
+                    }
+                    0b110000..=0b111111 => format!("{}.{} $f{}, $f{}", mnemonic, fmt, self.rd(), self.rt()),
+                    _ => format!("{}.{} $f{}, $f{}", mnemonic, fmt, self.sa(), self.rd()),
+                }
+            }
+            _ => return None,
+        };
+        Some(text)
+    }
+}
@@ -0,0 +1,18 @@
+#![deny(trivial_casts, trivial_numeric_casts)]
+
+extern crate byteorder;
+
+extern crate extprim;
+extern crate minifb;
+extern crate num;
+
+#[macro_use]
+extern crate enum_primitive;
+
+#[macro_use]
+extern crate nom;
+
+pub mod debugger;
+pub mod disasm;
+pub mod middleware;
+pub mod n64;
@@ -1,23 +1,9 @@
 #![deny(trivial_casts, trivial_numeric_casts)]
 
-extern crate byteorder;
+extern crate rustendo64;
 
-extern crate extprim;
-extern crate minifb;
-extern crate num;
-
-#[macro_use]
-extern crate enum_primitive;
-
-#[macro_use]
-extern crate nom;
-
-mod debugger;
-mod middleware;
-mod n64;
-
-use debugger::Debugger;
-use n64::N64;
+use rustendo64::debugger::Debugger;
+use rustendo64::n64::{N64, RdpMode};
 use std::env;
 use std::fs::File;
 use std::io::Read;
@@ -26,11 +12,25 @@ use std::path::Path;
 fn main() {
     let pif_file_name = env::args().nth(1).unwrap();
     let rom_file_name = env::args().nth(2).unwrap();
+    // The RDP renders on a worker thread unless asked to stay on the
+    // emulation thread, which keeps runs deterministic
+    let options: Vec<String> = env::args().skip(3).collect();
+    let rdp_mode = if options.iter().any(|arg| arg == "--sync-rdp") {
+        RdpMode::Synchronous
+    } else {
+        RdpMode::Threaded
+    };
+    // Threads drawing bands of scanlines in parallel
+    let rdp_threads = options.iter()
+        .position(|arg| arg == "--rdp-threads")
+        .and_then(|i| options.get(i + 1))
+        .map(|count| count.parse().unwrap())
+        .unwrap_or(1);
 
     let pif = read_bin(pif_file_name);
     let rom = read_bin(rom_file_name);
 
-    let n64 = N64::new(pif, rom);
+    let n64 = N64::new(pif, rom, rdp_mode, rdp_threads);
     let mut debugger = Debugger::new(n64);
     debugger.run();
 }
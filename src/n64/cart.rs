@@ -0,0 +1,118 @@
+use byteorder::{BigEndian, ByteOrder};
+
+use std::fmt;
+use std::str;
+
+use super::video_interface::TvSystem;
+
+// Offset of the game code in the cart image. Everything between the header
+// and this offset is the IPL3 boot code, which runs from SP DMEM.
+pub const BOOT_CODE_START: usize = 0x0040;
+pub const GAME_CODE_START: usize = 0x1000;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ImageFormat {
+    BigEndian,    // .z64, native byte order
+    ByteSwapped,  // .v64, 16-bit words swapped
+    LittleEndian, // .n64, 32-bit words swapped
+}
+
+impl ImageFormat {
+    pub fn detect(image: &[u8]) -> Option<ImageFormat> {
+        // The first byte of the header is always 0x80 (PI_BSD_DOM1_LAT_REG value)
+        match image.get(0..4) {
+            Some(&[0x80, 0x37, 0x12, 0x40]) => Some(ImageFormat::BigEndian),
+            Some(&[0x37, 0x80, 0x40, 0x12]) => Some(ImageFormat::ByteSwapped),
+            Some(&[0x40, 0x12, 0x37, 0x80]) => Some(ImageFormat::LittleEndian),
+            _ => None,
+        }
+    }
+}
+
+// Converts a cart image of any byte order to the big-endian layout the
+// emulator expects
+pub fn to_big_endian(mut image: Box<[u8]>) -> Result<Box<[u8]>, String> {
+    let format = match ImageFormat::detect(&image) {
+        Some(format) => format,
+        None => return Err("Unrecognized cart image byte order".into()),
+    };
+
+    match format {
+        ImageFormat::BigEndian => {}
+        ImageFormat::ByteSwapped => {
+            for chunk in image.chunks_mut(2) {
+                chunk.reverse();
+            }
+        }
+        ImageFormat::LittleEndian => {
+            for chunk in image.chunks_mut(4) {
+                chunk.reverse();
+            }
+        }
+    }
+
+    Ok(image)
+}
+
+pub struct CartHeader {
+    pub pi_bsd_dom1_config: u32,
+    pub clock_rate: u32,
+    pub entry_point: u32,
+    pub release: u32,
+    pub crc1: u32,
+    pub crc2: u32,
+    pub image_name: String,
+    pub media_format: u8,
+    pub cartridge_id: [u8; 2],
+    pub country_code: u8,
+    pub version: u8,
+}
+
+impl CartHeader {
+    // Expects a big-endian image, see `to_big_endian`
+    pub fn parse(image: &[u8]) -> Result<CartHeader, String> {
+        if image.len() < GAME_CODE_START {
+            return Err(format!("Cart image is too small ({} bytes)", image.len()));
+        }
+
+        Ok(CartHeader {
+            pi_bsd_dom1_config: BigEndian::read_u32(&image[0x00..]),
+            clock_rate: BigEndian::read_u32(&image[0x04..]),
+            entry_point: BigEndian::read_u32(&image[0x08..]),
+            release: BigEndian::read_u32(&image[0x0c..]),
+            crc1: BigEndian::read_u32(&image[0x10..]),
+            crc2: BigEndian::read_u32(&image[0x14..]),
+            image_name: String::from_utf8_lossy(&image[0x20..0x34]).trim_end_matches(|c| c == ' ' || c == '\0').into(),
+            media_format: image[0x3b],
+            cartridge_id: [image[0x3c], image[0x3d]],
+            country_code: image[0x3e],
+            version: image[0x3f],
+        })
+    }
+}
+
+impl CartHeader {
+    // Carts for Europe and Australia run on PAL consoles, all others on NTSC
+    // ones
+    pub fn tv_system(&self) -> TvSystem {
+        match self.country_code {
+            b'D' | b'F' | b'H' | b'I' | b'L' | b'P' | b'S' | b'U' | b'W' | b'X' | b'Y' => TvSystem::Pal,
+            _ => TvSystem::Ntsc,
+        }
+    }
+}
+
+impl fmt::Debug for CartHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let game_code = [self.media_format, self.cartridge_id[0], self.cartridge_id[1], self.country_code];
+
+        writeln!(f, "Image name:   {:?}", self.image_name)?;
+        writeln!(f, "Game code:    {}", str::from_utf8(&game_code).unwrap_or("????"))?;
+        writeln!(f, "Version:      {}", self.version)?;
+        writeln!(f, "Entry point:  {:#010X}", self.entry_point)?;
+        writeln!(f, "Clock rate:   {:#010X}", self.clock_rate)?;
+        writeln!(f, "Release:      {:#010X}", self.release)?;
+        writeln!(f, "PI DOM1:      {:#010X}", self.pi_bsd_dom1_config)?;
+        write!(f, "CRC:          {:08X} {:08X}", self.crc1, self.crc2)
+    }
+}
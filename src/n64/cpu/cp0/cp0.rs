@@ -1,4 +1,5 @@
-use super::{reg_config, reg_cause, reg_status};
+use super::super::MemoryAccess;
+use super::{reg_config, reg_cause, reg_status, ExceptionCode};
 
 #[derive(Debug, Default)]
 pub struct Cp0 {
@@ -13,6 +14,7 @@ pub struct Cp0 {
     reg_status: reg_status::RegStatus,
     reg_cause: reg_cause::RegCause,
     reg_epc: u64, // Exception program counter
+    reg_error_epc: u64,
     reg_config: reg_config::RegConfig,
 
     reg_watch_lo: u32,
@@ -25,6 +27,54 @@ pub struct Cp0 {
 }
 
 impl Cp0 {
+    pub fn reverse_endian(&self) -> bool {
+        self.reg_status.reverse_endian()
+    }
+
+    pub fn watch_hit(&self, phys_addr: u64, access: MemoryAccess) -> bool {
+        // Watch exceptions are not taken while already handling an exception
+        if self.reg_status.exception_level() {
+            return false;
+        }
+
+        let enabled = match access {
+            MemoryAccess::Load => (self.reg_watch_lo & (1 << 1)) != 0,
+            MemoryAccess::Store => (self.reg_watch_lo & (1 << 0)) != 0,
+        };
+
+        // WatchHi holds PAddr1 (bits 35:32), WatchLo PAddr0 (bits 31:3)
+        let watch_addr = ((self.reg_watch_hi as u64 & 0xf) << 32) |
+                         (self.reg_watch_lo as u64 & 0xffff_fff8);
+
+        enabled && (phys_addr & 0xf_ffff_fff8) == watch_addr
+    }
+
+    // Returns the address of the exception vector to continue execution at
+    pub fn enter_exception(&mut self, code: ExceptionCode, pc: u64, in_delay_slot: bool) -> u64 {
+        // EPC and BD are left alone for nested exceptions
+        if !self.reg_status.exception_level() {
+            self.reg_epc = pc;
+            self.reg_cause.set_branch_delay(in_delay_slot);
+        }
+
+        self.reg_cause.set_exception(code);
+        self.reg_status.set_exception_level(true);
+
+        self.reg_status.general_exception_vector()
+    }
+
+    // Errors (reset, NMI, cache errors) are returned from first, through
+    // ErrorEPC
+    pub fn return_from_exception(&mut self) -> u64 {
+        if self.reg_status.error_level() {
+            self.reg_status.set_error_level(false);
+            self.reg_error_epc
+        } else {
+            self.reg_status.set_exception_level(false);
+            self.reg_epc
+        }
+    }
+
     pub fn store_tlb_entry(&mut self) {
         let index = (self.reg_index & 0b11111) as usize;
 
@@ -60,9 +110,10 @@ impl Cp0 {
             14 => { self.reg_epc = data; }
             16 => self.reg_config = (data as u32).into(),
             18 => self.reg_watch_lo = (data as u32),
-            29 => self.reg_watch_hi = (data as u32),
+            19 => self.reg_watch_hi = (data as u32),
             28 => self.reg_tag_lo = (data as u32),
             29 => self.reg_tag_hi = (data as u32),
+            30 => self.reg_error_epc = data,
             _ => panic!("Unrecognized CP0 reg: {}, {:#018x}", index, data),
         }
     }
@@ -73,6 +124,9 @@ impl Cp0 {
             12 => { 0 /* TODO */ }
             13 => { self.reg_cause.to_u32() as u64 }
             14 => { self.reg_epc }
+            18 => { self.reg_watch_lo as u64 }
+            19 => { self.reg_watch_hi as u64 }
+            30 => { self.reg_error_epc }
             _ => panic!("Trying to read CP0 reg {}", index)
         }
     }
@@ -4,3 +4,4 @@ mod reg_cause;
 mod reg_status;
 
 pub use self::cp0::Cp0;
+pub use self::reg_cause::ExceptionCode;
@@ -10,6 +10,12 @@ pub struct RegCause {
     exception_code: u8, // Exception code
 }
 
+// Only the codes raised by the emulator so far, see Table 6-2 in the VR4300 User's Manual
+#[derive(Debug, Clone, Copy)]
+pub enum ExceptionCode {
+    Watch = 23,
+}
+
 impl RegCause {
     pub fn setSoftwareInterruptPendingFields(&mut self, value: u32)
     {
@@ -22,6 +28,14 @@ impl RegCause {
         self.ip_timer = false;
     }
 
+    pub fn set_exception(&mut self, code: ExceptionCode) {
+        self.exception_code = code as u8;
+    }
+
+    pub fn set_branch_delay(&mut self, in_delay_slot: bool) {
+        self.bd = in_delay_slot;
+    }
+
     pub fn to_u32(&self) -> u32 {
         (if self.bd { 1 << 31 } else { 0 }) |
         ((self.ce as u32) << 28) |
@@ -40,6 +40,44 @@ pub struct RegStatus {
     interrupts_enabled: bool,
 }
 
+impl RegStatus {
+    pub fn is_user_mode(&self) -> bool {
+        // EXL and ERL force kernel mode regardless of KSU
+        match self.mode {
+            Mode::User => !self.exception_level && !self.error_level,
+            _ => false,
+        }
+    }
+
+    pub fn reverse_endian(&self) -> bool {
+        // RE only has an effect in user mode
+        self.reverse_endian && self.is_user_mode()
+    }
+
+    pub fn exception_level(&self) -> bool {
+        self.exception_level || self.error_level
+    }
+
+    pub fn set_exception_level(&mut self, value: bool) {
+        self.exception_level = value;
+    }
+
+    pub fn error_level(&self) -> bool {
+        self.error_level
+    }
+
+    pub fn set_error_level(&mut self, value: bool) {
+        self.error_level = value;
+    }
+
+    pub fn general_exception_vector(&self) -> u64 {
+        match self.diagnostic_status.tlb_general_exception_vector_location {
+            TLBGeneralExceptionVectorLocation::Normal => 0xffff_ffff_8000_0180,
+            TLBGeneralExceptionVectorLocation::Bootstrap => 0xffff_ffff_bfc0_0380,
+        }
+    }
+}
+
 impl From<u32> for RegStatus {
     fn from(value: u32) -> Self {
         RegStatus {
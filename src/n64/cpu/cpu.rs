@@ -10,6 +10,9 @@ use extprim::u128::u128;
 
 use std::fmt;
 
+// One instruction is executed per cycle
+pub const CLOCK_HZ: u32 = 93_750_000;
+
 const NUM_GPR: usize = 32;
 
 enum SignExtendResult {
@@ -80,15 +83,35 @@ impl Cpu {
             let instr = self.read_instruction(interconnect, pc);
             self.delay_slot_pc = None;
 
-            self.execute_instruction(interconnect, instr);
+            // Exceptions in a delay slot report the address of the branch
+            if !self.check_watch(instr, pc - 4, true) {
+                self.execute_instruction(interconnect, instr);
+            }
         } else {
-            let instr = self.read_instruction(interconnect, self.reg_pc);
+            let pc = self.reg_pc;
+            let instr = self.read_instruction(interconnect, pc);
 
             self.reg_pc += 4;
-            self.execute_instruction(interconnect, instr);
+            if !self.check_watch(instr, pc, false) {
+                self.execute_instruction(interconnect, instr);
+            }
         }
     }
 
+    // Raises a watch exception before a load/store touches the watched
+    // doubleword. Returns whether the instruction was aborted.
+    fn check_watch(&mut self, instr: Instruction, pc: u64, in_delay_slot: bool) -> bool {
+        if let Some(access) = instr.memory_access() {
+            let phys_addr = self.virt_addr_to_phys_addr(self.resolve_offset(instr));
+            if self.cp0.watch_hit(phys_addr, access) {
+                self.reg_pc = self.cp0.enter_exception(cp0::ExceptionCode::Watch, pc, in_delay_slot);
+                return true;
+            }
+        }
+
+        false
+    }
+
     fn read_instruction(&self, interconnect: &mut Interconnect, addr: u64) -> Instruction {
         Instruction(self.read_word(interconnect, addr))
     }
@@ -283,7 +306,8 @@ impl Cpu {
                         match instr.cop0_co_op() {
                             Cop0CoOpcode::Tlbwi => self.cp0.store_tlb_entry(),
                             Cop0CoOpcode::Eret => {
-                                self.reg_pc = self.cp0.read_reg(14 /* EPC */)
+                                self.reg_pc = self.cp0.return_from_exception();
+                                self.reg_llbit = false;
                             }
                         }
                     }
@@ -510,13 +534,39 @@ impl Cpu {
                 rs.wrapping_add(imm_sign_extended)
             }),
 
+            Ldl => {
+                let virt_addr = self.resolve_offset(instr);
+                let aligned_addr = virt_addr & 0xffff_ffff_ffff_fff8;
+                let mem = self.read_doubleword(interconnect, aligned_addr);
+                let reg = self.read_reg_gpr(instr.rt());
+
+                let shift = self.unaligned_byte_offset(virt_addr, 8) * 8;
+                let value = (mem << shift) | reg & ((1 << shift) - 1);
+
+                self.write_reg_gpr(instr.rt(), value);
+            }
+
+            Ldr => {
+                let virt_addr = self.resolve_offset(instr);
+                let aligned_addr = virt_addr & 0xffff_ffff_ffff_fff8;
+                let mem = self.read_doubleword(interconnect, aligned_addr);
+                let reg = self.read_reg_gpr(instr.rt());
+
+                let shift = (7 - self.unaligned_byte_offset(virt_addr, 8)) * 8;
+                let value = (mem >> shift) | reg & !(0xffff_ffff_ffff_ffff >> shift);
+
+                self.write_reg_gpr(instr.rt(), value);
+            }
+
             Lb => {
-                let byte = self.read_byte(interconnect, self.resolve_offset(instr));
+                let virt_addr = self.reverse_endian_addr(self.resolve_offset(instr), 1);
+                let byte = self.read_byte(interconnect, virt_addr);
                 self.write_reg_gpr(instr.rt(), byte as i8 as u64);
             }
 
             Lh => {
-                let halfword = self.read_halfword(interconnect, self.resolve_offset(instr));
+                let virt_addr = self.reverse_endian_addr(self.resolve_offset(instr), 2);
+                let halfword = self.read_halfword(interconnect, virt_addr);
                 self.write_reg_gpr(instr.rt(), halfword as i16 as u64);
             }
 
@@ -524,11 +574,11 @@ impl Cpu {
                 let base = instr.rs();
                 let sign_extended_offset = instr.offset_sign_extended();
                 let virt_addr = self.read_reg_gpr(base).wrapping_add(sign_extended_offset);
-                let aligned_addr = virt_addr & 0xffff_ffff_ffff_fffc;
+                let aligned_addr = self.reverse_endian_addr(virt_addr & 0xffff_ffff_ffff_fffc, 4);
                 let mem = self.read_word(interconnect, aligned_addr) as u64;
                 let reg = self.read_reg_gpr(instr.rt());
 
-                let shift = virt_addr - aligned_addr;
+                let shift = self.unaligned_byte_offset(virt_addr, 4);
 
                 let value = match shift {
                     0 => mem,
@@ -542,17 +592,20 @@ impl Cpu {
             }
 
             Lw => {
-                let word = self.read_word(interconnect, self.resolve_offset(instr));
+                let virt_addr = self.reverse_endian_addr(self.resolve_offset(instr), 4);
+                let word = self.read_word(interconnect, virt_addr);
                 self.write_reg_gpr(instr.rt(), word as i32 as u64);
             }
 
             Lbu => {
-                let byte = self.read_byte(interconnect, self.resolve_offset(instr));
+                let virt_addr = self.reverse_endian_addr(self.resolve_offset(instr), 1);
+                let byte = self.read_byte(interconnect, virt_addr);
                 self.write_reg_gpr(instr.rt(), byte as u64);
             }
 
             Lhu => {
-                let halfword = self.read_halfword(interconnect, self.resolve_offset(instr));
+                let virt_addr = self.reverse_endian_addr(self.resolve_offset(instr), 2);
+                let halfword = self.read_halfword(interconnect, virt_addr);
                 self.write_reg_gpr(instr.rt(), halfword as u64);
             }
 
@@ -560,12 +613,12 @@ impl Cpu {
                 let base = instr.rs();
                 let sign_extended_offset = instr.offset_sign_extended();
                 let virt_addr = self.read_reg_gpr(base).wrapping_add(sign_extended_offset);
-                let aligned_addr = virt_addr & 0xffff_ffff_ffff_fffC;
+                let aligned_addr = self.reverse_endian_addr(virt_addr & 0xffff_ffff_ffff_fffC, 4);
 
                 let mem = self.read_word(interconnect, aligned_addr) as u64;
                 let reg = self.read_reg_gpr(instr.rt());
 
-                let shift = virt_addr - aligned_addr;
+                let shift = self.unaligned_byte_offset(virt_addr, 4);
                 let value = match shift {
                     0 => (mem & 0xff00_0000) >> 24 | reg & 0xffff_ffff_ffff_ff00,
                     1 => (mem & 0xffff_0000) >> 16 | reg & 0xffff_ffff_ffff_0000,
@@ -578,18 +631,19 @@ impl Cpu {
             }
 
             Lwu => {
-                let word = self.read_word(interconnect, self.resolve_offset(instr));
+                let virt_addr = self.reverse_endian_addr(self.resolve_offset(instr), 4);
+                let word = self.read_word(interconnect, virt_addr);
                 self.write_reg_gpr(instr.rt(), word as u64);
             }
 
             Sb => {
                 let byte = (self.read_reg_gpr(instr.rt()) & 0xff) as u8;
-                let virtual_addr = self.resolve_offset(instr);
+                let virtual_addr = self.reverse_endian_addr(self.resolve_offset(instr), 1);
                 self.write_byte(interconnect, virtual_addr, byte);
             }
 
             Sh => {
-                let virt_addr = self.resolve_offset(instr);
+                let virt_addr = self.reverse_endian_addr(self.resolve_offset(instr), 2);
                 let halfword = (self.read_reg_gpr(instr.rt()) & 0xffff) as u16;
                 self.write_byte(interconnect, virt_addr, (halfword >> 8) as u8);
                 self.write_byte(interconnect, virt_addr + 1, (halfword & 0xff) as u8);
@@ -597,28 +651,58 @@ impl Cpu {
 
             Swl => {
                 let virt_addr = self.resolve_offset(instr);
-                let word = self.read_reg_gpr(instr.rt());
-                let base = virt_addr & 0b11;
-                for i in base..4 {
-                    let value = ((word >> ((3 - (i - base)) * 8)) & 0xff) as u8;
-                    self.write_byte(interconnect, virt_addr + i, value);
-                }
+                let aligned_addr = self.reverse_endian_addr(virt_addr & 0xffff_ffff_ffff_fffc, 4);
+                let mem = self.read_word(interconnect, aligned_addr);
+                let reg = self.read_reg_gpr(instr.rt()) as u32;
+
+                // Store the most significant bytes of rt up to the end of the aligned word
+                let shift = self.unaligned_byte_offset(virt_addr, 4) * 8;
+                let value = (mem & !(0xffff_ffff >> shift)) | (reg >> shift);
+
+                self.write_word(interconnect, aligned_addr, value);
             }
 
             Sw => {
-                let virt_addr = self.resolve_offset(instr);
+                let virt_addr = self.reverse_endian_addr(self.resolve_offset(instr), 4);
                 let word = self.read_reg_gpr(instr.rt()) as u32;
                 self.write_word(interconnect, virt_addr, word);
             }
 
+            Sdl => {
+                let virt_addr = self.resolve_offset(instr);
+                let aligned_addr = virt_addr & 0xffff_ffff_ffff_fff8;
+                let mem = self.read_doubleword(interconnect, aligned_addr);
+                let reg = self.read_reg_gpr(instr.rt());
+
+                let shift = self.unaligned_byte_offset(virt_addr, 8) * 8;
+                let value = (mem & !(0xffff_ffff_ffff_ffff >> shift)) | (reg >> shift);
+
+                self.write_doubleword(interconnect, aligned_addr, value);
+            }
+
+            Sdr => {
+                let virt_addr = self.resolve_offset(instr);
+                let aligned_addr = virt_addr & 0xffff_ffff_ffff_fff8;
+                let mem = self.read_doubleword(interconnect, aligned_addr);
+                let reg = self.read_reg_gpr(instr.rt());
+
+                let shift = (7 - self.unaligned_byte_offset(virt_addr, 8)) * 8;
+                let value = (reg << shift) | (mem & ((1 << shift) - 1));
+
+                self.write_doubleword(interconnect, aligned_addr, value);
+            }
+
             Swr => {
                 let virt_addr = self.resolve_offset(instr);
-                let word = self.read_reg_gpr(instr.rt());
-                let to = (virt_addr & 0b11) + 1;
-                for i in 0..to {
-                    let value = ((word >> ((to - i - 1) * 8)) & 0xff) as u8;
-                    self.write_byte(interconnect, virt_addr + i, value);
-                }
+                let aligned_addr = self.reverse_endian_addr(virt_addr & 0xffff_ffff_ffff_fffc, 4);
+                let mem = self.read_word(interconnect, aligned_addr);
+                let reg = self.read_reg_gpr(instr.rt()) as u32;
+
+                // Store the least significant bytes of rt from the start of the aligned word
+                let shift = (3 - self.unaligned_byte_offset(virt_addr, 4)) * 8;
+                let value = (reg << shift) | (mem & ((1 << shift) - 1));
+
+                self.write_word(interconnect, aligned_addr, value);
             }
 
             Cache => {
@@ -636,7 +720,7 @@ impl Cpu {
 
                 let sign_extended_offset = instr.offset_sign_extended();
                 let virt_addr = self.read_reg_gpr(base).wrapping_add(sign_extended_offset);
-                let mem = self.read_word(interconnect, virt_addr);
+                let mem = self.read_word(interconnect, self.reverse_endian_addr(virt_addr, 4));
                 let reg = self.read_reg_fpr(instr.rt()).to_bits();
                 let reg = (reg & 0xffffffff_00000000) | (mem as u64);
 
@@ -655,7 +739,7 @@ impl Cpu {
 
             Swc1 => {
                 // TODO: Assuming here that FR is 1
-                let virt_addr = self.resolve_offset(instr);
+                let virt_addr = self.reverse_endian_addr(self.resolve_offset(instr), 4);
 
                 let word = self.read_reg_fpr(instr.rt()).to_bits();
                 self.write_word(interconnect, virt_addr, word as u32);
@@ -807,6 +891,28 @@ impl Cpu {
         virt_addr
     }
 
+    fn reverse_endian_addr(&self, virt_addr: u64, size: u64) -> u64 {
+        // Memory is always big-endian. With RE set in user mode the byte lanes
+        // within each doubleword are mirrored, so a naturally aligned access
+        // of `size` bytes ends up at the opposite end of its doubleword.
+        if self.cp0.reverse_endian() {
+            virt_addr ^ (8 - size)
+        } else {
+            virt_addr
+        }
+    }
+
+    fn unaligned_byte_offset(&self, virt_addr: u64, size: u64) -> u64 {
+        // Byte offset into the aligned word/doubleword as seen from the
+        // big-endian LWL/LWR/SWL/SWR (and doubleword) logic
+        let offset = virt_addr & (size - 1);
+        if self.cp0.reverse_endian() {
+            offset ^ (size - 1)
+        } else {
+            offset
+        }
+    }
+
     fn branch<F>(&mut self, instr: Instruction, write_link: WriteLink, f: F) -> bool
     where
         F: FnOnce(u64, u64) -> bool,
@@ -967,3 +1073,270 @@ impl fmt::Debug for Cpu {
         writeln!(f, "{:#?}", self.cp0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use n64::Interconnect;
+
+    // Kseg0 address of the bytes the tests access, physical 0x1000
+    const BASE: u64 = 0xffff_ffff_8000_1000;
+    const BASE_REG: usize = 1;
+    const RT: usize = 2;
+
+    const MEMORY: [u8; 16] = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77,
+        0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+    ];
+    const REGISTER: u64 = 0x0123_4567_89ab_cdef;
+
+    const STATUS_EXL: u64 = 1 << 1;
+    const STATUS_ERL: u64 = 1 << 2;
+    const STATUS_USER_MODE: u64 = 0b10 << 3;
+    const STATUS_RE: u64 = 1 << 25;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Kind {
+        Load { signed: bool },
+        Store,
+        LoadLeft,
+        LoadRight,
+        StoreLeft,
+        StoreRight,
+    }
+
+    // Opcode, kind and access size
+    const OPS: [(u32, Kind, usize); 19] = [
+        (0b100000, Kind::Load { signed: true }, 1),  // LB
+        (0b100100, Kind::Load { signed: false }, 1), // LBU
+        (0b100001, Kind::Load { signed: true }, 2),  // LH
+        (0b100101, Kind::Load { signed: false }, 2), // LHU
+        (0b100011, Kind::Load { signed: true }, 4),  // LW
+        (0b100111, Kind::Load { signed: false }, 4), // LWU
+        (0b110111, Kind::Load { signed: false }, 8), // LD
+        (0b101000, Kind::Store, 1),                  // SB
+        (0b101001, Kind::Store, 2),                  // SH
+        (0b101011, Kind::Store, 4),                  // SW
+        (0b111111, Kind::Store, 8),                  // SD
+        (0b100010, Kind::LoadLeft, 4),               // LWL
+        (0b100110, Kind::LoadRight, 4),              // LWR
+        (0b011010, Kind::LoadLeft, 8),               // LDL
+        (0b011011, Kind::LoadRight, 8),              // LDR
+        (0b101010, Kind::StoreLeft, 4),              // SWL
+        (0b101110, Kind::StoreRight, 4),             // SWR
+        (0b101100, Kind::StoreLeft, 8),              // SDL
+        (0b101101, Kind::StoreRight, 8),             // SDR
+    ];
+
+    // Where test programs are placed, physical 0x2000
+    const PROGRAM: u64 = 0xffff_ffff_8000_2000;
+    const GENERAL_EXCEPTION_VECTOR: u64 = 0xffff_ffff_8000_0180;
+
+    const WATCH_LO_STORE: u64 = 1 << 0;
+    const WATCH_LO_LOAD: u64 = 1 << 1;
+
+    const LW: u32 = 0b100011;
+    const SW: u32 = 0b101011;
+    const ERET: u32 = 0x4200_0018;
+
+    fn setup(status: u64) -> (Cpu, Interconnect) {
+        let mut cpu = Cpu::new();
+        let mut interconnect = Interconnect::without_roms();
+        cpu.cp0.write_reg(12, status);
+        cpu.write_reg_gpr(BASE_REG, BASE);
+        cpu.write_reg_gpr(RT, REGISTER);
+        for (i, &byte) in MEMORY.iter().enumerate() {
+            interconnect.write_byte(0x1000 + i as u32, byte);
+        }
+        (cpu, interconnect)
+    }
+
+    fn load_store(opcode: u32, offset: usize) -> u32 {
+        (opcode << 26) | ((BASE_REG as u32) << 21) | ((RT as u32) << 16) | offset as u32
+    }
+
+    // Places an instruction at PROGRAM and steps over it
+    fn step(cpu: &mut Cpu, interconnect: &mut Interconnect, instr: u32) {
+        interconnect.write_word((PROGRAM & 0x1fff_ffff) as u32, instr);
+        cpu.reg_pc = PROGRAM;
+        cpu.step(interconnect);
+    }
+
+    fn exception_code(cpu: &mut Cpu) -> u64 {
+        (cpu.cp0.read_reg(13) >> 2) & 0b1_1111
+    }
+
+    // Runs an instruction and returns rt and the memory after it
+    fn run(status: u64, opcode: u32, offset: usize) -> (u64, Vec<u8>) {
+        let (mut cpu, mut interconnect) = setup(status);
+        cpu.execute_instruction(&mut interconnect, Instruction(load_store(opcode, offset)));
+        let memory = (0..MEMORY.len() as u32).map(|i| interconnect.read_byte(0x1000 + i)).collect();
+        (cpu.read_reg_gpr(RT), memory)
+    }
+
+    // A byte level model of the accesses as the program sees them. With
+    // reversed endianness the byte lanes of every doubleword are mirrored
+    // and values are little-endian.
+    fn expected(reversed: bool, kind: Kind, size: usize, offset: usize) -> (u64, Vec<u8>) {
+        let lane = |addr: usize| if reversed { addr ^ 7 } else { addr };
+        let mut memory = MEMORY.to_vec();
+        // Register bytes from the least significant one
+        let mut reg: Vec<u8> = (0..8).map(|i| (REGISTER >> (i * 8)) as u8).collect();
+
+        // Pairs of a register byte and the address it is loaded from or
+        // stored to
+        let start = offset & !(size - 1);
+        let bytes: Vec<(usize, usize)> = match kind {
+            Kind::Load { .. } | Kind::Store => {
+                (0..size).map(|i| (i, if reversed { offset + i } else { offset + size - 1 - i })).collect()
+            }
+            // Left accesses fill the value from its most significant byte
+            // on, with the bytes up to the end of the aligned block in
+            // big-endian order and down to its start in little-endian order.
+            // Right accesses fill it from the least significant byte on,
+            // going the other way.
+            _ => {
+                let left = kind == Kind::LoadLeft || kind == Kind::StoreLeft;
+                let addrs: Vec<usize> = if left != reversed {
+                    (offset..start + size).collect()
+                } else {
+                    (start..offset + 1).rev().collect()
+                };
+                addrs.into_iter().enumerate().map(|(k, addr)| (if left { size - 1 - k } else { k }, addr)).collect()
+            }
+        };
+
+        for (index, addr) in bytes {
+            match kind {
+                Kind::Store | Kind::StoreLeft | Kind::StoreRight => memory[lane(addr)] = reg[index],
+                _ => reg[index] = MEMORY[lane(addr)],
+            }
+        }
+
+        let mut value = reg.iter().rev().fold(0, |value, &byte| (value << 8) | byte as u64);
+        match kind {
+            Kind::Load { signed } if size < 8 => {
+                let shift = 64 - size * 8;
+                value <<= shift;
+                value = if signed { ((value as i64) >> shift) as u64 } else { value >> shift };
+            }
+            Kind::LoadLeft if size == 4 => value = value as i32 as u64,
+            _ => {}
+        }
+        (value, memory)
+    }
+
+    fn check_all(status: u64, reversed: bool) {
+        for &(opcode, kind, size) in OPS.iter() {
+            let aligned = match kind {
+                Kind::Load { .. } | Kind::Store => true,
+                _ => false,
+            };
+            for offset in 0..MEMORY.len() {
+                if aligned && offset % size != 0 {
+                    continue;
+                }
+                assert_eq!(run(status, opcode, offset), expected(reversed, kind, size, offset),
+                           "{:?} of {} bytes at offset {}", kind, size, offset);
+            }
+        }
+    }
+
+    #[test]
+    fn user_mode_with_re_reverses_endianness() {
+        check_all(STATUS_USER_MODE | STATUS_RE, true);
+    }
+
+    #[test]
+    fn user_mode_without_re_is_big_endian() {
+        check_all(STATUS_USER_MODE, false);
+    }
+
+    #[test]
+    fn kernel_mode_ignores_re() {
+        check_all(STATUS_RE, false);
+        // EXL forces kernel mode
+        check_all(STATUS_USER_MODE | STATUS_RE | STATUS_EXL, false);
+    }
+
+    #[test]
+    fn watch_exception_on_load() {
+        let (mut cpu, mut interconnect) = setup(0);
+        cpu.cp0.write_reg(18, 0x1000 | WATCH_LO_LOAD);
+        // The watched doubleword is hit from any of its bytes
+        step(&mut cpu, &mut interconnect, load_store(LW, 4));
+
+        assert_eq!(cpu.reg_pc, GENERAL_EXCEPTION_VECTOR);
+        assert_eq!(exception_code(&mut cpu), cp0::ExceptionCode::Watch as u64);
+        assert_eq!(cpu.cp0.read_reg(14), PROGRAM);
+        // The load doesn't happen
+        assert_eq!(cpu.read_reg_gpr(RT), REGISTER);
+    }
+
+    #[test]
+    fn watch_exception_on_store() {
+        let (mut cpu, mut interconnect) = setup(0);
+        cpu.cp0.write_reg(18, 0x1008 | WATCH_LO_STORE);
+        step(&mut cpu, &mut interconnect, load_store(SW, 8));
+
+        assert_eq!(cpu.reg_pc, GENERAL_EXCEPTION_VECTOR);
+        assert_eq!(exception_code(&mut cpu), cp0::ExceptionCode::Watch as u64);
+        assert_eq!(interconnect.read_word(0x1008), 0x8899_aabb);
+    }
+
+    #[test]
+    fn watch_ignores_other_accesses() {
+        let (mut cpu, mut interconnect) = setup(0);
+        // Loads don't trigger a store watch, nor other doublewords
+        cpu.cp0.write_reg(18, 0x1000 | WATCH_LO_STORE);
+        step(&mut cpu, &mut interconnect, load_store(LW, 0));
+        cpu.cp0.write_reg(18, 0x1000 | WATCH_LO_LOAD | WATCH_LO_STORE);
+        step(&mut cpu, &mut interconnect, load_store(SW, 8));
+
+        assert_eq!(cpu.reg_pc, PROGRAM + 4);
+        // The store writes what the load read
+        assert_eq!(interconnect.read_word(0x1008), 0x0011_2233);
+    }
+
+    #[test]
+    fn watch_suppressed_at_exception_level() {
+        let (mut cpu, mut interconnect) = setup(STATUS_EXL);
+        cpu.cp0.write_reg(18, 0x1000 | WATCH_LO_LOAD);
+        step(&mut cpu, &mut interconnect, load_store(LW, 0));
+
+        assert_eq!(cpu.reg_pc, PROGRAM + 4);
+        assert_eq!(cpu.read_reg_gpr(RT), 0x0011_2233);
+    }
+
+    #[test]
+    fn eret_clears_llbit_and_returns_from_errors_first() {
+        let (mut cpu, mut interconnect) = setup(STATUS_EXL);
+        cpu.cp0.write_reg(14, 0xffff_ffff_8000_3000);
+        cpu.reg_llbit = true;
+        step(&mut cpu, &mut interconnect, ERET);
+
+        assert_eq!(cpu.reg_pc, 0xffff_ffff_8000_3000);
+        assert!(!cpu.reg_llbit);
+        // Watch exceptions are taken again once EXL is cleared
+        cpu.cp0.write_reg(18, 0x1000 | WATCH_LO_LOAD);
+        step(&mut cpu, &mut interconnect, load_store(LW, 0));
+        assert_eq!(cpu.reg_pc, GENERAL_EXCEPTION_VECTOR);
+
+        // With ERL set, ERET clears only ERL and returns to ErrorEPC
+        let (mut cpu, mut interconnect) = setup(STATUS_EXL | STATUS_ERL);
+        cpu.cp0.write_reg(14, 0xffff_ffff_8000_3000);
+        cpu.cp0.write_reg(30, 0xffff_ffff_8000_4000);
+        cpu.cp0.write_reg(18, 0x1000 | WATCH_LO_LOAD);
+        step(&mut cpu, &mut interconnect, ERET);
+        assert_eq!(cpu.reg_pc, 0xffff_ffff_8000_4000);
+
+        // EXL is still set, so watch exceptions are not taken yet
+        step(&mut cpu, &mut interconnect, load_store(LW, 0));
+        assert_eq!(cpu.reg_pc, PROGRAM + 4);
+
+        step(&mut cpu, &mut interconnect, ERET);
+        assert_eq!(cpu.reg_pc, 0xffff_ffff_8000_3000);
+        step(&mut cpu, &mut interconnect, load_store(LW, 0));
+        assert_eq!(cpu.reg_pc, GENERAL_EXCEPTION_VECTOR);
+    }
+}
@@ -7,6 +7,12 @@ use num::FromPrimitive;
 #[derive(Clone, Copy)]
 pub struct Instruction(pub u32);
 
+#[derive(Debug, Clone, Copy)]
+pub enum MemoryAccess {
+    Load,
+    Store,
+}
+
 impl Instruction {
     #[inline(always)]
     pub fn opcode(&self) -> Opcode {
@@ -76,6 +82,22 @@ impl Instruction {
         (self.offset() as i16) as u64
     }
 
+    pub fn memory_access(&self) -> Option<MemoryAccess> {
+        match self.opcode() {
+            Opcode::Lb | Opcode::Lbu | Opcode::Lh | Opcode::Lhu |
+            Opcode::Lw | Opcode::Lwl | Opcode::Lwr | Opcode::Lwu |
+            Opcode::Ld | Opcode::Ldl | Opcode::Ldr |
+            Opcode::Lwc1 | Opcode::Ldc1 => Some(MemoryAccess::Load),
+
+            Opcode::Sb | Opcode::Sh |
+            Opcode::Sw | Opcode::Swl | Opcode::Swr |
+            Opcode::Sd | Opcode::Sdl | Opcode::Sdr |
+            Opcode::Swc1 | Opcode::Sdc1 => Some(MemoryAccess::Store),
+
+            _ => None,
+        }
+    }
+
     #[inline(always)]
     pub fn special_op(&self) -> SpecialOpcode {
         let value = self.0 & 0b111111;
@@ -3,5 +3,6 @@ mod cp0;
 pub mod opcode;
 mod instruction;
 
-pub use self::cpu::Cpu;
+pub use self::cpu::{Cpu, CLOCK_HZ};
 pub use self::instruction::Instruction;
+pub use self::instruction::MemoryAccess;
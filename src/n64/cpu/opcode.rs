@@ -32,6 +32,8 @@ enum_from_primitive! {
 
         Daddi =   0b011000,
         Daddiu =  0b011001,
+        Ldl =     0b011010,
+        Ldr =     0b011011,
 
         Lb =      0b100000,
         Lh =      0b100001,
@@ -47,6 +49,8 @@ enum_from_primitive! {
         Sh =      0b101001,
         Swl =     0b101010,
         Sw =      0b101011,
+        Sdl =     0b101100,
+        Sdr =     0b101101,
         Swr =     0b101110,
 
         Cache =   0b101111,
@@ -1,11 +1,12 @@
 use byteorder::{BigEndian, ByteOrder};
 
+use super::cart::CartHeader;
 use super::dma::DMARequest;
 use super::mem_map::RDRAM_LENGTH;
 use super::mem_map::{self, Addr};
 use super::sinks::{Sink, VideoFrame};
-use super::video_interface::FramebufferFormat;
-use super::{AudioInterface, MipsInterface, PeripheralInterface, Pif, Rdp, RdramInterface, RspRegs, SerialInterface, VideoInterface};
+use super::video_interface::{FramebufferFormat, TvSystem};
+use super::{AudioInterface, MipsInterface, PeripheralInterface, Pif, Rdp, RdpMode, RdramInterface, RspRegs, SerialInterface, VideoInterface};
 
 use std::fmt;
 
@@ -29,28 +30,45 @@ pub struct Interconnect {
 }
 
 impl Interconnect {
-    pub fn new(boot_rom: Box<[u8]>, cart_rom: Box<[u8]>) -> Interconnect {
-        let mut interconnect = Interconnect {
+    // The TV system follows the country code in the cart header
+    pub fn new(boot_rom: Box<[u8]>, cart_rom: Box<[u8]>, rdp_mode: RdpMode, rdp_threads: usize) -> Interconnect {
+        let tv_system = CartHeader::parse(&cart_rom).map_or(TvSystem::Ntsc, |header| header.tv_system());
+        let mut interconnect = Interconnect::with_roms(boot_rom, cart_rom, tv_system, rdp_mode, rdp_threads);
+        interconnect.pif.init_cic_seed(&*interconnect.cart_rom);
+        interconnect
+    }
+
+    // Without a cartridge there is no CIC seed to set up, which is enough for
+    // running the processors against RDRAM in tests. The boot ROM is all
+    // NOPs.
+    #[cfg(test)]
+    pub fn without_roms() -> Interconnect {
+        // The boot ROM loops on its first instruction, so the CPU can be
+        // stepped for as long as needed
+        let mut boot_rom = vec![0; mem_map::PIF_ROM_LENGTH as usize].into_boxed_slice();
+        BigEndian::write_u32(&mut boot_rom, 0x1000_ffff); // b -1
+        Interconnect::with_roms(boot_rom, Box::new([]), TvSystem::Ntsc, RdpMode::Synchronous, 1)
+    }
+
+    fn with_roms(boot_rom: Box<[u8]>, cart_rom: Box<[u8]>, tv_system: TvSystem, rdp_mode: RdpMode, rdp_threads: usize) -> Interconnect {
+        Interconnect {
             rdram: vec![0; RDRAM_LENGTH as usize].into_boxed_slice(),
 
             pif: Pif::new(boot_rom),
 
-            rdp: Rdp,
+            rdp: Rdp::new(rdp_mode, rdp_threads),
             rsp: RspRegs::new(),
 
             mi: MipsInterface::new(),
             ai: AudioInterface::default(),
-            vi: VideoInterface::new(),
+            vi: VideoInterface::new(tv_system),
 
             pi: PeripheralInterface::default(),
             ri: RdramInterface::default(),
             si: SerialInterface::default(),
 
             cart_rom: cart_rom,
-        };
-
-        interconnect.pif.init_cic_seed(&*interconnect.cart_rom);
-        interconnect
+        }
     }
 
     pub fn pif(&self) -> &Pif {
@@ -60,6 +78,39 @@ impl Interconnect {
         &mut self.rsp
     }
 
+    pub fn rsp_regs(&self) -> &RspRegs {
+        &self.rsp
+    }
+
+    pub fn rdp(&mut self) -> &mut Rdp {
+        &mut self.rdp
+    }
+
+    pub fn submit_rdp_commands(&mut self, commands: &[u64]) {
+        self.rdp.submit_commands(commands, &mut self.rdram, &mut self.mi);
+    }
+
+    pub fn rdram(&mut self) -> &mut [u8] {
+        &mut self.rdram
+    }
+
+    // For HLE tasks that work on both at once
+    pub fn rdram_and_dmem(&mut self) -> (&mut [u8], &mut [u8]) {
+        (&mut self.rdram, self.rsp.dmem_mut())
+    }
+
+    // The SP DMA engine runs on the RSP clock
+    pub fn step_rsp_dma(&mut self, cycles: u32) {
+        for _ in 0..cycles {
+            self.rsp.step_dma(&mut self.rdram);
+        }
+    }
+
+    // Called when the RSP executes BREAK
+    pub fn rsp_break(&mut self) {
+        self.rsp.set_break(&mut self.mi);
+    }
+
     pub fn read_word_debug(&self, addr: u32) -> Option<u32> {
         let mapped_address = mem_map::map_addr(addr);
         match mapped_address {
@@ -78,7 +129,7 @@ impl Interconnect {
         }
     }
 
-    pub fn read_word(&self, addr: u32) -> u32 {
+    pub fn read_word(&mut self, addr: u32) -> u32 {
         let mapped_address = mem_map::map_addr(addr);
         // println!("Reading {:?} ", mapped_address);
         let word = match mapped_address {
@@ -87,14 +138,33 @@ impl Interconnect {
             Addr::SpDmem(offset) => self.rsp.read_dmem(offset),
             Addr::SpImem(offset) => self.rsp.read_imem(offset),
 
+            Addr::SpMemAddrReg => self.rsp.read_mem_addr_reg(),
+            Addr::SpDramAddrReg => self.rsp.read_dram_addr_reg(),
+            Addr::SpRdLenReg => self.rsp.read_rd_len_reg(),
+            Addr::SpWrLenReg => self.rsp.read_wr_len_reg(),
             Addr::SpStatusReg => self.rsp.read_status_reg(),
+            Addr::SpDmaFullReg => self.rsp.read_dma_full_reg(),
             Addr::SpDmaBusyReg => self.rsp.read_dma_busy_reg(),
+            Addr::SpSemaphoreReg => self.rsp.read_semaphore_reg(),
             Addr::SpPcReg => self.rsp.read_pc_reg(),
 
+            Addr::DpcStartReg => self.rdp.read_start_reg(),
+            Addr::DpcEndReg => self.rdp.read_end_reg(),
+            Addr::DpcCurrentReg => self.rdp.read_current_reg(),
             Addr::DpcStatusReg => self.rdp.read_status_reg(),
+            Addr::DpcClockReg => self.rdp.read_clock_reg(),
+            Addr::DpcBufBusyReg => self.rdp.read_buf_busy_reg(),
+            Addr::DpcPipeBusyReg => self.rdp.read_pipe_busy_reg(),
+            Addr::DpcTmemReg => self.rdp.read_tmem_reg(),
+
+            Addr::DpsTbistReg => self.rdp.read_tbist_reg(),
+            Addr::DpsTestModeReg => self.rdp.read_test_mode_reg(),
+            Addr::DpsBuftestAddrReg => self.rdp.read_buftest_addr_reg(),
+            Addr::DpsBuftestDataReg => self.rdp.read_buftest_data_reg(),
 
             Addr::MiModeReg => self.mi.read_mode_reg(),
             Addr::MiVersionReg => self.mi.read_version_reg(),
+            Addr::MiIntrReg => self.mi.read_intr_reg(),
             Addr::MiIntrMaskReg => self.mi.read_intr_mask_reg(),
 
             Addr::ViIntrReg => self.vi.read_intr_reg(),
@@ -143,13 +213,30 @@ impl Interconnect {
             Addr::SpMemAddrReg => self.rsp.write_mem_addr_reg(value),
             Addr::SpDramAddrReg => self.rsp.write_dram_addr_reg(value),
             Addr::SpRdLenReg => self.rsp.write_rd_len_reg(value),
-            Addr::SpStatusReg => self.rsp.write_status_reg(value),
-            Addr::SpDmaBusyReg => self.rsp.write_dma_busy_reg(value),
+            Addr::SpWrLenReg => self.rsp.write_wr_len_reg(value),
+            Addr::SpStatusReg => self.rsp.write_status_reg(value, &mut self.mi),
             Addr::SpSemaphoreReg => self.rsp.write_semaphore_reg(value),
             Addr::SpPcReg => self.rsp.write_pc_reg(value),
 
+            Addr::DpcStartReg => self.rdp.write_start_reg(value),
+            Addr::DpcEndReg => self.rdp.write_end_reg(value),
             Addr::DpcStatusReg => self.rdp.write_status_reg(value),
 
+            // Read-only registers, writes to them have no effect. The RSP
+            // can reach all of them with MTC0.
+            Addr::SpDmaFullReg |
+            Addr::SpDmaBusyReg |
+            Addr::DpcCurrentReg |
+            Addr::DpcClockReg |
+            Addr::DpcBufBusyReg |
+            Addr::DpcPipeBusyReg |
+            Addr::DpcTmemReg => {}
+
+            Addr::DpsTbistReg => self.rdp.write_tbist_reg(value),
+            Addr::DpsTestModeReg => self.rdp.write_test_mode_reg(value),
+            Addr::DpsBuftestAddrReg => self.rdp.write_buftest_addr_reg(value),
+            Addr::DpsBuftestDataReg => self.rdp.write_buftest_data_reg(value),
+
             Addr::MiModeReg => self.mi.write_mode_reg(value),
             Addr::MiIntrMaskReg => self.mi.write_intr_mask_reg(value),
 
@@ -243,8 +330,7 @@ impl Interconnect {
         // Execute DMA
         let dma = self.pi.get_dma_write_chunk();
         self.do_dma(dma);
-        let dma = self.rsp.get_dma_read_chunk();
-        self.do_dma(dma);
+        self.rdp.step(&mut self.rdram, self.rsp.dmem(), &mut self.mi);
 
         let draw_frame = self.vi.step();
 
@@ -288,3 +374,21 @@ impl fmt::Debug for Interconnect {
         write!(f, "TODO: Impl Debug for Interconnect")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_only_registers_ignore_writes() {
+        let mut interconnect = Interconnect::without_roms();
+        // SP_DMA_FULL, SP_DMA_BUSY and DPC_CURRENT to DPC_TMEM, as the RSP
+        // addresses them from COP0
+        for &index in &[5, 6, 10, 12, 13, 14, 15] {
+            let addr = mem_map::rsp_cop0_reg_addr(index);
+            let value = interconnect.read_word(addr);
+            interconnect.write_word(addr, 0xffff_ffff);
+            assert_eq!(interconnect.read_word(addr), value, "COP0 register {}", index);
+        }
+    }
+}
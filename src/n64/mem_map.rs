@@ -16,16 +16,30 @@ const SP_DRAM_ADDR_REG: u32 =       0x0404_0004;
 const SP_RD_LEN_REG: u32 =          0x0404_0008;
 const SP_WR_LEN_REG: u32 =          0x0404_000C;
 const SP_STATUS_REG: u32 =          0x0404_0010;
+const SP_DMA_FULL_REG: u32 =        0x0404_0014;
 const SP_DMA_BUSY_REG: u32 =        0x0404_0018;
 const SP_SEMAPHORE_REG: u32 =       0x0404_001C;
 
 const SP_PC_REG: u32 =              0x0408_0000;
 
 const DPC_BASE_REG: u32 =           0x0410_0000;
+const DPC_START_REG: u32 =          0x0410_0000;
+const DPC_END_REG: u32 =            0x0410_0004;
+const DPC_CURRENT_REG: u32 =        0x0410_0008;
 const DPC_STATUS_REG: u32 =         0x0410_000c;
+const DPC_CLOCK_REG: u32 =          0x0410_0010;
+const DPC_BUFBUSY_REG: u32 =        0x0410_0014;
+const DPC_PIPEBUSY_REG: u32 =       0x0410_0018;
+const DPC_TMEM_REG: u32 =           0x0410_001c;
+
+const DPS_TBIST_REG: u32 =          0x0420_0000;
+const DPS_TEST_MODE_REG: u32 =      0x0420_0004;
+const DPS_BUFTEST_ADDR_REG: u32 =   0x0420_0008;
+const DPS_BUFTEST_DATA_REG: u32 =   0x0420_000c;
 
 const MI_MODE_REG: u32 =            0x0430_0000;
 const MI_VERSION_REG: u32 =         0x0430_0004;
+const MI_INTR_REG: u32 =            0x0430_0008;
 const MI_INTR_MASK_REG: u32 =       0x0430_000C;
 
 const VI_STATUS_REG: u32 =          0x0440_0000;
@@ -100,14 +114,28 @@ pub enum Addr {
     SpRdLenReg,
     SpWrLenReg,
     SpStatusReg,
+    SpDmaFullReg,
     SpDmaBusyReg,
     SpSemaphoreReg,
     SpPcReg,
 
+    DpcStartReg,
+    DpcEndReg,
+    DpcCurrentReg,
     DpcStatusReg,
+    DpcClockReg,
+    DpcBufBusyReg,
+    DpcPipeBusyReg,
+    DpcTmemReg,
+
+    DpsTbistReg,
+    DpsTestModeReg,
+    DpsBuftestAddrReg,
+    DpsBuftestDataReg,
 
     MiModeReg,
     MiVersionReg,
+    MiIntrReg,
     MiIntrMaskReg,
 
     ViStatusReg,
@@ -182,14 +210,28 @@ pub fn map_addr(addr: u32) -> Addr {
         SP_RD_LEN_REG => Addr::SpRdLenReg,
         SP_WR_LEN_REG => Addr::SpWrLenReg,
         SP_STATUS_REG => Addr::SpStatusReg,
+        SP_DMA_FULL_REG => Addr::SpDmaFullReg,
         SP_DMA_BUSY_REG => Addr::SpDmaBusyReg,
         SP_SEMAPHORE_REG => Addr::SpSemaphoreReg,
         SP_PC_REG => Addr::SpPcReg,
 
+        DPC_START_REG => Addr::DpcStartReg,
+        DPC_END_REG => Addr::DpcEndReg,
+        DPC_CURRENT_REG => Addr::DpcCurrentReg,
         DPC_STATUS_REG => Addr::DpcStatusReg,
+        DPC_CLOCK_REG => Addr::DpcClockReg,
+        DPC_BUFBUSY_REG => Addr::DpcBufBusyReg,
+        DPC_PIPEBUSY_REG => Addr::DpcPipeBusyReg,
+        DPC_TMEM_REG => Addr::DpcTmemReg,
+
+        DPS_TBIST_REG => Addr::DpsTbistReg,
+        DPS_TEST_MODE_REG => Addr::DpsTestModeReg,
+        DPS_BUFTEST_ADDR_REG => Addr::DpsBuftestAddrReg,
+        DPS_BUFTEST_DATA_REG => Addr::DpsBuftestDataReg,
 
         MI_MODE_REG => Addr::MiModeReg,
         MI_VERSION_REG => Addr::MiVersionReg,
+        MI_INTR_REG => Addr::MiIntrReg,
         MI_INTR_MASK_REG => Addr::MiIntrMaskReg,
 
         VI_STATUS_REG => Addr::ViStatusReg,
@@ -245,3 +287,84 @@ pub fn map_addr(addr: u32) -> Addr {
         _ => panic!("Unrecognized physical address: {:#08X}", addr),
     }
 }
+
+// RSP COP0 registers 0-7 are the SP registers, 8-15 the DP command registers
+pub fn rsp_cop0_reg_addr(index: u32) -> u32 {
+    match index & 0b1111 {
+        index @ 0..=7 => SP_MEM_ADDR_REG + index * 4,
+        index => DPC_BASE_REG + (index - 8) * 4,
+    }
+}
+
+// Name of the hardware register at a physical address, for debug output
+pub fn register_name(addr: u32) -> Option<&'static str> {
+    let name = match addr {
+        RDRAM_MODE_REG => "RDRAM_MODE_REG",
+        SP_MEM_ADDR_REG => "SP_MEM_ADDR_REG",
+        SP_DRAM_ADDR_REG => "SP_DRAM_ADDR_REG",
+        SP_RD_LEN_REG => "SP_RD_LEN_REG",
+        SP_WR_LEN_REG => "SP_WR_LEN_REG",
+        SP_STATUS_REG => "SP_STATUS_REG",
+        SP_DMA_FULL_REG => "SP_DMA_FULL_REG",
+        SP_DMA_BUSY_REG => "SP_DMA_BUSY_REG",
+        SP_SEMAPHORE_REG => "SP_SEMAPHORE_REG",
+        SP_PC_REG => "SP_PC_REG",
+        DPC_START_REG => "DPC_START_REG",
+        DPC_END_REG => "DPC_END_REG",
+        DPC_CURRENT_REG => "DPC_CURRENT_REG",
+        DPC_STATUS_REG => "DPC_STATUS_REG",
+        DPC_CLOCK_REG => "DPC_CLOCK_REG",
+        DPC_BUFBUSY_REG => "DPC_BUFBUSY_REG",
+        DPC_PIPEBUSY_REG => "DPC_PIPEBUSY_REG",
+        DPC_TMEM_REG => "DPC_TMEM_REG",
+        DPS_TBIST_REG => "DPS_TBIST_REG",
+        DPS_TEST_MODE_REG => "DPS_TEST_MODE_REG",
+        DPS_BUFTEST_ADDR_REG => "DPS_BUFTEST_ADDR_REG",
+        DPS_BUFTEST_DATA_REG => "DPS_BUFTEST_DATA_REG",
+        MI_MODE_REG => "MI_MODE_REG",
+        MI_VERSION_REG => "MI_VERSION_REG",
+        MI_INTR_REG => "MI_INTR_REG",
+        MI_INTR_MASK_REG => "MI_INTR_MASK_REG",
+        VI_STATUS_REG => "VI_STATUS_REG",
+        VI_ORIGIN_REG => "VI_ORIGIN_REG",
+        VI_WIDTH_REG => "VI_WIDTH_REG",
+        VI_INTR_REG => "VI_INTR_REG",
+        VI_CURRENT_REG => "VI_CURRENT_REG",
+        VI_TIMING_REG => "VI_TIMING_REG",
+        VI_V_SYNC_REG => "VI_V_SYNC_REG",
+        VI_H_SYNC_REG => "VI_H_SYNC_REG",
+        VI_H_SYNC_LEAP_REG => "VI_H_SYNC_LEAP_REG",
+        VI_H_START_REG => "VI_H_START_REG",
+        VI_V_START_REG => "VI_V_START_REG",
+        VI_V_BURST_REG => "VI_V_BURST_REG",
+        VI_X_SCALE_REG => "VI_X_SCALE_REG",
+        VI_Y_SCALE_REG => "VI_Y_SCALE_REG",
+        AI_DRAM_ADDR_REG => "AI_DRAM_ADDR_REG",
+        AI_LEN_REG => "AI_LEN_REG",
+        AI_CONTROL_REG => "AI_CONTROL_REG",
+        AI_STATUS_REG => "AI_STATUS_REG",
+        AI_DACRATE_REG => "AI_DACRATE_REG",
+        AI_BITRATE_REG => "AI_BITRATE_REG",
+        PI_DRAM_ADDRESS_REG => "PI_DRAM_ADDRESS_REG",
+        PI_CART_ADDR_REG => "PI_CART_ADDR_REG",
+        PI_WR_LEN_REG => "PI_WR_LEN_REG",
+        PI_STATUS_REG => "PI_STATUS_REG",
+        PI_BSD_DOM1_LAT_REG => "PI_BSD_DOM1_LAT_REG",
+        PI_BSD_DOM1_PWD_REG => "PI_BSD_DOM1_PWD_REG",
+        PI_BSD_DOM1_PGS_REG => "PI_BSD_DOM1_PGS_REG",
+        PI_BSD_DOM1_RLS_REG => "PI_BSD_DOM1_RLS_REG",
+        PI_BSD_DOM2_LAT_REG => "PI_BSD_DOM2_LAT_REG",
+        PI_BSD_DOM2_PWD_REG => "PI_BSD_DOM2_PWD_REG",
+        PI_BSD_DOM2_PGS_REG => "PI_BSD_DOM2_PGS_REG",
+        PI_BSD_DOM2_RLS_REG => "PI_BSD_DOM2_RLS_REG",
+        RI_MODE_REG => "RI_MODE_REG",
+        RI_CONFIG_REG => "RI_CONFIG_REG",
+        RI_CURRENT_LOAD_REG => "RI_CURRENT_LOAD_REG",
+        RI_SELECT_REG => "RI_SELECT_REG",
+        RI_REFRESH_REG => "RI_REFRESH_REG",
+        SI_STATUS_REG => "SI_STATUS_REG",
+
+        _ => return None,
+    };
+    Some(name)
+}
@@ -1,3 +1,24 @@
+#[derive(Debug, Clone, Copy)]
+pub enum Interrupt {
+    Sp,
+    Si,
+    Ai,
+    Vi,
+    Pi,
+    Dp,
+}
+
+#[derive(Debug, Default)]
+pub struct MipsIntrReg
+{
+    sp_intr: bool,
+    si_intr: bool,
+    ai_intr: bool,
+    vi_intr: bool,
+    pi_intr: bool,
+    dp_intr: bool,
+}
+
 #[derive(Debug)]
 pub struct MipsIntrMaskReg
 {
@@ -16,6 +37,7 @@ pub struct MipsInterface
     ebus_test_mode: bool,
     rdram_reg_mode: bool,
 
+    intr: MipsIntrReg,
     intr_mask: MipsIntrMaskReg,
 }
 
@@ -27,6 +49,7 @@ impl MipsInterface {
             ebus_test_mode: false,
             rdram_reg_mode: false,
 
+            intr: MipsIntrReg::default(),
             intr_mask: MipsIntrMaskReg {
                 sp_intr_mask: false,
                 si_intr_mask: false,
@@ -59,12 +82,40 @@ impl MipsInterface {
         if ((value >>  8) & 0x01) != 0 { self.init_mode = true; }
         if ((value >>  9) & 0x01) != 0 { self.ebus_test_mode = false; }
         if ((value >> 10) & 0x01) != 0 { self.ebus_test_mode = true; }
-        if ((value >> 11) & 0x01) != 0 { /* clear dp interrupt */ }
+        if ((value >> 11) & 0x01) != 0 { self.clear_interrupt(Interrupt::Dp); }
         if ((value >> 12) & 0x01) != 0 { self.rdram_reg_mode = false; }
         if ((value >> 13) & 0x01) != 0 { self.rdram_reg_mode = true; }
         println!("WARNING: Stub for write MI mode register {:08X}", value);
     }
 
+    pub fn raise_interrupt(&mut self, interrupt: Interrupt) {
+        *self.intr_flag(interrupt) = true;
+    }
+
+    pub fn clear_interrupt(&mut self, interrupt: Interrupt) {
+        *self.intr_flag(interrupt) = false;
+    }
+
+    fn intr_flag(&mut self, interrupt: Interrupt) -> &mut bool {
+        match interrupt {
+            Interrupt::Sp => &mut self.intr.sp_intr,
+            Interrupt::Si => &mut self.intr.si_intr,
+            Interrupt::Ai => &mut self.intr.ai_intr,
+            Interrupt::Vi => &mut self.intr.vi_intr,
+            Interrupt::Pi => &mut self.intr.pi_intr,
+            Interrupt::Dp => &mut self.intr.dp_intr,
+        }
+    }
+
+    pub fn read_intr_reg(&self) -> u32 {
+        (if self.intr.sp_intr { 1 << 0 } else { 0 }) |
+        (if self.intr.si_intr { 1 << 1 } else { 0 }) |
+        (if self.intr.ai_intr { 1 << 2 } else { 0 }) |
+        (if self.intr.vi_intr { 1 << 3 } else { 0 }) |
+        (if self.intr.pi_intr { 1 << 4 } else { 0 }) |
+        (if self.intr.dp_intr { 1 << 5 } else { 0 })
+    }
+
     pub fn read_intr_mask_reg(&self) -> u32 {
         (if self.intr_mask.sp_intr_mask { 1 << 0 } else { 0 }) |
         (if self.intr_mask.si_intr_mask { 1 << 1 } else { 0 }) |
@@ -1,4 +1,5 @@
 mod audio_interface;
+pub mod cart;
 pub mod cpu;
 mod interconnect;
 pub mod mem_map;
@@ -20,10 +21,10 @@ pub use self::interconnect::Interconnect;
 pub use self::n64::N64;
 pub use self::peripheral_interface::PeripheralInterface;
 pub use self::pif::Pif;
-pub use self::rdp::Rdp;
+pub use self::rdp::{read_trace, replay_trace, Rdp, RdpMode, RdramRegion, TraceBatch};
 pub use self::rsp::Rsp;
 pub use self::rsp::RspRegs;
-pub use self::mips_interface::MipsInterface;
+pub use self::mips_interface::{Interrupt, MipsInterface};
 pub use self::serial_interface::SerialInterface;
 pub use self::rdram_interface::RdramInterface;
 pub use self::video_interface::VideoInterface;
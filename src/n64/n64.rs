@@ -1,19 +1,35 @@
 use super::sinks::{Sink, VideoFrame};
-use super::{Cpu, Interconnect, Rsp};
+use super::{cpu, Cpu, Interconnect, Rdp, RdpMode, Rsp};
+
+// The RSP runs two instructions for every three of the CPU
+const RSP_CLOCK_HZ: u32 = 62_500_000;
+
+// The RSP runs once this many of its cycles have added up, so that the
+// per-instruction work is spread over a burst
+const RSP_BURST_CYCLES: u32 = 64;
 
 #[derive(Debug)]
 pub struct N64 {
     cpu: Cpu,
     rsp: Rsp,
     interconnect: Interconnect,
+
+    // RSP clock ticks left over after the last step, in units of
+    // 1 / cpu::CLOCK_HZ
+    rsp_clock_fraction: u32,
+    // RSP cycles added up for the next burst
+    rsp_cycles: u32,
 }
 
 impl N64 {
-    pub fn new(boot_rom: Box<[u8]>, cart_rom: Box<[u8]>) -> N64 {
+    pub fn new(boot_rom: Box<[u8]>, cart_rom: Box<[u8]>, rdp_mode: RdpMode, rdp_threads: usize) -> N64 {
         N64 {
             cpu: Cpu::new(),
             rsp: Rsp::new(),
-            interconnect: Interconnect::new(boot_rom, cart_rom),
+            interconnect: Interconnect::new(boot_rom, cart_rom, rdp_mode, rdp_threads),
+
+            rsp_clock_fraction: 0,
+            rsp_cycles: 0,
         }
     }
 
@@ -21,13 +37,156 @@ impl N64 {
         &self.cpu
     }
 
+    pub fn rsp(&self) -> &Rsp {
+        &self.rsp
+    }
+
     pub fn interconnect(&self) -> &Interconnect {
         &self.interconnect
     }
 
+    pub fn rdp(&mut self) -> &mut Rdp {
+        self.interconnect.rdp()
+    }
+
+    // Steps the CPU by one cycle, and the RSP and the rest of the system by
+    // the same time
     pub fn step(&mut self, frame_sink: &mut Sink<VideoFrame>) {
+        self.step_until_rsp(frame_sink, |_| false);
+    }
+
+    // The same, but the RSP stops before the instruction at an IMEM offset
+    // for which `stop` returns true. Returns that offset. The cycles left
+    // are kept, so the RSP continues from there on the next step.
+    pub fn step_until_rsp<F>(&mut self, frame_sink: &mut dyn Sink<VideoFrame>, mut stop: F) -> Option<u32>
+        where F: FnMut(u32) -> bool
+    {
         self.cpu.step(&mut self.interconnect);
-        self.rsp.step(&mut self.interconnect);
+
+        self.rsp_clock_fraction += RSP_CLOCK_HZ;
+        self.rsp_cycles += self.rsp_clock_fraction / cpu::CLOCK_HZ;
+        self.rsp_clock_fraction %= cpu::CLOCK_HZ;
+
+        let mut stopped_at = None;
+        if self.interconnect.rsp_regs().is_halted() {
+            // Only DMAs started by the CPU are running
+            self.interconnect.step_rsp_dma(self.rsp_cycles);
+            self.rsp_cycles = 0;
+        } else if self.rsp_cycles >= RSP_BURST_CYCLES {
+            let executed = self.rsp.run(&mut self.interconnect, self.rsp_cycles as usize, |pc| {
+                if stop(pc) {
+                    stopped_at = Some(pc);
+                }
+                stopped_at.is_some()
+            }) as u32;
+            self.rsp_cycles -= executed;
+
+            // A halted or waiting RSP sits out the rest of the burst, while
+            // the DMA engine keeps going
+            if stopped_at.is_none() {
+                self.interconnect.step_rsp_dma(self.rsp_cycles);
+                self.rsp_cycles = 0;
+            }
+        }
+
         self.interconnect.step(frame_sink);
+        stopped_at
+    }
+
+    // Steps only the RSP, leaving the CPU and the rest of the system alone
+    pub fn step_rsp(&mut self) {
+        self.rsp.step(&mut self.interconnect);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NullSink;
+
+    impl Sink<VideoFrame> for NullSink {
+        fn append(&mut self, _: VideoFrame) {}
+    }
+
+    fn n64() -> N64 {
+        N64 {
+            cpu: Cpu::new(),
+            rsp: Rsp::new(),
+            interconnect: Interconnect::without_roms(),
+            rsp_clock_fraction: 0,
+            rsp_cycles: 0,
+        }
+    }
+
+    // Clears the halt bit of SP_STATUS
+    fn start_rsp(n64: &mut N64) {
+        n64.interconnect.write_word(0x0404_0010, 1 << 0);
+    }
+
+    #[test]
+    fn rsp_runs_at_two_thirds_of_the_cpu_clock_in_bursts() {
+        let mut n64 = n64();
+        // IMEM is all NOPs
+        start_rsp(&mut n64);
+
+        for steps in 1..1501 {
+            n64.step(&mut NullSink);
+            let cycles = steps * 2 / 3;
+            let instructions = cycles - cycles % RSP_BURST_CYCLES;
+            assert_eq!(n64.interconnect.rsp_regs().pc(), instructions * 4, "after {} steps", steps);
+        }
+    }
+
+    #[test]
+    fn rsp_stops_inside_a_burst() {
+        let mut n64 = n64();
+        start_rsp(&mut n64);
+
+        let mut steps = 0;
+        let stopped_at = loop {
+            steps += 1;
+            if let Some(pc) = n64.step_until_rsp(&mut NullSink, |pc| pc == 0x94) {
+                break pc;
+            }
+        };
+        assert_eq!(stopped_at, 0x94);
+        assert_eq!(steps, 96);
+        assert_eq!(n64.interconnect.rsp_regs().pc(), 0x94);
+
+        // The cycles left are kept for the next burst, none are lost
+        assert_eq!(n64.rsp_cycles, 64 - 0x94 / 4);
+        for steps in steps + 1..301 {
+            assert_eq!(n64.step_until_rsp(&mut NullSink, |_| false), None);
+            assert_eq!(n64.interconnect.rsp_regs().pc() / 4 + n64.rsp_cycles, steps * 2 / 3);
+        }
+        assert_eq!(n64.interconnect.rsp_regs().pc(), 0x94 + 64 * 4 * 2);
+    }
+
+    #[test]
+    fn dma_runs_on_the_rsp_clock_while_the_rsp_waits_for_it() {
+        let mut n64 = n64();
+        let program = [
+            0x4008_3000, // mfc0 $t0, SP_DMA_BUSY
+            0x1500_fffe, // bnez $t0, -2
+            0x0000_0000, // nop
+            0x0000_000d, // break
+        ];
+        for (i, &word) in program.iter().enumerate() {
+            n64.interconnect.rsp().write_imem(i as u32 * 4, word);
+        }
+        // 0x1000 bytes from RDRAM to DMEM take 512 RSP cycles
+        n64.interconnect.write_word(0x0404_0000, 0);
+        n64.interconnect.write_word(0x0404_0004, 0);
+        n64.interconnect.write_word(0x0404_0008, 0x0fff);
+        start_rsp(&mut n64);
+
+        let mut steps = 0;
+        while !n64.interconnect.rsp_regs().is_halted() {
+            n64.step(&mut NullSink);
+            steps += 1;
+        }
+        // The RSP polls once per burst, every 96 CPU cycles
+        assert!(steps > 768 && steps <= 768 + 96, "Broke after {} steps", steps);
     }
 }
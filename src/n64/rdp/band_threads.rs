@@ -0,0 +1,92 @@
+// Threads kept for the lifetime of the RDP to draw the bands, so that
+// flushing a batch doesn't spawn a thread per band. The first band is drawn
+// on the calling thread.
+
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+#[derive(Debug)]
+pub struct BandThreads {
+    // Dropped to stop the threads
+    jobs: Vec<Sender<Job>>,
+    // Whether each job ran without panicking
+    done: Receiver<bool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl BandThreads {
+    // Threads for all bands but the first
+    pub fn new(count: usize) -> BandThreads {
+        let (done_sender, done_receiver) = mpsc::channel();
+        let mut jobs = Vec::new();
+        let mut threads = Vec::new();
+        for band in 1..count {
+            let (job_sender, job_receiver) = mpsc::channel::<Job>();
+            let done = done_sender.clone();
+            let thread = thread::Builder::new()
+                .name(format!("rdp band {}", band))
+                .spawn(move || {
+                    for job in job_receiver {
+                        if done.send(run_job(job)).is_err() {
+                            break;
+                        }
+                    }
+                })
+                .unwrap();
+            jobs.push(job_sender);
+            threads.push(thread);
+        }
+
+        BandThreads {
+            jobs: jobs,
+            done: done_receiver,
+            threads: threads,
+        }
+    }
+
+    // Runs one job per band and returns once all of them have finished. A
+    // panic in any of them is raised again after that.
+    pub fn run<'a>(&self, jobs: Vec<Box<dyn FnOnce() + Send + 'a>>) {
+        assert!(jobs.len() <= self.jobs.len() + 1, "More jobs than band threads");
+        let mut jobs = jobs.into_iter();
+        let first = match jobs.next() {
+            Some(job) => job,
+            None => return,
+        };
+
+        let mut sent = 0;
+        for (sender, job) in self.jobs.iter().zip(jobs) {
+            // The jobs borrow from the caller for 'a. That is sound as no
+            // job outlives this call: every job sent is waited for below,
+            // also when the first one panics.
+            let job: Job = unsafe { mem::transmute(job) };
+            sender.send(job).expect("Band thread stopped");
+            sent += 1;
+        }
+
+        let mut ok = run_job(first);
+        for _ in 0..sent {
+            ok &= self.done.recv().expect("Band thread stopped");
+        }
+        if !ok {
+            panic!("Drawing a band panicked");
+        }
+    }
+}
+
+impl Drop for BandThreads {
+    fn drop(&mut self) {
+        self.jobs.clear();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run_job<'a>(job: Box<dyn FnOnce() + Send + 'a>) -> bool {
+    panic::catch_unwind(AssertUnwindSafe(job)).is_ok()
+}
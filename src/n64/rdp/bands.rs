@@ -0,0 +1,419 @@
+// Rendering split into bands of scanlines, each drawn by its own renderer
+// on its own thread of a BandThreads.
+//
+// Every renderer follows all commands, so they all hold the same state, but
+// only draws the lines of its band. The bands write to disjoint parts of the
+// color and Z images and only read back what they wrote, so running them in
+// parallel gives the same result as running the commands on one renderer.
+//
+// Commands are gathered until one which the bands can't run independently:
+// image and scissor changes move the bands, so they wait for the gathered
+// commands and then run on every renderer in turn. Texture loads are
+// gathered with a copy of the RDRAM they read, taken when they come in.
+// That is only the same as reading it later when the gathered commands
+// don't draw there, so a load from the color or Z image waits for them.
+
+use std::cmp;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::band_threads::BandThreads;
+use super::command::RdpCommand;
+use super::references::{RdramRegion, ReferenceTracker};
+use super::renderer::Renderer;
+
+// RDRAM as seen by a renderer. Accesses outside of it read as None and
+// writes to them are dropped.
+pub trait Memory {
+    fn bytes(&self, addr: usize, length: usize) -> Option<&[u8]>;
+    fn bytes_mut(&mut self, addr: usize, length: usize) -> Option<&mut [u8]>;
+}
+
+impl Memory for [u8] {
+    fn bytes(&self, addr: usize, length: usize) -> Option<&[u8]> {
+        self.get(addr..addr + length)
+    }
+
+    fn bytes_mut(&mut self, addr: usize, length: usize) -> Option<&mut [u8]> {
+        self.get_mut(addr..addr + length)
+    }
+}
+
+// The parts of the color and Z images holding the lines of a band
+#[derive(Debug, Default)]
+pub struct BandMemory<'a> {
+    // Start address and contents
+    regions: Vec<(usize, &'a mut [u8])>,
+}
+
+impl<'a> Memory for BandMemory<'a> {
+    fn bytes(&self, addr: usize, length: usize) -> Option<&[u8]> {
+        self.regions.iter()
+            .filter(|&&(start, _)| addr >= start)
+            .filter_map(|&(start, ref data)| data.get(addr - start..addr - start + length))
+            .next()
+    }
+
+    fn bytes_mut(&mut self, addr: usize, length: usize) -> Option<&mut [u8]> {
+        self.regions.iter_mut()
+            .filter(|&&mut (start, _)| addr >= start)
+            .filter_map(|&mut (start, ref mut data)| data.get_mut(addr - start..addr - start + length))
+            .next()
+    }
+}
+
+// The bytes of RDRAM the renderers wrote to. Bands running in parallel
+// mark their bytes in the same set. Only the bytes themselves are copied
+// back by the worker, as the CPU may have written to the ones next to them.
+#[derive(Debug)]
+pub struct WrittenBytes {
+    // One bit per byte
+    words: Vec<AtomicU64>,
+}
+
+impl WrittenBytes {
+    pub fn new(rdram_length: usize) -> WrittenBytes {
+        WrittenBytes {
+            words: (0..(rdram_length + 63) / 64).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    fn mark(&self, addr: usize, length: usize) {
+        let end = addr + length;
+        let mut pos = addr;
+        while pos < end {
+            let bit = pos % 64;
+            let count = cmp::min(64 - bit, end - pos);
+            let mask = if count == 64 { !0 } else { ((1 << count) - 1) << bit };
+            if let Some(word) = self.words.get(pos / 64) {
+                // Most writes go to bytes which are marked already
+                if (word.load(Ordering::Relaxed) & mask) != mask {
+                    word.fetch_or(mask, Ordering::Relaxed);
+                }
+            }
+            pos += count;
+        }
+    }
+
+    // The written byte ranges, with neighbouring ranges merged. Clears the
+    // set.
+    pub fn take_ranges(&mut self) -> Vec<(usize, usize)> {
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        for (index, word) in self.words.iter_mut().enumerate() {
+            let mut bits = mem::replace(word.get_mut(), 0);
+            while bits != 0 {
+                let bit = bits.trailing_zeros() as usize;
+                let count = cmp::min((!(bits >> bit)).trailing_zeros() as usize, 64 - bit);
+                bits = if bit + count == 64 { 0 } else { bits & !(((1 << count) - 1) << bit) };
+
+                let start = index * 64 + bit;
+                let end = start + count;
+                match ranges.last_mut() {
+                    Some(last) if last.1 == start => last.1 = end,
+                    _ => ranges.push((start, end)),
+                }
+            }
+        }
+        ranges
+    }
+}
+
+// Memory which marks the bytes written through it
+struct Tracked<'a, M: Memory + ?Sized + 'a> {
+    memory: &'a mut M,
+    written: &'a WrittenBytes,
+}
+
+impl<'a, M: Memory + ?Sized + 'a> Memory for Tracked<'a, M> {
+    fn bytes(&self, addr: usize, length: usize) -> Option<&[u8]> {
+        self.memory.bytes(addr, length)
+    }
+
+    fn bytes_mut(&mut self, addr: usize, length: usize) -> Option<&mut [u8]> {
+        let bytes = self.memory.bytes_mut(addr, length);
+        if bytes.is_some() {
+            self.written.mark(addr, length);
+        }
+        bytes
+    }
+}
+
+// What a texture load reads, copied from RDRAM
+struct Snapshot<'a>(&'a RdramRegion);
+
+impl<'a> Memory for Snapshot<'a> {
+    fn bytes(&self, addr: usize, length: usize) -> Option<&[u8]> {
+        let offset = addr.checked_sub(self.0.addr as usize)?;
+        self.0.data.get(offset..offset + length)
+    }
+
+    fn bytes_mut(&mut self, _: usize, _: usize) -> Option<&mut [u8]> {
+        None
+    }
+}
+
+#[derive(Debug)]
+struct Pending {
+    command: RdpCommand,
+    words: Vec<u64>,
+    // The source of a texture load
+    snapshot: Option<RdramRegion>,
+}
+
+#[derive(Debug)]
+pub struct Bands {
+    renderers: Vec<Renderer>,
+    // Commands gathered since the last one which had to run on its own
+    pending: Vec<Pending>,
+    // Follows the images, to find the sources of texture loads
+    references: ReferenceTracker,
+    // Only kept when asked for
+    written: Option<WrittenBytes>,
+    // None with a single band
+    threads: Option<BandThreads>,
+}
+
+impl Bands {
+    // With a single band commands run as they come in
+    pub fn new(count: usize) -> Bands {
+        let count = cmp::max(count, 1);
+        Bands {
+            renderers: (0..count).map(|_| Renderer::new()).collect(),
+            pending: Vec::new(),
+            references: ReferenceTracker::default(),
+            written: None,
+            threads: if count > 1 { Some(BandThreads::new(count)) } else { None },
+        }
+    }
+
+    // Starts keeping track of the bytes of RDRAM written to
+    pub fn track_writes(&mut self, rdram_length: usize) {
+        self.written = Some(WrittenBytes::new(rdram_length));
+    }
+
+    // The byte ranges written to since the last call
+    pub fn take_written(&mut self) -> Vec<(usize, usize)> {
+        match self.written {
+            Some(ref mut written) => written.take_ranges(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn execute(&mut self, command: RdpCommand, words: &[u64], rdram: &mut [u8]) {
+        let written = self.written.as_ref();
+        if self.renderers.len() == 1 {
+            execute(&mut self.renderers[0], command, words, rdram, written);
+            return;
+        }
+
+        let mut references = Vec::new();
+        self.references.command_references(words, &mut references);
+        let mut snapshot = None;
+        match command {
+            RdpCommand::SetColorImage |
+            RdpCommand::SetZImage |
+            RdpCommand::SetScissor => {
+                self.flush(rdram);
+                let written = self.written.as_ref();
+                for renderer in &mut self.renderers {
+                    execute(renderer, command, words, rdram, written);
+                }
+                return;
+            }
+            RdpCommand::LoadTile |
+            RdpCommand::LoadBlock |
+            RdpCommand::LoadTlut => if let Some(source) = references.first() {
+                let drawn = self.references.drawn_ranges();
+                if drawn.iter().any(|&(start, end)| source.start < end && start < source.end) {
+                    self.flush(rdram);
+                }
+                snapshot = Some(RdramRegion::capture(rdram, source.start, source.end));
+            },
+            _ => {}
+        }
+        self.pending.push(Pending {
+            command: command,
+            words: words.to_vec(),
+            snapshot: snapshot,
+        });
+    }
+
+    // Runs the gathered commands, in parallel when the bands don't share
+    // any memory. Otherwise they run one command at a time, every band in
+    // turn from the top, which is the order a single renderer draws in.
+    pub fn flush(&mut self, rdram: &mut [u8]) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let commands = mem::replace(&mut self.pending, Vec::new());
+
+        let (top, bottom) = self.renderers[0].scissor_lines();
+        let count = self.renderers.len() as u32;
+        let lines = bottom.saturating_sub(top);
+        let writes_z = commands.iter().any(|pending| writes_z(pending.command));
+        let mut ranges = Vec::with_capacity(self.renderers.len());
+        for (i, renderer) in (0..count).zip(self.renderers.iter_mut()) {
+            let start = top + lines * i / count;
+            let end = top + lines * (i + 1) / count;
+            renderer.set_band(start, end);
+            ranges.push(renderer.band_ranges(writes_z));
+        }
+
+        let (renderers, written) = (&mut self.renderers, self.written.as_ref());
+        match (split(rdram, &ranges), self.threads.as_ref()) {
+            (Some(memories), Some(threads)) => {
+                let commands = &commands;
+                let mut jobs: Vec<Box<dyn FnOnce() + Send>> = Vec::with_capacity(memories.len());
+                for (renderer, mut memory) in renderers.iter_mut().zip(memories) {
+                    jobs.push(Box::new(move || {
+                        for pending in commands {
+                            run_pending(renderer, pending, &mut memory, written);
+                        }
+                    }));
+                }
+                threads.run(jobs);
+            }
+            _ => {
+                for pending in &commands {
+                    for renderer in renderers.iter_mut() {
+                        run_pending(renderer, pending, rdram, written);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn read_color_image(&self, rdram: &[u8]) -> (u32, u32, Vec<u8>) {
+        self.renderers[0].read_color_image(rdram)
+    }
+}
+
+fn execute<M: Memory + ?Sized>(renderer: &mut Renderer, command: RdpCommand, words: &[u64], memory: &mut M,
+                                written: Option<&WrittenBytes>) {
+    match written {
+        Some(written) => renderer.execute(command, words, &mut Tracked { memory: memory, written: written }),
+        None => renderer.execute(command, words, memory),
+    }
+}
+
+// Texture loads read their copy instead of the memory
+fn run_pending<M: Memory + ?Sized>(renderer: &mut Renderer, pending: &Pending, memory: &mut M,
+                                    written: Option<&WrittenBytes>) {
+    match pending.snapshot {
+        Some(ref snapshot) => renderer.execute(pending.command, &pending.words, &mut Snapshot(snapshot)),
+        None => execute(renderer, pending.command, &pending.words, memory, written),
+    }
+}
+
+fn writes_z(command: RdpCommand) -> bool {
+    match command {
+        RdpCommand::FillZbufferTriangle |
+        RdpCommand::TextureZbufferTriangle |
+        RdpCommand::ShadeZbufferTriangle |
+        RdpCommand::ShadeTextureZbufferTriangle => true,
+        _ => false,
+    }
+}
+
+// Splits RDRAM into the byte ranges of every band. Returns None if ranges of
+// different bands overlap.
+fn split<'a>(rdram: &'a mut [u8], ranges: &[Vec<(usize, usize)>]) -> Option<Vec<BandMemory<'a>>> {
+    let mut all = Vec::new();
+    for (band, band_ranges) in ranges.iter().enumerate() {
+        for &(start, end) in band_ranges {
+            let end = cmp::min(end, rdram.len());
+            if start < end {
+                all.push((start, end, band));
+            }
+        }
+    }
+    all.sort();
+
+    // Ranges of the same band are merged
+    let mut merged: Vec<(usize, usize, usize)> = Vec::with_capacity(all.len());
+    for (start, end, band) in all {
+        if let Some(last) = merged.last_mut() {
+            if start < last.1 {
+                if band != last.2 {
+                    return None;
+                }
+                last.1 = cmp::max(last.1, end);
+                continue;
+            }
+        }
+        merged.push((start, end, band));
+    }
+
+    let mut memories: Vec<BandMemory> = ranges.iter().map(|_| BandMemory::default()).collect();
+    let mut rest = rdram;
+    let mut offset = 0;
+    for (start, end, band) in merged {
+        let (_, tail) = rest.split_at_mut(start - offset);
+        let (region, tail) = tail.split_at_mut(end - start);
+        memories[band].regions.push((start, region));
+        rest = tail;
+        offset = end;
+    }
+    Some(memories)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_scene::{self, COLOR_IMAGE, IMAGE_BYTES, WIDTH, Z_IMAGE};
+
+    // The color image as RGBA and the Z image
+    fn render(words: &[u64], count: usize) -> (Vec<u8>, Vec<u8>) {
+        let mut rdram = vec![0; 0x0020_0000];
+        test_scene::write_texture(&mut rdram, 0);
+        let mut bands = Bands::new(count);
+        for (command, words) in test_scene::commands(words) {
+            bands.execute(command, words, &mut rdram);
+        }
+        bands.flush(&mut rdram);
+
+        let (_, _, color) = bands.read_color_image(&rdram);
+        (color, rdram[Z_IMAGE as usize..Z_IMAGE as usize + IMAGE_BYTES].to_vec())
+    }
+
+    fn assert_same_for_any_band_count(words: &[u64]) {
+        let (color, z) = render(words, 1);
+        let mut colors: Vec<&[u8]> = color.chunks(4).collect();
+        colors.sort();
+        colors.dedup();
+        assert!(colors.len() > 100, "Only {} colors drawn", colors.len());
+        assert!(z.chunks(2).any(|depth| depth != &z[..2]), "Nothing drawn to the Z image");
+
+        for &count in &[2, 3, 8] {
+            let (band_color, band_z) = render(words, count);
+            assert!(band_color == color, "Color image differs with {} bands", count);
+            assert!(band_z == z, "Z image differs with {} bands", count);
+        }
+    }
+
+    #[test]
+    fn written_bytes_are_merged_into_ranges() {
+        let mut written = WrittenBytes::new(1024);
+        written.mark(2, 2);
+        written.mark(60, 200);
+        written.mark(260, 4);
+        written.mark(512, 64);
+        written.mark(1020, 8);
+        assert_eq!(written.take_ranges(), vec![(2, 4), (60, 264), (512, 576), (1020, 1024)]);
+        assert_eq!(written.take_ranges(), vec![]);
+    }
+
+    #[test]
+    fn band_count_does_not_change_the_result() {
+        assert_same_for_any_band_count(&test_scene::scene());
+    }
+
+    #[test]
+    fn textures_loaded_from_the_color_image_see_what_was_drawn() {
+        let mut words = test_scene::clear();
+        words.extend(test_scene::triangles(0));
+        words.extend(test_scene::textured_rectangle_from(COLOR_IMAGE, WIDTH, 40, 8));
+        words.extend(test_scene::triangles(3));
+        assert_same_for_any_band_count(&words);
+    }
+}
@@ -0,0 +1,147 @@
+use std::cmp;
+
+use super::color::Color;
+
+// Selectors of the (P * A + M * B) / (A + B) inputs of one cycle
+#[derive(Debug, Clone, Copy, Default)]
+struct BlenderCycle {
+    p: u8,
+    a: u8,
+    m: u8,
+    b: u8,
+}
+
+// Per-pixel blender inputs
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Inputs {
+    pub pixel: Color,
+    // Combined alpha, or coverage with alpha_cvg_select
+    pub alpha: u8,
+    pub shade_alpha: u8,
+    pub memory: Color,
+    // 3-bit coverage stored with the memory color
+    pub memory_coverage: u8,
+}
+
+// The blender state set up by SET_OTHER_MODES and the blend and fog colors
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Blender {
+    cycles: [BlenderCycle; 2],
+
+    pub blend_color: Color,
+    pub fog_color: Color,
+}
+
+impl Blender {
+    pub fn set_other_modes(&mut self, other_modes: u64) {
+        let field = |shift: u64| ((other_modes >> shift) & 0b11) as u8;
+        self.cycles[0] = BlenderCycle { p: field(30), a: field(26), m: field(22), b: field(18) };
+        self.cycles[1] = BlenderCycle { p: field(28), a: field(24), m: field(20), b: field(16) };
+    }
+
+    // The P input is passed through unblended when blending isn't enabled
+    pub fn pass(&self, cycle: usize, inputs: &Inputs) -> Color {
+        self.color(self.cycles[cycle].p, inputs)
+    }
+
+    // The factors are 5 bits wide. Only the last cycle divides by their sum,
+    // the first cycle of 2 cycle mode treats B as 1.0 - A.
+    pub fn blend(&self, cycle: usize, inputs: &Inputs, divide: bool) -> Color {
+        let selectors = &self.cycles[cycle];
+        let p = self.color(selectors.p, inputs);
+        let m = self.color(selectors.m, inputs);
+
+        let a = (match selectors.a {
+            0 => inputs.alpha,
+            1 => self.fog_color.a,
+            2 => inputs.shade_alpha,
+            _ => 0,
+        } >> 3) as u32;
+        let b = match selectors.b {
+            0 => !a & 0x1f,
+            1 => (inputs.memory_coverage << 2) as u32,
+            2 => 0x1f,
+            _ => 0,
+        };
+
+        let channel = |p: u8, m: u8| {
+            if divide {
+                match a + b {
+                    0 => p,
+                    sum => ((p as u32 * a + m as u32 * b) / sum) as u8,
+                }
+            } else {
+                cmp::min((p as u32 * a + m as u32 * (b + 1)) >> 5, 0xff) as u8
+            }
+        };
+        Color::new(channel(p.r, m.r), channel(p.g, m.g), channel(p.b, m.b), p.a)
+    }
+
+    fn color(&self, selector: u8, inputs: &Inputs) -> Color {
+        match selector {
+            0 => inputs.pixel,
+            1 => inputs.memory,
+            2 => self.blend_color,
+            _ => self.fog_color,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Selectors of the first cycle
+    fn other_modes(p: u64, a: u64, m: u64, b: u64) -> u64 {
+        p << 30 | a << 26 | m << 22 | b << 18
+    }
+
+    fn inputs() -> Inputs {
+        Inputs {
+            pixel: Color::new(200, 0, 62, 0x11),
+            alpha: 0x80,
+            shade_alpha: 0xff,
+            memory: Color::new(100, 255, 62, 0x22),
+            memory_coverage: 7,
+        }
+    }
+
+    #[test]
+    fn blending_divides_by_the_sum_of_the_factors() {
+        let mut blender = Blender::default();
+        // PIXEL * ALPHA + MEMORY * (1 - ALPHA)
+        blender.set_other_modes(other_modes(0, 0, 1, 0));
+
+        // The factors are 16 and 15
+        let color = blender.blend(0, &inputs(), true);
+        assert_eq!(color, Color::new(151, 123, 62, 0x11));
+
+        // Without dividing, B is one more
+        let color = blender.blend(0, &inputs(), false);
+        assert_eq!(color, Color::new(150, 127, 62, 0x11));
+    }
+
+    #[test]
+    fn blending_takes_fog_and_coverage_factors() {
+        let mut blender = Blender::default();
+        blender.fog_color = Color::new(0, 0, 0, 0x40);
+        // BLEND * FOG_ALPHA + MEMORY * MEMORY_COVERAGE
+        blender.set_other_modes(other_modes(2, 1, 1, 1));
+        blender.blend_color = Color::new(255, 255, 0, 0);
+
+        // The factors are 8 and 28
+        let color = blender.blend(0, &inputs(), true);
+        assert_eq!(color, Color::new(134, 255, 48, 0));
+    }
+
+    #[test]
+    fn unblended_pixels_take_the_p_input() {
+        let mut blender = Blender::default();
+        blender.fog_color = Color::new(1, 2, 3, 4);
+        // FOG in the first cycle, MEMORY in the second
+        blender.set_other_modes(3 << 30 | 1 << 28);
+
+        assert_eq!(blender.pass(0, &inputs()), blender.fog_color);
+        assert_eq!(blender.pass(1, &inputs()), inputs().memory);
+    }
+}
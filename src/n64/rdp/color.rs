@@ -0,0 +1,37 @@
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub fn new(r: u8, g: u8, b: u8, a: u8) -> Color {
+        Color { r: r, g: g, b: b, a: a }
+    }
+
+    pub fn from_rgba32(value: u32) -> Color {
+        Color::new((value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8)
+    }
+
+    pub fn to_rgba32(&self) -> u32 {
+        (self.r as u32) << 24 | (self.g as u32) << 16 | (self.b as u32) << 8 | self.a as u32
+    }
+
+    // 5 bits per color channel and a single alpha (coverage) bit
+    pub fn from_rgba16(value: u16) -> Color {
+        let expand = |c: u16| ((c << 3) | (c >> 2)) as u8;
+        Color::new(expand((value >> 11) & 0x1f),
+                   expand((value >> 6) & 0x1f),
+                   expand((value >> 1) & 0x1f),
+                   if (value & 1) != 0 { 0xff } else { 0 })
+    }
+
+    pub fn to_rgba16(&self) -> u16 {
+        (self.r as u16 >> 3) << 11 |
+        (self.g as u16 >> 3) << 6 |
+        (self.b as u16 >> 3) << 1 |
+        (self.a >> 7) as u16
+    }
+}
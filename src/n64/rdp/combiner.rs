@@ -0,0 +1,237 @@
+use std::cmp;
+
+use super::color::Color;
+
+// Combiner inputs are 9-bit, 1.0 is one more than the largest color value
+const ONE: i32 = 0x100;
+
+// Selectors of the (A - B) * C + D inputs of one cycle
+#[derive(Debug, Clone, Copy, Default)]
+struct CombinerCycle {
+    rgb: [u8; 4],
+    alpha: [u8; 4],
+}
+
+// Per-pixel combiner inputs
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Inputs {
+    pub texel0: Color,
+    pub texel1: Color,
+    pub shade: Color,
+    pub lod_fraction: u8,
+    pub noise: u8,
+}
+
+// The color combiner state set up by SET_COMBINE_MODE and the color, key
+// and convert commands
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Combiner {
+    cycles: [CombinerCycle; 2],
+
+    pub prim_color: Color,
+    pub prim_lod_fraction: u8,
+    pub env_color: Color,
+
+    // Chroma key, per channel in R, G, B order. Widths are 4.8.
+    key_center: [u8; 3],
+    key_scale: [u8; 3],
+    key_width: [u16; 3],
+
+    // YUV conversion coefficients, only K4 and K5 are combiner inputs
+    k4: i32,
+    k5: i32,
+}
+
+impl Combiner {
+    pub fn set_combine_mode(&mut self, w0: u64) {
+        let field = |shift: u64, bits: u64| ((w0 >> shift) & ((1 << bits) - 1)) as u8;
+        self.cycles[0] = CombinerCycle {
+            rgb: [field(52, 4), field(28, 4), field(47, 5), field(15, 3)],
+            alpha: [field(44, 3), field(12, 3), field(41, 3), field(9, 3)],
+        };
+        self.cycles[1] = CombinerCycle {
+            rgb: [field(37, 4), field(24, 4), field(32, 5), field(6, 3)],
+            alpha: [field(21, 3), field(3, 3), field(18, 3), field(0, 3)],
+        };
+    }
+
+    pub fn set_key_r(&mut self, w0: u64) {
+        self.key_width[0] = ((w0 >> 16) & 0xfff) as u16;
+        self.key_center[0] = (w0 >> 8) as u8;
+        self.key_scale[0] = w0 as u8;
+    }
+
+    pub fn set_key_gb(&mut self, w0: u64) {
+        self.key_width[1] = ((w0 >> 44) & 0xfff) as u16;
+        self.key_width[2] = ((w0 >> 32) & 0xfff) as u16;
+        self.key_center[1] = (w0 >> 24) as u8;
+        self.key_scale[1] = (w0 >> 16) as u8;
+        self.key_center[2] = (w0 >> 8) as u8;
+        self.key_scale[2] = w0 as u8;
+    }
+
+    // The coefficients are 9-bit signed
+    pub fn set_convert(&mut self, w0: u64) {
+        let coefficient = |shift: u64| ((((w0 >> shift) & 0x1ff) << 7) as u16 as i16 >> 7) as i32;
+        self.k4 = coefficient(9);
+        self.k5 = coefficient(0);
+    }
+
+    // Runs one combiner cycle, `combined` is the output of the first cycle
+    // in the second one
+    pub fn combine(&self, cycle: usize, combined: Color, inputs: &Inputs) -> Color {
+        let selectors = &self.cycles[cycle];
+        let channel = |c: usize| {
+            let (a, b, c, d) = self.rgb_terms(selectors, combined, inputs, c);
+            clamp_channel(((a - b) * c + (d << 8) + 0x80) >> 8)
+        };
+        let (a, b, c, d) = self.alpha_terms(selectors, combined, inputs);
+        let alpha = clamp_channel(((a - b) * c + (d << 8) + 0x80) >> 8);
+
+        Color::new(channel(0), channel(1), channel(2), alpha)
+    }
+
+    // Keying replaces the alpha by how close the color is to the key
+    // center, (A - B) * C being the scaled distance for every channel
+    pub fn key_alpha(&self, cycle: usize, combined: Color, inputs: &Inputs) -> u8 {
+        let selectors = &self.cycles[cycle];
+        (0..3).map(|c| {
+            let (a, b, c_term, _) = self.rgb_terms(selectors, combined, inputs, c);
+            let distance = ((a - b) * c_term).abs() >> 8;
+            clamp_channel(self.key_width[c] as i32 - distance)
+        }).min().unwrap_or(0)
+    }
+
+    fn rgb_terms(&self, selectors: &CombinerCycle, combined: Color, inputs: &Inputs, c: usize) -> (i32, i32, i32, i32) {
+        let channel = |color: Color| [color.r, color.g, color.b][c] as i32;
+        let common = |selector: u8| match selector {
+            0 => Some(channel(combined)),
+            1 => Some(channel(inputs.texel0)),
+            2 => Some(channel(inputs.texel1)),
+            3 => Some(channel(self.prim_color)),
+            4 => Some(channel(inputs.shade)),
+            5 => Some(channel(self.env_color)),
+            _ => None,
+        };
+
+        let a = common(selectors.rgb[0]).unwrap_or_else(|| match selectors.rgb[0] {
+            6 => ONE,
+            7 => inputs.noise as i32,
+            _ => 0,
+        });
+        let b = common(selectors.rgb[1]).unwrap_or_else(|| match selectors.rgb[1] {
+            6 => self.key_center[c] as i32,
+            7 => self.k4,
+            _ => 0,
+        });
+        let c_term = common(selectors.rgb[2]).unwrap_or_else(|| match selectors.rgb[2] {
+            6 => self.key_scale[c] as i32,
+            7 => combined.a as i32,
+            8 => inputs.texel0.a as i32,
+            9 => inputs.texel1.a as i32,
+            10 => self.prim_color.a as i32,
+            11 => inputs.shade.a as i32,
+            12 => self.env_color.a as i32,
+            13 => inputs.lod_fraction as i32,
+            14 => self.prim_lod_fraction as i32,
+            15 => self.k5,
+            _ => 0,
+        });
+        let d = common(selectors.rgb[3]).unwrap_or_else(|| match selectors.rgb[3] {
+            6 => ONE,
+            _ => 0,
+        });
+        (a, b, c_term, d)
+    }
+
+    // The C input replaces the combined alpha by the LOD fractions
+    fn alpha_terms(&self, selectors: &CombinerCycle, combined: Color, inputs: &Inputs) -> (i32, i32, i32, i32) {
+        let common = |selector: u8| match selector {
+            1 => inputs.texel0.a as i32,
+            2 => inputs.texel1.a as i32,
+            3 => self.prim_color.a as i32,
+            4 => inputs.shade.a as i32,
+            5 => self.env_color.a as i32,
+            _ => 0,
+        };
+        let add_input = |selector: u8| match selector {
+            0 => combined.a as i32,
+            6 => ONE,
+            selector => common(selector),
+        };
+
+        let c = match selectors.alpha[2] {
+            0 => inputs.lod_fraction as i32,
+            6 => self.prim_lod_fraction as i32,
+            selector => common(selector),
+        };
+        (add_input(selectors.alpha[0]), add_input(selectors.alpha[1]), c, add_input(selectors.alpha[3]))
+    }
+}
+
+fn clamp_channel(value: i32) -> u8 {
+    cmp::min(cmp::max(value, 0), 0xff) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Selectors of a cycle as (A, B, C, D) for color and alpha
+    fn combine_mode(cycle: usize, rgb: [u64; 4], alpha: [u64; 4]) -> u64 {
+        match cycle {
+            0 => rgb[0] << 52 | rgb[1] << 28 | rgb[2] << 47 | rgb[3] << 15 |
+                 alpha[0] << 44 | alpha[1] << 12 | alpha[2] << 41 | alpha[3] << 9,
+            _ => rgb[0] << 37 | rgb[1] << 24 | rgb[2] << 32 | rgb[3] << 6 |
+                 alpha[0] << 21 | alpha[1] << 3 | alpha[2] << 18 | alpha[3],
+        }
+    }
+
+    fn inputs() -> Inputs {
+        Inputs {
+            texel0: Color::new(200, 100, 50, 0x80),
+            shade: Color::new(128, 255, 0, 0x40),
+            ..Inputs::default()
+        }
+    }
+
+    #[test]
+    fn cycles_compute_a_minus_b_times_c_plus_d() {
+        let mut combiner = Combiner::default();
+        combiner.prim_color = Color::new(10, 100, 30, 0x80);
+        combiner.env_color = Color::new(100, 150, 50, 0x10);
+        // (TEXEL0 - ENV) * SHADE + PRIM, (TEXEL0 - 0) * PRIM + ENV
+        combiner.set_combine_mode(combine_mode(1, [1, 5, 4, 3], [1, 7, 3, 5]));
+
+        let color = combiner.combine(1, Color::default(), &inputs());
+        // 50 + 10, 100 - 49.8, 0 + 30 and 32 + 16, rounded
+        assert_eq!(color, Color::new(60, 50, 30, 80));
+    }
+
+    #[test]
+    fn results_are_clamped() {
+        let mut combiner = Combiner::default();
+        combiner.prim_color = Color::new(255, 0, 0, 0);
+        // (TEXEL0 - SHADE) * KEY_SCALE + PRIM, with the scale just under 1.0
+        combiner.set_combine_mode(combine_mode(1, [1, 4, 6, 3], [7, 7, 7, 7]));
+        combiner.key_scale = [0xff; 3];
+
+        let color = combiner.combine(1, Color::default(), &inputs());
+        assert_eq!(color, Color::new(255, 0, 50, 0));
+    }
+
+    #[test]
+    fn second_cycle_takes_the_combined_color() {
+        let mut combiner = Combiner::default();
+        combiner.env_color = Color::new(0x80, 0x80, 0x80, 0x80);
+        // TEXEL0 * SHADE, then COMBINED * ENV
+        let first = combine_mode(0, [1, 8, 4, 7], [1, 7, 4, 7]);
+        let second = combine_mode(1, [0, 8, 5, 7], [0, 7, 5, 7]);
+        combiner.set_combine_mode(first | second);
+
+        let combined = combiner.combine(0, Color::default(), &inputs());
+        assert_eq!(combined, Color::new(100, 100, 0, 0x20));
+        let color = combiner.combine(1, combined, &inputs());
+        assert_eq!(color, Color::new(50, 50, 0, 0x10));
+    }
+}
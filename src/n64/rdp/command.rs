@@ -0,0 +1,71 @@
+use num::FromPrimitive;
+
+// Command ids, bits 61-56 of the first word of a command
+enum_from_primitive! {
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum RdpCommand {
+        NoOp = 0x00,
+        FillTriangle = 0x08,
+        FillZbufferTriangle = 0x09,
+        TextureTriangle = 0x0a,
+        TextureZbufferTriangle = 0x0b,
+        ShadeTriangle = 0x0c,
+        ShadeZbufferTriangle = 0x0d,
+        ShadeTextureTriangle = 0x0e,
+        ShadeTextureZbufferTriangle = 0x0f,
+        TextureRectangle = 0x24,
+        TextureRectangleFlip = 0x25,
+        SyncLoad = 0x26,
+        SyncPipe = 0x27,
+        SyncTile = 0x28,
+        SyncFull = 0x29,
+        SetKeyGb = 0x2a,
+        SetKeyR = 0x2b,
+        SetConvert = 0x2c,
+        SetScissor = 0x2d,
+        SetPrimDepth = 0x2e,
+        SetOtherModes = 0x2f,
+        LoadTlut = 0x30,
+        SetTileSize = 0x32,
+        LoadBlock = 0x33,
+        LoadTile = 0x34,
+        SetTile = 0x35,
+        FillRectangle = 0x36,
+        SetFillColor = 0x37,
+        SetFogColor = 0x38,
+        SetBlendColor = 0x39,
+        SetPrimColor = 0x3a,
+        SetEnvColor = 0x3b,
+        SetCombine = 0x3c,
+        SetTextureImage = 0x3d,
+        SetZImage = 0x3e,
+        SetColorImage = 0x3f,
+    }
+}
+
+impl RdpCommand {
+    // None for ids which don't belong to a command
+    pub fn from_word(word: u64) -> Option<RdpCommand> {
+        RdpCommand::from_u8(id(word))
+    }
+}
+
+pub fn id(word: u64) -> u8 {
+    ((word >> 56) & 0x3f) as u8
+}
+
+// Number of 64-bit words of the command starting with `word`. Triangles are
+// followed by coefficient blocks for shade (8 words), texture (8 words) and
+// depth (2 words) depending on the low bits of the id.
+pub fn length(word: u64) -> usize {
+    match id(word) {
+        id @ 0x08..=0x0f => {
+            4 +
+            if (id & 0b100) != 0 { 8 } else { 0 } +
+            if (id & 0b010) != 0 { 8 } else { 0 } +
+            if (id & 0b001) != 0 { 2 } else { 0 }
+        }
+        0x24 | 0x25 => 2,
+        _ => 1,
+    }
+}
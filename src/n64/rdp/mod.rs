@@ -0,0 +1,19 @@
+mod band_threads;
+mod bands;
+mod blender;
+mod color;
+mod combiner;
+mod command;
+mod rdp;
+mod references;
+mod renderer;
+#[cfg(test)]
+mod test_scene;
+mod tmem;
+mod trace;
+mod triangle;
+mod worker;
+
+pub use self::rdp::{Rdp, RdpMode};
+pub use self::references::RdramRegion;
+pub use self::trace::{read_trace, replay_trace, TraceBatch};
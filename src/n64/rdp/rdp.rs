@@ -0,0 +1,525 @@
+use byteorder::{BigEndian, ByteOrder};
+
+use std::io;
+use std::mem;
+use std::path::Path;
+
+use n64::{Interrupt, MipsInterface};
+
+use super::command::{self, RdpCommand};
+use super::bands::Bands;
+use super::trace::TraceWriter;
+use super::worker::Worker;
+
+// The command, clock and busy counters are 24 bits wide
+const COUNTER_MASK: u32 = 0x00ff_ffff;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RdpMode {
+    // Commands are rendered as they are fetched, which keeps emulation
+    // deterministic
+    Synchronous,
+    // Commands are rendered on a worker thread, their results show up in
+    // RDRAM at SYNC_FULL
+    Threaded,
+}
+
+#[derive(Debug)]
+enum Backend {
+    Synchronous(Bands),
+    Threaded(Worker),
+}
+
+#[derive(Debug)]
+pub struct Rdp {
+    start: u32,
+    end: u32,
+    current: u32,
+
+    // Commands are fetched from DMEM over the XBUS instead of from RDRAM
+    xbus_dmem_dma: bool,
+    freeze: bool,
+    flush: bool,
+    // A new START address waits for the next END write
+    start_valid: bool,
+    // A new END address hasn't been picked up by the fetch engine yet
+    end_valid: bool,
+    // Set by rendering commands, cleared by SYNC_FULL
+    pipe_busy: bool,
+
+    clock: u32,
+    buf_busy: u32,
+    pipe_busy_counter: u32,
+    tmem_busy: u32,
+
+    // Words of a command which hasn't been fetched completely yet
+    command_buffer: Vec<u64>,
+    backend: Backend,
+    trace: Option<TraceWriter>,
+
+    // Span test registers (DPS)
+    tbist: u32,
+    test_mode: u32,
+    buftest_addr: u32,
+    buftest_data: u32,
+}
+
+impl Rdp {
+    // Rendering is split into `render_threads` bands of scanlines drawn in
+    // parallel, which gives the same result for any count
+    pub fn new(mode: RdpMode, render_threads: usize) -> Rdp {
+        Rdp {
+            start: 0,
+            end: 0,
+            current: 0,
+
+            xbus_dmem_dma: false,
+            freeze: false,
+            flush: false,
+            start_valid: false,
+            end_valid: false,
+            pipe_busy: false,
+
+            clock: 0,
+            buf_busy: 0,
+            pipe_busy_counter: 0,
+            tmem_busy: 0,
+
+            command_buffer: Vec::new(),
+            backend: match mode {
+                RdpMode::Synchronous => Backend::Synchronous(Bands::new(render_threads)),
+                RdpMode::Threaded => Backend::Threaded(Worker::new(render_threads)),
+            },
+            trace: None,
+
+            tbist: 0,
+            test_mode: 0,
+            buftest_addr: 0,
+            buftest_data: 0,
+        }
+    }
+
+    pub fn read_start_reg(&self) -> u32 {
+        self.start
+    }
+
+    // Ignored while a previous START address is still pending
+    pub fn write_start_reg(&mut self, value: u32) {
+        if !self.start_valid {
+            self.start = value & 0x00ff_fff8;
+            self.start_valid = true;
+        }
+    }
+
+    pub fn read_end_reg(&self) -> u32 {
+        self.end
+    }
+
+    // A pending START address begins a new buffer, otherwise the current
+    // buffer is extended
+    pub fn write_end_reg(&mut self, value: u32) {
+        self.end = value & 0x00ff_fff8;
+        if self.start_valid {
+            self.current = self.start;
+            self.start_valid = false;
+        }
+        self.end_valid = true;
+    }
+
+    pub fn read_current_reg(&self) -> u32 {
+        self.current
+    }
+
+    pub fn read_status_reg(&self) -> u32 {
+        let dma_busy = self.current < self.end;
+        // Commands are fetched in full on every step, so there is always
+        // room for more
+        let cbuf_ready = true;
+
+        (if self.xbus_dmem_dma { 1 } else { 0 } <<  0) |
+        (if self.freeze        { 1 } else { 0 } <<  1) |
+        (if self.flush         { 1 } else { 0 } <<  2) |
+        (if self.pipe_busy     { 1 } else { 0 } <<  3) | // Start GCLK
+        (if self.pipe_busy     { 1 } else { 0 } <<  4) | // TMEM busy
+        (if self.pipe_busy     { 1 } else { 0 } <<  5) | // Pipe busy
+        (if self.pipe_busy     { 1 } else { 0 } <<  6) | // Command busy
+        (if cbuf_ready         { 1 } else { 0 } <<  7) |
+        (if dma_busy           { 1 } else { 0 } <<  8) |
+        (if self.end_valid     { 1 } else { 0 } <<  9) |
+        (if self.start_valid   { 1 } else { 0 } << 10)
+    }
+
+    pub fn write_status_reg(&mut self, value: u32) {
+        if (value & (1 << 0)) != 0 { self.xbus_dmem_dma = false; }
+        if (value & (1 << 1)) != 0 { self.xbus_dmem_dma = true; }
+        if (value & (1 << 2)) != 0 { self.freeze = false; }
+        if (value & (1 << 3)) != 0 { self.freeze = true; }
+        if (value & (1 << 4)) != 0 { self.flush = false; }
+        if (value & (1 << 5)) != 0 { self.flush = true; }
+        if (value & (1 << 6)) != 0 { self.tmem_busy = 0; }
+        if (value & (1 << 7)) != 0 { self.pipe_busy_counter = 0; }
+        if (value & (1 << 8)) != 0 { self.buf_busy = 0; }
+        if (value & (1 << 9)) != 0 { self.clock = 0; }
+    }
+
+    pub fn read_clock_reg(&self) -> u32 {
+        self.clock
+    }
+
+    pub fn read_buf_busy_reg(&self) -> u32 {
+        self.buf_busy
+    }
+
+    pub fn read_pipe_busy_reg(&self) -> u32 {
+        self.pipe_busy_counter
+    }
+
+    pub fn read_tmem_reg(&self) -> u32 {
+        self.tmem_busy
+    }
+
+    pub fn read_tbist_reg(&self) -> u32 {
+        self.tbist
+    }
+
+    pub fn write_tbist_reg(&mut self, value: u32) {
+        self.tbist = value & 0x7ff;
+    }
+
+    pub fn read_test_mode_reg(&self) -> u32 {
+        self.test_mode
+    }
+
+    pub fn write_test_mode_reg(&mut self, value: u32) {
+        self.test_mode = value & 1;
+    }
+
+    pub fn read_buftest_addr_reg(&self) -> u32 {
+        self.buftest_addr
+    }
+
+    pub fn write_buftest_addr_reg(&mut self, value: u32) {
+        self.buftest_addr = value & 0x7f;
+    }
+
+    pub fn read_buftest_data_reg(&self) -> u32 {
+        self.buftest_data
+    }
+
+    pub fn write_buftest_data_reg(&mut self, value: u32) {
+        self.buftest_data = value;
+    }
+
+    // Fetches and executes the commands between CURRENT and END
+    pub fn step(&mut self, rdram: &mut [u8], dmem: &[u8], mi: &mut MipsInterface) {
+        self.complete_syncs(rdram, mi, false);
+        if self.freeze {
+            return;
+        }
+
+        if self.pipe_busy {
+            self.clock = (self.clock + 1) & COUNTER_MASK;
+            self.pipe_busy_counter = (self.pipe_busy_counter + 1) & COUNTER_MASK;
+        }
+
+        self.end_valid = false;
+        let mut words = Vec::new();
+        while self.current < self.end {
+            let word = if self.xbus_dmem_dma {
+                BigEndian::read_u64(&dmem[(self.current & 0x0ff8) as usize..])
+            } else {
+                let offset = self.current as usize;
+                if offset + 8 <= rdram.len() { BigEndian::read_u64(&rdram[offset..]) } else { 0 }
+            };
+            self.current += 8;
+            self.buf_busy = (self.buf_busy + 1) & COUNTER_MASK;
+            words.push(word);
+        }
+
+        if !words.is_empty() {
+            self.record_batch(&words, rdram, mi);
+        }
+        for word in words {
+            self.push_word(word, rdram, mi);
+        }
+        self.flush_backend(rdram);
+    }
+
+    // Commands produced by high level emulation of the graphics microcode
+    pub fn submit_commands(&mut self, commands: &[u64], rdram: &mut [u8], mi: &mut MipsInterface) {
+        self.record_batch(commands, rdram, mi);
+        for &word in commands {
+            self.push_word(word, rdram, mi);
+        }
+        self.flush_backend(rdram);
+    }
+
+    // Records every following command batch to a trace file
+    pub fn start_trace<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.stop_trace()?;
+        self.trace = Some(TraceWriter::create(path)?);
+        Ok(())
+    }
+
+    pub fn stop_trace(&mut self) -> io::Result<()> {
+        match self.trace.take() {
+            Some(trace) => trace.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_tracing(&self) -> bool {
+        self.trace.is_some()
+    }
+
+    // The color image as RGBA bytes, with the lines up to the bottom of the
+    // scissor box
+    pub fn read_color_image(&self, rdram: &[u8]) -> (u32, u32, Vec<u8>) {
+        match self.backend {
+            Backend::Synchronous(ref bands) => bands.read_color_image(rdram),
+            Backend::Threaded(_) => panic!("The color image can only be read from a synchronous RDP"),
+        }
+    }
+
+    // Tracing stops on the first write error. The worker is waited for, so
+    // the regions are captured with what it has drawn so far.
+    fn record_batch(&mut self, words: &[u64], rdram: &mut [u8], mi: &mut MipsInterface) {
+        if self.trace.is_none() {
+            return;
+        }
+        let completed = match self.backend {
+            Backend::Threaded(ref mut worker) => worker.drain(rdram),
+            Backend::Synchronous(_) => 0,
+        };
+        self.syncs_completed(completed, mi);
+
+        let result = match self.trace {
+            Some(ref mut trace) => trace.record_batch(words, rdram),
+            None => return,
+        };
+        if let Err(e) = result {
+            println!("WARNING: Stopping RDP trace: {}", e);
+            self.trace = None;
+        }
+    }
+
+    fn push_word(&mut self, word: u64, rdram: &mut [u8], mi: &mut MipsInterface) {
+        self.command_buffer.push(word);
+        if self.command_buffer.len() == command::length(self.command_buffer[0]) {
+            let words = mem::replace(&mut self.command_buffer, Vec::new());
+            self.execute(&words, rdram, mi);
+        }
+    }
+
+    fn execute(&mut self, words: &[u64], rdram: &mut [u8], mi: &mut MipsInterface) {
+        let command = match RdpCommand::from_word(words[0]) {
+            Some(command) => command,
+            None => {
+                println!("WARNING: Unrecognized RDP command: {:#018X}", words[0]);
+                return;
+            }
+        };
+
+        match (command, &mut self.backend) {
+            (RdpCommand::NoOp, _) |
+            (RdpCommand::SyncLoad, _) |
+            (RdpCommand::SyncPipe, _) |
+            (RdpCommand::SyncTile, _) => {}
+            (RdpCommand::SyncFull, &mut Backend::Synchronous(_)) => {
+                self.pipe_busy = false;
+                mi.raise_interrupt(Interrupt::Dp);
+            }
+            // The interrupt is raised once the worker is done
+            (RdpCommand::SyncFull, &mut Backend::Threaded(ref mut worker)) => worker.sync_full(),
+            (_, &mut Backend::Synchronous(ref mut bands)) => {
+                self.pipe_busy = true;
+                bands.execute(command, words, rdram);
+            }
+            (_, &mut Backend::Threaded(_)) => {
+                self.complete_syncs(rdram, mi, true);
+                self.pipe_busy = true;
+                if let Backend::Threaded(ref mut worker) = self.backend {
+                    worker.queue(command, words, rdram);
+                }
+            }
+        }
+    }
+
+    // Copies what the worker rendered until finished SYNC_FULLs to RDRAM
+    // and raises the DP interrupt for them
+    fn complete_syncs(&mut self, rdram: &mut [u8], mi: &mut MipsInterface, wait: bool) {
+        let completed = match self.backend {
+            Backend::Threaded(ref mut worker) => worker.complete_syncs(rdram, wait),
+            Backend::Synchronous(_) => 0,
+        };
+        self.syncs_completed(completed, mi);
+    }
+
+    fn syncs_completed(&mut self, completed: usize, mi: &mut MipsInterface) {
+        if let Backend::Threaded(ref worker) = self.backend {
+            if completed > 0 {
+                if worker.syncs_in_flight() == 0 {
+                    self.pipe_busy = false;
+                }
+                mi.raise_interrupt(Interrupt::Dp);
+            }
+        }
+    }
+
+    // Finishes the commands gathered for rendering in bands, or sends them
+    // to the worker
+    fn flush_backend(&mut self, rdram: &mut [u8]) {
+        match self.backend {
+            Backend::Synchronous(ref mut bands) => bands.flush(rdram),
+            Backend::Threaded(ref mut worker) => worker.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use super::super::test_scene::{self, COLOR_IMAGE};
+    use super::super::trace::{read_trace, replay_trace, TraceBatch};
+
+    const RDRAM_LENGTH: usize = 0x0020_0000;
+    const SYNC_FULL: u64 = 0x29 << 56;
+
+    struct Harness {
+        rdp: Rdp,
+        rdram: Vec<u8>,
+        mi: MipsInterface,
+    }
+
+    impl Harness {
+        fn new(mode: RdpMode) -> Harness {
+            let mut rdram = vec![0; RDRAM_LENGTH];
+            test_scene::write_texture(&mut rdram, 0);
+            Harness {
+                rdp: Rdp::new(mode, 2),
+                rdram: rdram,
+                mi: MipsInterface::new(),
+            }
+        }
+
+        fn submit(&mut self, words: &[u64]) {
+            self.rdp.submit_commands(words, &mut self.rdram, &mut self.mi);
+        }
+
+        // Submits a SYNC_FULL and waits for its interrupt
+        fn sync_full(&mut self) {
+            self.submit(&[SYNC_FULL]);
+            let timeout = Instant::now() + Duration::from_secs(10);
+            while self.mi.read_intr_reg() & (1 << 5) == 0 {
+                assert!(Instant::now() < timeout, "No DP interrupt after SYNC_FULL");
+                thread::sleep(Duration::from_millis(1));
+                self.rdp.step(&mut self.rdram, &[], &mut self.mi);
+            }
+            self.mi.clear_interrupt(Interrupt::Dp);
+        }
+    }
+
+    // Runs the same steps on a synchronous and a threaded RDP
+    fn both_modes<F: Fn(&mut Harness)>(steps: F) -> (Harness, Harness) {
+        let mut synchronous = Harness::new(RdpMode::Synchronous);
+        let mut threaded = Harness::new(RdpMode::Threaded);
+        steps(&mut synchronous);
+        steps(&mut threaded);
+        (synchronous, threaded)
+    }
+
+    #[test]
+    fn threaded_rendering_matches_synchronous() {
+        let (synchronous, threaded) = both_modes(|harness| {
+            harness.submit(&test_scene::scene());
+            harness.sync_full();
+        });
+        assert!(synchronous.rdram == threaded.rdram);
+    }
+
+    #[test]
+    fn textures_are_captured_for_every_load() {
+        let (synchronous, threaded) = both_modes(|harness| {
+            harness.submit(&test_scene::clear());
+            harness.submit(&test_scene::textured_rectangle(0, 0));
+            test_scene::write_texture(&mut harness.rdram, 1);
+            harness.submit(&test_scene::textured_rectangle(32, 0));
+            harness.sync_full();
+        });
+        assert!(synchronous.rdram == threaded.rdram);
+    }
+
+    #[test]
+    fn cpu_writes_next_to_drawn_pixels_survive_sync_full() {
+        // Pixel 20 of the first line, the rectangle covers pixels 0 to 15
+        let pixel = COLOR_IMAGE as usize + 40;
+        let (synchronous, threaded) = both_modes(|harness| {
+            harness.submit(&test_scene::clear());
+            harness.sync_full();
+            harness.submit(&test_scene::textured_rectangle(0, 0));
+            harness.rdram[pixel] = 0xab;
+            harness.rdram[pixel + 1] = 0xcd;
+            harness.sync_full();
+        });
+        assert_eq!(&threaded.rdram[pixel..pixel + 2], &[0xab, 0xcd]);
+        assert!(synchronous.rdram == threaded.rdram);
+    }
+
+    // Records the scene on a synchronous RDP. Returns the trace and the
+    // color image of the recorded run.
+    fn record_scene(name: &str) -> (Vec<TraceBatch>, (u32, u32, Vec<u8>)) {
+        let path = env::temp_dir().join(format!("rdp_trace_{}_{}.bin", name, process::id()));
+        let mut harness = Harness::new(RdpMode::Synchronous);
+        harness.rdp.start_trace(&path).unwrap();
+        harness.submit(&test_scene::scene());
+        harness.sync_full();
+        harness.rdp.stop_trace().unwrap();
+
+        let batches = read_trace(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        (batches, harness.rdp.read_color_image(&harness.rdram))
+    }
+
+    #[test]
+    fn replayed_traces_draw_the_recorded_image() {
+        let (batches, recorded) = record_scene("replay");
+        // The scene and the SYNC_FULL
+        assert_eq!(batches.len(), 2);
+        assert_eq!((recorded.0, recorded.1), (test_scene::WIDTH, test_scene::HEIGHT));
+        assert!(replay_trace(&batches, 1) == recorded);
+    }
+
+    #[test]
+    fn replayed_traces_are_the_same_for_any_render_thread_count() {
+        let (batches, _) = record_scene("threads");
+        let image = replay_trace(&batches, 1);
+        for &threads in &[2, 3, 8] {
+            assert!(replay_trace(&batches, threads) == image, "Image differs with {} render threads", threads);
+        }
+    }
+
+    #[test]
+    fn traces_match_between_modes() {
+        let trace = |mode| {
+            let path = env::temp_dir().join(format!("rdp_trace_{:?}_{}.bin", mode, process::id()));
+            let mut harness = Harness::new(mode);
+            harness.rdp.start_trace(&path).unwrap();
+            harness.submit(&test_scene::clear());
+            harness.submit(&test_scene::triangles(0));
+            harness.submit(&test_scene::textured_rectangle(40, 4));
+            harness.sync_full();
+            harness.rdp.stop_trace().unwrap();
+
+            let data = fs::read(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+            data
+        };
+        assert!(trace(RdpMode::Synchronous) == trace(RdpMode::Threaded));
+    }
+}
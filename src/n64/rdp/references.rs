@@ -0,0 +1,187 @@
+// Tracking of the RDRAM byte ranges RDP commands read and write, for
+// capturing traces and for feeding the RDP worker thread
+
+use std::cmp;
+
+use super::command::RdpCommand;
+use super::renderer::Image;
+
+// A copy of part of RDRAM
+#[derive(Debug, Clone)]
+pub struct RdramRegion {
+    pub addr: u32,
+    pub data: Vec<u8>,
+}
+
+impl RdramRegion {
+    pub fn capture(rdram: &[u8], start: usize, end: usize) -> RdramRegion {
+        let end = cmp::min(end, rdram.len());
+        let start = cmp::min(start, end);
+        RdramRegion {
+            addr: start as u32,
+            data: rdram[start..end].to_vec(),
+        }
+    }
+
+    pub fn apply(&self, rdram: &mut [u8]) {
+        let start = self.addr as usize;
+        if let Some(dst) = rdram.get_mut(start..start + self.data.len()) {
+            dst.copy_from_slice(&self.data);
+        }
+    }
+}
+
+// A byte range a command reads. Drawing commands also write to the color and
+// depth images they read.
+#[derive(Debug, Clone, Copy)]
+pub struct Reference {
+    pub start: usize,
+    pub end: usize,
+    pub written: bool,
+}
+
+// Follows the image setup commands, which decide on the ranges later
+// commands reference
+#[derive(Debug, Clone, Default)]
+pub struct ReferenceTracker {
+    texture_image: Image,
+    color_image: Image,
+    z_image_addr: u32,
+    // Bottom of the scissor box in 10.2, the last line drawn to
+    scissor_yl: u32,
+}
+
+impl ReferenceTracker {
+    // Adds the ranges of a complete command to `references`
+    pub fn command_references(&mut self, words: &[u64], references: &mut Vec<Reference>) {
+        let w0 = words[0];
+        let command = match RdpCommand::from_word(w0) {
+            Some(command) => command,
+            None => return,
+        };
+
+        let read = |start: usize, end: usize| Reference { start: start, end: end, written: false };
+        let written = |start: usize, end: usize| Reference { start: start, end: end, written: true };
+
+        match command {
+            RdpCommand::SetTextureImage => self.texture_image = Image::from_word(w0),
+            RdpCommand::SetColorImage => self.color_image = Image::from_word(w0),
+            RdpCommand::SetZImage => self.z_image_addr = (w0 & 0x03ff_ffff) as u32,
+            RdpCommand::SetScissor => self.scissor_yl = (w0 & 0xfff) as u32,
+
+            RdpCommand::LoadTile | RdpCommand::LoadTlut => {
+                let (sl, tl) = (((w0 >> 44) & 0xfff) as u32 >> 2, ((w0 >> 32) & 0xfff) as u32 >> 2);
+                let (sh, th) = (((w0 >> 12) & 0xfff) as u32 >> 2, (w0 & 0xfff) as u32 >> 2);
+                let th = if command == RdpCommand::LoadTlut { tl } else { th };
+                let (start, end) = self.texture_range(sl, tl, sh, th);
+                references.push(read(start, end));
+            }
+            RdpCommand::LoadBlock => {
+                let (sl, tl) = (((w0 >> 44) & 0xfff) as u32, ((w0 >> 32) & 0xfff) as u32);
+                let sh = ((w0 >> 12) & 0xfff) as u32;
+                let image = self.texture_image;
+                let start = image.addr + image.texel_offset(sl, tl);
+                let length = Image { width: sh.saturating_sub(sl) + 1, ..image }.texel_offset(0, 1);
+                references.push(read(start as usize, (start + length + 8) as usize));
+            }
+
+            RdpCommand::FillRectangle |
+            RdpCommand::TextureRectangle |
+            RdpCommand::TextureRectangleFlip |
+            RdpCommand::FillTriangle |
+            RdpCommand::TextureTriangle |
+            RdpCommand::ShadeTriangle |
+            RdpCommand::ShadeTextureTriangle => {
+                let (start, end) = self.color_image_range();
+                references.push(written(start, end));
+            }
+            RdpCommand::FillZbufferTriangle |
+            RdpCommand::TextureZbufferTriangle |
+            RdpCommand::ShadeZbufferTriangle |
+            RdpCommand::ShadeTextureZbufferTriangle => {
+                let (start, end) = self.color_image_range();
+                references.push(written(start, end));
+                let (start, end) = self.z_image_range();
+                references.push(written(start, end));
+            }
+            _ => {}
+        }
+    }
+
+    // Whole lines of the texture image, up to the end of the last texel
+    fn texture_range(&self, sl: u32, tl: u32, sh: u32, th: u32) -> (usize, usize) {
+        let image = self.texture_image;
+        let start = image.addr + image.texel_offset(sl, tl);
+        let end = image.addr + image.texel_offset(sh + 1, th) + 4;
+        (start as usize, end as usize)
+    }
+
+    // The ranges drawing commands write to with the current images and
+    // scissor box
+    pub fn drawn_ranges(&self) -> [(usize, usize); 2] {
+        [self.color_image_range(), self.z_image_range()]
+    }
+
+    fn color_image_range(&self) -> (usize, usize) {
+        let image = self.color_image;
+        let start = image.addr as usize;
+        (start, (image.addr + image.texel_offset(0, (self.scissor_yl >> 2) + 1)) as usize)
+    }
+
+    fn z_image_range(&self) -> (usize, usize) {
+        let lines = (self.scissor_yl >> 2) + 1;
+        let start = self.z_image_addr as usize;
+        (start, start + (self.color_image.width * lines * 2) as usize)
+    }
+}
+
+// A set of byte ranges, kept sorted and merged
+#[derive(Debug, Clone, Default)]
+pub struct Ranges {
+    ranges: Vec<(usize, usize)>,
+}
+
+impl Ranges {
+    pub fn insert(&mut self, start: usize, end: usize) {
+        if start >= end {
+            return;
+        }
+
+        let (mut start, mut end) = (start, end);
+        let mut merged = Vec::with_capacity(self.ranges.len() + 1);
+        for &(s, e) in &self.ranges {
+            if e < start || s > end {
+                merged.push((s, e));
+            } else {
+                start = cmp::min(start, s);
+                end = cmp::max(end, e);
+            }
+        }
+        let index = merged.iter().position(|&(s, _)| s > start).unwrap_or(merged.len());
+        merged.insert(index, (start, end));
+        self.ranges = merged;
+    }
+
+    // The parts of a range which aren't in the set
+    pub fn missing(&self, start: usize, end: usize) -> Vec<(usize, usize)> {
+        let mut missing = Vec::new();
+        let mut pos = start;
+        for &(s, e) in &self.ranges {
+            if e <= pos || s >= end {
+                continue;
+            }
+            if s > pos {
+                missing.push((pos, s));
+            }
+            pos = cmp::max(pos, e);
+        }
+        if pos < end {
+            missing.push((pos, end));
+        }
+        missing
+    }
+
+    pub fn clear(&mut self) {
+        self.ranges.clear();
+    }
+}
@@ -0,0 +1,935 @@
+use byteorder::{BigEndian, ByteOrder};
+
+use std::cmp;
+
+use super::bands::Memory;
+use super::blender::{self, Blender};
+use super::color::Color;
+use super::combiner::{self, Combiner};
+use super::command::RdpCommand;
+use super::tmem::{Filter, Sampling, Tlut, Tmem};
+use super::triangle::{Attributes, Bounds, Triangle};
+
+const SIZE_8: u32 = 1;
+const SIZE_16: u32 = 2;
+const SIZE_32: u32 = 3;
+
+// Other modes bits
+const OTHER_MODES_CYCLE_TYPE_SHIFT: u64 = 52;
+const OTHER_MODES_PERSPECTIVE: u64 = 1 << 51;
+const OTHER_MODES_TLUT_ENABLE: u64 = 1 << 47;
+const OTHER_MODES_TLUT_IA16: u64 = 1 << 46;
+const OTHER_MODES_BILINEAR: u64 = 1 << 45;
+const OTHER_MODES_MID_TEXEL: u64 = 1 << 44;
+const OTHER_MODES_KEY_ENABLE: u64 = 1 << 40;
+const OTHER_MODES_RGB_DITHER_SHIFT: u64 = 38;
+const OTHER_MODES_FORCE_BLEND: u64 = 1 << 14;
+const OTHER_MODES_ALPHA_CVG_SELECT: u64 = 1 << 13;
+const OTHER_MODES_CVG_TIMES_ALPHA: u64 = 1 << 12;
+const OTHER_MODES_Z_MODE_SHIFT: u64 = 10;
+const OTHER_MODES_CVG_DEST_SHIFT: u64 = 8;
+const OTHER_MODES_COLOR_ON_CVG: u64 = 1 << 7;
+const OTHER_MODES_IMAGE_READ: u64 = 1 << 6;
+const OTHER_MODES_Z_UPDATE: u64 = 1 << 5;
+const OTHER_MODES_Z_COMPARE: u64 = 1 << 4;
+const OTHER_MODES_ANTIALIAS: u64 = 1 << 3;
+const OTHER_MODES_Z_SOURCE_PRIM: u64 = 1 << 2;
+const OTHER_MODES_DITHER_ALPHA: u64 = 1 << 1;
+const OTHER_MODES_ALPHA_COMPARE: u64 = 1 << 0;
+
+// Coverage destination modes
+const CVG_DEST_CLAMP: u64 = 0;
+const CVG_DEST_WRAP: u64 = 1;
+const CVG_DEST_ZAP: u64 = 2;
+
+// Rectangles cover all 8 samples of their pixels
+const FULL_COVERAGE: u8 = 8;
+
+// 4x4 RGB dither thresholds
+const MAGIC_SQUARE: [u8; 16] = [0, 6, 1, 7, 4, 2, 5, 3, 3, 5, 2, 4, 7, 1, 6, 0];
+const BAYER: [u8; 16] = [0, 4, 1, 5, 4, 0, 5, 1, 3, 7, 2, 6, 7, 3, 6, 2];
+
+const Z_MODE_DECAL: u64 = 3;
+
+// Depth values are 18 bits wide, unsigned 15.3
+const Z_MAX: i32 = 0x3ffff;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CycleType {
+    OneCycle,
+    TwoCycle,
+    Copy,
+    Fill,
+}
+
+// Color, depth and texture images in RDRAM. The format field is left out,
+// only the pixel size matters when accessing them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Image {
+    pub size: u32,
+    // In pixels
+    pub width: u32,
+    pub addr: u32,
+}
+
+impl Image {
+    pub fn from_word(w0: u64) -> Image {
+        Image {
+            size: ((w0 >> 51) & 0b11) as u32,
+            width: ((w0 >> 32) & 0x3ff) as u32 + 1,
+            addr: (w0 & 0x03ff_ffff) as u32,
+        }
+    }
+
+    // Byte offset of a pixel, 4-bit pixels share a byte with their neighbour
+    pub fn texel_offset(&self, x: u32, y: u32) -> u32 {
+        let index = y * self.width + x;
+        match self.size {
+            0 => index >> 1,
+            size => index << (size - 1),
+        }
+    }
+}
+
+// Edges are given in 10.2 fixed point
+#[derive(Debug, Clone, Copy, Default)]
+struct Rectangle {
+    xh: u32,
+    yh: u32,
+    xl: u32,
+    yl: u32,
+}
+
+// The rendering state set up by commands, and the rasterizer drawing into
+// RDRAM with it
+#[derive(Debug, Clone)]
+pub struct Renderer {
+    color_image: Image,
+    z_image_addr: u32,
+    scissor: Rectangle,
+    other_modes: u64,
+    // The lines drawn, when rendering is split into bands
+    band: (u32, u32),
+
+    fill_color: u32,
+    prim_depth: u32,
+    prim_delta_z: u32,
+    // Changed for every primitive, so that noise differs between them but
+    // rendering stays deterministic
+    noise_seed: u32,
+
+    combiner: Combiner,
+    blender: Blender,
+    tmem: Tmem,
+}
+
+impl Renderer {
+    pub fn new() -> Renderer {
+        Renderer {
+            color_image: Image::default(),
+            z_image_addr: 0,
+            scissor: Rectangle::default(),
+            other_modes: 0,
+            band: (0, u32::MAX),
+
+            fill_color: 0,
+            prim_depth: 0,
+            prim_delta_z: 0,
+            noise_seed: 0,
+
+            combiner: Combiner::default(),
+            blender: Blender::default(),
+            tmem: Tmem::new(),
+        }
+    }
+
+    pub fn execute<M: Memory + ?Sized>(&mut self, command: RdpCommand, words: &[u64], rdram: &mut M) {
+        let w0 = words[0];
+        match command {
+            RdpCommand::SetColorImage => self.color_image = Image::from_word(w0),
+            RdpCommand::SetZImage => self.z_image_addr = (w0 & 0x03ff_ffff) as u32,
+            RdpCommand::SetScissor => {
+                self.scissor = Rectangle {
+                    xh: ((w0 >> 44) & 0xfff) as u32,
+                    yh: ((w0 >> 32) & 0xfff) as u32,
+                    xl: ((w0 >> 12) & 0xfff) as u32,
+                    yl: (w0 & 0xfff) as u32,
+                };
+            }
+            RdpCommand::SetOtherModes => {
+                self.other_modes = w0 & 0x00ff_ffff_ffff_ffff;
+                self.blender.set_other_modes(self.other_modes);
+            }
+
+            RdpCommand::SetFillColor => self.fill_color = w0 as u32,
+            RdpCommand::SetPrimColor => {
+                self.combiner.prim_color = Color::from_rgba32(w0 as u32);
+                self.combiner.prim_lod_fraction = (w0 >> 32) as u8;
+            }
+            RdpCommand::SetEnvColor => self.combiner.env_color = Color::from_rgba32(w0 as u32),
+            RdpCommand::SetBlendColor => self.blender.blend_color = Color::from_rgba32(w0 as u32),
+            RdpCommand::SetFogColor => self.blender.fog_color = Color::from_rgba32(w0 as u32),
+            RdpCommand::SetCombine => self.combiner.set_combine_mode(w0),
+            RdpCommand::SetKeyR => self.combiner.set_key_r(w0),
+            RdpCommand::SetKeyGb => self.combiner.set_key_gb(w0),
+            RdpCommand::SetConvert => self.combiner.set_convert(w0),
+            // The primitive depth is 15.0, the same as the integer part of
+            // the per-pixel depth
+            RdpCommand::SetPrimDepth => {
+                self.prim_depth = (((w0 >> 16) & 0x7fff) as u32) << 3;
+                self.prim_delta_z = (w0 & 0xffff) as u32;
+            }
+
+            RdpCommand::SetTextureImage => self.tmem.set_texture_image(Image::from_word(w0)),
+            RdpCommand::SetTile => self.tmem.set_tile(w0),
+            RdpCommand::SetTileSize => self.tmem.set_tile_size(w0),
+            RdpCommand::LoadTile => self.tmem.load_tile(w0, &*rdram),
+            RdpCommand::LoadBlock => self.tmem.load_block(w0, &*rdram),
+            RdpCommand::LoadTlut => self.tmem.load_tlut(w0, &*rdram),
+
+            RdpCommand::FillTriangle |
+            RdpCommand::FillZbufferTriangle |
+            RdpCommand::TextureTriangle |
+            RdpCommand::TextureZbufferTriangle |
+            RdpCommand::ShadeTriangle |
+            RdpCommand::ShadeZbufferTriangle |
+            RdpCommand::ShadeTextureTriangle |
+            RdpCommand::ShadeTextureZbufferTriangle => self.triangle(words, rdram),
+            RdpCommand::FillRectangle => self.fill_rectangle(w0, rdram),
+            RdpCommand::TextureRectangle => self.texture_rectangle(words, false, rdram),
+            RdpCommand::TextureRectangleFlip => self.texture_rectangle(words, true, rdram),
+
+            _ => println!("WARNING: Unimplemented RDP command: {:?}", command),
+        }
+    }
+
+    pub fn read_color_image(&self, rdram: &[u8]) -> (u32, u32, Vec<u8>) {
+        let (width, height) = (self.color_image.width, self.scissor.yl >> 2);
+        let mut data = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let (color, _) = self.read_pixel(rdram, x, y);
+                data.extend_from_slice(&[color.r, color.g, color.b, color.a]);
+            }
+        }
+        (width, height, data)
+    }
+
+    // The lines from the top of the scissor box up to its bottom edge
+    pub fn scissor_lines(&self) -> (u32, u32) {
+        (self.scissor.yh >> 2, self.scissor.yl >> 2)
+    }
+
+    pub fn set_band(&mut self, start: u32, end: u32) {
+        self.band = (start, end);
+    }
+
+    // The byte ranges of the color image, and of the Z image if asked for,
+    // holding the lines of the band
+    pub fn band_ranges(&self, z: bool) -> Vec<(usize, usize)> {
+        let (start, end) = self.band;
+        let image = self.color_image;
+        let mut ranges = vec![((image.addr + image.texel_offset(0, start)) as usize,
+                               (image.addr + image.texel_offset(0, end)) as usize)];
+        if z {
+            ranges.push((self.z_addr(0, start), self.z_addr(0, end)));
+        }
+        ranges
+    }
+
+    fn cycle_type(&self) -> CycleType {
+        match (self.other_modes >> OTHER_MODES_CYCLE_TYPE_SHIFT) & 0b11 {
+            0 => CycleType::OneCycle,
+            1 => CycleType::TwoCycle,
+            2 => CycleType::Copy,
+            _ => CycleType::Fill,
+        }
+    }
+
+    // Copy mode always point samples
+    fn sampling(&self) -> Sampling {
+        let filter = if self.cycle_type() == CycleType::Copy || (self.other_modes & OTHER_MODES_BILINEAR) == 0 {
+            Filter::Point
+        } else if (self.other_modes & OTHER_MODES_MID_TEXEL) != 0 {
+            Filter::Average
+        } else {
+            Filter::Bilinear
+        };
+        let tlut = if (self.other_modes & OTHER_MODES_TLUT_ENABLE) == 0 {
+            Tlut::Disabled
+        } else if (self.other_modes & OTHER_MODES_TLUT_IA16) != 0 {
+            Tlut::Ia16
+        } else {
+            Tlut::Rgba16
+        };
+
+        Sampling {
+            filter: filter,
+            tlut: tlut,
+        }
+    }
+
+    fn fill_rectangle<M: Memory + ?Sized>(&mut self, w0: u64, rdram: &mut M) {
+        let rect = Rectangle {
+            xl: ((w0 >> 44) & 0xfff) as u32,
+            yl: ((w0 >> 32) & 0xfff) as u32,
+            xh: ((w0 >> 12) & 0xfff) as u32,
+            yh: (w0 & 0xfff) as u32,
+        };
+
+        self.noise_seed = self.noise_seed.wrapping_add(1);
+        let cycle_type = self.cycle_type();
+        let (x0, y0, x1, y1) = self.clip(&rect, cycle_type);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                match cycle_type {
+                    CycleType::Fill => self.write_fill_pixel(rdram, x, y),
+                    _ => {
+                        let inputs = combiner::Inputs {
+                            noise: self.noise(x, y),
+                            ..combiner::Inputs::default()
+                        };
+                        self.draw_pixel(rdram, x, y, FULL_COVERAGE, &inputs);
+                    }
+                }
+            }
+        }
+    }
+
+    fn texture_rectangle<M: Memory + ?Sized>(&mut self, words: &[u64], flip: bool, rdram: &mut M) {
+        let (w0, w1) = (words[0], words[1]);
+        let rect = Rectangle {
+            xl: ((w0 >> 44) & 0xfff) as u32,
+            yl: ((w0 >> 32) & 0xfff) as u32,
+            xh: ((w0 >> 12) & 0xfff) as u32,
+            yh: (w0 & 0xfff) as u32,
+        };
+        let tile = ((w0 >> 24) & 0b111) as usize;
+
+        // S and T are s10.5, the deltas s5.10. Coordinates are tracked in
+        // 1/1024 texels.
+        let s = ((w1 >> 48) as u16 as i16 as i32) << 5;
+        let t = ((w1 >> 32) as u16 as i16 as i32) << 5;
+        let mut dsdx = (w1 >> 16) as u16 as i16 as i32;
+        let dtdy = w1 as u16 as i16 as i32;
+
+        self.noise_seed = self.noise_seed.wrapping_add(1);
+        let cycle_type = self.cycle_type();
+        let lod_fraction = lod_fraction(cmp::max(dsdx.abs(), dtdy.abs()) >> 5);
+        // Copy mode writes four pixels per clock
+        if cycle_type == CycleType::Copy {
+            dsdx >>= 2;
+        }
+
+        let sampling = self.sampling();
+        let (x0, y0, x1, y1) = self.clip(&rect, cycle_type);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let dx = x as i32 - (rect.xh >> 2) as i32;
+                let dy = y as i32 - (rect.yh >> 2) as i32;
+                // Flipped rectangles step S down and T across
+                let (ds, dt) = if flip { (dy * dsdx, dx * dtdy) } else { (dx * dsdx, dy * dtdy) };
+
+                let (s, t) = ((s + ds) >> 5, (t + dt) >> 5);
+                let texel0 = self.tmem.sample(tile, s, t, sampling);
+
+                // Copy mode writes texels as they are, the alpha compare
+                // only drops transparent ones
+                if cycle_type == CycleType::Copy {
+                    if (self.other_modes & OTHER_MODES_ALPHA_COMPARE) == 0 || texel0.a != 0 {
+                        self.write_pixel(rdram, x, y, texel0);
+                    }
+                    continue;
+                }
+
+                let inputs = combiner::Inputs {
+                    texel0: texel0,
+                    texel1: self.second_texel(tile, s, t, texel0),
+                    lod_fraction: lod_fraction,
+                    noise: self.noise(x, y),
+                    ..combiner::Inputs::default()
+                };
+                self.draw_pixel(rdram, x, y, FULL_COVERAGE, &inputs);
+            }
+        }
+    }
+
+    fn triangle<M: Memory + ?Sized>(&mut self, words: &[u64], rdram: &mut M) {
+        let triangle = Triangle::decode(words);
+        self.noise_seed = self.noise_seed.wrapping_add(1);
+        let cycle_type = self.cycle_type();
+        let sampling = self.sampling();
+        let (texture_dx, texture_dy) = triangle.texture_steps();
+
+        let bounds = Bounds {
+            x0: self.scissor.xh >> 2,
+            y0: cmp::max(self.scissor.yh >> 2, self.band.0),
+            x1: cmp::min(self.scissor.xl >> 2, self.color_image.width),
+            y1: cmp::min(self.scissor.yl >> 2, self.band.1),
+        };
+
+        let z_source_prim = (self.other_modes & OTHER_MODES_Z_SOURCE_PRIM) != 0;
+        let delta_z = if z_source_prim { self.prim_delta_z } else { triangle.delta_z() };
+
+        triangle.rasterize(&bounds, |x, y, coverage, attributes| {
+            if cycle_type == CycleType::Fill {
+                self.write_fill_pixel(rdram, x, y);
+                return;
+            }
+
+            let z = if z_source_prim { self.prim_depth } else { clamp_z(attributes.z >> 13) };
+            if triangle.has_z() && !self.depth_test(rdram, x, y, z, delta_z) {
+                return;
+            }
+
+            let mut inputs = combiner::Inputs {
+                noise: self.noise(x, y),
+                ..combiner::Inputs::default()
+            };
+            if triangle.has_shade() {
+                inputs.shade = shade_color(attributes);
+            }
+            if triangle.has_texture() {
+                // The LOD is the largest distance in texels to the
+                // neighbouring pixels
+                let (s, t) = self.texture_coords(&attributes.texture);
+                let step = |steps: &[i32; 3]| {
+                    let mut texture = attributes.texture;
+                    for i in 0..3 {
+                        texture[i] = texture[i].wrapping_add(steps[i]);
+                    }
+                    let (s1, t1) = self.texture_coords(&texture);
+                    cmp::max((s1 - s).abs(), (t1 - t).abs())
+                };
+                inputs.lod_fraction = lod_fraction(cmp::max(step(&texture_dx), step(&texture_dy)));
+                inputs.texel0 = self.tmem.sample(triangle.tile, s, t, sampling);
+                inputs.texel1 = self.second_texel(triangle.tile, s, t, inputs.texel0);
+            }
+
+            if !self.draw_pixel(rdram, x, y, coverage, &inputs) {
+                return;
+            }
+            if triangle.has_z() && (self.other_modes & OTHER_MODES_Z_UPDATE) != 0 {
+                write_u16(rdram, self.z_addr(x, y), compress_z(z));
+            }
+        });
+    }
+
+    // S and T are s10.5 texel coordinates. With perspective correction they
+    // are divided by W, which is normalized to 1.0 at 0x7fff.
+    fn texture_coords(&self, texture: &[i32; 3]) -> (i32, i32) {
+        let mut s = texture[0] >> 16;
+        let mut t = texture[1] >> 16;
+        if (self.other_modes & OTHER_MODES_PERSPECTIVE) != 0 {
+            let w = cmp::max(texture[2] >> 16, 1);
+            s = (s << 15) / w;
+            t = (t << 15) / w;
+        }
+        (s, t)
+    }
+
+    // The second cycle samples the next tile. In 1 cycle mode there is only
+    // one texel.
+    fn second_texel(&self, tile: usize, s: i32, t: i32, texel0: Color) -> Color {
+        match self.cycle_type() {
+            CycleType::TwoCycle => self.tmem.sample((tile + 1) & 0b111, s, t, self.sampling()),
+            _ => texel0,
+        }
+    }
+
+    // Returns whether the pixel passes the depth compare against the Z
+    // buffer. Decal mode only passes pixels at the depth already stored.
+    fn depth_test<M: Memory + ?Sized>(&self, rdram: &M, x: u32, y: u32, z: u32, delta_z: u32) -> bool {
+        if (self.other_modes & OTHER_MODES_Z_COMPARE) == 0 {
+            return true;
+        }
+
+        let addr = self.z_addr(x, y);
+        let stored = match rdram.bytes(addr, 2) {
+            Some(bytes) => decompress_z(BigEndian::read_u16(bytes)),
+            None => return false,
+        };
+
+        match (self.other_modes >> OTHER_MODES_Z_MODE_SHIFT) & 0b11 {
+            Z_MODE_DECAL => (z as i32 - stored as i32).abs() as u32 <= cmp::max(delta_z, 1),
+            _ => z < stored,
+        }
+    }
+
+    // The Z buffer has the same layout as the 16-bit color image
+    fn z_addr(&self, x: u32, y: u32) -> usize {
+        (self.z_image_addr + (y * self.color_image.width + x) * 2) as usize
+    }
+
+    // Runs a pixel of a 1 or 2 cycle primitive through the combiner, the
+    // alpha compare and the blender, and writes its color and coverage.
+    // Returns false for rejected pixels. In 1 cycle mode the combiner uses
+    // the settings of the second cycle and the blender those of the first.
+    fn draw_pixel<M: Memory + ?Sized>(&self, rdram: &mut M, x: u32, y: u32, coverage: u8, inputs: &combiner::Inputs) -> bool {
+        let two_cycle = self.cycle_type() == CycleType::TwoCycle;
+
+        let first = if two_cycle { self.combiner.combine(0, Color::default(), inputs) } else { Color::default() };
+        let mut color = self.combiner.combine(1, first, inputs);
+        if (self.other_modes & OTHER_MODES_KEY_ENABLE) != 0 {
+            color.a = self.combiner.key_alpha(1, first, inputs);
+        }
+
+        let mut coverage = coverage as u32;
+        if (self.other_modes & OTHER_MODES_CVG_TIMES_ALPHA) != 0 {
+            coverage = (color.a as u32 * coverage + 0x80) >> 8;
+        }
+        if coverage == 0 {
+            return false;
+        }
+        let alpha = if (self.other_modes & OTHER_MODES_ALPHA_CVG_SELECT) != 0 {
+            cmp::min(coverage << 5, 0xff) as u8
+        } else {
+            color.a
+        };
+
+        if (self.other_modes & OTHER_MODES_ALPHA_COMPARE) != 0 {
+            let threshold = if (self.other_modes & OTHER_MODES_DITHER_ALPHA) != 0 {
+                inputs.noise
+            } else {
+                self.blender.blend_color.a
+            };
+            if alpha < threshold {
+                return false;
+            }
+        }
+
+        let (memory, memory_coverage) = if (self.other_modes & OTHER_MODES_IMAGE_READ) != 0 {
+            self.read_pixel(rdram, x, y)
+        } else {
+            (Color::default(), 7)
+        };
+
+        // Coverage overflows when the pixel is fully covered by the new and
+        // the stored primitive together, partially covered pixels are edges
+        let sum = memory_coverage as u32 + coverage;
+        let overflow = sum >= 8;
+        let new_coverage = match (self.other_modes >> OTHER_MODES_CVG_DEST_SHIFT) & 0b11 {
+            CVG_DEST_CLAMP => cmp::min(sum, 7),
+            CVG_DEST_WRAP => sum & 7,
+            CVG_DEST_ZAP => 7,
+            _ => memory_coverage as u32,
+        };
+
+        let mut blend_inputs = blender::Inputs {
+            pixel: color,
+            alpha: alpha,
+            shade_alpha: inputs.shade.a,
+            memory: memory,
+            memory_coverage: memory_coverage,
+        };
+        let last_cycle = if two_cycle {
+            blend_inputs.pixel = self.blender.blend(0, &blend_inputs, false);
+            1
+        } else {
+            0
+        };
+
+        let blend = (self.other_modes & OTHER_MODES_FORCE_BLEND) != 0 ||
+                    ((self.other_modes & OTHER_MODES_ANTIALIAS) != 0 && !overflow);
+        let mut color = if (self.other_modes & OTHER_MODES_COLOR_ON_CVG) != 0 && !overflow {
+            memory
+        } else if blend {
+            self.blender.blend(last_cycle, &blend_inputs, true)
+        } else {
+            self.blender.pass(last_cycle, &blend_inputs)
+        };
+
+        if self.color_image.size == SIZE_16 {
+            color = self.dither(color, x, y, inputs.noise);
+        }
+        // The alpha bits of the color image hold the coverage
+        color.a = (new_coverage << 5) as u8;
+        self.write_pixel(rdram, x, y, color);
+        true
+    }
+
+    // Dithering rounds the channels up to the next 16-bit color value when
+    // their low bits are above the threshold of the pixel
+    fn dither(&self, color: Color, x: u32, y: u32, noise: u8) -> Color {
+        let index = ((y & 3) * 4 + (x & 3)) as usize;
+        let threshold = match (self.other_modes >> OTHER_MODES_RGB_DITHER_SHIFT) & 0b11 {
+            0 => MAGIC_SQUARE[index],
+            1 => BAYER[index],
+            2 => noise & 7,
+            _ => return color,
+        };
+
+        let channel = |c: u8| if (c & 7) > threshold && c < 0xf8 { c + 8 } else { c };
+        Color::new(channel(color.r), channel(color.g), channel(color.b), color.a)
+    }
+
+    // A hash of the pixel position and the primitive
+    fn noise(&self, x: u32, y: u32) -> u8 {
+        let mut hash = x.wrapping_mul(0x9e37_79b1) ^ y.wrapping_mul(0x85eb_ca77) ^ self.noise_seed.wrapping_mul(0xc2b2_ae3d);
+        hash ^= hash >> 15;
+        hash = hash.wrapping_mul(0x2c1b_3c6d);
+        hash ^= hash >> 12;
+        (hash >> 24) as u8
+    }
+
+    // Returns the pixel range covered by a rectangle within the scissor box,
+    // the color image and the band. Fill and copy mode include the lower
+    // right edge.
+    fn clip(&self, rect: &Rectangle, cycle_type: CycleType) -> (u32, u32, u32, u32) {
+        let end = |coord: u32| match cycle_type {
+            CycleType::Fill | CycleType::Copy => (coord >> 2) + 1,
+            _ => (coord + 3) >> 2,
+        };
+
+        let x0 = cmp::max(rect.xh >> 2, self.scissor.xh >> 2);
+        let y0 = cmp::max(cmp::max(rect.yh >> 2, self.scissor.yh >> 2), self.band.0);
+        let x1 = cmp::min(cmp::min(end(rect.xl), self.scissor.xl >> 2), self.color_image.width);
+        let y1 = cmp::min(cmp::min(end(rect.yl), self.scissor.yl >> 2), self.band.1);
+        (x0, y0, cmp::max(x0, x1), cmp::max(y0, y1))
+    }
+
+    // The fill color holds two 16-bit or four 8-bit pixels, picked by the
+    // pixel's position in the 32-bit word
+    fn write_fill_pixel<M: Memory + ?Sized>(&self, rdram: &mut M, x: u32, y: u32) {
+        let addr = (self.color_image.addr + self.color_image.texel_offset(x, y)) as usize;
+        match self.color_image.size {
+            SIZE_8 => {
+                let shift = 24 - (addr & 0b11) * 8;
+                write_u8(rdram, addr, (self.fill_color >> shift) as u8);
+            }
+            SIZE_16 => {
+                let shift = if (addr & 0b10) == 0 { 16 } else { 0 };
+                write_u16(rdram, addr, (self.fill_color >> shift) as u16);
+            }
+            SIZE_32 => write_u32(rdram, addr, self.fill_color),
+            size => println!("WARNING: Unsupported color image size for fill: {}", size),
+        }
+    }
+
+    // Returns the color and the 3-bit coverage of a pixel. 16-bit pixels
+    // only keep the top bit of the coverage.
+    fn read_pixel<M: Memory + ?Sized>(&self, rdram: &M, x: u32, y: u32) -> (Color, u8) {
+        let addr = (self.color_image.addr + self.color_image.texel_offset(x, y)) as usize;
+        match self.color_image.size {
+            SIZE_16 => match rdram.bytes(addr, 2) {
+                Some(bytes) => {
+                    let value = BigEndian::read_u16(bytes);
+                    (Color::from_rgba16(value), if (value & 1) != 0 { 7 } else { 0 })
+                }
+                None => (Color::default(), 0),
+            },
+            SIZE_32 => match rdram.bytes(addr, 4) {
+                Some(bytes) => {
+                    let color = Color::from_rgba32(BigEndian::read_u32(bytes));
+                    (color, color.a >> 5)
+                }
+                None => (Color::default(), 0),
+            },
+            _ => (Color::default(), 0),
+        }
+    }
+
+    fn write_pixel<M: Memory + ?Sized>(&self, rdram: &mut M, x: u32, y: u32, color: Color) {
+        let addr = (self.color_image.addr + self.color_image.texel_offset(x, y)) as usize;
+        match self.color_image.size {
+            SIZE_16 => write_u16(rdram, addr, color.to_rgba16()),
+            SIZE_32 => write_u32(rdram, addr, color.to_rgba32()),
+            size => println!("WARNING: Unsupported color image size: {}", size),
+        }
+    }
+}
+
+// Shade channels are s15.16 and clamped to 8 bits
+fn shade_color(attributes: &Attributes) -> Color {
+    let channel = |value: i32| cmp::min(cmp::max(value >> 16, 0), 0xff) as u8;
+    let shade = &attributes.shade;
+    Color::new(channel(shade[0]), channel(shade[1]), channel(shade[2]), channel(shade[3]))
+}
+
+// The LOD fraction is the position between two mipmap levels, the level
+// being the log2 of the texels per pixel (10.5). Magnified textures have none.
+fn lod_fraction(lod: i32) -> u8 {
+    if lod < 32 {
+        return 0;
+    }
+    let level = 31 - ((lod >> 5) as u32).leading_zeros();
+    (((lod << 3) >> level) & 0xff) as u8
+}
+
+fn clamp_z(z: i32) -> u32 {
+    cmp::min(cmp::max(z, 0), Z_MAX) as u32
+}
+
+// Depth is stored as a 14-bit floating point value: the exponent counts the
+// leading ones of the 18-bit depth, the mantissa keeps the 11 bits after
+// them. The low two bits of the word hold the delta Z, which isn't kept.
+fn compress_z(z: u32) -> u16 {
+    let exponent = cmp::min((!(z << 14)).leading_zeros(), 7);
+    let shift = if exponent < 7 { 6 - exponent } else { 0 };
+    let mantissa = (z >> shift) & 0x7ff;
+    ((exponent << 13) | (mantissa << 2)) as u16
+}
+
+fn decompress_z(value: u16) -> u32 {
+    let exponent = (value >> 13) as u32;
+    let mantissa = ((value >> 2) & 0x7ff) as u32;
+    let shift = if exponent < 7 { 6 - exponent } else { 0 };
+    let base = 0x40000 - (0x40000 >> exponent);
+    base + (mantissa << shift)
+}
+
+// Writes past the end of RDRAM are dropped
+fn write_u8<M: Memory + ?Sized>(rdram: &mut M, addr: usize, value: u8) {
+    if let Some(bytes) = rdram.bytes_mut(addr, 1) {
+        bytes[0] = value;
+    }
+}
+
+fn write_u16<M: Memory + ?Sized>(rdram: &mut M, addr: usize, value: u16) {
+    if let Some(bytes) = rdram.bytes_mut(addr, 2) {
+        BigEndian::write_u16(bytes, value);
+    }
+}
+
+fn write_u32<M: Memory + ?Sized>(rdram: &mut M, addr: usize, value: u32) {
+    if let Some(bytes) = rdram.bytes_mut(addr, 4) {
+        BigEndian::write_u32(bytes, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLOR_IMAGE: u32 = 0x1000;
+    const Z_IMAGE: u32 = 0x2000;
+    const TEXTURE: u32 = 0x3000;
+
+    fn execute(renderer: &mut Renderer, rdram: &mut [u8], words: &[u64]) {
+        let command = RdpCommand::from_word(words[0]).unwrap();
+        renderer.execute(command, words, rdram);
+    }
+
+    // An 8x8 color image with the scissor box around it
+    fn renderer(rdram: &mut [u8], size: u32, cycle_type: u64) -> Renderer {
+        let mut renderer = Renderer::new();
+        execute(&mut renderer, rdram, &[0x3f << 56 | (size as u64) << 51 | 7 << 32 | COLOR_IMAGE as u64]);
+        execute(&mut renderer, rdram, &[0x2d << 56 | 32 << 12 | 32]);
+        execute(&mut renderer, rdram, &[0x2f << 56 | cycle_type << OTHER_MODES_CYCLE_TYPE_SHIFT]);
+        renderer
+    }
+
+    // Edges in 10.2
+    fn rectangle(id: u64, xh: u64, yh: u64, xl: u64, yl: u64) -> u64 {
+        id << 56 | xl << 44 | yl << 32 | xh << 12 | yh
+    }
+
+    fn pixel16(rdram: &[u8], x: u32, y: u32) -> u16 {
+        BigEndian::read_u16(&rdram[(COLOR_IMAGE + (y * 8 + x) * 2) as usize..])
+    }
+
+    fn pixel32(rdram: &[u8], x: u32, y: u32) -> u32 {
+        BigEndian::read_u32(&rdram[(COLOR_IMAGE + (y * 8 + x) * 4) as usize..])
+    }
+
+    // Loads a 4x2 RGBA16 texture into tile 0
+    fn load_texture(renderer: &mut Renderer, rdram: &mut [u8]) -> [[u16; 4]; 2] {
+        let texels = [[0xf801, 0x07c1, 0x003f, 0xffff], [0x8421, 0x4211, 0x2109, 0x1085]];
+        for t in 0..2 {
+            for s in 0..4 {
+                BigEndian::write_u16(&mut rdram[(TEXTURE + (t * 4 + s) * 2) as usize..], texels[t as usize][s as usize]);
+            }
+        }
+        execute(renderer, rdram, &[0x3d << 56 | (SIZE_16 as u64) << 51 | 3 << 32 | TEXTURE as u64]);
+        execute(renderer, rdram, &[0x35 << 56 | (SIZE_16 as u64) << 51 | 1 << 41]);
+        execute(renderer, rdram, &[0x34 << 56 | 3 << 14 | 1 << 2]);
+        texels
+    }
+
+    #[test]
+    fn fill_rectangles_include_their_lower_right_edge() {
+        let mut rdram = vec![0; 0x2000];
+        let mut renderer = renderer(&mut rdram, SIZE_16, 3);
+        // Even pixels take the upper half of the fill color, odd ones the lower
+        execute(&mut renderer, &mut rdram, &[0x37 << 56 | 0xaaaa_5555]);
+        execute(&mut renderer, &mut rdram, &[rectangle(0x36, 1 << 2, 1 << 2, 3 << 2, 2 << 2)]);
+
+        for y in 0..8 {
+            for x in 0..8 {
+                let inside = x >= 1 && x <= 3 && y >= 1 && y <= 2;
+                let expected = match (inside, x & 1) {
+                    (false, _) => 0,
+                    (true, 0) => 0xaaaa,
+                    (true, _) => 0x5555,
+                };
+                assert_eq!(pixel16(&rdram, x, y), expected, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn fill_rectangles_write_the_whole_fill_color_to_32_bit_images() {
+        let mut rdram = vec![0; 0x2000];
+        let mut renderer = renderer(&mut rdram, SIZE_32, 3);
+        execute(&mut renderer, &mut rdram, &[0x37 << 56 | 0x1122_3344]);
+        execute(&mut renderer, &mut rdram, &[rectangle(0x36, 2 << 2, 0, 2 << 2, 7 << 2)]);
+
+        for y in 0..8 {
+            for x in 0..8 {
+                let expected = if x == 2 { 0x1122_3344 } else { 0 };
+                assert_eq!(pixel32(&rdram, x, y), expected, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn one_cycle_rectangles_exclude_their_lower_right_edge() {
+        let mut rdram = vec![0; 0x2000];
+        let mut renderer = renderer(&mut rdram, SIZE_32, 0);
+        execute(&mut renderer, &mut rdram, &[rectangle(0x36, 1 << 2, 1 << 2, 3 << 2, 2 << 2)]);
+
+        // The written pixels have full coverage in their alpha bits
+        for y in 0..8 {
+            for x in 0..8 {
+                let inside = x >= 1 && x < 3 && y == 1;
+                let expected = if inside { 0xe0 } else { 0 };
+                assert_eq!(pixel32(&rdram, x, y) & 0xff, expected, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn copy_mode_texture_rectangles_copy_texels() {
+        let mut rdram = vec![0; 0x4000];
+        let mut renderer = renderer(&mut rdram, SIZE_16, 2);
+        let texels = load_texture(&mut renderer, &mut rdram);
+        // From (2, 1) to (5, 2), one texel per pixel: DsDx is 4.0 in copy mode
+        let w0 = rectangle(0x24, 2 << 2, 1 << 2, 5 << 2, 2 << 2);
+        execute(&mut renderer, &mut rdram, &[w0, 0x1000 << 16 | 0x400]);
+
+        for y in 0..8 {
+            for x in 0..8 {
+                let expected = if x >= 2 && x <= 5 && y >= 1 && y <= 2 {
+                    texels[(y - 1) as usize][(x - 2) as usize]
+                } else {
+                    0
+                };
+                assert_eq!(pixel16(&rdram, x, y), expected, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn flipped_texture_rectangles_step_s_down_and_t_across() {
+        let mut rdram = vec![0; 0x4000];
+        let mut renderer = renderer(&mut rdram, SIZE_16, 2);
+        let texels = load_texture(&mut renderer, &mut rdram);
+        let w0 = rectangle(0x25, 2 << 2, 1 << 2, 3 << 2, 4 << 2);
+        execute(&mut renderer, &mut rdram, &[w0, 0x1000 << 16 | 0x400]);
+
+        for y in 0..8 {
+            for x in 0..8 {
+                let expected = if x >= 2 && x <= 3 && y >= 1 && y <= 4 {
+                    texels[(x - 2) as usize][(y - 1) as usize]
+                } else {
+                    0
+                };
+                assert_eq!(pixel16(&rdram, x, y), expected, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn depth_keeps_the_precision_of_its_exponent() {
+        assert_eq!(compress_z(0), 0);
+        assert_eq!(compress_z(0x20000), 1 << 13);
+        assert_eq!(compress_z(Z_MAX as u32), 0xfffc);
+
+        let mut last = 0;
+        for z in 0..(Z_MAX as u32 + 1) {
+            let compressed = compress_z(z);
+            assert!(compressed >= last, "{:05x}", z);
+            last = compressed;
+
+            // Each exponent halves the range and the bits dropped
+            let exponent = (compressed >> 13) as u32;
+            let dropped = if exponent < 7 { 6 - exponent } else { 0 };
+            assert_eq!(decompress_z(compressed), z & !((1 << dropped) - 1), "{:05x}", z);
+        }
+    }
+
+    #[test]
+    fn depth_compare_passes_closer_pixels_and_decals_at_the_same_depth() {
+        let mut rdram = vec![0; 0x4000];
+        let mut renderer = renderer(&mut rdram, SIZE_16, 0);
+        execute(&mut renderer, &mut rdram, &[0x3e << 56 | Z_IMAGE as u64]);
+        let z = 0x20000;
+        let addr = renderer.z_addr(1, 2);
+        write_u16(&mut rdram[..], addr, compress_z(z));
+
+        // Without the compare everything passes
+        assert!(renderer.depth_test(&rdram[..], 1, 2, z + 0x100, 0));
+
+        execute(&mut renderer, &mut rdram, &[0x2f << 56 | OTHER_MODES_Z_COMPARE]);
+        assert!(renderer.depth_test(&rdram[..], 1, 2, z - 1, 0));
+        assert!(!renderer.depth_test(&rdram[..], 1, 2, z, 0));
+        assert!(!renderer.depth_test(&rdram[..], 1, 2, z + 1, 0));
+
+        // Decals pass within the delta Z of the stored depth
+        execute(&mut renderer, &mut rdram, &[0x2f << 56 | Z_MODE_DECAL << OTHER_MODES_Z_MODE_SHIFT | OTHER_MODES_Z_COMPARE]);
+        assert!(renderer.depth_test(&rdram[..], 1, 2, z, 0));
+        assert!(renderer.depth_test(&rdram[..], 1, 2, z + 4, 4));
+        assert!(renderer.depth_test(&rdram[..], 1, 2, z - 4, 4));
+        assert!(!renderer.depth_test(&rdram[..], 1, 2, z + 5, 4));
+        assert!(!renderer.depth_test(&rdram[..], 1, 2, z - 0x100, 4));
+    }
+
+    // A 1 cycle fill rectangle over the whole image with the primitive color
+    fn prim_rectangle(renderer: &mut Renderer, rdram: &mut [u8], other_modes: u64, prim_color: u32) {
+        // 0 * 0 + PRIM for color and alpha
+        let combine = 8 << 37 | 16 << 32 | 8 << 24 | 3 << 6 | 7 << 21 | 7 << 18 | 7 << 3 | 3;
+        execute(renderer, rdram, &[0x3c << 56 | combine]);
+        execute(renderer, rdram, &[0x3a << 56 | prim_color as u64]);
+        execute(renderer, rdram, &[0x2f << 56 | other_modes]);
+        execute(renderer, rdram, &[rectangle(0x36, 0, 0, 8 << 2, 8 << 2)]);
+    }
+
+    #[test]
+    fn alpha_compare_drops_pixels_below_the_blend_alpha() {
+        let mut rdram = vec![0; 0x2000];
+        let mut renderer = renderer(&mut rdram, SIZE_32, 0);
+        execute(&mut renderer, &mut rdram, &[0x39 << 56 | 0x41]);
+
+        prim_rectangle(&mut renderer, &mut rdram, OTHER_MODES_ALPHA_COMPARE, 0x1122_3340);
+        assert!(rdram.iter().all(|&byte| byte == 0));
+
+        // Equal alpha passes, the alpha bits are replaced by the coverage
+        prim_rectangle(&mut renderer, &mut rdram, OTHER_MODES_ALPHA_COMPARE, 0x1122_3341);
+        for y in 0..8 {
+            for x in 0..8 {
+                assert_eq!(pixel32(&rdram, x, y), 0x1122_33e0, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn dithering_rounds_up_above_the_threshold_of_the_pixel() {
+        // Red is 1.5 steps of the 5-bit image
+        let red = |rdram: &[u8], x, y| pixel16(rdram, x, y) >> 11;
+        for &(mode, thresholds) in &[(0, Some(&MAGIC_SQUARE)), (1, Some(&BAYER)), (3, None)] {
+            let mut rdram = vec![0; 0x2000];
+            let mut renderer = renderer(&mut rdram, SIZE_16, 0);
+            prim_rectangle(&mut renderer, &mut rdram, mode << OTHER_MODES_RGB_DITHER_SHIFT, 0x0c00_00ff);
+
+            for y in 0..8 {
+                for x in 0..8 {
+                    let expected = match thresholds {
+                        Some(thresholds) if thresholds[((y & 3) * 4 + (x & 3)) as usize] < 4 => 2,
+                        _ => 1,
+                    };
+                    assert_eq!(red(&rdram, x, y), expected, "mode {} ({}, {})", mode, x, y);
+                }
+            }
+        }
+    }
+}
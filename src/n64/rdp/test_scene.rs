@@ -0,0 +1,230 @@
+// Command streams for the tests: a 64x48 scene of overlapping Z buffered
+// triangles and a textured rectangle, with the texture loaded in between.
+
+use byteorder::{BigEndian, ByteOrder};
+
+use super::command::{self, RdpCommand};
+
+pub const WIDTH: u32 = 64;
+pub const HEIGHT: u32 = 48;
+pub const COLOR_IMAGE: u32 = 0x0010_0000;
+pub const Z_IMAGE: u32 = 0x0011_0000;
+pub const TEXTURE: u32 = 0x0012_0000;
+pub const TEXTURE_SIZE: u32 = 16;
+
+// Both images are 16 bits per pixel
+pub const IMAGE_BYTES: usize = (WIDTH * HEIGHT * 2) as usize;
+
+const FORMAT_RGBA: u64 = 0;
+const SIZE_16: u64 = 2;
+
+const CYCLE_ONE: u64 = 0;
+const CYCLE_FILL: u64 = 3;
+const Z_COMPARE: u64 = 1 << 4;
+const Z_UPDATE: u64 = 1 << 5;
+
+// Combiner inputs, the same for both cycles
+const COMBINE_SHADE: ([u64; 4], [u64; 4]) = ([15, 15, 31, 4], [7, 7, 7, 4]);
+const COMBINE_TEXEL0: ([u64; 4], [u64; 4]) = ([15, 15, 31, 1], [7, 7, 7, 1]);
+
+// A 16x16 RGBA16 texture with a pattern depending on `seed`
+pub fn write_texture(rdram: &mut [u8], seed: u16) {
+    for t in 0..TEXTURE_SIZE {
+        for s in 0..TEXTURE_SIZE {
+            let texel = ((s * 0x0842 + t * 0x1084) as u16 ^ seed.wrapping_mul(0x9e37)) | 1;
+            let addr = (TEXTURE + (t * TEXTURE_SIZE + s) * 2) as usize;
+            BigEndian::write_u16(&mut rdram[addr..], texel);
+        }
+    }
+}
+
+// Clears both images, then draws triangles before and after loading the
+// texture for a rectangle
+pub fn scene() -> Vec<u64> {
+    let mut words = clear();
+    words.extend(triangles(0));
+    words.extend(textured_rectangle(40, 4));
+    words.extend(triangles(3));
+    words
+}
+
+pub fn clear() -> Vec<u64> {
+    vec![
+        set_scissor(0, 0, WIDTH, HEIGHT),
+        set_other_modes(CYCLE_FILL, 0),
+        set_color_image(Z_IMAGE),
+        set_fill_color(0xfffc_fffc),
+        fill_rectangle(0, 0, WIDTH - 1, HEIGHT - 1),
+        set_color_image(COLOR_IMAGE),
+        set_fill_color(0x0843_0843),
+        fill_rectangle(0, 0, WIDTH - 1, HEIGHT - 1),
+        set_z_image(Z_IMAGE),
+    ]
+}
+
+// Three overlapping triangles crossing every band, moved right by `offset`
+pub fn triangles(offset: u32) -> Vec<u64> {
+    let x = |x: u32| (x + offset) as f32;
+    let mut words = vec![
+        set_other_modes(CYCLE_ONE, Z_COMPARE | Z_UPDATE),
+        set_combine(COMBINE_SHADE),
+    ];
+    words.extend(shade_z_triangle([
+        [x(2), 1.0, 16000.0, 255.0, 0.0, 0.0],
+        [x(50), 20.0, 16000.0, 0.0, 255.0, 0.0],
+        [x(10), 46.0, 16000.0, 0.0, 0.0, 255.0],
+    ]));
+    words.extend(shade_z_triangle([
+        [x(30), 2.0, 30000.0, 255.0, 255.0, 0.0],
+        [x(5), 30.0, 2000.0, 0.0, 255.0, 255.0],
+        [x(58), 44.0, 12000.0, 255.0, 0.0, 255.0],
+    ]));
+    words.extend(shade_z_triangle([
+        [x(20), 5.5, 8000.0, 255.0, 255.0, 255.0],
+        [x(60), 10.25, 24000.0, 64.0, 128.0, 192.0],
+        [x(40), 47.0, 8000.0, 192.0, 128.0, 64.0],
+    ]));
+    words
+}
+
+// Loads the texture and draws it at (x, y), scaled up twice vertically
+pub fn textured_rectangle(x: u32, y: u32) -> Vec<u64> {
+    textured_rectangle_from(TEXTURE, TEXTURE_SIZE, x, y)
+}
+
+// The same with the texture loaded from the top left of an image
+pub fn textured_rectangle_from(image: u32, image_width: u32, x: u32, y: u32) -> Vec<u64> {
+    let last = TEXTURE_SIZE - 1;
+    vec![
+        set_texture_image(image, image_width),
+        set_tile(0, TEXTURE_SIZE * 2 / 8),
+        load_tile(0, last, last),
+        set_other_modes(CYCLE_ONE, 0),
+        set_combine(COMBINE_TEXEL0),
+        texture_rectangle(x, y, x + TEXTURE_SIZE, y + TEXTURE_SIZE * 2),
+        // S and T start at 0, one texel per pixel across and half down
+        (1 << 10) << 16 | 1 << 9,
+    ]
+}
+
+// Splits a stream of words into commands
+pub fn commands(words: &[u64]) -> Vec<(RdpCommand, &[u64])> {
+    let mut commands = Vec::new();
+    let mut rest = words;
+    while !rest.is_empty() {
+        let (current, next) = rest.split_at(command::length(rest[0]));
+        commands.push((RdpCommand::from_word(current[0]).unwrap(), current));
+        rest = next;
+    }
+    commands
+}
+
+fn set_color_image(addr: u32) -> u64 {
+    0x3f << 56 | FORMAT_RGBA << 53 | SIZE_16 << 51 | ((WIDTH - 1) as u64) << 32 | addr as u64
+}
+
+fn set_z_image(addr: u32) -> u64 {
+    0x3e << 56 | addr as u64
+}
+
+fn set_texture_image(addr: u32, width: u32) -> u64 {
+    0x3d << 56 | FORMAT_RGBA << 53 | SIZE_16 << 51 | ((width - 1) as u64) << 32 | addr as u64
+}
+
+fn set_scissor(x0: u32, y0: u32, x1: u32, y1: u32) -> u64 {
+    0x2d << 56 | ((x0 << 2) as u64) << 44 | ((y0 << 2) as u64) << 32 | ((x1 << 2) as u64) << 12 | (y1 << 2) as u64
+}
+
+fn set_other_modes(cycle_type: u64, low: u64) -> u64 {
+    0x2f << 56 | cycle_type << 52 | low
+}
+
+fn set_fill_color(color: u32) -> u64 {
+    0x37 << 56 | color as u64
+}
+
+fn set_combine(inputs: ([u64; 4], [u64; 4])) -> u64 {
+    let (rgb, alpha) = inputs;
+    0x3c << 56 |
+    rgb[0] << 52 | rgb[2] << 47 | alpha[0] << 44 | alpha[2] << 41 |
+    rgb[0] << 37 | rgb[2] << 32 | rgb[1] << 28 | rgb[1] << 24 |
+    alpha[0] << 21 | alpha[2] << 18 | rgb[3] << 15 | alpha[1] << 12 |
+    alpha[3] << 9 | rgb[3] << 6 | alpha[1] << 3 | alpha[3]
+}
+
+fn set_tile(tile: u64, line: u32) -> u64 {
+    0x35 << 56 | FORMAT_RGBA << 53 | SIZE_16 << 51 | (line as u64) << 41 | tile << 24
+}
+
+fn load_tile(tile: u64, sh: u32, th: u32) -> u64 {
+    0x34 << 56 | tile << 24 | ((sh << 2) as u64) << 12 | (th << 2) as u64
+}
+
+fn fill_rectangle(x0: u32, y0: u32, x1: u32, y1: u32) -> u64 {
+    0x36 << 56 | ((x1 << 2) as u64) << 44 | ((y1 << 2) as u64) << 32 | ((x0 << 2) as u64) << 12 | (y0 << 2) as u64
+}
+
+fn texture_rectangle(x0: u32, y0: u32, x1: u32, y1: u32) -> u64 {
+    0x24 << 56 | ((x1 << 2) as u64) << 44 | ((y1 << 2) as u64) << 32 | ((x0 << 2) as u64) << 12 | (y0 << 2) as u64
+}
+
+// A shaded, Z buffered triangle from X, Y, Z and RGB per vertex. Z is in the
+// 15-bit range of the Z buffer.
+fn shade_z_triangle(vertices: [[f32; 6]; 3]) -> Vec<u64> {
+    let mut v = vertices;
+    v.sort_by(|a, b| a[1].partial_cmp(&b[1]).unwrap());
+
+    let (hx, hy) = (v[2][0] - v[0][0], v[2][1] - v[0][1]);
+    let (mx, my) = (v[1][0] - v[0][0], v[1][1] - v[0][1]);
+    let (lx, ly) = (v[2][0] - v[1][0], v[2][1] - v[1][1]);
+    let nz = hx * my - hy * mx;
+    let left_major = nz < 0.0;
+
+    let slope = |dx: f32, dy: f32| if dy != 0.0 { dx / dy } else { 0.0 };
+    let (dxhdy, dxmdy, dxldy) = (slope(hx, hy), slope(mx, my), slope(lx, ly));
+
+    let (yh, ym, yl) = ((v[0][1] * 4.0).floor(), (v[1][1] * 4.0).floor(), (v[2][1] * 4.0).floor());
+    let start_y = v[0][1].floor() - v[0][1];
+    let xh = v[0][0] + start_y * dxhdy;
+    let xm = v[0][0] + start_y * dxmdy;
+    let xl = v[1][0] + (ym / 4.0 - v[1][1]) * dxldy;
+
+    let mut words = vec![
+        0x0d << 56 | (left_major as u64) << 55 |
+        (yl as i32 as u64 & 0x3fff) << 32 | (ym as i32 as u64 & 0x3fff) << 16 | (yh as i32 as u64 & 0x3fff),
+        (fixed(xl) as u64) << 32 | fixed(dxldy) as u64,
+        (fixed(xh) as u64) << 32 | fixed(dxhdy) as u64,
+        (fixed(xm) as u64) << 32 | fixed(dxmdy) as u64,
+    ];
+
+    // Start value on the major edge, then the change along x, along the
+    // major edge and along y
+    let gradient = |i: usize| {
+        let (ma, ha) = (v[1][i] - v[0][i], v[2][i] - v[0][i]);
+        let dx = (ma * hy - ha * my) / -nz;
+        let dy = (ha * mx - ma * hx) / -nz;
+        let de = dy + dx * dxhdy;
+        [v[0][i] + start_y * de, dx, de, dy]
+    };
+
+    let shade = [gradient(3), gradient(4), gradient(5), [255.0, 0.0, 0.0, 0.0]];
+    let pack = |which: usize, high: bool| {
+        shade.iter().fold(0u64, |word, attribute| {
+            let value = fixed(attribute[which]);
+            (word << 16) | if high { value >> 16 } else { value & 0xffff } as u64
+        })
+    };
+    words.extend_from_slice(&[
+        pack(0, true), pack(1, true), pack(0, false), pack(1, false),
+        pack(2, true), pack(3, true), pack(2, false), pack(3, false),
+    ]);
+
+    let z = gradient(2);
+    words.push((fixed(z[0]) as u64) << 32 | fixed(z[1]) as u64);
+    words.push((fixed(z[2]) as u64) << 32 | fixed(z[3]) as u64);
+    words
+}
+
+fn fixed(value: f32) -> u32 {
+    (value * 65536.0) as i32 as u32
+}
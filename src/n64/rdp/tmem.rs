@@ -0,0 +1,556 @@
+use byteorder::{BigEndian, ByteOrder};
+
+use std::cmp;
+
+use super::bands::Memory;
+use super::color::Color;
+use super::renderer::Image;
+
+const TMEM_SIZE: usize = 0x1000;
+
+// 32-bit and YUV texels are split, red and green (or chroma) are stored in
+// the low half of TMEM and blue and alpha (or luma) at the same offset in the
+// high half. The high half also holds the TLUT.
+const TMEM_HIGH_HALF: usize = 0x800;
+
+// Odd lines have the 32-bit halves of each 64-bit word swapped, so that
+// neighbouring lines can be read from different banks
+const ODD_LINE_SWAP: usize = 4;
+
+const FORMAT_RGBA: u32 = 0;
+const FORMAT_YUV: u32 = 1;
+const FORMAT_CI: u32 = 2;
+const FORMAT_IA: u32 = 3;
+const FORMAT_I: u32 = 4;
+
+const SIZE_4: u32 = 0;
+const SIZE_8: u32 = 1;
+const SIZE_16: u32 = 2;
+const SIZE_32: u32 = 3;
+
+// Wrapping masks wider than the largest texture are limited
+const MAX_MASK: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    Point,
+    Bilinear,
+    // Equal weights for the four texels
+    Average,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tlut {
+    Disabled,
+    Rgba16,
+    Ia16,
+}
+
+// Texture sampling state from the other modes
+#[derive(Debug, Clone, Copy)]
+pub struct Sampling {
+    pub filter: Filter,
+    pub tlut: Tlut,
+}
+
+// Coordinate handling along S or T
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TileAxis {
+    pub clamp: bool,
+    pub mirror: bool,
+    pub mask: u32,
+    pub shift: u32,
+
+    // 10.2 texel coordinates
+    pub low: u32,
+    pub high: u32,
+}
+
+impl TileAxis {
+    fn set(&mut self, bits: u64) {
+        self.clamp = (bits & (1 << 9)) != 0;
+        self.mirror = (bits & (1 << 8)) != 0;
+        self.mask = ((bits >> 4) & 0xf) as u32;
+        self.shift = (bits & 0xf) as u32;
+    }
+
+    // Turns an s10.5 coordinate into the tile relative coordinates of a
+    // texel and its neighbour, and the 5-bit fraction between them. Shifts
+    // of 11 and up shift left instead of right.
+    fn texels(&self, coord: i32) -> (u32, u32, u32) {
+        let shifted = if self.shift < 11 { coord >> self.shift } else { coord << (16 - self.shift) };
+        let mut coord = shifted - ((self.low << 3) as i32);
+
+        // Without a mask the tile is always clamped
+        let clamp = self.clamp || self.mask == 0;
+        let max = (self.high as i32 - self.low as i32) << 3;
+        if clamp {
+            coord = cmp::max(cmp::min(coord, cmp::max(max, 0)), 0);
+        }
+
+        let fraction = (coord & 0x1f) as u32;
+        let first = coord >> 5;
+        let second = if clamp { cmp::min(first + 1, cmp::max(max >> 5, 0)) } else { first + 1 };
+        (self.wrap(first), self.wrap(second), fraction)
+    }
+
+    // Mirroring flips every other repetition of the masked range
+    fn wrap(&self, coord: i32) -> u32 {
+        let coord = coord as u32;
+        if self.mask == 0 {
+            return coord & 0x3ff;
+        }
+
+        let mask = cmp::min(self.mask, MAX_MASK);
+        let flip = self.mirror && ((coord >> mask) & 1) != 0;
+        let coord = if flip { !coord } else { coord };
+        coord & ((1 << mask) - 1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tile {
+    pub format: u32,
+    pub size: u32,
+    // Both in 64-bit words
+    pub line: u32,
+    pub tmem_addr: u32,
+    pub palette: u32,
+
+    pub s: TileAxis,
+    pub t: TileAxis,
+}
+
+impl Tile {
+    fn line_start(&self, line: u32) -> usize {
+        (self.tmem_addr * 8 + line * self.line * 8) as usize
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Tmem {
+    data: Box<[u8]>,
+    tiles: [Tile; 8],
+    texture_image: Image,
+}
+
+impl Tmem {
+    pub fn new() -> Tmem {
+        Tmem {
+            data: vec![0; TMEM_SIZE].into_boxed_slice(),
+            tiles: [Tile::default(); 8],
+            texture_image: Image::default(),
+        }
+    }
+
+    pub fn set_texture_image(&mut self, image: Image) {
+        self.texture_image = image;
+    }
+
+    pub fn set_tile(&mut self, w0: u64) {
+        let tile = &mut self.tiles[((w0 >> 24) & 0b111) as usize];
+        tile.format = ((w0 >> 53) & 0b111) as u32;
+        tile.size = ((w0 >> 51) & 0b11) as u32;
+        tile.line = ((w0 >> 41) & 0x1ff) as u32;
+        tile.tmem_addr = ((w0 >> 32) & 0x1ff) as u32;
+        tile.palette = ((w0 >> 20) & 0xf) as u32;
+        tile.t.set(w0 >> 10);
+        tile.s.set(w0);
+    }
+
+    pub fn set_tile_size(&mut self, w0: u64) {
+        let tile = &mut self.tiles[((w0 >> 24) & 0b111) as usize];
+        tile.s.low = ((w0 >> 44) & 0xfff) as u32;
+        tile.t.low = ((w0 >> 32) & 0xfff) as u32;
+        tile.s.high = ((w0 >> 12) & 0xfff) as u32;
+        tile.t.high = (w0 & 0xfff) as u32;
+    }
+
+    // Copies a rectangle of the texture image to the tile's TMEM area, one
+    // line of the tile per row
+    pub fn load_tile<M: Memory + ?Sized>(&mut self, w0: u64, rdram: &M) {
+        self.set_tile_size(w0);
+        let tile = self.tiles[((w0 >> 24) & 0b111) as usize];
+        let image = self.texture_image;
+
+        let (s0, s1) = (tile.s.low >> 2, tile.s.high >> 2);
+        let (t0, t1) = (tile.t.low >> 2, tile.t.high >> 2);
+        for t in t0..(t1 + 1) {
+            let line_start = tile.line_start(t - t0);
+            let odd = ((t - t0) & 1) != 0;
+            for s in s0..(s1 + 1) {
+                let src = image.addr + image.texel_offset(s, t);
+                self.load_texel(&tile, image.size, rdram, src, line_start, s - s0, odd);
+            }
+        }
+    }
+
+    // Copies consecutive texels of the texture image to the tile's TMEM
+    // area. SH is the index of the last texel, DXT (1.11) the fraction of a
+    // line each 64-bit word advances, which decides on the odd-line swap.
+    pub fn load_block<M: Memory + ?Sized>(&mut self, w0: u64, rdram: &M) {
+        let tile = self.tiles[((w0 >> 24) & 0b111) as usize];
+        let image = self.texture_image;
+
+        let sl = ((w0 >> 44) & 0xfff) as u32;
+        let tl = ((w0 >> 32) & 0xfff) as u32;
+        let sh = ((w0 >> 12) & 0xfff) as u32;
+        let dxt = (w0 & 0xfff) as u32;
+
+        let start = image.addr + image.texel_offset(sl, tl);
+        let line_start = tile.line_start(0);
+        for i in 0..(sh.saturating_sub(sl) + 1) {
+            let offset = image.texel_offset(i, 0);
+            let odd = ((((offset >> 3) * dxt) >> 11) & 1) != 0;
+            self.load_texel(&tile, image.size, rdram, start + offset, line_start, i, odd);
+        }
+    }
+
+    // Copies 16-bit palette entries to the tile's TMEM area. Every entry is
+    // stored four times, once for each of the texels a filter may look up.
+    pub fn load_tlut<M: Memory + ?Sized>(&mut self, w0: u64, rdram: &M) {
+        self.set_tile_size(w0);
+        let tile = self.tiles[((w0 >> 24) & 0b111) as usize];
+        let image = self.texture_image;
+
+        let (s0, s1) = (tile.s.low >> 2, tile.s.high >> 2);
+        let t = tile.t.low >> 2;
+        let read = |offset: u32| rdram.bytes(offset as usize, 1).map_or(0, |bytes| bytes[0]);
+        for s in s0..(s1 + 1) {
+            let src = image.addr + image.texel_offset(s, t);
+            let dst = tile.line_start(0) + (s - s0) as usize * 8;
+            for copy in 0..4 {
+                self.write(dst + copy * 2, read(src));
+                self.write(dst + copy * 2 + 1, read(src + 1));
+            }
+        }
+    }
+
+    fn load_texel<M: Memory + ?Sized>(&mut self, tile: &Tile, size: u32, rdram: &M, src: u32, line_start: usize, index: u32, odd: bool) {
+        let read = |offset: u32| rdram.bytes(offset as usize, 1).map_or(0, |bytes| bytes[0]);
+        let swap = if odd { ODD_LINE_SWAP } else { 0 };
+        match size {
+            SIZE_32 => {
+                let dst = (line_start + index as usize * 2) ^ swap;
+                self.write_split(dst, read(src), read(src + 2));
+                self.write_split(dst + 1, read(src + 1), read(src + 3));
+            }
+            // Chroma goes to the low half, luma to the high half
+            SIZE_16 if tile.format == FORMAT_YUV => {
+                let dst = (line_start + index as usize) ^ swap;
+                self.write_split(dst, read(src), read(src + 1));
+            }
+            // 4-bit texels are loaded in pairs
+            SIZE_4 => if (index & 1) == 0 {
+                self.write((line_start + (index >> 1) as usize) ^ swap, read(src));
+            },
+            _ => {
+                let bytes = 1 << (size - 1);
+                for i in 0..bytes {
+                    self.write((line_start + (index * bytes + i) as usize) ^ swap, read(src + i));
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, offset: usize, value: u8) {
+        self.data[offset & (TMEM_SIZE - 1)] = value;
+    }
+
+    fn write_split(&mut self, offset: usize, low: u8, high: u8) {
+        let offset = offset & (TMEM_HIGH_HALF - 1);
+        self.data[offset] = low;
+        self.data[offset + TMEM_HIGH_HALF] = high;
+    }
+
+    fn read(&self, offset: usize) -> u8 {
+        self.data[offset & (TMEM_SIZE - 1)]
+    }
+
+    fn read_u16(&self, offset: usize) -> u16 {
+        BigEndian::read_u16(&self.data[offset & (TMEM_SIZE - 2)..])
+    }
+
+    // Samples the texture of a tile at s10.5 texel coordinates
+    pub fn sample(&self, tile_index: usize, s: i32, t: i32, sampling: Sampling) -> Color {
+        let tile = &self.tiles[tile_index];
+        let (s0, s1, sf) = tile.s.texels(s);
+        let (t0, t1, tf) = tile.t.texels(t);
+
+        match sampling.filter {
+            Filter::Point => self.texel(tile, s0, t0, sampling.tlut),
+            Filter::Bilinear | Filter::Average => {
+                let (sf, tf) = if sampling.filter == Filter::Average { (16, 16) } else { (sf, tf) };
+                let texels = [
+                    (self.texel(tile, s0, t0, sampling.tlut), (32 - sf) * (32 - tf)),
+                    (self.texel(tile, s1, t0, sampling.tlut), sf * (32 - tf)),
+                    (self.texel(tile, s0, t1, sampling.tlut), (32 - sf) * tf),
+                    (self.texel(tile, s1, t1, sampling.tlut), sf * tf),
+                ];
+                let channel = |get: fn(&Color) -> u8| {
+                    let sum = texels.iter().fold(0, |sum, &(ref color, weight)| sum + get(color) as u32 * weight);
+                    ((sum + 512) >> 10) as u8
+                };
+                Color::new(channel(|c| c.r), channel(|c| c.g), channel(|c| c.b), channel(|c| c.a))
+            }
+        }
+    }
+
+    // Decodes the texel at tile relative integer coordinates
+    fn texel(&self, tile: &Tile, s: u32, t: u32, tlut: Tlut) -> Color {
+        let swap = if (t & 1) != 0 { ODD_LINE_SWAP } else { 0 };
+        let line = tile.line_start(t);
+        let s = s as usize;
+
+        match (tile.format, tile.size) {
+            (FORMAT_RGBA, SIZE_32) => {
+                let offset = ((line + s * 2) ^ swap) & (TMEM_HIGH_HALF - 1);
+                let rg = self.read_u16(offset);
+                let ba = self.read_u16(offset + TMEM_HIGH_HALF);
+                Color::new((rg >> 8) as u8, rg as u8, (ba >> 8) as u8, ba as u8)
+            }
+            (FORMAT_YUV, SIZE_16) => {
+                // Texel pairs share their chroma, U comes first
+                let low = |offset: usize| self.read(((line + offset) ^ swap) & (TMEM_HIGH_HALF - 1));
+                let u = low(s & !1);
+                let v = low(s | 1);
+                let y = self.read((((line + s) ^ swap) & (TMEM_HIGH_HALF - 1)) + TMEM_HIGH_HALF);
+                yuv_to_rgb(y, u, v)
+            }
+            (FORMAT_CI, SIZE_4) | (FORMAT_CI, SIZE_8) => {
+                let index = match tile.size {
+                    SIZE_4 => (tile.palette << 4) | self.nibble(line, s, swap) as u32,
+                    _ => self.read((line + s) ^ swap) as u32,
+                };
+                self.palette_color(index, tlut)
+            }
+            (FORMAT_IA, SIZE_4) => {
+                let value = self.nibble(line, s, swap);
+                let i = value >> 1;
+                let i = (i << 5) | (i << 2) | (i >> 1);
+                Color::new(i, i, i, if (value & 1) != 0 { 0xff } else { 0 })
+            }
+            (FORMAT_IA, SIZE_8) => {
+                let value = self.read((line + s) ^ swap);
+                let i = (value >> 4) * 0x11;
+                Color::new(i, i, i, (value & 0xf) * 0x11)
+            }
+            (FORMAT_IA, SIZE_16) => {
+                let value = self.read_u16((line + s * 2) ^ swap);
+                let i = (value >> 8) as u8;
+                Color::new(i, i, i, value as u8)
+            }
+            (FORMAT_I, SIZE_4) => {
+                let i = self.nibble(line, s, swap) * 0x11;
+                Color::new(i, i, i, i)
+            }
+            (FORMAT_I, SIZE_8) => {
+                let i = self.read((line + s) ^ swap);
+                Color::new(i, i, i, i)
+            }
+            // Everything else, including the formats without a defined
+            // size combination, is read as RGBA16
+            _ => Color::from_rgba16(self.read_u16((line + s * 2) ^ swap)),
+        }
+    }
+
+    // The even texel of a pair is in the high nibble
+    fn nibble(&self, line: usize, s: usize, swap: usize) -> u8 {
+        let value = self.read((line + (s >> 1)) ^ swap);
+        if (s & 1) == 0 { value >> 4 } else { value & 0xf }
+    }
+
+    // Without a TLUT the color index is returned as an intensity
+    fn palette_color(&self, index: u32, tlut: Tlut) -> Color {
+        let entry = || self.read_u16(TMEM_HIGH_HALF + (index & 0xff) as usize * 8);
+        match tlut {
+            Tlut::Rgba16 => Color::from_rgba16(entry()),
+            Tlut::Ia16 => {
+                let value = entry();
+                let i = (value >> 8) as u8;
+                Color::new(i, i, i, value as u8)
+            }
+            Tlut::Disabled => {
+                let i = index as u8;
+                Color::new(i, i, i, i)
+            }
+        }
+    }
+}
+
+// BT.601 conversion with the coefficients the microcode libraries load by
+// default
+fn yuv_to_rgb(y: u8, u: u8, v: u8) -> Color {
+    let y = y as i32;
+    let u = u as i32 - 128;
+    let v = v as i32 - 128;
+    let clamp = |value: i32| cmp::max(cmp::min(value, 0xff), 0) as u8;
+    Color::new(clamp(y + ((v * 1436) >> 10)),
+               clamp(y - ((u * 352 + v * 731) >> 10)),
+               clamp(y + ((u * 1815) >> 10)),
+               0xff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXTURE: u32 = 0x100;
+
+    // Tile 0 with lines of one 64-bit word at the start of TMEM
+    fn with_tile(format: u32, size: u32, palette: u32) -> Tmem {
+        let mut tmem = Tmem::new();
+        tmem.set_tile((format as u64) << 53 | (size as u64) << 51 | 1 << 41 | (palette as u64) << 20);
+        tmem
+    }
+
+    fn texel(tmem: &Tmem, s: u32, t: u32, tlut: Tlut) -> Color {
+        tmem.texel(&tmem.tiles[0], s, t, tlut)
+    }
+
+    fn gray(i: u8, a: u8) -> Color {
+        Color::new(i, i, i, a)
+    }
+
+    // S and T from 0.0 to 3.0, clamped or wrapped with a mask
+    fn axis(clamp: bool, mirror: bool, mask: u32) -> TileAxis {
+        TileAxis {
+            clamp: clamp,
+            mirror: mirror,
+            mask: mask,
+            shift: 0,
+            low: 0,
+            high: 3 << 2,
+        }
+    }
+
+    // The first texel sampled at each integer coordinate
+    fn texels(axis: &TileAxis, coords: ::std::ops::Range<i32>) -> Vec<u32> {
+        coords.map(|coord| axis.texels(coord << 5).0).collect()
+    }
+
+    #[test]
+    fn texels_are_decoded_by_format_and_size() {
+        let mut tmem = with_tile(FORMAT_RGBA, SIZE_16, 0);
+        tmem.data[0..2].copy_from_slice(&[0xf8, 0x01]);
+        assert_eq!(texel(&tmem, 0, 0, Tlut::Disabled), Color::new(0xff, 0, 0, 0xff));
+
+        // Red and green in the low half, blue and alpha in the high half
+        let mut tmem = with_tile(FORMAT_RGBA, SIZE_32, 0);
+        tmem.data[0..2].copy_from_slice(&[0x12, 0x34]);
+        tmem.data[TMEM_HIGH_HALF..TMEM_HIGH_HALF + 2].copy_from_slice(&[0x56, 0x78]);
+        assert_eq!(texel(&tmem, 0, 0, Tlut::Disabled), Color::new(0x12, 0x34, 0x56, 0x78));
+
+        let mut tmem = with_tile(FORMAT_IA, SIZE_4, 0);
+        tmem.data[0] = 0xe3;
+        assert_eq!(texel(&tmem, 0, 0, Tlut::Disabled), gray(0xff, 0));
+        assert_eq!(texel(&tmem, 1, 0, Tlut::Disabled), gray(0x24, 0xff));
+
+        let mut tmem = with_tile(FORMAT_IA, SIZE_8, 0);
+        tmem.data[0] = 0x5a;
+        assert_eq!(texel(&tmem, 0, 0, Tlut::Disabled), gray(0x55, 0xaa));
+
+        let mut tmem = with_tile(FORMAT_IA, SIZE_16, 0);
+        tmem.data[0..2].copy_from_slice(&[0x80, 0x40]);
+        assert_eq!(texel(&tmem, 0, 0, Tlut::Disabled), gray(0x80, 0x40));
+
+        let mut tmem = with_tile(FORMAT_I, SIZE_4, 0);
+        tmem.data[0] = 0x7c;
+        assert_eq!(texel(&tmem, 0, 0, Tlut::Disabled), gray(0x77, 0x77));
+        assert_eq!(texel(&tmem, 1, 0, Tlut::Disabled), gray(0xcc, 0xcc));
+
+        let mut tmem = with_tile(FORMAT_I, SIZE_8, 0);
+        tmem.data[0] = 0x42;
+        assert_eq!(texel(&tmem, 0, 0, Tlut::Disabled), gray(0x42, 0x42));
+
+        // Chroma pairs in the low half, luma in the high half
+        let mut tmem = with_tile(FORMAT_YUV, SIZE_16, 0);
+        tmem.data[0..2].copy_from_slice(&[0x80, 0xff]);
+        tmem.data[TMEM_HIGH_HALF] = 0x80;
+        assert_eq!(texel(&tmem, 0, 0, Tlut::Disabled), Color::new(0xff, 38, 0x80, 0xff));
+
+        // Without a TLUT the index is the intensity
+        let mut tmem = with_tile(FORMAT_CI, SIZE_8, 0);
+        tmem.data[0] = 0x42;
+        assert_eq!(texel(&tmem, 0, 0, Tlut::Disabled), gray(0x42, 0x42));
+    }
+
+    #[test]
+    fn odd_lines_are_stored_with_their_words_halves_swapped() {
+        let mut rdram = vec![0; 0x200];
+        for i in 0..16 {
+            rdram[TEXTURE as usize + i] = i as u8;
+        }
+        let mut tmem = with_tile(FORMAT_RGBA, SIZE_16, 0);
+        tmem.set_texture_image(Image { size: SIZE_16, width: 4, addr: TEXTURE });
+        tmem.load_tile(3 << 14 | 1 << 2, &rdram[..]);
+
+        assert_eq!(&tmem.data[0..8], &[0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(&tmem.data[8..16], &[12, 13, 14, 15, 8, 9, 10, 11]);
+        for t in 0..2 {
+            for s in 0..4 {
+                let offset = (t * 8 + s * 2) as u16;
+                let expected = Color::from_rgba16(offset << 8 | (offset + 1));
+                assert_eq!(texel(&tmem, s, t, Tlut::Disabled), expected, "({}, {})", s, t);
+            }
+        }
+    }
+
+    #[test]
+    fn coordinates_are_clamped_wrapped_or_mirrored() {
+        assert_eq!(texels(&axis(true, false, 2), -2..6), vec![0, 0, 0, 1, 2, 3, 3, 3]);
+        assert_eq!(texels(&axis(false, false, 2), -2..6), vec![2, 3, 0, 1, 2, 3, 0, 1]);
+        assert_eq!(texels(&axis(false, true, 2), -2..10), vec![1, 0, 0, 1, 2, 3, 3, 2, 1, 0, 0, 1]);
+        // Without a mask the tile is clamped
+        assert_eq!(texels(&axis(false, false, 0), 2..6), vec![2, 3, 3, 3]);
+
+        // The neighbour for filtering wraps as well
+        assert_eq!(axis(false, false, 2).texels(3 << 5 | 8), (3, 0, 8));
+        assert_eq!(axis(true, false, 2).texels(3 << 5 | 8), (3, 3, 0));
+    }
+
+    #[test]
+    fn coordinates_are_shifted_and_offset_by_the_tile() {
+        let mut axis = axis(false, false, 3);
+        axis.shift = 1;
+        assert_eq!(texels(&axis, 0..6), vec![0, 0, 1, 1, 2, 2]);
+        // Shifts of 11 and up shift left
+        axis.shift = 15;
+        assert_eq!(texels(&axis, 0..4), vec![0, 2, 4, 6]);
+
+        axis.shift = 0;
+        axis.low = 2 << 2;
+        assert_eq!(texels(&axis, 2..6), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn color_indexes_are_looked_up_in_the_tlut() {
+        let mut rdram = vec![0; 0x200];
+        let palette: Vec<u16> = (0..16).map(|i| 0x1111 * i as u16).collect();
+        for (i, &entry) in palette.iter().enumerate() {
+            BigEndian::write_u16(&mut rdram[TEXTURE as usize + i * 2..], entry);
+        }
+
+        // Palette 1 is the second 16 entries of the TLUT
+        let mut tmem = with_tile(FORMAT_CI, SIZE_4, 1);
+        tmem.set_texture_image(Image { size: SIZE_16, width: 16, addr: TEXTURE });
+        tmem.set_tile(7 << 24 | (FORMAT_RGBA as u64) << 53 | (SIZE_16 as u64) << 51 | (0x100 + 16) << 32);
+        tmem.load_tlut(7 << 24 | 15 << 14, &rdram[..]);
+
+        // Every entry is stored four times
+        let entry = TMEM_HIGH_HALF + 17 * 8;
+        assert_eq!(&tmem.data[entry..entry + 8], &[0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11]);
+
+        tmem.data[0] = 0x1f;
+        assert_eq!(texel(&tmem, 0, 0, Tlut::Rgba16), Color::from_rgba16(palette[1]));
+        assert_eq!(texel(&tmem, 1, 0, Tlut::Rgba16), Color::from_rgba16(palette[15]));
+        assert_eq!(texel(&tmem, 1, 0, Tlut::Ia16), gray(0xff, 0xff));
+        assert_eq!(texel(&tmem, 1, 0, Tlut::Disabled), gray(0x1f, 0x1f));
+
+        // 8-bit indexes leave out the palette
+        let tile = &mut tmem.tiles[0];
+        tile.size = SIZE_8;
+        tmem.data[0] = 0x15;
+        assert_eq!(texel(&tmem, 0, 0, Tlut::Rgba16), Color::from_rgba16(palette[5]));
+    }
+}
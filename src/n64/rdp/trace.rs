@@ -0,0 +1,260 @@
+// Recording of the command batches the RDP executes, together with the
+// RDRAM regions those commands read, so that they can be replayed without
+// the rest of the system.
+//
+// A trace starts with TRACE_MAGIC and a version word. Every batch follows
+// as a region count, the regions as address, length and data, then a word
+// count and the command words. All values are big endian.
+
+use byteorder::{BigEndian, ByteOrder};
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::mem;
+use std::path::Path;
+
+use n64::mem_map::RDRAM_LENGTH;
+use n64::MipsInterface;
+
+use super::command;
+use super::references::{RdramRegion, ReferenceTracker};
+use super::rdp::{Rdp, RdpMode};
+
+const TRACE_MAGIC: &'static [u8; 8] = b"RDPTRACE";
+const TRACE_VERSION: u32 = 1;
+
+// The regions are copies of RDRAM taken before the batch ran
+#[derive(Debug, Clone)]
+pub struct TraceBatch {
+    pub regions: Vec<RdramRegion>,
+    pub commands: Vec<u64>,
+}
+
+impl TraceBatch {
+    // Restores the regions the batch references
+    pub fn apply(&self, rdram: &mut [u8]) {
+        for region in &self.regions {
+            region.apply(rdram);
+        }
+    }
+}
+
+pub fn read_trace<P: AsRef<Path>>(path: P) -> io::Result<Vec<TraceBatch>> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+
+    let mut reader = TraceReader { data: &data, pos: 0 };
+    if reader.bytes(TRACE_MAGIC.len())? != TRACE_MAGIC {
+        return Err(invalid_data("Not an RDP trace"));
+    }
+    let version = reader.u32()?;
+    if version != TRACE_VERSION {
+        return Err(invalid_data(&format!("Unsupported RDP trace version {}", version)));
+    }
+
+    let mut batches = Vec::new();
+    while reader.pos < data.len() {
+        let mut regions = Vec::new();
+        for _ in 0..reader.u32()? {
+            let addr = reader.u32()?;
+            let length = reader.u32()? as usize;
+            regions.push(RdramRegion {
+                addr: addr,
+                data: reader.bytes(length)?.to_vec(),
+            });
+        }
+
+        let mut commands = Vec::new();
+        for _ in 0..reader.u32()? {
+            commands.push(reader.u64()?);
+        }
+
+        batches.push(TraceBatch {
+            regions: regions,
+            commands: commands,
+        });
+    }
+    Ok(batches)
+}
+
+// Runs the batches of a trace on a synchronous RDP, in an RDRAM of their
+// own, and returns the color image they leave
+pub fn replay_trace(batches: &[TraceBatch], render_threads: usize) -> (u32, u32, Vec<u8>) {
+    let mut rdram = vec![0; RDRAM_LENGTH as usize];
+    let mut rdp = Rdp::new(RdpMode::Synchronous, render_threads);
+    let mut mi = MipsInterface::new();
+    for batch in batches {
+        batch.apply(&mut rdram);
+        rdp.submit_commands(&batch.commands, &mut rdram, &mut mi);
+    }
+    rdp.read_color_image(&rdram)
+}
+
+struct TraceReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> TraceReader<'a> {
+    fn bytes(&mut self, length: usize) -> io::Result<&'a [u8]> {
+        let data = self.data;
+        match data.get(self.pos..self.pos + length) {
+            Some(bytes) => {
+                self.pos += length;
+                Ok(bytes)
+            }
+            None => Err(invalid_data("Truncated RDP trace")),
+        }
+    }
+
+    // Values are assembled bytewise, they aren't aligned within the trace
+    fn u32(&mut self) -> io::Result<u32> {
+        self.u64_bytes(4).map(|value| value as u32)
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        self.u64_bytes(8)
+    }
+
+    fn u64_bytes(&mut self, length: usize) -> io::Result<u64> {
+        self.bytes(length).map(|bytes| bytes.iter().fold(0, |value, &byte| (value << 8) | byte as u64))
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[derive(Debug)]
+pub struct TraceWriter {
+    writer: BufWriter<File>,
+
+    // Words of a command split across batches
+    pending: Vec<u64>,
+    references: ReferenceTracker,
+}
+
+impl TraceWriter {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<TraceWriter> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(TRACE_MAGIC)?;
+        writer.write_all(&be_u32(TRACE_VERSION))?;
+
+        Ok(TraceWriter {
+            writer: writer,
+
+            pending: Vec::new(),
+            references: ReferenceTracker::default(),
+        })
+    }
+
+    // Writes a batch with the RDRAM regions its commands reference. Has to
+    // be called before the commands run, so the regions are captured as the
+    // commands will see them.
+    pub fn record_batch(&mut self, commands: &[u64], rdram: &[u8]) -> io::Result<()> {
+        let mut references = Vec::new();
+        for &word in commands {
+            self.pending.push(word);
+            if self.pending.len() == command::length(self.pending[0]) {
+                let words = mem::replace(&mut self.pending, Vec::new());
+                self.references.command_references(&words, &mut references);
+            }
+        }
+
+        let mut seen = HashSet::new();
+        references.retain(|reference| seen.insert((reference.start, reference.end)));
+
+        self.writer.write_all(&be_u32(references.len() as u32))?;
+        for reference in &references {
+            let region = RdramRegion::capture(rdram, reference.start, reference.end);
+            self.writer.write_all(&be_u32(region.addr))?;
+            self.writer.write_all(&be_u32(region.data.len() as u32))?;
+            self.writer.write_all(&region.data)?;
+        }
+
+        self.writer.write_all(&be_u32(commands.len() as u32))?;
+        for &word in commands {
+            let mut bytes = [0; 8];
+            BigEndian::write_u64(&mut bytes, word);
+            self.writer.write_all(&bytes)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn be_u32(value: u32) -> [u8; 4] {
+    let mut bytes = [0; 4];
+    BigEndian::write_u32(&mut bytes, value);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use super::*;
+
+    fn read(name: &str, data: &[u8]) -> io::Result<Vec<TraceBatch>> {
+        let path = env::temp_dir().join(format!("rdp_trace_{}_{}.bin", name, process::id()));
+        fs::write(&path, data).unwrap();
+        let result = read_trace(&path);
+        fs::remove_file(&path).unwrap();
+        result
+    }
+
+    // One batch, with a region of 4 bytes at 0x100 and 2 command words
+    fn trace() -> Vec<u8> {
+        let mut data = TRACE_MAGIC.to_vec();
+        data.extend_from_slice(&be_u32(TRACE_VERSION));
+        data.extend_from_slice(&be_u32(1));
+        data.extend_from_slice(&be_u32(0x100));
+        data.extend_from_slice(&be_u32(4));
+        data.extend_from_slice(&[1, 2, 3, 4]);
+        data.extend_from_slice(&be_u32(2));
+        data.extend_from_slice(&[0x29, 0, 0, 0, 0, 0, 0, 0]);
+        data.extend_from_slice(&[0, 1, 2, 3, 4, 5, 6, 7]);
+        data
+    }
+
+    #[test]
+    fn batches_are_read_and_applied() {
+        let batches = read("batches", &trace()).unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].commands, vec![0x2900_0000_0000_0000, 0x0001_0203_0405_0607]);
+
+        let mut rdram = vec![0; 0x200];
+        batches[0].apply(&mut rdram);
+        assert_eq!(&rdram[0xfe..0x106], &[0, 0, 1, 2, 3, 4, 0, 0]);
+    }
+
+    #[test]
+    fn truncated_traces_are_rejected() {
+        let data = trace();
+        // Cut off right after the header the trace is complete, with no
+        // batches
+        let header = TRACE_MAGIC.len() + 4;
+        assert_eq!(read("empty", &data[..header]).unwrap().len(), 0);
+        for length in (0..data.len()).filter(|&length| length != header) {
+            let error = read("truncated", &data[..length]).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{} bytes", length);
+        }
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        let mut data = trace();
+        data[0] = b'X';
+        assert_eq!(read("magic", &data).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let mut data = trace();
+        data[TRACE_MAGIC.len() + 3] = TRACE_VERSION as u8 + 1;
+        assert_eq!(read("version", &data).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
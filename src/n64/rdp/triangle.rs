@@ -0,0 +1,293 @@
+// Edge walking for the triangle commands. Edges and attributes are walked
+// in fixed point exactly as they are given by the command, 8 coverage
+// samples per pixel are taken on 4 subscanlines.
+
+// Horizontal sample positions in 1/65536 pixels, even and odd subscanlines
+// are offset from each other
+const SAMPLE_OFFSETS: [[i64; 2]; 2] = [[0x0000, 0x8000], [0x4000, 0xc000]];
+
+// Pixel range to draw into, the ends are exclusive
+#[derive(Debug, Clone, Copy)]
+pub struct Bounds {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+// Start value and gradients of up to four s15.16 attributes: along x, along
+// the major edge and along y
+#[derive(Debug, Clone, Copy, Default)]
+struct Coefficients {
+    start: [i32; 4],
+    dx: [i32; 4],
+    de: [i32; 4],
+    dy: [i32; 4],
+}
+
+impl Coefficients {
+    // Integer and fractional halves are stored in separate words
+    fn decode(words: &[u64]) -> Coefficients {
+        let combine = |int: u64, frac: u64, i: usize| {
+            let shift = 48 - i * 16;
+            ((int >> shift) as u16 as u32) << 16 | (frac >> shift) as u16 as u32
+        };
+
+        let mut coefficients = Coefficients::default();
+        for i in 0..4 {
+            coefficients.start[i] = combine(words[0], words[2], i) as i32;
+            coefficients.dx[i] = combine(words[1], words[3], i) as i32;
+            coefficients.de[i] = combine(words[4], words[6], i) as i32;
+            coefficients.dy[i] = combine(words[5], words[7], i) as i32;
+        }
+        coefficients
+    }
+
+    fn at(&self, i: usize, dx: i64, dy: i64) -> i32 {
+        (self.start[i] as i64 + self.de[i] as i64 * dy + ((self.dx[i] as i64 * dx) >> 16)) as i32
+    }
+}
+
+// Interpolated s15.16 attributes of a pixel
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Attributes {
+    pub shade: [i32; 4],
+    // S, T and W
+    pub texture: [i32; 3],
+    pub z: i32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Triangle {
+    // The major edge is the left edge of the triangle
+    left_major: bool,
+    pub tile: usize,
+
+    // s11.2
+    yh: i32,
+    ym: i32,
+    yl: i32,
+
+    // s15.16 positions and slopes per scanline. XH and XM are given at the
+    // scanline containing YH, XL at YM.
+    xh: i32,
+    dxhdy: i32,
+    xm: i32,
+    dxmdy: i32,
+    xl: i32,
+    dxldy: i32,
+
+    shade: Option<Coefficients>,
+    texture: Option<Coefficients>,
+    // Start value, d/dx, d/de and d/dy
+    z: Option<[i32; 4]>,
+}
+
+impl Triangle {
+    // The low three bits of the command id select the shade, texture and
+    // depth coefficient blocks following the edges
+    pub fn decode(words: &[u64]) -> Triangle {
+        let w0 = words[0];
+        let id = (w0 >> 56) & 0b111;
+        let signed_y = |value: u64| (((value & 0x3fff) << 2) as u16 as i16 >> 2) as i32;
+        let edge = |word: u64| ((word >> 32) as i32, word as u32 as i32);
+
+        let (xl, dxldy) = edge(words[1]);
+        let (xh, dxhdy) = edge(words[2]);
+        let (xm, dxmdy) = edge(words[3]);
+
+        let mut rest = &words[4..];
+        let mut take = |count: usize| {
+            let (block, remaining) = rest.split_at(count);
+            rest = remaining;
+            block
+        };
+        let shade = if (id & 0b100) != 0 { Some(Coefficients::decode(take(8))) } else { None };
+        let texture = if (id & 0b010) != 0 { Some(Coefficients::decode(take(8))) } else { None };
+        let z = if (id & 0b001) != 0 {
+            let block = take(2);
+            Some([(block[0] >> 32) as i32, block[0] as u32 as i32, (block[1] >> 32) as i32, block[1] as u32 as i32])
+        } else {
+            None
+        };
+
+        Triangle {
+            left_major: (w0 & (1 << 55)) != 0,
+            tile: ((w0 >> 48) & 0b111) as usize,
+
+            yl: signed_y(w0 >> 32),
+            ym: signed_y(w0 >> 16),
+            yh: signed_y(w0),
+
+            xh: xh,
+            dxhdy: dxhdy,
+            xm: xm,
+            dxmdy: dxmdy,
+            xl: xl,
+            dxldy: dxldy,
+
+            shade: shade,
+            texture: texture,
+            z: z,
+        }
+    }
+
+    pub fn has_shade(&self) -> bool {
+        self.shade.is_some()
+    }
+
+    pub fn has_texture(&self) -> bool {
+        self.texture.is_some()
+    }
+
+    pub fn has_z(&self) -> bool {
+        self.z.is_some()
+    }
+
+    // Steps of S, T and W for one pixel along x and along y
+    pub fn texture_steps(&self) -> ([i32; 3], [i32; 3]) {
+        match self.texture {
+            Some(ref texture) => ([texture.dx[0], texture.dx[1], texture.dx[2]],
+                                  [texture.dy[0], texture.dy[1], texture.dy[2]]),
+            None => ([0; 3], [0; 3]),
+        }
+    }
+
+    // Largest change of depth over a pixel, in the 18-bit depth format
+    pub fn delta_z(&self) -> u32 {
+        self.z.map(|z| ((z[1] as i64).abs() + (z[3] as i64).abs()) >> 13).unwrap_or(0) as u32
+    }
+
+    // Calls `pixel` with the pixel position, coverage (1-8) and attributes
+    // of every pixel within the bounds touched by the triangle
+    pub fn rasterize<F>(&self, bounds: &Bounds, mut pixel: F)
+        where F: FnMut(u32, u32, u8, &Attributes)
+    {
+        if bounds.x0 >= bounds.x1 {
+            return;
+        }
+
+        // Edges are walked from the top of the scanline containing YH
+        let top = self.yh >> 2;
+        let first_line = top.max(bounds.y0 as i32);
+        let last_line = ((self.yl + 3) >> 2).min(bounds.y1 as i32);
+
+        let width = (bounds.x1 - bounds.x0) as usize;
+        let mut coverage = vec![0u8; width];
+
+        for y in first_line..last_line {
+            for c in coverage.iter_mut() {
+                *c = 0;
+            }
+
+            let mut any = false;
+            for sub in 0..4 {
+                let ys = y * 4 + sub;
+                if ys < self.yh || ys >= self.yl {
+                    continue;
+                }
+
+                let major = self.xh as i64 + ((self.dxhdy as i64 * (ys - top * 4) as i64) >> 2);
+                let minor = if ys < self.ym {
+                    self.xm as i64 + ((self.dxmdy as i64 * (ys - top * 4) as i64) >> 2)
+                } else {
+                    self.xl as i64 + ((self.dxldy as i64 * (ys - self.ym) as i64) >> 2)
+                };
+                let (left, right) = if self.left_major { (major, minor) } else { (minor, major) };
+                if left >= right {
+                    continue;
+                }
+
+                let start = ((left >> 16).max(bounds.x0 as i64)) as u32;
+                let end = (((right + 0xffff) >> 16).min(bounds.x1 as i64)) as u32;
+                for x in start..end {
+                    for &offset in &SAMPLE_OFFSETS[(sub & 1) as usize] {
+                        let sample = ((x as i64) << 16) + offset;
+                        if sample >= left && sample < right {
+                            coverage[(x - bounds.x0) as usize] += 1;
+                            any = true;
+                        }
+                    }
+                }
+            }
+
+            if !any {
+                continue;
+            }
+
+            // Attributes are stepped along the major edge, then across
+            let dy = (y - top) as i64;
+            let major = self.xh as i64 + self.dxhdy as i64 * dy;
+            for (i, &samples) in coverage.iter().enumerate() {
+                if samples == 0 {
+                    continue;
+                }
+                let x = bounds.x0 + i as u32;
+                let dx = ((x as i64) << 16) - major;
+
+                let mut attributes = Attributes::default();
+                if let Some(ref shade) = self.shade {
+                    for c in 0..4 {
+                        attributes.shade[c] = shade.at(c, dx, dy);
+                    }
+                }
+                if let Some(ref texture) = self.texture {
+                    for c in 0..3 {
+                        attributes.texture[c] = texture.at(c, dx, dy);
+                    }
+                }
+                if let Some(z) = self.z {
+                    attributes.z = (z[0] as i64 + z[2] as i64 * dy + ((z[1] as i64 * dx) >> 16)) as i32;
+                }
+
+                pixel(x, y as u32, samples, &attributes);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edge(x: i32, dxdy: i32) -> u64 {
+        (x as u32 as u64) << 32 | dxdy as u32 as u64
+    }
+
+    // Coverage of the pixels touched, by line
+    fn coverage(triangle: &Triangle, bounds: &Bounds) -> Vec<Vec<(u32, u8)>> {
+        let mut lines = vec![Vec::new(); bounds.y1 as usize];
+        triangle.rasterize(bounds, |x, y, coverage, _| lines[y as usize].push((x, coverage)));
+        lines
+    }
+
+    // A right triangle with the major edge at x = 0 and the minor edge
+    // running from (4, 0) to (0, 4)
+    fn right_triangle() -> Triangle {
+        let w0 = 0x08 << 56 | 1 << 55 | 16 << 32 | 16 << 16;
+        Triangle::decode(&[w0, edge(0, 0), edge(0, 0), edge(4 << 16, -0x10000)])
+    }
+
+    #[test]
+    fn pixels_on_the_diagonal_are_half_covered() {
+        let bounds = Bounds { x0: 0, y0: 0, x1: 8, y1: 8 };
+        let lines = coverage(&right_triangle(), &bounds);
+
+        assert_eq!(lines[0], vec![(0, 8), (1, 8), (2, 8), (3, 4)]);
+        assert_eq!(lines[1], vec![(0, 8), (1, 8), (2, 4)]);
+        assert_eq!(lines[2], vec![(0, 8), (1, 4)]);
+        assert_eq!(lines[3], vec![(0, 4)]);
+        assert!(lines[4..].iter().all(|line| line.is_empty()));
+    }
+
+    #[test]
+    fn pixels_outside_the_bounds_are_skipped() {
+        let bounds = Bounds { x0: 1, y0: 1, x1: 2, y1: 8 };
+        let lines = coverage(&right_triangle(), &bounds);
+
+        assert!(lines[0].is_empty());
+        assert_eq!(lines[1], vec![(1, 8)]);
+        assert_eq!(lines[2], vec![(1, 4)]);
+        assert!(lines[3..].iter().all(|line| line.is_empty()));
+    }
+}
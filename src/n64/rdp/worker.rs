@@ -0,0 +1,213 @@
+// Rendering on a worker thread.
+//
+// The emulation thread keeps owning RDRAM. The worker renders into a mirror
+// of its own, which is fed with copies of the ranges commands read, taken
+// when the commands are queued. Ranges the RDP writes to are only copied
+// once between two SYNC_FULLs: after that the mirror holds what the RDP
+// itself wrote there, which is newer than RDRAM. Everything else, such as
+// textures, is copied on every reference, as the CPU may change it between
+// commands. At SYNC_FULL the worker sends back the bytes it wrote, which
+// are copied to RDRAM before the DP interrupt is raised. Queueing commands
+// while a SYNC_FULL is still in flight waits for it, so no copy is taken
+// from RDRAM before the RDP's writes have arrived there.
+
+use std::mem;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+
+use n64::mem_map::RDRAM_LENGTH;
+
+use super::command::RdpCommand;
+use super::references::{RdramRegion, Ranges, ReferenceTracker};
+use super::bands::Bands;
+
+#[derive(Debug)]
+enum Request {
+    Commands {
+        // Ranges the worker doesn't have yet
+        regions: Vec<RdramRegion>,
+        commands: Vec<(RdpCommand, Vec<u64>)>,
+    },
+    SyncFull,
+    // Sends back what was written so far, without a SYNC_FULL
+    Drain,
+}
+
+// The bytes written since the last SYNC_FULL or drain
+#[derive(Debug)]
+struct Written {
+    regions: Vec<RdramRegion>,
+    sync_full: bool,
+}
+
+#[derive(Debug)]
+pub struct Worker {
+    // Dropped to stop the thread
+    requests: Option<Sender<Request>>,
+    completed: Receiver<Written>,
+    thread: Option<JoinHandle<()>>,
+
+    references: ReferenceTracker,
+    // Ranges the RDP writes to, copied to the worker since the last
+    // SYNC_FULL
+    owned: Ranges,
+    pending: Vec<(RdpCommand, Vec<u64>)>,
+    pending_regions: Vec<RdramRegion>,
+    syncs_in_flight: usize,
+}
+
+impl Worker {
+    pub fn new(render_threads: usize) -> Worker {
+        let (request_sender, request_receiver) = mpsc::channel();
+        let (completed_sender, completed_receiver) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("rdp".into())
+            .spawn(move || run(request_receiver, completed_sender, render_threads))
+            .unwrap();
+
+        Worker {
+            requests: Some(request_sender),
+            completed: completed_receiver,
+            thread: Some(thread),
+
+            references: ReferenceTracker::default(),
+            owned: Ranges::default(),
+            pending: Vec::new(),
+            pending_regions: Vec::new(),
+            syncs_in_flight: 0,
+        }
+    }
+
+    pub fn syncs_in_flight(&self) -> usize {
+        self.syncs_in_flight
+    }
+
+    // Queues a command, with copies of the ranges it reads which the worker
+    // doesn't own. Has to wait for SYNC_FULLs in flight first.
+    pub fn queue(&mut self, command: RdpCommand, words: &[u64], rdram: &[u8]) {
+        let mut references = Vec::new();
+        self.references.command_references(words, &mut references);
+        for reference in references {
+            for (start, end) in self.owned.missing(reference.start, reference.end) {
+                self.pending_regions.push(RdramRegion::capture(rdram, start, end));
+            }
+            if reference.written {
+                self.owned.insert(reference.start, reference.end);
+            }
+        }
+        self.pending.push((command, words.to_vec()));
+    }
+
+    // Sends the queued commands to the worker
+    pub fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let request = Request::Commands {
+            regions: mem::replace(&mut self.pending_regions, Vec::new()),
+            commands: mem::replace(&mut self.pending, Vec::new()),
+        };
+        self.send(request);
+    }
+
+    pub fn sync_full(&mut self) {
+        self.flush();
+        self.send(Request::SyncFull);
+        self.owned.clear();
+        self.syncs_in_flight += 1;
+    }
+
+    // Copies the bytes written until finished SYNC_FULLs to RDRAM and
+    // returns how many finished. Blocks until all are done when `wait` is
+    // set.
+    pub fn complete_syncs(&mut self, rdram: &mut [u8], wait: bool) -> usize {
+        let mut completed = 0;
+        while self.syncs_in_flight > 0 {
+            let written = if wait {
+                self.completed.recv().expect("RDP worker thread stopped")
+            } else {
+                match self.completed.try_recv() {
+                    Ok(written) => written,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => panic!("RDP worker thread stopped"),
+                }
+            };
+            completed += self.apply(written, rdram);
+        }
+        completed
+    }
+
+    // Waits for all queued commands and copies everything they wrote to
+    // RDRAM. Returns how many SYNC_FULLs finished on the way.
+    pub fn drain(&mut self, rdram: &mut [u8]) -> usize {
+        self.flush();
+        self.send(Request::Drain);
+
+        let mut completed = 0;
+        loop {
+            let written = self.completed.recv().expect("RDP worker thread stopped");
+            let sync_full = written.sync_full;
+            completed += self.apply(written, rdram);
+            if !sync_full {
+                return completed;
+            }
+        }
+    }
+
+    // Returns 1 for a finished SYNC_FULL
+    fn apply(&mut self, written: Written, rdram: &mut [u8]) -> usize {
+        for region in &written.regions {
+            region.apply(rdram);
+        }
+        if written.sync_full {
+            self.syncs_in_flight -= 1;
+            1
+        } else {
+            0
+        }
+    }
+
+    fn send(&self, request: Request) {
+        if let Some(ref requests) = self.requests {
+            requests.send(request).expect("RDP worker thread stopped");
+        }
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.requests = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run(requests: Receiver<Request>, completed: Sender<Written>, render_threads: usize) {
+    let mut rdram = vec![0; RDRAM_LENGTH as usize];
+    let mut bands = Bands::new(render_threads);
+    bands.track_writes(rdram.len());
+
+    for request in requests {
+        let sync_full = match request {
+            Request::Commands { regions, commands } => {
+                for region in &regions {
+                    region.apply(&mut rdram);
+                }
+                for (command, words) in commands {
+                    bands.execute(command, &words, &mut rdram);
+                }
+                bands.flush(&mut rdram);
+                continue;
+            }
+            Request::SyncFull => true,
+            Request::Drain => false,
+        };
+
+        let regions = bands.take_written().into_iter().map(|(start, end)| RdramRegion::capture(&rdram, start, end)).collect();
+        if completed.send(Written { regions: regions, sync_full: sync_full }).is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,1005 @@
+// Flags shared by the audio commands
+const A_INIT: u32 = 0x01;
+const A_LOOP: u32 = 0x02;
+const A_LEFT: u32 = 0x02;
+const A_VOL: u32 = 0x04;
+const A_AUX: u32 = 0x08;
+
+// ABI1 buffers are relative to the start of the sample area
+const ABI1_DMEM_BASE: u32 = 0x05c0;
+
+// naudio works on a fixed set of buffers
+const NAUDIO_COUNT: u32 = 0x0170;
+const NAUDIO_MAIN: u32 = 0x04f0;
+const NAUDIO_MAIN2: u32 = 0x0660;
+const NAUDIO_DRY_LEFT: u32 = 0x09d0;
+const NAUDIO_DRY_RIGHT: u32 = 0x0b40;
+const NAUDIO_WET_LEFT: u32 = 0x0cb0;
+const NAUDIO_WET_RIGHT: u32 = 0x0e20;
+
+const DMEM_SIZE: usize = 0x1000;
+
+// The audio list formats ("ABIs") with an HLE implementation. ABI1 is the
+// original SDK microcode, naudio its successor with fixed buffer locations,
+// and ABI2 the list format used by the later Nintendo-developed microcodes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AudioAbi {
+    Abi1,
+    Abi2,
+    Naudio,
+}
+
+// Interprets the audio command list of a task. The samples are produced in
+// DMEM, as the microcode does, and written back to RDRAM by the list's
+// SAVEBUFF commands, from where the game hands them to the AI.
+pub fn run_audio_list(abi: AudioAbi, data_ptr: u32, data_size: u32, rdram: &mut [u8], dmem: &mut [u8]) {
+    let mut audio = Audio::new(abi, rdram, dmem);
+
+    for i in 0..data_size / 8 {
+        let w0 = audio.rdram_u32(data_ptr + i * 8);
+        let w1 = audio.rdram_u32(data_ptr + i * 8 + 4);
+        audio.execute(w0, w1);
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Ramp {
+    value: i32,
+    step: i32,
+    target: i32,
+}
+
+impl Ramp {
+    fn step(&mut self) -> i16 {
+        self.value = self.value.wrapping_add(self.step);
+        let target_reached = if self.step <= 0 {
+            self.value <= self.target
+        } else {
+            self.value >= self.target
+        };
+        if target_reached {
+            self.value = self.target;
+            self.step = 0;
+        }
+        (self.value >> 16) as i16
+    }
+}
+
+struct Audio<'a> {
+    abi: AudioAbi,
+    rdram: &'a mut [u8],
+    dmem: &'a mut [u8],
+
+    segments: [u32; 16],
+
+    // Buffers set by SETBUFF, in bytes
+    input: u32,
+    output: u32,
+    count: u32,
+    dry_right: u32,
+    wet_left: u32,
+    wet_right: u32,
+
+    // Envelope mixer state
+    dry: i16,
+    wet: i16,
+    volume: [i16; 2],
+    target: [i16; 2],
+    rate: [i32; 2],
+    env_values: [u16; 3],
+    env_steps: [u16; 3],
+
+    loop_addr: u32,
+    adpcm_table: [i16; 128],
+}
+
+impl<'a> Audio<'a> {
+    fn new(abi: AudioAbi, rdram: &'a mut [u8], dmem: &'a mut [u8]) -> Audio<'a> {
+        Audio {
+            abi: abi,
+            rdram: rdram,
+            dmem: dmem,
+
+            segments: [0; 16],
+
+            input: 0,
+            output: 0,
+            count: 0,
+            dry_right: 0,
+            wet_left: 0,
+            wet_right: 0,
+
+            dry: 0,
+            wet: 0,
+            volume: [0; 2],
+            target: [0; 2],
+            rate: [0; 2],
+            env_values: [0; 3],
+            env_steps: [0; 3],
+
+            loop_addr: 0,
+            adpcm_table: [0; 128],
+        }
+    }
+
+    fn execute(&mut self, w0: u32, w1: u32) {
+        let command = (w0 >> 24) & 0x1f;
+        match self.abi {
+            AudioAbi::Abi1 => self.execute_abi1(command, w0, w1),
+            AudioAbi::Naudio => self.execute_naudio(command, w0, w1),
+            AudioAbi::Abi2 => self.execute_abi2(command, w0, w1),
+        }
+    }
+
+    fn execute_abi1(&mut self, command: u32, w0: u32, w1: u32) {
+        let flags = (w0 >> 16) & 0xff;
+        match command {
+            // SPNOOP
+            0x00 => {}
+            // ADPCM
+            0x01 => {
+                let addr = self.segment_addr(w1);
+                let (output, input, count) = (self.output, self.input, align(self.count, 32));
+                self.adpcm(flags, false, output, input, count, addr);
+            }
+            // CLEARBUFF
+            0x02 => self.clear(ABI1_DMEM_BASE + (w0 & 0xffff), align(w1 & 0xffff, 16)),
+            // ENVMIXER
+            0x03 => {
+                let addr = self.segment_addr(w1);
+                self.envmix_exp(flags, addr);
+            }
+            // LOADBUFF
+            0x04 => {
+                let addr = self.segment_addr(w1);
+                let (input, count) = (self.input, self.count);
+                self.load(input & !3, addr & !3, align(count, 4));
+            }
+            // RESAMPLE
+            0x05 => {
+                let addr = self.segment_addr(w1);
+                let (output, input, count) = (self.output, self.input, align(self.count, 16));
+                self.resample(flags, output, input, count, (w0 & 0xffff) << 1, addr);
+            }
+            // SAVEBUFF
+            0x06 => {
+                let addr = self.segment_addr(w1);
+                let (output, count) = (self.output, self.count);
+                self.save(output & !3, addr & !3, align(count, 4));
+            }
+            // SEGMENT
+            0x07 => self.segments[((w1 >> 24) & 0xf) as usize] = w1 & 0x00ff_ffff,
+            // SETBUFF
+            0x08 => {
+                let dmem = ABI1_DMEM_BASE + (w0 & 0xffff);
+                let dmemo = ABI1_DMEM_BASE + (w1 >> 16);
+                let count = w1 & 0xffff;
+                if (flags & A_AUX) != 0 {
+                    self.dry_right = dmem;
+                    self.wet_left = dmemo;
+                    self.wet_right = ABI1_DMEM_BASE + count;
+                } else {
+                    self.input = dmem;
+                    self.output = dmemo;
+                    self.count = count;
+                }
+            }
+            // SETVOL
+            0x09 => self.set_volume(flags, w0, w1),
+            // DMEMMOVE
+            0x0a => {
+                let dmemi = ABI1_DMEM_BASE + (w0 & 0xffff);
+                let dmemo = ABI1_DMEM_BASE + (w1 >> 16);
+                self.move_dmem(dmemo, dmemi, align(w1 & 0xffff, 16));
+            }
+            // LOADADPCM
+            0x0b => {
+                let addr = self.segment_addr(w1);
+                self.load_adpcm_table(addr, w0 & 0xffff);
+            }
+            // MIXER
+            0x0c => {
+                let dmemi = ABI1_DMEM_BASE + (w1 >> 16);
+                let dmemo = ABI1_DMEM_BASE + (w1 & 0xffff);
+                let count = self.count;
+                self.mix(dmemo, dmemi, count, w0 as i16);
+            }
+            // INTERLEAVE
+            0x0d => {
+                let left = ABI1_DMEM_BASE + (w1 >> 16);
+                let right = ABI1_DMEM_BASE + (w1 & 0xffff);
+                let (output, count) = (self.output, self.count);
+                self.interleave(output, left, right, count);
+            }
+            // POLEF
+            0x0e => {
+                let addr = self.segment_addr(w1);
+                let (output, input, count) = (self.output, self.input, self.count);
+                self.polef(flags, output, input, count, w0 as i16, addr);
+            }
+            // SETLOOP
+            0x0f => self.loop_addr = self.segment_addr(w1),
+            _ => println!("WARNING: [HLE][AUDIO] Unknown ABI1 command {:08X} {:08X}", w0, w1),
+        }
+    }
+
+    fn execute_naudio(&mut self, command: u32, w0: u32, w1: u32) {
+        let flags = (w0 >> 16) & 0xff;
+        match command {
+            // SPNOOP and two commands without effect on the output
+            0x00 | 0x07 | 0x08 => {}
+            // ADPCM
+            0x01 => {
+                let addr = w0 & 0x00ff_ffff;
+                let flags = w1 >> 28;
+                let count = (w1 >> 16) & 0xfff;
+                let dmemi = NAUDIO_MAIN + ((w1 >> 12) & 0xf);
+                let dmemo = NAUDIO_MAIN + (w1 & 0xfff);
+                self.adpcm(flags, false, dmemo, dmemi, align(count, 32), addr);
+            }
+            // CLEARBUFF
+            0x02 => self.clear(NAUDIO_MAIN + (w0 & 0xffff), align(w1 & 0xfff, 16)),
+            // ENVMIXER
+            0x03 => {
+                self.input = NAUDIO_MAIN;
+                self.output = NAUDIO_DRY_LEFT;
+                self.dry_right = NAUDIO_DRY_RIGHT;
+                self.wet_left = NAUDIO_WET_LEFT;
+                self.wet_right = NAUDIO_WET_RIGHT;
+                self.count = NAUDIO_COUNT;
+                self.envmix_exp(flags, w1 & 0x00ff_ffff);
+            }
+            // LOADBUFF
+            0x04 => self.load(w0 & 0xfff, w1 & 0x00ff_ffff, (w0 >> 12) & 0xfff),
+            // RESAMPLE
+            0x05 => {
+                let addr = w0 & 0x00ff_ffff;
+                let flags = w1 >> 30;
+                let pitch = (w1 >> 14) & 0xffff;
+                let dmemi = NAUDIO_MAIN + ((w1 >> 2) & 0xfff);
+                let dmemo = if (w1 & 0x3) != 0 { NAUDIO_MAIN2 } else { NAUDIO_MAIN };
+                self.resample(flags, dmemo, dmemi, NAUDIO_COUNT, pitch << 1, addr);
+            }
+            // SAVEBUFF
+            0x06 => self.save(w0 & 0xfff, w1 & 0x00ff_ffff, (w0 >> 12) & 0xfff),
+            // SETVOL
+            0x09 => self.set_volume(flags, w0, w1),
+            // DMEMMOVE
+            0x0a => {
+                let dmemi = NAUDIO_MAIN + (w0 & 0xffff);
+                let dmemo = NAUDIO_MAIN + (w1 >> 16);
+                self.move_dmem(dmemo, dmemi, (w1 & 0xffff).wrapping_add(3) & !3);
+            }
+            // LOADADPCM
+            0x0b => self.load_adpcm_table(w1 & 0x00ff_ffff, w0 & 0xffff),
+            // MIXER
+            0x0c => {
+                let dmemi = NAUDIO_MAIN + (w1 >> 16);
+                let dmemo = NAUDIO_MAIN + (w1 & 0xffff);
+                self.mix(dmemo, dmemi, NAUDIO_COUNT, w0 as i16);
+            }
+            // INTERLEAVE
+            0x0d => self.interleave(NAUDIO_MAIN, NAUDIO_DRY_LEFT, NAUDIO_DRY_RIGHT, NAUDIO_COUNT),
+            // Updates the fractional part of the right envelope rate
+            0x0e => self.rate[1] = (self.rate[1] & !0xffff) | (w1 & 0xffff) as i32,
+            // SETLOOP
+            0x0f => self.loop_addr = w1 & 0x00ff_ffff,
+            _ => println!("WARNING: [HLE][AUDIO] Unknown naudio command {:08X} {:08X}", w0, w1),
+        }
+    }
+
+    fn execute_abi2(&mut self, command: u32, w0: u32, w1: u32) {
+        let flags = (w0 >> 16) & 0xff;
+        match command {
+            // SPNOOP
+            0x00 => {}
+            // ADPCM
+            0x01 => {
+                let addr = w1 & 0x00ff_ffff;
+                let two_bit_per_sample = (flags & 0x04) != 0;
+                let (output, input, count) = (self.output, self.input, align(self.count, 32));
+                self.adpcm(flags, two_bit_per_sample, output, input, count, addr);
+            }
+            // CLEARBUFF
+            0x02 => self.clear(w0 & 0xffff, align(w1 & 0xfff, 16)),
+            // ADDMIXER
+            0x04 => self.add(w1 & 0xffff, w1 >> 16, (w0 >> 12) & 0xff0),
+            // RESAMPLE
+            0x05 => {
+                let addr = w1 & 0x00ff_ffff;
+                let (output, input, count) = (self.output, self.input, align(self.count, 16));
+                self.resample(flags, output, input, count, (w0 & 0xffff) << 1, addr);
+            }
+            // SETBUFF
+            0x08 => {
+                self.input = w0 & 0xffff;
+                self.output = w1 >> 16;
+                self.count = w1 & 0xffff;
+            }
+            // DMEMMOVE
+            0x0a => self.move_dmem(w1 >> 16, w0 & 0xffff, (w1 & 0xffff).wrapping_add(3) & !3),
+            // LOADADPCM
+            0x0b => self.load_adpcm_table(w1 & 0x00ff_ffff, w0 & 0xffff),
+            // MIXER
+            0x0c => self.mix(w1 & 0xffff, w1 >> 16, (w0 >> 12) & 0xff0, w0 as i16),
+            // INTERLEAVE
+            0x0d => {
+                let count = match (w0 >> 12) & 0xff0 {
+                    0 => self.count,
+                    count => count,
+                };
+                let output = self.output;
+                self.interleave(output, w1 >> 16, w1 & 0xffff, count);
+            }
+            // POLEF
+            0x0e => {
+                let (output, input, count) = (self.output, self.input, self.count);
+                self.polef(flags, output, input, count, w0 as i16, w1 & 0x00ff_ffff);
+            }
+            // SETLOOP
+            0x0f => self.loop_addr = w1 & 0x00ff_ffff,
+            // INTERL, keeps every other sample
+            0x11 => {
+                let (dmemi, dmemo) = (w1 >> 16, w1 & 0xffff);
+                for i in 0..(w0 & 0xffff) {
+                    let sample = self.dmem_i16(dmemi + i * 4);
+                    self.set_dmem_i16(dmemo + i * 2, sample);
+                }
+            }
+            // ENVSETUP1
+            0x12 => {
+                self.env_values[2] = ((w0 >> 8) & 0xff00) as u16;
+                self.env_steps[2] = w0 as u16;
+                self.env_steps[0] = (w1 >> 16) as u16;
+                self.env_steps[1] = w1 as u16;
+            }
+            // ENVMIXER
+            0x13 => self.envmix_nead(w0, w1),
+            // LOADBUFF
+            0x14 => self.load(w0 & 0xfff, w1 & 0x00ff_ffff, (w0 >> 12) & 0xfff),
+            // SAVEBUFF
+            0x15 => self.save(w0 & 0xfff, w1 & 0x00ff_ffff, (w0 >> 12) & 0xfff),
+            // ENVSETUP2
+            0x16 => {
+                self.env_values[0] = (w1 >> 16) as u16;
+                self.env_values[1] = w1 as u16;
+            }
+            // HILOGAIN, a u4.4 gain
+            0x18 => {
+                let gain = ((w0 >> 16) & 0xff) as u8 as i8 as i32;
+                let dmem = w1 >> 16;
+                for i in 0..(w0 & 0xffff) / 2 {
+                    let sample = self.dmem_i16(dmem + i * 2) as i32;
+                    self.set_dmem_i16(dmem + i * 2, clamp_s16((sample * gain) >> 4));
+                }
+            }
+            // DUPLICATE, copies a 128 byte block several times
+            0x1a => {
+                let count = (w0 >> 16) & 0xff;
+                let (dmemi, dmemo) = (w0 & 0xffff, w1 >> 16);
+                for i in 0..count {
+                    self.move_dmem(dmemo + i * 0x80, dmemi, 0x80);
+                }
+            }
+            _ => println!("WARNING: [HLE][AUDIO] Unknown ABI2 command {:08X} {:08X}", w0, w1),
+        }
+    }
+
+    fn set_volume(&mut self, flags: u32, w0: u32, w1: u32) {
+        if (flags & A_AUX) != 0 {
+            self.dry = w0 as i16;
+            self.wet = w1 as i16;
+        } else {
+            let lr = if (flags & A_LEFT) != 0 { 0 } else { 1 };
+            if (flags & A_VOL) != 0 {
+                self.volume[lr] = w0 as i16;
+            } else {
+                self.target[lr] = w0 as i16;
+                self.rate[lr] = w1 as i32;
+            }
+        }
+    }
+
+    fn load_adpcm_table(&mut self, addr: u32, count: u32) {
+        let entries = (align(count, 16) / 2).min(self.adpcm_table.len() as u32);
+        for i in 0..entries {
+            self.adpcm_table[i as usize] = self.rdram_u16(addr + i * 2) as i16;
+        }
+    }
+
+    // Decodes frames of 16 samples, each a header byte with the scale and
+    // predictor index followed by 8 (or 4 for 2-bit samples) bytes of residuals
+    fn adpcm(&mut self, flags: u32, two_bit_per_sample: bool, mut dmemo: u32, mut dmemi: u32, mut count: u32, addr: u32) {
+        let mut last_frame = [0i16; 16];
+        if (flags & A_INIT) == 0 {
+            let source = if (flags & A_LOOP) != 0 { self.loop_addr } else { addr };
+            for i in 0..16 {
+                last_frame[i] = self.rdram_u16(source + i as u32 * 2) as i16;
+            }
+        }
+
+        for &sample in &last_frame {
+            self.set_dmem_i16(dmemo, sample);
+            dmemo += 2;
+        }
+
+        while count >= 32 {
+            let code = self.dmem_u8(dmemi);
+            dmemi += 1;
+            let scale = (code >> 4) as u32;
+            let book = ((code & 0xf) as usize) << 4;
+
+            let mut frame = [0i16; 16];
+            if two_bit_per_sample {
+                let shift = if scale < 14 { 14 - scale } else { 0 };
+                for i in 0..4 {
+                    let byte = self.dmem_u8(dmemi + i as u32);
+                    for j in 0..4 {
+                        let sample = ((byte >> (6 - j * 2)) & 0b11) as u16;
+                        frame[i * 4 + j] = ((sample << 14) as i16) >> shift;
+                    }
+                }
+                dmemi += 4;
+            } else {
+                let shift = if scale < 12 { 12 - scale } else { 0 };
+                for i in 0..8 {
+                    let byte = self.dmem_u8(dmemi + i as u32);
+                    frame[i * 2] = (((byte & 0xf0) as u16) << 8) as i16 >> shift;
+                    frame[i * 2 + 1] = (((byte & 0x0f) as u16) << 12) as i16 >> shift;
+                }
+                dmemi += 8;
+            }
+
+            let mut book_entry = [0i16; 16];
+            book_entry.copy_from_slice(&self.adpcm_table[book..book + 16]);
+
+            let (l1, l2) = (last_frame[14], last_frame[15]);
+            compute_residuals(&mut last_frame[..8], &frame[..8], &book_entry, l1, l2);
+            let (l1, l2) = (last_frame[6], last_frame[7]);
+            compute_residuals(&mut last_frame[8..], &frame[8..], &book_entry, l1, l2);
+
+            for &sample in &last_frame {
+                self.set_dmem_i16(dmemo, sample);
+                dmemo += 2;
+            }
+            count -= 32;
+        }
+
+        for i in 0..16 {
+            self.set_rdram_u16(addr + i as u32 * 2, last_frame[i] as u16);
+        }
+    }
+
+    // Four tap interpolation with the pitch as a 16.16 step. The last input
+    // samples and the fractional position are saved for the next task.
+    fn resample(&mut self, flags: u32, dmemo: u32, dmemi: u32, count: u32, pitch: u32, addr: u32) {
+        let mut ipos = dmemi.wrapping_sub(8);
+        let mut pitch_accu;
+
+        if (flags & A_INIT) != 0 {
+            for i in 0..4 {
+                self.set_dmem_i16(ipos + i * 2, 0);
+            }
+            pitch_accu = 0;
+        } else {
+            for i in 0..4 {
+                let sample = self.rdram_u16(addr + i * 2) as i16;
+                self.set_dmem_i16(ipos + i * 2, sample);
+            }
+            pitch_accu = self.rdram_u16(addr + 8) as u32;
+        }
+
+        for i in 0..count / 2 {
+            let lut = resample_coefficients((pitch_accu >> 10) & 0x3f);
+            let mut accu = 0;
+            for tap in 0..4 {
+                let sample = self.dmem_i16(ipos + tap * 2) as i32;
+                accu += (sample * lut[tap as usize]) >> 15;
+            }
+            self.set_dmem_i16(dmemo + i * 2, clamp_s16(accu));
+
+            pitch_accu += pitch;
+            ipos = ipos.wrapping_add((pitch_accu >> 16) * 2);
+            pitch_accu &= 0xffff;
+        }
+
+        for i in 0..4 {
+            let sample = self.dmem_i16(ipos + i * 2) as u16;
+            self.set_rdram_u16(addr + i * 2, sample);
+        }
+        self.set_rdram_u16(addr + 8, pitch_accu as u16);
+    }
+
+    // Applies exponential volume ramps and mixes the input into the dry and,
+    // with A_AUX, the wet buffers. The ramp state is kept in RDRAM.
+    fn envmix_exp(&mut self, flags: u32, addr: u32) {
+        let aux = (flags & A_AUX) != 0;
+        let mut ramps = [Ramp::default(); 2];
+        let mut exp_seq = [0i32; 2];
+        let mut exp_rates = [0i32; 2];
+        let (mut dry, mut wet) = (self.dry, self.wet);
+
+        if (flags & A_INIT) != 0 {
+            for i in 0..2 {
+                ramps[i].value = (self.volume[i] as i32) << 16;
+                ramps[i].target = (self.target[i] as i32) << 16;
+                exp_rates[i] = self.rate[i];
+                exp_seq[i] = (self.volume[i] as i32).wrapping_mul(self.rate[i]);
+            }
+        } else {
+            wet = self.rdram_u16(addr) as i16;
+            dry = self.rdram_u16(addr + 4) as i16;
+            for i in 0..2 {
+                let offset = i as u32 * 4;
+                ramps[i].target = self.rdram_u32(addr + 8 + offset) as i32;
+                exp_rates[i] = self.rdram_u32(addr + 16 + offset) as i32;
+                exp_seq[i] = self.rdram_u32(addr + 24 + offset) as i32;
+                ramps[i].value = self.rdram_u32(addr + 32 + offset) as i32;
+            }
+        }
+
+        for ramp in &mut ramps {
+            ramp.step = ramp.target.wrapping_sub(ramp.value);
+        }
+
+        let buffers = [self.output, self.dry_right, self.wet_left, self.wet_right];
+        let channels = if aux { 4 } else { 2 };
+        let mut ptr = 0;
+
+        for _ in 0..(self.count + 15) / 16 {
+            for i in 0..2 {
+                if ramps[i].step != 0 {
+                    exp_seq[i] = ((exp_seq[i] as i64 * exp_rates[i] as i64) >> 16) as i32;
+                    ramps[i].step = exp_seq[i].wrapping_sub(ramps[i].value) >> 3;
+                }
+            }
+
+            for _ in 0..8 {
+                let left = ramps[0].step() as i32;
+                let right = ramps[1].step() as i32;
+                let gains = [
+                    clamp_s16((left * dry as i32 + 0x4000) >> 15),
+                    clamp_s16((right * dry as i32 + 0x4000) >> 15),
+                    clamp_s16((left * wet as i32 + 0x4000) >> 15),
+                    clamp_s16((right * wet as i32 + 0x4000) >> 15),
+                ];
+
+                let sample = self.dmem_i16(self.input + ptr * 2) as i32;
+                for channel in 0..channels {
+                    let addr = buffers[channel] + ptr * 2;
+                    let mixed = self.dmem_i16(addr) as i32 + ((sample * gains[channel] as i32) >> 15);
+                    self.set_dmem_i16(addr, clamp_s16(mixed));
+                }
+                ptr += 1;
+            }
+        }
+
+        self.set_rdram_u16(addr, wet as u16);
+        self.set_rdram_u16(addr + 4, dry as u16);
+        for i in 0..2 {
+            let offset = i as u32 * 4;
+            self.set_rdram_u32(addr + 8 + offset, ramps[i].target as u32);
+            self.set_rdram_u32(addr + 16 + offset, exp_rates[i] as u32);
+            self.set_rdram_u32(addr + 24 + offset, exp_seq[i] as u32);
+            self.set_rdram_u32(addr + 32 + offset, ramps[i].value as u32);
+        }
+    }
+
+    // ABI2 envelope mixer with linear ramps set up by ENVSETUP1/2. The low
+    // bits of w0 select channels to invert for the surround effect.
+    fn envmix_nead(&mut self, w0: u32, w1: u32) {
+        let dmemi = (w0 >> 12) & 0xff0;
+        let count = align((w0 >> 8) & 0xff, 8);
+        let swap_wet_lr = ((w0 >> 4) & 1) != 0;
+        let xors = [
+            -(((w0 >> 3) & 1) as i16),
+            -(((w0 >> 2) & 1) as i16),
+            -(((w0 >> 1) & 1) as i16),
+            -((w0 & 1) as i16),
+        ];
+
+        let dry_left = (w1 >> 20) & 0xff0;
+        let dry_right = (w1 >> 12) & 0xff0;
+        let mut wet_left = (w1 >> 4) & 0xff0;
+        let mut wet_right = (w1 << 4) & 0xff0;
+        if swap_wet_lr {
+            ::std::mem::swap(&mut wet_left, &mut wet_right);
+        }
+
+        for block in 0..count / 8 {
+            for i in 0..8 {
+                let offset = (block * 8 + i) * 2;
+                let sample = self.dmem_i16(dmemi + offset) as i32;
+
+                let left = ((sample * self.env_values[0] as i32) >> 16) as i16 ^ xors[0];
+                let right = ((sample * self.env_values[1] as i32) >> 16) as i16 ^ xors[1];
+                let wet_l = ((left as i32 * self.env_values[2] as i32) >> 16) as i16 ^ xors[2];
+                let wet_r = ((right as i32 * self.env_values[2] as i32) >> 16) as i16 ^ xors[3];
+
+                let outputs = [(dry_left, left), (dry_right, right), (wet_left, wet_l), (wet_right, wet_r)];
+                for &(buffer, value) in &outputs {
+                    let mixed = self.dmem_i16(buffer + offset) as i32 + value as i32;
+                    self.set_dmem_i16(buffer + offset, clamp_s16(mixed));
+                }
+            }
+            for i in 0..3 {
+                self.env_values[i] = self.env_values[i].wrapping_add(self.env_steps[i]);
+            }
+        }
+    }
+
+    // Two-pole IIR filter with the coefficients in the ADPCM table
+    fn polef(&mut self, flags: u32, mut dmemo: u32, mut dmemi: u32, count: u32, gain: i16, addr: u32) {
+        if count == 0 {
+            return;
+        }
+
+        let (mut l1, mut l2) = if (flags & A_INIT) != 0 {
+            (0, 0)
+        } else {
+            (self.rdram_u16(addr + 4) as i16, self.rdram_u16(addr + 6) as i16)
+        };
+
+        let mut h1 = [0i16; 8];
+        let mut h2_before = [0i16; 8];
+        let mut h2 = [0i16; 8];
+        for i in 0..8 {
+            h1[i] = self.adpcm_table[i];
+            h2_before[i] = self.adpcm_table[8 + i];
+            h2[i] = ((h2_before[i] as i32 * gain as i32) >> 14) as i16;
+        }
+
+        let mut last = [0i16; 4];
+        for _ in 0..align(count, 16) / 16 {
+            let mut frame = [0i16; 8];
+            for i in 0..8 {
+                frame[i] = self.dmem_i16(dmemi + i as u32 * 2);
+            }
+            dmemi += 16;
+
+            let mut output = [0i16; 8];
+            for i in 0..8 {
+                let mut accu = frame[i] as i32 * gain as i32;
+                accu += h1[i] as i32 * l1 as i32 + h2_before[i] as i32 * l2 as i32;
+                accu += (0..i).map(|k| h2[k] as i32 * frame[i - 1 - k] as i32).sum::<i32>();
+                output[i] = clamp_s16(accu >> 14);
+                self.set_dmem_i16(dmemo + i as u32 * 2, output[i]);
+            }
+            l1 = output[6];
+            l2 = output[7];
+            last.copy_from_slice(&output[4..]);
+            dmemo += 16;
+        }
+
+        for i in 0..4 {
+            self.set_rdram_u16(addr + i as u32 * 2, last[i] as u16);
+        }
+    }
+
+    fn mix(&mut self, dmemo: u32, dmemi: u32, count: u32, gain: i16) {
+        for i in 0..count / 2 {
+            let sample = self.dmem_i16(dmemi + i * 2) as i32;
+            let mixed = self.dmem_i16(dmemo + i * 2) as i32 + ((sample * gain as i32) >> 15);
+            self.set_dmem_i16(dmemo + i * 2, clamp_s16(mixed));
+        }
+    }
+
+    fn add(&mut self, dmemo: u32, dmemi: u32, count: u32) {
+        for i in 0..count / 2 {
+            let sum = self.dmem_i16(dmemo + i * 2) as i32 + self.dmem_i16(dmemi + i * 2) as i32;
+            self.set_dmem_i16(dmemo + i * 2, clamp_s16(sum));
+        }
+    }
+
+    // Produces count bytes of interleaved stereo samples. The output often
+    // overlaps the left channel, so the inputs are copied first.
+    fn interleave(&mut self, dmemo: u32, left: u32, right: u32, count: u32) {
+        let samples = count / 2;
+        let left: Vec<i16> = (0..samples).map(|i| self.dmem_i16(left + i * 2)).collect();
+        let right: Vec<i16> = (0..samples).map(|i| self.dmem_i16(right + i * 2)).collect();
+        for i in 0..samples {
+            self.set_dmem_i16(dmemo + i * 4, left[i as usize]);
+            self.set_dmem_i16(dmemo + i * 4 + 2, right[i as usize]);
+        }
+    }
+
+    fn clear(&mut self, dmem: u32, count: u32) {
+        for i in 0..count {
+            self.set_dmem_u8(dmem + i, 0);
+        }
+    }
+
+    fn move_dmem(&mut self, dmemo: u32, dmemi: u32, count: u32) {
+        for i in 0..count {
+            let byte = self.dmem_u8(dmemi + i);
+            self.set_dmem_u8(dmemo + i, byte);
+        }
+    }
+
+    fn load(&mut self, dmem: u32, addr: u32, count: u32) {
+        for i in 0..count {
+            let byte = self.rdram_u8(addr + i);
+            self.set_dmem_u8(dmem + i, byte);
+        }
+    }
+
+    fn save(&mut self, dmem: u32, addr: u32, count: u32) {
+        for i in 0..count {
+            let byte = self.dmem_u8(dmem + i);
+            self.set_rdram_u8(addr + i, byte);
+        }
+    }
+
+    fn segment_addr(&self, addr: u32) -> u32 {
+        let segment = ((addr >> 24) & 0xf) as usize;
+        self.segments[segment].wrapping_add(addr & 0x00ff_ffff) & 0x00ff_ffff
+    }
+
+    // DMEM accesses wrap around like on the RSP
+    fn dmem_u8(&self, addr: u32) -> u8 {
+        self.dmem[addr as usize & (DMEM_SIZE - 1)]
+    }
+
+    fn set_dmem_u8(&mut self, addr: u32, value: u8) {
+        self.dmem[addr as usize & (DMEM_SIZE - 1)] = value;
+    }
+
+    fn dmem_i16(&self, addr: u32) -> i16 {
+        ((self.dmem_u8(addr) as u16) << 8 | self.dmem_u8(addr + 1) as u16) as i16
+    }
+
+    fn set_dmem_i16(&mut self, addr: u32, value: i16) {
+        self.set_dmem_u8(addr, (value >> 8) as u8);
+        self.set_dmem_u8(addr + 1, value as u8);
+    }
+
+    fn rdram_u8(&self, addr: u32) -> u8 {
+        self.rdram.get(addr as usize).cloned().unwrap_or(0)
+    }
+
+    fn set_rdram_u8(&mut self, addr: u32, value: u8) {
+        if let Some(byte) = self.rdram.get_mut(addr as usize) {
+            *byte = value;
+        }
+    }
+
+    fn rdram_u16(&self, addr: u32) -> u16 {
+        (self.rdram_u8(addr) as u16) << 8 | self.rdram_u8(addr + 1) as u16
+    }
+
+    fn set_rdram_u16(&mut self, addr: u32, value: u16) {
+        self.set_rdram_u8(addr, (value >> 8) as u8);
+        self.set_rdram_u8(addr + 1, value as u8);
+    }
+
+    fn rdram_u32(&self, addr: u32) -> u32 {
+        (self.rdram_u16(addr) as u32) << 16 | self.rdram_u16(addr + 2) as u32
+    }
+
+    fn set_rdram_u32(&mut self, addr: u32, value: u32) {
+        self.set_rdram_u16(addr, (value >> 16) as u16);
+        self.set_rdram_u16(addr + 2, value as u16);
+    }
+}
+
+// Reconstructs 8 samples from the residuals and the two previous samples,
+// using the predictor coefficients from the codebook entry
+fn compute_residuals(dst: &mut [i16], src: &[i16], book: &[i16; 16], l1: i16, l2: i16) {
+    let (book1, book2) = book.split_at(8);
+    for i in 0..8 {
+        let mut accu = (src[i] as i32) << 11;
+        accu += book1[i] as i32 * l1 as i32 + book2[i] as i32 * l2 as i32;
+        accu += (0..i).map(|k| book2[k] as i32 * src[i - 1 - k] as i32).sum::<i32>();
+        dst[i] = clamp_s16(accu >> 11);
+    }
+}
+
+// The interpolation table of the microcodes, four taps for each of 64
+// phases, in s.15
+const RESAMPLE_LUT: [u16; 64 * 4] = [
+    0x0c39, 0x66ad, 0x0d46, 0xffdf, 0x0b39, 0x6696, 0x0e5f, 0xffd8,
+    0x0a44, 0x6669, 0x0f83, 0xffd0, 0x095a, 0x6626, 0x10b4, 0xffc8,
+    0x087d, 0x65cd, 0x11f0, 0xffbf, 0x07ab, 0x655e, 0x1338, 0xffb6,
+    0x06e4, 0x64d9, 0x148c, 0xffac, 0x0628, 0x643f, 0x15eb, 0xffa1,
+    0x0577, 0x638f, 0x1756, 0xff96, 0x04d1, 0x62cb, 0x18cb, 0xff8a,
+    0x0435, 0x61f3, 0x1a4c, 0xff7e, 0x03a4, 0x6106, 0x1bd7, 0xff71,
+    0x031c, 0x6007, 0x1d6c, 0xff64, 0x029f, 0x5ef5, 0x1f0b, 0xff56,
+    0x022a, 0x5dd0, 0x20b3, 0xff48, 0x01be, 0x5c9a, 0x2264, 0xff3a,
+    0x015b, 0x5b53, 0x241e, 0xff2c, 0x0101, 0x59fc, 0x25e0, 0xff1e,
+    0x00ae, 0x5896, 0x27a9, 0xff10, 0x0063, 0x5720, 0x297a, 0xff02,
+    0x001f, 0x559d, 0x2b50, 0xfef4, 0xffe2, 0x540d, 0x2d2c, 0xfee8,
+    0xffac, 0x5270, 0x2f0d, 0xfedb, 0xff7c, 0x50c7, 0x30f3, 0xfed0,
+    0xff53, 0x4f14, 0x32dc, 0xfec6, 0xff2e, 0x4d57, 0x34c8, 0xfebd,
+    0xff0f, 0x4b91, 0x36b6, 0xfeb6, 0xfef5, 0x49c2, 0x38a5, 0xfeb0,
+    0xfedf, 0x47ed, 0x3a95, 0xfeac, 0xfece, 0x4611, 0x3c85, 0xfeab,
+    0xfec0, 0x4430, 0x3e74, 0xfeac, 0xfeb6, 0x424a, 0x4060, 0xfeaf,
+    0xfeaf, 0x4060, 0x424a, 0xfeb6, 0xfeac, 0x3e74, 0x4430, 0xfec0,
+    0xfeab, 0x3c85, 0x4611, 0xfece, 0xfeac, 0x3a95, 0x47ed, 0xfedf,
+    0xfeb0, 0x38a5, 0x49c2, 0xfef5, 0xfeb6, 0x36b6, 0x4b91, 0xff0f,
+    0xfebd, 0x34c8, 0x4d57, 0xff2e, 0xfec6, 0x32dc, 0x4f14, 0xff53,
+    0xfed0, 0x30f3, 0x50c7, 0xff7c, 0xfedb, 0x2f0d, 0x5270, 0xffac,
+    0xfee8, 0x2d2c, 0x540d, 0xffe2, 0xfef4, 0x2b50, 0x559d, 0x001f,
+    0xff02, 0x297a, 0x5720, 0x0063, 0xff10, 0x27a9, 0x5896, 0x00ae,
+    0xff1e, 0x25e0, 0x59fc, 0x0101, 0xff2c, 0x241e, 0x5b53, 0x015b,
+    0xff3a, 0x2264, 0x5c9a, 0x01be, 0xff48, 0x20b3, 0x5dd0, 0x022a,
+    0xff56, 0x1f0b, 0x5ef5, 0x029f, 0xff64, 0x1d6c, 0x6007, 0x031c,
+    0xff71, 0x1bd7, 0x6106, 0x03a4, 0xff7e, 0x1a4c, 0x61f3, 0x0435,
+    0xff8a, 0x18cb, 0x62cb, 0x04d1, 0xff96, 0x1756, 0x638f, 0x0577,
+    0xffa1, 0x15eb, 0x643f, 0x0628, 0xffac, 0x148c, 0x64d9, 0x06e4,
+    0xffb6, 0x1338, 0x655e, 0x07ab, 0xffbf, 0x11f0, 0x65cd, 0x087d,
+    0xffc8, 0x10b4, 0x6626, 0x095a, 0xffd0, 0x0f83, 0x6669, 0x0a44,
+    0xffd8, 0x0e5f, 0x6696, 0x0b39, 0xffdf, 0x0d46, 0x66ad, 0x0c39,
+];
+
+fn resample_coefficients(phase: u32) -> [i32; 4] {
+    let mut coefficients = [0; 4];
+    for i in 0..4 {
+        coefficients[i] = RESAMPLE_LUT[phase as usize * 4 + i] as i16 as i32;
+    }
+    coefficients
+}
+
+fn align(value: u32, alignment: u32) -> u32 {
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+fn clamp_s16(value: i32) -> i16 {
+    value.max(-32768).min(32767) as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(abi: AudioAbi, rdram: &mut [u8], dmem: &mut [u8], commands: &[(u32, u32)]) {
+        let mut audio = Audio::new(abi, rdram, dmem);
+        for &(w0, w1) in commands {
+            audio.execute(w0, w1);
+        }
+    }
+
+    fn write_i16s(memory: &mut [u8], addr: usize, values: &[i16]) {
+        for (i, &value) in values.iter().enumerate() {
+            memory[addr + i * 2] = (value >> 8) as u8;
+            memory[addr + i * 2 + 1] = value as u8;
+        }
+    }
+
+    fn read_i16s(memory: &[u8], addr: usize, count: usize) -> Vec<i16> {
+        (0..count).map(|i| ((memory[addr + i * 2] as u16) << 8 | memory[addr + i * 2 + 1] as u16) as i16).collect()
+    }
+
+    #[test]
+    fn adpcm_frame_is_decoded_with_the_codebook() {
+        let mut rdram = vec![0; 0x1000];
+        let mut dmem = vec![0; DMEM_SIZE];
+        // Predictor 0 adds the previous two samples of the last half frame,
+        // and each residual to the one after it
+        let mut book = [0; 16];
+        book[0] = 0x800;
+        book[8] = 0x800;
+        write_i16s(&mut rdram, 0x100, &book);
+        // Scale 2, predictor 0, residuals 1 to 7, -1, -8 to -2 and 0
+        let frame = [0x20, 0x12, 0x34, 0x56, 0x7f, 0x8e, 0xdc, 0xba, 0x90];
+        dmem[ABI1_DMEM_BASE as usize..ABI1_DMEM_BASE as usize + 9].copy_from_slice(&frame);
+
+        run(AudioAbi::Abi1, &mut rdram, &mut dmem, &[
+            (0x0b00_0020, 0x0000_0100), // LOADADPCM, one entry
+            (0x0800_0000, 0x0100_0020), // SETBUFF, 32 bytes to 0x100
+            (0x0101_0000, 0x0000_0200), // ADPCM, A_INIT
+        ]);
+
+        // The last frame comes first, zero after A_INIT
+        let output = ABI1_DMEM_BASE as usize + 0x100;
+        assert_eq!(read_i16s(&dmem, output, 16), vec![0; 16]);
+        let expected = [4, 12, 20, 28, 36, 44, 52, 24, 44, -40, -20, -28, -36, -44, -52, -28];
+        assert_eq!(read_i16s(&dmem, output + 32, 16), expected.to_vec());
+        // The frame is saved as the state of the next task
+        assert_eq!(read_i16s(&rdram, 0x200, 16), expected.to_vec());
+    }
+
+    #[test]
+    fn exponential_envelope_ramps_to_its_target() {
+        let mut rdram = vec![0; 0x1000];
+        let mut dmem = vec![0; DMEM_SIZE];
+        write_i16s(&mut dmem, ABI1_DMEM_BASE as usize, &[0x4000; 16]);
+
+        run(AudioAbi::Abi1, &mut rdram, &mut dmem, &[
+            (0x0906_1000, 0x0000_0000), // SETVOL, left volume
+            (0x0902_2000, 0x0002_0000), // SETVOL, left target and rate
+            (0x0904_1000, 0x0000_0000), // SETVOL, right volume
+            (0x0900_1000, 0x0001_0000), // SETVOL, right target and rate
+            (0x0908_4000, 0x0000_0000), // SETVOL, dry and wet
+            (0x0800_0000, 0x0100_0020), // SETBUFF, 16 samples to 0x100
+            (0x0808_0200, 0x0300_0400), // SETBUFF, A_AUX
+            (0x0301_0000, 0x0000_0100), // ENVMIXER, A_INIT
+        ]);
+
+        // The left volume goes up in steps of an eighth of the distance to
+        // the next exponential value until it reaches the target
+        let mut left = vec![0x580, 0x700];
+        left.extend(vec![0x800; 14]);
+        assert_eq!(read_i16s(&dmem, ABI1_DMEM_BASE as usize + 0x100, 16), left);
+        assert_eq!(read_i16s(&dmem, ABI1_DMEM_BASE as usize + 0x200, 16), vec![0x400; 16]);
+    }
+
+    #[test]
+    fn linear_envelope_ramps_once_per_block() {
+        let mut rdram = vec![0; 0];
+        let mut dmem = vec![0; DMEM_SIZE];
+        write_i16s(&mut dmem, 0x100, &[0x4000; 16]);
+
+        run(AudioAbi::Abi2, &mut rdram, &mut dmem, &[
+            (0x1280_0000, 0x1000_f000), // ENVSETUP1, wet 0x8000, steps 0x1000 and -0x1000
+            (0x1600_0000, 0x8000_4000), // ENVSETUP2, left 0x8000, right 0x4000
+            (0x1310_1001, 0x2030_4050), // ENVMIXER, 16 samples, wet right inverted
+        ]);
+
+        let block = |first: i16, second: i16| {
+            let mut samples = vec![first; 8];
+            samples.extend(vec![second; 8]);
+            samples
+        };
+        assert_eq!(read_i16s(&dmem, 0x200, 16), block(0x2000, 0x2400));
+        assert_eq!(read_i16s(&dmem, 0x300, 16), block(0x1000, 0x0c00));
+        assert_eq!(read_i16s(&dmem, 0x400, 16), block(0x1000, 0x1200));
+        assert_eq!(read_i16s(&dmem, 0x500, 16), block(!0x0800, !0x0600));
+    }
+
+    #[test]
+    fn interleave_may_overwrite_the_left_channel() {
+        let mut rdram = vec![0; 0];
+        let mut dmem = vec![0; DMEM_SIZE];
+        let base = ABI1_DMEM_BASE as usize;
+        write_i16s(&mut dmem, base + 0x100, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        write_i16s(&mut dmem, base + 0x200, &[-1, -2, -3, -4, -5, -6, -7, -8]);
+
+        run(AudioAbi::Abi1, &mut rdram, &mut dmem, &[
+            (0x0800_0000, 0x0100_0010), // SETBUFF, 8 samples to 0x100
+            (0x0d00_0000, 0x0100_0200), // INTERLEAVE
+        ]);
+
+        let expected = [1, -1, 2, -2, 3, -3, 4, -4, 5, -5, 6, -6, 7, -7, 8, -8];
+        assert_eq!(read_i16s(&dmem, base + 0x100, 16), expected.to_vec());
+    }
+
+    #[test]
+    fn buffers_round_trip_through_dmem() {
+        let mut rdram = vec![0; 0x2000];
+        let mut dmem = vec![0; DMEM_SIZE];
+        let samples: Vec<u8> = (0..0x20).collect();
+        rdram[0x1100..0x1120].copy_from_slice(&samples);
+
+        let list = [
+            (0x0700_0000, 0x0300_1000), // SEGMENT 3 at 0x1000
+            (0x0800_0040, 0x0040_0020), // SETBUFF, 32 bytes at 0x40
+            (0x0400_0000, 0x0300_0100), // LOADBUFF
+            (0x0600_0000, 0x0300_0200), // SAVEBUFF
+        ];
+        for (i, &(w0, w1)) in list.iter().enumerate() {
+            write_i16s(&mut rdram, i * 8, &[(w0 >> 16) as i16, w0 as i16, (w1 >> 16) as i16, w1 as i16]);
+        }
+        run_audio_list(AudioAbi::Abi1, 0, list.len() as u32 * 8, &mut rdram, &mut dmem);
+
+        let dmem_buffer = ABI1_DMEM_BASE as usize + 0x40;
+        assert_eq!(&dmem[dmem_buffer..dmem_buffer + 0x20], &samples[..]);
+        assert_eq!(&rdram[0x1200..0x1220], &samples[..]);
+    }
+
+    #[test]
+    fn resample_at_the_same_rate_uses_the_first_row_of_the_table() {
+        let mut rdram = vec![0; 0x1000];
+        let mut dmem = vec![0; DMEM_SIZE];
+        write_i16s(&mut dmem, ABI1_DMEM_BASE as usize + 0x100, &[0x4000; 8]);
+
+        run(AudioAbi::Abi1, &mut rdram, &mut dmem, &[
+            (0x0800_0100, 0x0200_0010), // SETBUFF, 8 samples from 0x100 to 0x200
+            (0x0501_8000, 0x0000_0100), // RESAMPLE, A_INIT, pitch 1.0
+        ]);
+
+        // The taps of phase 0 add up to just over 1.0, starting from the four
+        // zeroed samples before the input
+        let expected = [0, -17, 1682, 14824, 16388, 16388, 16388, 16388];
+        assert_eq!(read_i16s(&dmem, ABI1_DMEM_BASE as usize + 0x200, 8), expected.to_vec());
+    }
+}
@@ -0,0 +1,1057 @@
+use byteorder::{BigEndian, ByteOrder};
+
+use std::mem;
+
+// Display list commands which are passed on to the RDP unchanged, apart from
+// resolving segmented addresses
+const RDP_TEXRECT: u8 = 0xe4;
+const RDP_TEXRECT_FLIP: u8 = 0xe5;
+const RDP_SET_OTHER_MODES: u8 = 0xef;
+const RDP_SET_TEXTURE_IMAGE: u8 = 0xfd;
+const RDP_SET_Z_IMAGE: u8 = 0xfe;
+const RDP_SET_COLOR_IMAGE: u8 = 0xff;
+
+// Bit of the high othermode word enabling perspective correct texturing
+const OTHERMODE_H_TEXTURE_PERSP: u32 = 1 << 19;
+
+// Protects against display lists which never end
+const MAX_COMMANDS: usize = 1 << 20;
+
+const MAX_DISPLAY_LIST_DEPTH: usize = 18;
+const MAX_MATRIX_STACK_DEPTH: usize = 32;
+const MAX_VERTICES: usize = 64;
+const MAX_LIGHTS: usize = 8;
+
+// Triangles are clipped against the near plane and a guard band around the
+// viewport, which keeps the screen coordinates within range of the RDP
+const GUARD_BAND: f32 = 4.0;
+
+const CLIP_X_NEG: u8 = 1 << 0;
+const CLIP_X_POS: u8 = 1 << 1;
+const CLIP_Y_NEG: u8 = 1 << 2;
+const CLIP_Y_POS: u8 = 1 << 3;
+const CLIP_NEAR: u8 = 1 << 4;
+const CLIP_GUARD_BAND: u8 = 1 << 5;
+
+// The graphics microcode families with an HLE implementation. They share the
+// RDP commands and vertex format, but differ in command numbering and
+// encoding of the geometry pipeline commands.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GfxMicrocode {
+    Fast3d,
+    F3dex,
+    F3dex2,
+}
+
+impl GfxMicrocode {
+    fn decode(&self, op: u8) -> Command {
+        use self::Command::*;
+
+        match *self {
+            GfxMicrocode::Fast3d | GfxMicrocode::F3dex => match op {
+                0x00 => Noop,
+                0x01 => Matrix,
+                0x03 => MoveMem,
+                0x04 => Vertex,
+                0x06 => DisplayList,
+                0xaf if *self == GfxMicrocode::F3dex => LoadUcode,
+                0xb0 if *self == GfxMicrocode::F3dex => BranchZ,
+                0xb1 if *self == GfxMicrocode::F3dex => Triangle2,
+                0xb2 if *self == GfxMicrocode::F3dex => ModifyVertex,
+                0xb2 => RdpHalfCont,
+                0xb3 => RdpHalf2,
+                0xb4 => RdpHalf1,
+                0xb5 => Line3d,
+                0xb6 => ClearGeometryMode,
+                0xb7 => SetGeometryMode,
+                0xb8 => EndDisplayList,
+                0xb9 => SetOtherModeL,
+                0xba => SetOtherModeH,
+                0xbb => Texture,
+                0xbc => MoveWord,
+                0xbd => PopMatrix,
+                0xbe => CullDisplayList,
+                0xbf => Triangle1,
+                RDP_TEXRECT | RDP_TEXRECT_FLIP => TexRect,
+                0xe6..=0xff => Rdp,
+                _ => Unknown,
+            },
+            GfxMicrocode::F3dex2 => match op {
+                0x00 | 0xe0 => Noop,
+                0x01 => Vertex,
+                0x02 => ModifyVertex,
+                0x03 => CullDisplayList,
+                0x04 => BranchZ,
+                0x05 => Triangle1,
+                0x06 => Triangle2,
+                0x07 => Quad,
+                0x08 => Line3d,
+                0xd7 => Texture,
+                0xd8 => PopMatrix,
+                0xd9 => GeometryMode,
+                0xda => Matrix,
+                0xdb => MoveWord,
+                0xdc => MoveMem,
+                0xdd => LoadUcode,
+                0xde => DisplayList,
+                0xdf => EndDisplayList,
+                0xe1 => RdpHalf1,
+                0xe2 => SetOtherModeL,
+                0xe3 => SetOtherModeH,
+                0xf1 => RdpHalf2,
+                RDP_TEXRECT | RDP_TEXRECT_FLIP => TexRect,
+                0xe6..=0xff => Rdp,
+                _ => Unknown,
+            },
+        }
+    }
+
+    fn geometry_mode_bits(&self) -> GeometryModeBits {
+        match *self {
+            GfxMicrocode::Fast3d | GfxMicrocode::F3dex => GeometryModeBits {
+                zbuffer: 0x0000_0001,
+                shade: 0x0000_0004,
+                shading_smooth: 0x0000_0200,
+                cull_front: 0x0000_1000,
+                cull_back: 0x0000_2000,
+                fog: 0x0001_0000,
+                lighting: 0x0002_0000,
+            },
+            GfxMicrocode::F3dex2 => GeometryModeBits {
+                zbuffer: 0x0000_0001,
+                shade: 0x0000_0004,
+                shading_smooth: 0x0020_0000,
+                cull_front: 0x0000_0200,
+                cull_back: 0x0000_0400,
+                fog: 0x0001_0000,
+                lighting: 0x0002_0000,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Command {
+    Noop,
+    Matrix,
+    PopMatrix,
+    MoveMem,
+    MoveWord,
+    Vertex,
+    ModifyVertex,
+    DisplayList,
+    EndDisplayList,
+    CullDisplayList,
+    BranchZ,
+    Triangle1,
+    Triangle2,
+    Quad,
+    Line3d,
+    Texture,
+    SetGeometryMode,
+    ClearGeometryMode,
+    GeometryMode,
+    SetOtherModeH,
+    SetOtherModeL,
+    RdpHalf1,
+    RdpHalf2,
+    RdpHalfCont,
+    LoadUcode,
+    TexRect,
+    Rdp,
+    Unknown,
+}
+
+struct GeometryModeBits {
+    zbuffer: u32,
+    shade: u32,
+    shading_smooth: u32,
+    cull_front: u32,
+    cull_back: u32,
+    fog: u32,
+    lighting: u32,
+}
+
+type Matrix = [[f32; 4]; 4];
+
+const IDENTITY: Matrix = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Vertex {
+    clip: [f32; 4],
+    color: [f32; 4],
+    tex: [f32; 2], // s10.5 texel coordinates
+    clip_flags: u8,
+}
+
+impl Vertex {
+    fn lerp(&self, other: &Vertex, t: f32) -> Vertex {
+        let mut result = *self;
+        for i in 0..4 {
+            result.clip[i] += (other.clip[i] - self.clip[i]) * t;
+            result.color[i] += (other.color[i] - self.color[i]) * t;
+        }
+        for i in 0..2 {
+            result.tex[i] += (other.tex[i] - self.tex[i]) * t;
+        }
+        result
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ScreenVertex {
+    x: f32,
+    y: f32,
+    z: f32,
+    inv_w: f32,
+    color: [f32; 4],
+    tex: [f32; 2],
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Light {
+    color: [f32; 3],
+    dir: [f32; 3],
+}
+
+struct Viewport {
+    scale: [f32; 3],
+    trans: [f32; 3],
+}
+
+// Interprets the display list of a graphics task and returns the RDP command
+// stream it produces
+pub fn run_display_list(ucode: GfxMicrocode, data_ptr: u32, rdram: &[u8]) -> Vec<u64> {
+    let mut gfx = Gfx::new(ucode, rdram);
+    gfx.run(data_ptr);
+    gfx.commands
+}
+
+struct Gfx<'a> {
+    ucode: GfxMicrocode,
+    bits: GeometryModeBits,
+    rdram: &'a [u8],
+    commands: Vec<u64>,
+
+    segments: [u32; 16],
+    display_lists: Vec<u32>,
+
+    modelview: Vec<Matrix>,
+    projection: Matrix,
+    mvp: Matrix,
+
+    viewport: Viewport,
+    vertices: [Vertex; MAX_VERTICES],
+
+    geometry_mode: u32,
+    othermode_h: u32,
+    othermode_l: u32,
+
+    texture_on: bool,
+    texture_tile: u32,
+    texture_level: u32,
+    texture_scale: [f32; 2],
+
+    lights: [Light; MAX_LIGHTS + 1],
+    num_lights: usize,
+
+    fog_multiplier: f32,
+    fog_offset: f32,
+
+    rdp_half_1: u32,
+}
+
+impl<'a> Gfx<'a> {
+    fn new(ucode: GfxMicrocode, rdram: &'a [u8]) -> Gfx<'a> {
+        Gfx {
+            ucode: ucode,
+            bits: ucode.geometry_mode_bits(),
+            rdram: rdram,
+            commands: Vec::new(),
+
+            segments: [0; 16],
+            display_lists: Vec::new(),
+
+            modelview: vec![IDENTITY],
+            projection: IDENTITY,
+            mvp: IDENTITY,
+
+            // 320x240, the same as the default viewport of the SDK
+            viewport: Viewport {
+                scale: [640.0, 480.0, 511.0],
+                trans: [640.0, 480.0, 511.0],
+            },
+            vertices: [Vertex::default(); MAX_VERTICES],
+
+            geometry_mode: 0,
+            othermode_h: 0,
+            othermode_l: 0,
+
+            texture_on: false,
+            texture_tile: 0,
+            texture_level: 0,
+            texture_scale: [1.0, 1.0],
+
+            lights: [Light::default(); MAX_LIGHTS + 1],
+            num_lights: 0,
+
+            fog_multiplier: 0.0,
+            fog_offset: 0.0,
+
+            rdp_half_1: 0,
+        }
+    }
+
+    fn run(&mut self, data_ptr: u32) {
+        let mut pc = data_ptr;
+
+        for _ in 0..MAX_COMMANDS {
+            let w0 = self.read_u32(pc);
+            let w1 = self.read_u32(pc + 4);
+            pc += 8;
+
+            let op = (w0 >> 24) as u8;
+            match self.ucode.decode(op) {
+                Command::Noop => {}
+                Command::Matrix => self.matrix(w0, w1),
+                Command::PopMatrix => self.pop_matrix(w1),
+                Command::MoveMem => self.move_mem(w0, w1),
+                Command::MoveWord => self.move_word(w0, w1),
+                Command::Vertex => self.vertex(w0, w1),
+                Command::ModifyVertex => self.modify_vertex(w0, w1),
+
+                Command::DisplayList => {
+                    let addr = self.segment_addr(w1);
+                    // The branch variant replaces the current display list
+                    let push = ((w0 >> 16) & 0xff) == 0;
+                    if push {
+                        if self.display_lists.len() >= MAX_DISPLAY_LIST_DEPTH {
+                            println!("WARNING: [HLE][GFX] Display list stack overflow at {:#08X}", pc);
+                            continue;
+                        }
+                        self.display_lists.push(pc);
+                    }
+                    pc = addr;
+                }
+                Command::EndDisplayList => {
+                    match self.display_lists.pop() {
+                        Some(addr) => pc = addr,
+                        None => return,
+                    }
+                }
+                Command::CullDisplayList => {
+                    if self.cull_display_list(w0, w1) {
+                        match self.display_lists.pop() {
+                            Some(addr) => pc = addr,
+                            None => return,
+                        }
+                    }
+                }
+                Command::BranchZ => {
+                    let index = ((w0 & 0xfff) / 2) as usize;
+                    if index < MAX_VERTICES && self.screen_z(&self.vertices[index]) <= w1 as i32 as f32 {
+                        pc = self.segment_addr(self.rdp_half_1);
+                    }
+                }
+
+                Command::Triangle1 => {
+                    let word = match self.ucode {
+                        GfxMicrocode::F3dex2 => w0,
+                        _ => w1,
+                    };
+                    self.triangle_from_word(word);
+                }
+                Command::Triangle2 => {
+                    self.triangle_from_word(w0);
+                    self.triangle_from_word(w1);
+                }
+                Command::Quad => {
+                    self.triangle_from_word(w0);
+                    self.triangle_from_word(w1);
+                }
+                Command::Line3d => {
+                    println!("WARNING: [HLE][GFX] Ignoring unsupported LINE3D command");
+                }
+
+                Command::Texture => self.texture(w0, w1),
+                Command::SetGeometryMode => self.geometry_mode |= w1,
+                Command::ClearGeometryMode => self.geometry_mode &= !w1,
+                Command::GeometryMode => {
+                    self.geometry_mode = (self.geometry_mode & (w0 | 0xff00_0000)) | w1;
+                }
+                Command::SetOtherModeH => {
+                    let mask = self.othermode_mask(w0);
+                    self.othermode_h = (self.othermode_h & !mask) | (w1 & mask);
+                    self.push_othermode();
+                }
+                Command::SetOtherModeL => {
+                    let mask = self.othermode_mask(w0);
+                    self.othermode_l = (self.othermode_l & !mask) | (w1 & mask);
+                    self.push_othermode();
+                }
+
+                Command::RdpHalf1 => self.rdp_half_1 = w1,
+                Command::RdpHalf2 | Command::RdpHalfCont => {}
+                Command::LoadUcode => {
+                    println!("WARNING: [HLE][GFX] Ignoring microcode switch to {:#08X}", w1);
+                }
+
+                Command::TexRect => {
+                    // The texture coordinates follow in the w1 of the next two commands
+                    let coords = self.read_u32(pc + 4);
+                    let deltas = self.read_u32(pc + 12);
+                    pc += 16;
+                    self.push_command(w0, w1);
+                    self.push_command(coords, deltas);
+                }
+                Command::Rdp => self.rdp_command(op, w0, w1),
+
+                Command::Unknown => {
+                    println!("WARNING: [HLE][GFX] Unknown {:?} command {:08X} {:08X}", self.ucode, w0, w1);
+                }
+            }
+        }
+
+        println!("WARNING: [HLE][GFX] Display list did not end after {} commands", MAX_COMMANDS);
+    }
+
+    fn rdp_command(&mut self, op: u8, w0: u32, w1: u32) {
+        match op {
+            RDP_SET_COLOR_IMAGE | RDP_SET_Z_IMAGE | RDP_SET_TEXTURE_IMAGE => {
+                let addr = self.segment_addr(w1);
+                self.push_command(w0, addr);
+            }
+            RDP_SET_OTHER_MODES => {
+                self.othermode_h = w0 & 0x00ff_ffff;
+                self.othermode_l = w1;
+                self.push_command(w0, w1);
+            }
+            _ => self.push_command(w0, w1),
+        }
+    }
+
+    fn push_command(&mut self, w0: u32, w1: u32) {
+        self.commands.push(((w0 as u64) << 32) | w1 as u64);
+    }
+
+    fn push_othermode(&mut self) {
+        let w0 = ((RDP_SET_OTHER_MODES as u32) << 24) | (self.othermode_h & 0x00ff_ffff);
+        let w1 = self.othermode_l;
+        self.push_command(w0, w1);
+    }
+
+    fn othermode_mask(&self, w0: u32) -> u32 {
+        let (shift, len) = match self.ucode {
+            GfxMicrocode::F3dex2 => {
+                let len = (w0 & 0xff) + 1;
+                (32u32.saturating_sub(((w0 >> 8) & 0xff) + len), len)
+            }
+            _ => ((w0 >> 8) & 0xff, w0 & 0xff),
+        };
+        let bits = if len >= 32 { 0xffff_ffff } else { (1u32 << len) - 1 };
+        bits.checked_shl(shift).unwrap_or(0)
+    }
+
+    fn matrix(&mut self, w0: u32, w1: u32) {
+        let (projection, load, push) = match self.ucode {
+            GfxMicrocode::F3dex2 => {
+                let params = (w0 & 0xff) ^ 0x01;
+                ((params & 0x04) != 0, (params & 0x02) != 0, (params & 0x01) != 0)
+            }
+            _ => {
+                let params = (w0 >> 16) & 0xff;
+                ((params & 0x01) != 0, (params & 0x02) != 0, (params & 0x04) != 0)
+            }
+        };
+
+        let addr = self.segment_addr(w1);
+        let matrix = self.read_matrix(addr);
+
+        if projection {
+            self.projection = if load { matrix } else { multiply(&matrix, &self.projection) };
+        } else {
+            let top = *self.modelview.last().unwrap();
+            if push && self.modelview.len() < MAX_MATRIX_STACK_DEPTH {
+                self.modelview.push(top);
+            }
+            let new_top = if load { matrix } else { multiply(&matrix, &top) };
+            *self.modelview.last_mut().unwrap() = new_top;
+        }
+
+        self.update_mvp();
+    }
+
+    fn pop_matrix(&mut self, w1: u32) {
+        let count = match self.ucode {
+            GfxMicrocode::F3dex2 => w1 / 64,
+            _ => 1,
+        };
+        for _ in 0..count {
+            if self.modelview.len() > 1 {
+                self.modelview.pop();
+            }
+        }
+        self.update_mvp();
+    }
+
+    fn update_mvp(&mut self) {
+        self.mvp = multiply(self.modelview.last().unwrap(), &self.projection);
+    }
+
+    fn move_mem(&mut self, w0: u32, w1: u32) {
+        let addr = self.segment_addr(w1);
+
+        match self.ucode {
+            GfxMicrocode::F3dex2 => {
+                let index = w0 & 0xff;
+                let offset = ((w0 >> 8) & 0xff) * 8;
+                match index {
+                    8 => self.load_viewport(addr),
+                    // The first two slots hold the LookAt structures
+                    10 if offset >= 48 => self.load_light(((offset - 48) / 24) as usize, addr),
+                    10 => {}
+                    _ => println!("WARNING: [HLE][GFX] Ignoring MOVEMEM to index {}", index),
+                }
+            }
+            _ => {
+                let index = (w0 >> 16) & 0xff;
+                match index {
+                    0x80 => self.load_viewport(addr),
+                    0x82 | 0x84 => {}
+                    0x86..=0x94 => self.load_light(((index - 0x86) / 2) as usize, addr),
+                    _ => println!("WARNING: [HLE][GFX] Ignoring MOVEMEM to index {:#04X}", index),
+                }
+            }
+        }
+    }
+
+    fn load_viewport(&mut self, addr: u32) {
+        for i in 0..3 {
+            self.viewport.scale[i] = self.read_u16(addr + i as u32 * 2) as i16 as f32;
+            self.viewport.trans[i] = self.read_u16(addr + 8 + i as u32 * 2) as i16 as f32;
+        }
+    }
+
+    fn load_light(&mut self, index: usize, addr: u32) {
+        if index > MAX_LIGHTS {
+            return;
+        }
+        let mut light = Light::default();
+        for i in 0..3 {
+            light.color[i] = self.rdram_byte(addr + i as u32) as f32;
+            light.dir[i] = self.rdram_byte(addr + 8 + i as u32) as i8 as f32;
+        }
+        light.dir = normalize(light.dir);
+        self.lights[index] = light;
+    }
+
+    fn move_word(&mut self, w0: u32, w1: u32) {
+        let (index, offset) = match self.ucode {
+            GfxMicrocode::F3dex2 => ((w0 >> 16) & 0xff, w0 & 0xffff),
+            _ => (w0 & 0xff, (w0 >> 8) & 0xffff),
+        };
+
+        match index {
+            // G_MW_NUMLIGHT
+            0x02 => {
+                let count = match self.ucode {
+                    GfxMicrocode::F3dex2 => w1 / 24,
+                    _ => ((w1.wrapping_sub(0x8000_0000)) >> 5).saturating_sub(1),
+                };
+                self.num_lights = (count as usize).min(MAX_LIGHTS);
+            }
+            // G_MW_SEGMENT
+            0x06 => {
+                let segment = ((offset / 4) & 0xf) as usize;
+                self.segments[segment] = w1 & 0x00ff_ffff;
+            }
+            // G_MW_FOG
+            0x08 => {
+                self.fog_multiplier = (w1 >> 16) as i16 as f32;
+                self.fog_offset = w1 as i16 as f32;
+            }
+            // G_MW_LIGHTCOL, each light is set by two writes of the same color
+            0x0a => {
+                let stride = match self.ucode {
+                    GfxMicrocode::F3dex2 => 24,
+                    _ => 32,
+                };
+                let light = (offset / stride) as usize;
+                if light <= MAX_LIGHTS && (offset % stride) == 0 {
+                    self.lights[light].color = [(w1 >> 24) as u8 as f32, (w1 >> 16) as u8 as f32, (w1 >> 8) as u8 as f32];
+                }
+            }
+            // G_MW_MATRIX, G_MW_CLIP, G_MW_POINTS/G_MW_FORCEMTX and G_MW_PERSPNORM
+            // don't affect the output
+            _ => {}
+        }
+    }
+
+    fn texture(&mut self, w0: u32, w1: u32) {
+        self.texture_on = match self.ucode {
+            GfxMicrocode::F3dex2 => ((w0 >> 1) & 0x7f) != 0,
+            _ => (w0 & 0xff) != 0,
+        };
+        self.texture_level = (w0 >> 11) & 0b111;
+        self.texture_tile = (w0 >> 8) & 0b111;
+        self.texture_scale = [(w1 >> 16) as f32 / 65536.0, (w1 & 0xffff) as f32 / 65536.0];
+    }
+
+    fn vertex(&mut self, w0: u32, w1: u32) {
+        let (first, count) = match self.ucode {
+            GfxMicrocode::Fast3d => ((w0 >> 16) & 0xf, ((w0 >> 20) & 0xf) + 1),
+            GfxMicrocode::F3dex => (((w0 >> 16) & 0xff) / 2, (w0 >> 10) & 0x3f),
+            GfxMicrocode::F3dex2 => {
+                let count = (w0 >> 12) & 0xff;
+                (((w0 >> 1) & 0x7f).wrapping_sub(count), count)
+            }
+        };
+
+        let addr = self.segment_addr(w1);
+        for i in 0..count {
+            let index = first.wrapping_add(i) as usize;
+            if index >= MAX_VERTICES {
+                break;
+            }
+            self.vertices[index] = self.load_vertex(addr + i * 16);
+        }
+    }
+
+    fn load_vertex(&self, addr: u32) -> Vertex {
+        let position = [
+            self.read_u16(addr) as i16 as f32,
+            self.read_u16(addr + 2) as i16 as f32,
+            self.read_u16(addr + 4) as i16 as f32,
+            1.0,
+        ];
+        let s = self.read_u16(addr + 8) as i16 as f32;
+        let t = self.read_u16(addr + 10) as i16 as f32;
+        let rgba = [
+            self.rdram_byte(addr + 12),
+            self.rdram_byte(addr + 13),
+            self.rdram_byte(addr + 14),
+            self.rdram_byte(addr + 15),
+        ];
+
+        let clip = transform(&position, &self.mvp);
+
+        let mut color = [rgba[0] as f32, rgba[1] as f32, rgba[2] as f32, rgba[3] as f32];
+        if (self.geometry_mode & self.bits.lighting) != 0 {
+            // The color holds the normal instead
+            let normal = [rgba[0] as i8 as f32, rgba[1] as i8 as f32, rgba[2] as i8 as f32, 0.0];
+            let normal = transform(&normal, self.modelview.last().unwrap());
+            let normal = normalize([normal[0], normal[1], normal[2]]);
+
+            let mut lit = self.lights[self.num_lights].color;
+            for light in &self.lights[..self.num_lights] {
+                let intensity = dot(normal, light.dir).max(0.0);
+                for i in 0..3 {
+                    lit[i] += light.color[i] * intensity;
+                }
+            }
+            for i in 0..3 {
+                color[i] = lit[i].min(255.0);
+            }
+        }
+
+        if (self.geometry_mode & self.bits.fog) != 0 && clip[3] != 0.0 {
+            let fog = (clip[2] / clip[3]) * self.fog_multiplier + self.fog_offset;
+            color[3] = fog.max(0.0).min(255.0);
+        }
+
+        let mut vertex = Vertex {
+            clip: clip,
+            color: color,
+            tex: [s * self.texture_scale[0], t * self.texture_scale[1]],
+            clip_flags: 0,
+        };
+        vertex.clip_flags = clip_flags(&vertex);
+        vertex
+    }
+
+    fn modify_vertex(&mut self, w0: u32, w1: u32) {
+        let index = ((w0 & 0xffff) / 2) as usize;
+        if index >= MAX_VERTICES {
+            return;
+        }
+
+        let vertex = &mut self.vertices[index];
+        match (w0 >> 16) & 0xff {
+            0x10 => {
+                vertex.color = [(w1 >> 24) as u8 as f32, (w1 >> 16) as u8 as f32, (w1 >> 8) as u8 as f32, w1 as u8 as f32];
+            }
+            0x14 => {
+                vertex.tex = [(w1 >> 16) as i16 as f32, w1 as i16 as f32];
+            }
+            location => {
+                println!("WARNING: [HLE][GFX] Ignoring MODIFYVTX of location {:#04X}", location);
+            }
+        }
+    }
+
+    fn cull_display_list(&self, w0: u32, w1: u32) -> bool {
+        let divisor = match self.ucode {
+            GfxMicrocode::Fast3d => 40,
+            _ => 2,
+        };
+        let first = ((w0 & 0xffff) / divisor) as usize;
+        let last = ((w1 & 0xffff) / divisor) as usize;
+        if first > last || last >= MAX_VERTICES {
+            return false;
+        }
+
+        // Culled when all vertices are outside the same side of the view volume
+        let outside = self.vertices[first..=last]
+            .iter()
+            .fold(0xff, |flags, vertex| flags & vertex.clip_flags);
+        (outside & (CLIP_X_NEG | CLIP_X_POS | CLIP_Y_NEG | CLIP_Y_POS)) != 0
+    }
+
+    fn triangle_from_word(&mut self, word: u32) {
+        let divisor = match self.ucode {
+            GfxMicrocode::Fast3d => 10,
+            _ => 2,
+        };
+        let index = |shift: u32| (((word >> shift) & 0xff) / divisor) as usize;
+        self.triangle(index(16), index(8), index(0));
+    }
+
+    fn triangle(&mut self, i1: usize, i2: usize, i3: usize) {
+        if i1 >= MAX_VERTICES || i2 >= MAX_VERTICES || i3 >= MAX_VERTICES {
+            return;
+        }
+        let (v1, v2, v3) = (self.vertices[i1], self.vertices[i2], self.vertices[i3]);
+
+        if (v1.clip_flags & v2.clip_flags & v3.clip_flags) != 0 {
+            return;
+        }
+
+        // Flat shaded triangles use the color of their first vertex
+        let smooth = (self.geometry_mode & self.bits.shading_smooth) != 0;
+        let flat_color = v1.color;
+
+        let mut polygon = vec![v1, v2, v3];
+        if ((v1.clip_flags | v2.clip_flags | v3.clip_flags) & (CLIP_NEAR | CLIP_GUARD_BAND)) != 0 {
+            polygon = clip_polygon(polygon);
+            if polygon.len() < 3 {
+                return;
+            }
+        }
+
+        let mut screen: Vec<ScreenVertex> = polygon.iter().map(|v| self.project(v)).collect();
+        if !smooth {
+            for vertex in &mut screen {
+                vertex.color = flat_color;
+            }
+        }
+
+        // Front faces are clockwise on screen
+        let area = (screen[1].x - screen[0].x) * (screen[2].y - screen[0].y) -
+                   (screen[1].y - screen[0].y) * (screen[2].x - screen[0].x);
+        let cull_front = (self.geometry_mode & self.bits.cull_front) != 0;
+        let cull_back = (self.geometry_mode & self.bits.cull_back) != 0;
+        if (area > 0.0 && cull_front) || (area < 0.0 && cull_back) {
+            return;
+        }
+
+        for i in 1..screen.len() - 1 {
+            self.push_triangle([screen[0], screen[i], screen[i + 1]]);
+        }
+    }
+
+    fn project(&self, vertex: &Vertex) -> ScreenVertex {
+        let inv_w = 1.0 / vertex.clip[3];
+        let scale = &self.viewport.scale;
+        let trans = &self.viewport.trans;
+
+        // The viewport is specified in quarter pixels
+        ScreenVertex {
+            x: (trans[0] + scale[0] * vertex.clip[0] * inv_w) / 4.0,
+            y: (trans[1] - scale[1] * vertex.clip[1] * inv_w) / 4.0,
+            z: self.screen_z(vertex),
+            inv_w: inv_w,
+            color: vertex.color,
+            tex: vertex.tex,
+        }
+    }
+
+    // Depth in the 15-bit range used by the RDP
+    fn screen_z(&self, vertex: &Vertex) -> f32 {
+        let z = self.viewport.trans[2] + self.viewport.scale[2] * vertex.clip[2] / vertex.clip[3];
+        (z * 32.0).max(0.0).min(32767.0)
+    }
+
+    // Builds an RDP triangle command with edge, shade, texture and depth
+    // coefficients. The vertices are sorted from top to bottom: the major (H)
+    // edge spans the whole triangle, the middle (M) edge the top part and
+    // the low (L) edge the bottom part.
+    fn push_triangle(&mut self, mut v: [ScreenVertex; 3]) {
+        if v[1].y < v[0].y { v.swap(0, 1); }
+        if v[2].y < v[1].y { v.swap(1, 2); }
+        if v[1].y < v[0].y { v.swap(0, 1); }
+
+        let (hx, hy) = (v[2].x - v[0].x, v[2].y - v[0].y);
+        let (mx, my) = (v[1].x - v[0].x, v[1].y - v[0].y);
+        let (lx, ly) = (v[2].x - v[1].x, v[2].y - v[1].y);
+
+        let nz = hx * my - hy * mx;
+        if nz == 0.0 {
+            return;
+        }
+        let left_major = nz < 0.0;
+
+        let slope = |dx: f32, dy: f32| if dy != 0.0 { dx / dy } else { 0.0 };
+        let (dxhdy, dxmdy, dxldy) = (slope(hx, hy), slope(mx, my), slope(lx, ly));
+
+        // Y coordinates are in s11.2, the edges start at the top scanline
+        let (yh, ym, yl) = ((v[0].y * 4.0).floor(), (v[1].y * 4.0).floor(), (v[2].y * 4.0).floor());
+        let start_y = v[0].y.floor() - v[0].y;
+        let xh = v[0].x + start_y * dxhdy;
+        let xm = v[0].x + start_y * dxmdy;
+        let xl = v[1].x + (ym / 4.0 - v[1].y) * dxldy;
+
+        let shade = (self.geometry_mode & self.bits.shade) != 0;
+        let textured = self.texture_on;
+        let zbuffer = (self.geometry_mode & self.bits.zbuffer) != 0;
+
+        let command = 0x08 | (shade as u64) << 2 | (textured as u64) << 1 | zbuffer as u64;
+        self.commands.push(
+            command << 56 |
+            (left_major as u64) << 55 |
+            (self.texture_level as u64) << 51 |
+            (self.texture_tile as u64) << 48 |
+            (yl as i32 as u64 & 0x3fff) << 32 |
+            (ym as i32 as u64 & 0x3fff) << 16 |
+            (yh as i32 as u64 & 0x3fff));
+        self.commands.push((fixed(xl) as u64) << 32 | fixed(dxldy) as u64);
+        self.commands.push((fixed(xh) as u64) << 32 | fixed(dxhdy) as u64);
+        self.commands.push((fixed(xm) as u64) << 32 | fixed(dxmdy) as u64);
+
+        // Gradients of an attribute: start value on the major edge, then
+        // its change along x, along the major edge and along y
+        let denominator = -nz;
+        let gradient = |a: [f32; 3]| {
+            let (ma, ha) = (a[1] - a[0], a[2] - a[0]);
+            let dx = (ma * hy - ha * my) / denominator;
+            let dy = (ha * mx - ma * hx) / denominator;
+            let de = dy + dx * dxhdy;
+            [a[0] + start_y * de, dx, de, dy]
+        };
+
+        if shade {
+            let mut coefficients = [[0.0; 4]; 4];
+            for i in 0..4 {
+                coefficients[i] = gradient([v[0].color[i], v[1].color[i], v[2].color[i]]);
+            }
+            self.push_coefficients(&coefficients);
+        }
+
+        if textured {
+            let perspective = (self.othermode_h & OTHERMODE_H_TEXTURE_PERSP) != 0;
+            let mut coefficients = [[0.0; 4]; 4];
+            if perspective {
+                // S and T are divided by W per pixel. W is normalized so the
+                // closest vertex uses the full s.15 range.
+                let max_inv_w = v.iter().fold(0.0f32, |max, v| max.max(v.inv_w));
+                let w = [v[0].inv_w / max_inv_w, v[1].inv_w / max_inv_w, v[2].inv_w / max_inv_w];
+                for i in 0..2 {
+                    coefficients[i] = gradient([v[0].tex[i] * w[0], v[1].tex[i] * w[1], v[2].tex[i] * w[2]]);
+                }
+                coefficients[2] = gradient([w[0] * 32767.0, w[1] * 32767.0, w[2] * 32767.0]);
+            } else {
+                for i in 0..2 {
+                    coefficients[i] = gradient([v[0].tex[i], v[1].tex[i], v[2].tex[i]]);
+                }
+            }
+            self.push_coefficients(&coefficients);
+        }
+
+        if zbuffer {
+            let z = gradient([v[0].z, v[1].z, v[2].z]);
+            self.commands.push((fixed(z[0]) as u64) << 32 | fixed(z[1]) as u64);
+            self.commands.push((fixed(z[2]) as u64) << 32 | fixed(z[3]) as u64);
+        }
+    }
+
+    // Writes the s15.16 start values and gradients of four attributes, split
+    // into integer and fractional halves the way the RDP expects them
+    fn push_coefficients(&mut self, coefficients: &[[f32; 4]; 4]) {
+        let pack = |which: usize, high: bool| {
+            coefficients.iter().fold(0u64, |word, attribute| {
+                let value = fixed(attribute[which]);
+                let half = if high { value >> 16 } else { value & 0xffff };
+                (word << 16) | half as u64
+            })
+        };
+
+        // Start value, d/dx, d/de and d/dy
+        let words = [
+            pack(0, true), pack(1, true), pack(0, false), pack(1, false),
+            pack(2, true), pack(3, true), pack(2, false), pack(3, false),
+        ];
+        self.commands.extend_from_slice(&words);
+    }
+
+    fn segment_addr(&self, addr: u32) -> u32 {
+        let segment = ((addr >> 24) & 0xf) as usize;
+        self.segments[segment].wrapping_add(addr & 0x00ff_ffff) & 0x00ff_ffff
+    }
+
+    fn rdram_byte(&self, addr: u32) -> u8 {
+        self.rdram.get(addr as usize).cloned().unwrap_or(0)
+    }
+
+    fn read_u16(&self, addr: u32) -> u16 {
+        let addr = addr as usize;
+        match self.rdram.get(addr..addr + 2) {
+            Some(bytes) => BigEndian::read_u16(bytes),
+            None => 0,
+        }
+    }
+
+    fn read_u32(&self, addr: u32) -> u32 {
+        let addr = addr as usize;
+        match self.rdram.get(addr..addr + 4) {
+            Some(bytes) => BigEndian::read_u32(bytes),
+            None => 0,
+        }
+    }
+
+    // Matrices are stored as 16 s15.16 values, with the integer halves of all
+    // elements first, followed by the fractional halves
+    fn read_matrix(&self, addr: u32) -> Matrix {
+        let mut matrix = [[0.0; 4]; 4];
+        for row in 0..4 {
+            for col in 0..4 {
+                let offset = (row * 8 + col * 2) as u32;
+                let int = self.read_u16(addr + offset) as u32;
+                let frac = self.read_u16(addr + 32 + offset) as u32;
+                matrix[row][col] = ((int << 16) | frac) as i32 as f32 / 65536.0;
+            }
+        }
+        matrix
+    }
+}
+
+// Vertices are row vectors, so `a * b` applies `a` first
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut result = [[0.0; 4]; 4];
+    for row in 0..4 {
+        for col in 0..4 {
+            result[row][col] = (0..4).map(|k| a[row][k] * b[k][col]).sum();
+        }
+    }
+    result
+}
+
+fn transform(v: &[f32; 4], m: &Matrix) -> [f32; 4] {
+    let mut result = [0.0; 4];
+    for col in 0..4 {
+        result[col] = (0..4).map(|k| v[k] * m[k][col]).sum();
+    }
+    result
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = dot(v, v).sqrt();
+    if length == 0.0 {
+        v
+    } else {
+        [v[0] / length, v[1] / length, v[2] / length]
+    }
+}
+
+fn fixed(value: f32) -> u32 {
+    (value * 65536.0) as i32 as u32
+}
+
+fn clip_flags(vertex: &Vertex) -> u8 {
+    let [x, y, z, w] = vertex.clip;
+    let mut flags = 0;
+    if x < -w { flags |= CLIP_X_NEG; }
+    if x > w { flags |= CLIP_X_POS; }
+    if y < -w { flags |= CLIP_Y_NEG; }
+    if y > w { flags |= CLIP_Y_POS; }
+    if z < -w { flags |= CLIP_NEAR; }
+    if x.abs() > w * GUARD_BAND || y.abs() > w * GUARD_BAND {
+        flags |= CLIP_GUARD_BAND;
+    }
+    flags
+}
+
+// Sutherland-Hodgman clipping against the near plane and the guard band
+fn clip_polygon(mut polygon: Vec<Vertex>) -> Vec<Vertex> {
+    let planes: [fn(&[f32; 4]) -> f32; 5] = [
+        |c| c[2] + c[3],
+        |c| c[3] * GUARD_BAND - c[0],
+        |c| c[3] * GUARD_BAND + c[0],
+        |c| c[3] * GUARD_BAND - c[1],
+        |c| c[3] * GUARD_BAND + c[1],
+    ];
+
+    for plane in planes.iter() {
+        let input = mem::replace(&mut polygon, Vec::new());
+        for i in 0..input.len() {
+            let current = &input[i];
+            let next = &input[(i + 1) % input.len()];
+            let (d_current, d_next) = (plane(&current.clip), plane(&next.clip));
+
+            if d_current >= 0.0 {
+                polygon.push(*current);
+            }
+            if (d_current >= 0.0) != (d_next >= 0.0) {
+                polygon.push(current.lerp(next, d_current / (d_current - d_next)));
+            }
+        }
+        if polygon.len() < 3 {
+            break;
+        }
+    }
+    polygon
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Whether a shaded triangle has colors changing across it
+    fn gouraud_shaded(ucode: GfxMicrocode, smooth: bool) -> bool {
+        let rdram = [0; 0];
+        let mut gfx = Gfx::new(ucode, &rdram);
+        gfx.geometry_mode = gfx.bits.shade | if smooth { gfx.bits.shading_smooth } else { 0 };
+
+        let positions = [[-0.5, 0.5], [0.5, 0.5], [0.0, -0.5]];
+        for (i, position) in positions.iter().enumerate() {
+            let shade = 64.0 * (i + 1) as f32;
+            gfx.vertices[i] = Vertex {
+                clip: [position[0], position[1], 0.0, 1.0],
+                color: [shade, shade, shade, 255.0],
+                ..Vertex::default()
+            };
+        }
+        gfx.triangle(0, 1, 2);
+
+        // Four edge words and eight shade words: the start values and d/dx,
+        // followed by d/de and d/dy
+        assert_eq!(gfx.commands.len(), 12);
+        let gradients = [5, 7, 8, 9, 10, 11];
+        gradients.iter().any(|&i| gfx.commands[i] != 0)
+    }
+
+    #[test]
+    fn shading_follows_geometry_mode() {
+        for &ucode in &[GfxMicrocode::Fast3d, GfxMicrocode::F3dex, GfxMicrocode::F3dex2] {
+            assert!(gouraud_shaded(ucode, true), "{:?}", ucode);
+            assert!(!gouraud_shaded(ucode, false), "{:?}", ucode);
+        }
+    }
+}
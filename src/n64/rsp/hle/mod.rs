@@ -0,0 +1,229 @@
+mod audio;
+mod gfx;
+mod task;
+mod ucode_db;
+
+pub use self::audio::AudioAbi;
+pub use self::gfx::GfxMicrocode;
+pub use self::task::OsTask;
+pub use self::ucode_db::{Handler, UcodeInfo};
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use n64::Interconnect;
+
+// Only the most recent tasks are kept for the debugger
+const MAX_TASK_RECORDS: usize = 256;
+
+// Microcode text beyond the size of IMEM is loaded as overlays and isn't
+// needed to tell microcodes apart, likewise data beyond the size of DMEM
+const MAX_HASHED_UCODE_SIZE: u32 = 0x1000;
+const MAX_UCODE_DATA_SIZE: u32 = 0x1000;
+
+#[derive(Debug, Clone)]
+pub struct TaskRecord {
+    // None for code started without a task header
+    pub task: Option<OsTask>,
+    pub ucode_hash: u32,
+    pub ucode: Option<UcodeInfo>,
+    pub hle: bool,
+}
+
+// High level emulation of RSP tasks. Microcodes are identified once by the
+// hash of their text and data, as builds of the same code differ only in
+// their data, and tasks using a microcode without an HLE handler fall back
+// to running it on the RSP.
+#[derive(Debug, Default)]
+pub struct Hle {
+    known_ucodes: HashMap<u32, Option<UcodeInfo>>,
+    // Microcodes already printed, each is only reported for its first task
+    reported_ucodes: HashSet<u32>,
+    tasks: Vec<TaskRecord>,
+}
+
+impl Hle {
+    pub fn new() -> Hle {
+        Hle::default()
+    }
+
+    pub fn tasks(&self) -> &[TaskRecord] {
+        &self.tasks
+    }
+
+    // Called when the RSP is started. Returns false if the code has to be
+    // executed by the RSP instead.
+    pub fn start_task(&mut self, interconnect: &mut Interconnect) -> bool {
+        // Code started directly by the CPU leaves whatever was in DMEM as the
+        // task header, so it is recognized by IMEM alone before the header
+        // is looked at
+        if let Some(info) = ucode_db::identify_boot_code(interconnect.rsp().imem()) {
+            return self.run(None, 0, Some(info), interconnect);
+        }
+
+        let task = OsTask::read(interconnect.rsp());
+        let (hash, info) = {
+            let rdram = interconnect.rdram();
+            let ucode = slice(rdram, task.ucode, task.ucode_size.min(MAX_HASHED_UCODE_SIZE));
+            let ucode_data = slice(rdram, task.ucode_data, task.ucode_data_size.min(MAX_UCODE_DATA_SIZE));
+
+            let hash = fnv1a(ucode_data, fnv1a(ucode, FNV1A_OFFSET_BASIS));
+            let info = self.known_ucodes
+                .entry(hash)
+                .or_insert_with(|| ucode_db::identify(task.task_type, ucode_data))
+                .clone();
+            (hash, info)
+        };
+
+        self.run(Some(task), hash, info, interconnect)
+    }
+
+    fn run(&mut self, task: Option<OsTask>, hash: u32, ucode: Option<UcodeInfo>, interconnect: &mut Interconnect) -> bool {
+        let handler = ucode.as_ref().and_then(|info| info.handler);
+
+        let record = TaskRecord {
+            task: task,
+            ucode_hash: hash,
+            ucode: ucode,
+            hle: handler.is_some(),
+        };
+        if self.reported_ucodes.insert(hash) {
+            println!("[RSP][HLE] {}", record);
+        }
+        if self.tasks.len() >= MAX_TASK_RECORDS {
+            self.tasks.remove(0);
+        }
+        self.tasks.push(record);
+
+        match (handler, task) {
+            (Some(Handler::Cicx105), _) => {
+                run_cic_x105(interconnect);
+                interconnect.rsp_break();
+            }
+            (Some(Handler::Gfx(microcode)), Some(task)) => {
+                let commands = gfx::run_display_list(microcode, task.data_ptr, interconnect.rdram());
+                interconnect.submit_rdp_commands(&commands);
+                finish_task(interconnect);
+            }
+            (Some(Handler::Audio(abi)), Some(task)) => {
+                let (rdram, dmem) = interconnect.rdram_and_dmem();
+                audio::run_audio_list(abi, task.data_ptr, task.data_size, rdram, dmem);
+                finish_task(interconnect);
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl fmt::Display for TaskRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = self.ucode.as_ref().map(|info| &info.name[..]).unwrap_or("unknown microcode");
+        let mode = if self.hle { "HLE" } else { "LLE" };
+        match self.task {
+            Some(ref task) => write!(f, "Task {} [{:#010X}] {}: {}", mode, self.ucode_hash, name, task),
+            None => write!(f, "Code {}: {}", mode, name),
+        }
+    }
+}
+
+fn finish_task(interconnect: &mut Interconnect) {
+    interconnect.rsp().set_task_done();
+    interconnect.rsp_break();
+}
+
+fn run_cic_x105(interconnect: &mut Interconnect) {
+    // dma_read(0x1120, 0x1e8, 0x1e8)
+    for i in 0..0x7C {
+        let val = interconnect.read_word(0x01e8 + i * 4);
+        interconnect.write_word(0x0400_1000 + 0x0120 + i * 4, val);
+    }
+
+    /* dma_write(0x1120, 0x2fb1f0, 0xfe817000) */
+    let mut dst_addr = 0x002f_b1f0;
+    let mut src_imem_addr = 0x0120;
+    for _ in 0..24 {
+        let val1 = interconnect.read_word(0x0400_1000 + src_imem_addr + 0);
+        let val2 = interconnect.read_word(0x0400_1000 + src_imem_addr + 4);
+        interconnect.write_word(dst_addr + 0, val1);
+        interconnect.write_word(dst_addr + 4, val2);
+        dst_addr += 0xff0;
+        src_imem_addr += 0x8;
+    }
+}
+
+fn slice(rdram: &[u8], addr: u32, len: u32) -> &[u8] {
+    let start = (addr as usize).min(rdram.len());
+    let end = (start + len as usize).min(rdram.len());
+    &rdram[start..end]
+}
+
+const FNV1A_OFFSET_BASIS: u32 = 0x811c_9dc5;
+
+// Continues the hash `hash` over `data`
+fn fnv1a(data: &[u8], hash: u32) -> u32 {
+    data.iter().fold(hash, |hash, &byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::{BigEndian, ByteOrder};
+    use n64::Interconnect;
+
+    const UCODE: u32 = 0x0010_0000;
+    const UCODE_DATA: u32 = 0x0011_0000;
+
+    fn task_header(interconnect: &mut Interconnect, task_type: u32, ucode_boot_size: u32) {
+        let fields = [
+            (0x00, task_type),
+            (0x0c, ucode_boot_size),
+            (0x10, 0x8000_0000 | UCODE),
+            (0x14, 0x1000),
+            (0x18, 0x8000_0000 | UCODE_DATA),
+            (0x1c, 0x800),
+        ];
+        for &(offset, value) in &fields {
+            interconnect.rsp().write_dmem(0x0fc0 + offset, value);
+        }
+    }
+
+    fn ucode_name(hle: &Hle, index: usize) -> String {
+        hle.tasks()[index].ucode.as_ref().map(|info| info.name.clone()).unwrap_or_default()
+    }
+
+    #[test]
+    fn boot_code_is_recognized_regardless_of_task_header() {
+        for &ucode_boot_size in &[0, 0x1000, 0xdead_beef] {
+            let mut interconnect = Interconnect::without_roms();
+            let mut hle = Hle::new();
+            task_header(&mut interconnect, 0, ucode_boot_size);
+            // The bytes of the CIC-NUS-6105 challenge code sum to 0x09e2
+            interconnect.rsp().write_imem(0x00, 0xffff_ffff);
+            interconnect.rsp().write_imem(0x04, 0xffff_ffff);
+            interconnect.rsp().write_imem(0x08, 0xffeb_0000);
+
+            assert!(hle.start_task(&mut interconnect));
+            assert!(hle.tasks()[0].task.is_none());
+            assert_eq!(ucode_name(&hle, 0), "CIC-NUS-6105 challenge");
+        }
+    }
+
+    #[test]
+    fn microcodes_sharing_text_are_told_apart_by_data() {
+        let mut interconnect = Interconnect::without_roms();
+        let mut hle = Hle::new();
+        task_header(&mut interconnect, 2, 0x1000);
+
+        for &word in &[0x1118_1350, 0x1118_12e0, 0x1118_1350] {
+            let data = &mut interconnect.rdram()[UCODE_DATA as usize + 0x10..];
+            BigEndian::write_u32(data, word);
+            assert!(hle.start_task(&mut interconnect));
+        }
+
+        assert_eq!(ucode_name(&hle, 0), "Audio ABI2 (Mario Kart 64)");
+        assert_eq!(ucode_name(&hle, 1), "Audio ABI2 (Star Fox 64)");
+        assert_eq!(ucode_name(&hle, 2), "Audio ABI2 (Mario Kart 64)");
+        assert!(hle.tasks()[0].ucode_hash != hle.tasks()[1].ucode_hash);
+    }
+}
@@ -0,0 +1,111 @@
+use std::fmt;
+
+use super::super::RspRegs;
+
+// The OSTask structure is placed at the end of DMEM by the CPU before it
+// starts the RSP boot microcode
+const TASK_HEADER_START: u32 = 0x0fc0;
+
+pub const TASK_TYPE_GFX: u32 = 1;
+pub const TASK_TYPE_AUDIO: u32 = 2;
+pub const TASK_TYPE_VIDEO: u32 = 3;
+pub const TASK_TYPE_JPEG: u32 = 4;
+
+// Task flags
+pub const TASK_FLAG_DP_WAIT: u32 = 0x0001;
+pub const TASK_FLAG_LOADABLE: u32 = 0x0002;
+pub const TASK_FLAG_YIELDED: u32 = 0x0004;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OsTask {
+    pub task_type: u32,
+    pub flags: u32,
+
+    pub ucode_boot: u32,
+    pub ucode_boot_size: u32,
+
+    pub ucode: u32,
+    pub ucode_size: u32,
+
+    pub ucode_data: u32,
+    pub ucode_data_size: u32,
+
+    pub dram_stack: u32,
+    pub dram_stack_size: u32,
+
+    pub output_buff: u32,
+    pub output_buff_size: u32,
+
+    pub data_ptr: u32,
+    pub data_size: u32,
+
+    pub yield_data_ptr: u32,
+    pub yield_data_size: u32,
+}
+
+impl OsTask {
+    pub fn read(rsp: &RspRegs) -> OsTask {
+        let field = |offset: u32| rsp.read_dmem(TASK_HEADER_START + offset);
+        // Pointers are given as KSEG0 addresses
+        let ptr = |offset: u32| field(offset) & 0x00ff_ffff;
+
+        OsTask {
+            task_type: field(0x00),
+            flags: field(0x04),
+
+            ucode_boot: ptr(0x08),
+            ucode_boot_size: field(0x0c),
+
+            ucode: ptr(0x10),
+            ucode_size: field(0x14),
+
+            ucode_data: ptr(0x18),
+            ucode_data_size: field(0x1c),
+
+            dram_stack: ptr(0x20),
+            dram_stack_size: field(0x24),
+
+            output_buff: ptr(0x28),
+            output_buff_size: field(0x2c),
+
+            data_ptr: ptr(0x30),
+            data_size: field(0x34),
+
+            yield_data_ptr: ptr(0x38),
+            yield_data_size: field(0x3c),
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self.task_type {
+            TASK_TYPE_GFX => "GFX",
+            TASK_TYPE_AUDIO => "AUDIO",
+            TASK_TYPE_VIDEO => "VIDEO",
+            TASK_TYPE_JPEG => "JPEG",
+            _ => "UNKNOWN",
+        }
+    }
+}
+
+impl fmt::Display for OsTask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({}) flags: {:#06X}", self.type_name(), self.task_type, self.flags)?;
+        if (self.flags & TASK_FLAG_DP_WAIT) != 0 {
+            write!(f, " DP_WAIT")?;
+        }
+        if (self.flags & TASK_FLAG_LOADABLE) != 0 {
+            write!(f, " LOADABLE")?;
+        }
+        if (self.flags & TASK_FLAG_YIELDED) != 0 {
+            write!(f, " YIELDED")?;
+        }
+        writeln!(f)?;
+        writeln!(f, "  ucode_boot:  {:#08X} ({:#X} bytes)", self.ucode_boot, self.ucode_boot_size)?;
+        writeln!(f, "  ucode:       {:#08X} ({:#X} bytes)", self.ucode, self.ucode_size)?;
+        writeln!(f, "  ucode_data:  {:#08X} ({:#X} bytes)", self.ucode_data, self.ucode_data_size)?;
+        writeln!(f, "  dram_stack:  {:#08X} ({:#X} bytes)", self.dram_stack, self.dram_stack_size)?;
+        writeln!(f, "  output_buff: {:#08X} ({:#X} bytes)", self.output_buff, self.output_buff_size)?;
+        writeln!(f, "  data_ptr:    {:#08X} ({:#X} bytes)", self.data_ptr, self.data_size)?;
+        write!(f, "  yield_data:  {:#08X} ({:#X} bytes)", self.yield_data_ptr, self.yield_data_size)
+    }
+}
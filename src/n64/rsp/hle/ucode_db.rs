@@ -0,0 +1,169 @@
+use byteorder::{BigEndian, ByteOrder};
+
+use super::{AudioAbi, GfxMicrocode};
+use super::task::TASK_TYPE_GFX;
+
+// How a recognized microcode is emulated
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Handler {
+    Cicx105,
+    Gfx(GfxMicrocode),
+    Audio(AudioAbi),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UcodeInfo {
+    pub name: String,
+    // None if the microcode is known but has to run on the RSP
+    pub handler: Option<Handler>,
+}
+
+enum Fingerprint {
+    // Sum of the first 44 bytes of IMEM, for code started without a task
+    ImemSum(u32),
+    // Word at a byte offset of the microcode's data section
+    DataWord(usize, u32),
+}
+
+struct KnownUcode {
+    fingerprint: Fingerprint,
+    name: &'static str,
+    handler: Option<Handler>,
+}
+
+const KNOWN_UCODES: &'static [KnownUcode] = &[
+    KnownUcode { fingerprint: Fingerprint::ImemSum(0x09e2), name: "CIC-NUS-6105 challenge", handler: Some(Handler::Cicx105) },
+
+    KnownUcode { fingerprint: Fingerprint::DataWord(0x00, 0x0000_0001), name: "Audio ABI1", handler: Some(Handler::Audio(AudioAbi::Abi1)) },
+    KnownUcode { fingerprint: Fingerprint::DataWord(0x00, 0x0000_127c), name: "Audio naudio", handler: Some(Handler::Audio(AudioAbi::Naudio)) },
+    KnownUcode { fingerprint: Fingerprint::DataWord(0x00, 0x0000_1280), name: "Audio naudio (Banjo-Kazooie)", handler: None },
+    KnownUcode { fingerprint: Fingerprint::DataWord(0x00, 0x1c58_126c), name: "Audio naudio (Donkey Kong 64)", handler: None },
+    KnownUcode { fingerprint: Fingerprint::DataWord(0x00, 0x1ae8_143c), name: "Audio naudio with MP3", handler: None },
+    KnownUcode { fingerprint: Fingerprint::DataWord(0x00, 0x1ab0_140c), name: "Audio naudio with MP3 (Conker's Bad Fur Day)", handler: None },
+
+    KnownUcode { fingerprint: Fingerprint::DataWord(0x10, 0x1118_1350), name: "Audio ABI2 (Mario Kart 64)", handler: Some(Handler::Audio(AudioAbi::Abi2)) },
+    KnownUcode { fingerprint: Fingerprint::DataWord(0x10, 0x1118_12e0), name: "Audio ABI2 (Star Fox 64)", handler: Some(Handler::Audio(AudioAbi::Abi2)) },
+    KnownUcode { fingerprint: Fingerprint::DataWord(0x10, 0x1104_12ac), name: "Audio ABI2 (Wave Race 64, JP)", handler: Some(Handler::Audio(AudioAbi::Abi2)) },
+    KnownUcode { fingerprint: Fingerprint::DataWord(0x10, 0x1104_12cc), name: "Audio ABI2 (Wave Race 64)", handler: Some(Handler::Audio(AudioAbi::Abi2)) },
+    KnownUcode { fingerprint: Fingerprint::DataWord(0x10, 0x1cd0_1250), name: "Audio ABI2 (F-Zero X)", handler: Some(Handler::Audio(AudioAbi::Abi2)) },
+    KnownUcode { fingerprint: Fingerprint::DataWord(0x10, 0x1f08_122c), name: "Audio ABI2", handler: Some(Handler::Audio(AudioAbi::Abi2)) },
+    KnownUcode { fingerprint: Fingerprint::DataWord(0x10, 0x1f38_122c), name: "Audio ABI2", handler: Some(Handler::Audio(AudioAbi::Abi2)) },
+    KnownUcode { fingerprint: Fingerprint::DataWord(0x10, 0x1f40_1230), name: "Audio ABI2", handler: Some(Handler::Audio(AudioAbi::Abi2)) },
+    KnownUcode { fingerprint: Fingerprint::DataWord(0x10, 0x1f70_1238), name: "Audio ABI2", handler: Some(Handler::Audio(AudioAbi::Abi2)) },
+    KnownUcode { fingerprint: Fingerprint::DataWord(0x10, 0x0001_0010), name: "Audio MusyX", handler: None },
+];
+
+// Graphics microcodes by the name and version in their version string, e.g.
+// "RSP Gfx ucode F3DEX       fifo 2.08  Yoshitaka Yasumoto 1999 Nintendo."
+// Variants which aren't listed, or are listed without a family, run on the
+// RSP.
+const KNOWN_GFX_UCODES: &'static [(&'static str, &'static [&'static str], Option<GfxMicrocode>)] = &[
+    ("Fast3D", &["2.0D", "2.0E", "2.0F", "2.0G", "2.0H"], Some(GfxMicrocode::Fast3d)),
+
+    ("F3DEX", &["0.95", "0.96", "1.00", "1.21", "1.22", "1.23"], Some(GfxMicrocode::F3dex)),
+    ("F3DEX.NoN", &["1.21", "1.22", "1.23"], Some(GfxMicrocode::F3dex)),
+    ("F3DLX", &["0.95", "0.96", "1.00", "1.21", "1.22", "1.23"], Some(GfxMicrocode::F3dex)),
+    ("F3DLX.NoN", &["1.21", "1.22", "1.23"], Some(GfxMicrocode::F3dex)),
+    ("F3DLX.Rej", &["1.21", "1.22", "1.23"], None),
+    ("F3DLP.Rej", &["1.21", "1.22", "1.23"], None),
+    ("L3DEX", &["1.21", "1.22", "1.23"], None),
+
+    ("F3DEX", &["2.04", "2.04H", "2.05", "2.06", "2.07", "2.08", "2.08H"], Some(GfxMicrocode::F3dex2)),
+    ("F3DEX.NoN", &["2.04", "2.04H", "2.05", "2.06", "2.07", "2.08", "2.08H"], Some(GfxMicrocode::F3dex2)),
+    ("F3DZEX", &["2.06H", "2.08I", "2.08J"], Some(GfxMicrocode::F3dex2)),
+    ("F3DZEX.NoN", &["2.06H", "2.08I", "2.08J"], Some(GfxMicrocode::F3dex2)),
+    ("F3DLX", &["2.04", "2.04H", "2.05", "2.06", "2.07", "2.08", "2.08H"], Some(GfxMicrocode::F3dex2)),
+    ("F3DLX.Rej", &["2.04", "2.04H", "2.05", "2.06", "2.07", "2.08", "2.08H"], None),
+    ("F3DLP.Rej", &["2.04", "2.04H", "2.05", "2.06", "2.07", "2.08", "2.08H"], None),
+    ("L3DEX", &["2.04", "2.04H", "2.05", "2.06", "2.07", "2.08", "2.08H"], None),
+];
+
+// Code which the CPU starts directly instead of through the OSTask boot
+// microcode, recognized by the contents of IMEM
+pub fn identify_boot_code(imem: &[u8]) -> Option<UcodeInfo> {
+    let sum = imem.iter().take(44).fold(0u32, |sum, &byte| sum + byte as u32);
+
+    KNOWN_UCODES.iter()
+        .find(|known| match known.fingerprint {
+            Fingerprint::ImemSum(value) => value == sum,
+            _ => false,
+        })
+        .map(|known| UcodeInfo { name: known.name.into(), handler: known.handler })
+}
+
+pub fn identify(task_type: u32, ucode_data: &[u8]) -> Option<UcodeInfo> {
+    if task_type == TASK_TYPE_GFX {
+        return identify_gfx(ucode_data);
+    }
+
+    KNOWN_UCODES.iter()
+        .find(|known| match known.fingerprint {
+            Fingerprint::DataWord(offset, value) => {
+                ucode_data.len() >= offset + 4 && BigEndian::read_u32(&ucode_data[offset..]) == value
+            }
+            _ => false,
+        })
+        .map(|known| UcodeInfo { name: known.name.into(), handler: known.handler })
+}
+
+// Graphics microcodes carry a version string. Ones which aren't in the
+// table are still named after it.
+fn identify_gfx(ucode_data: &[u8]) -> Option<UcodeInfo> {
+    let text = String::from_utf8_lossy(ucode_data);
+
+    let (name, version, full) = if let Some(start) = text.find("RSP Gfx ucode ") {
+        let line = text[start..].split(|c: char| c == '\0' || c == '\n').next().unwrap_or("");
+        let mut words = line[14..].split_whitespace();
+        let name = words.next().unwrap_or("").to_string();
+        let version = words.find(|w| w.starts_with(|c: char| c.is_digit(10))).unwrap_or("").to_string();
+        (name, version, line.split_whitespace().collect::<Vec<_>>().join(" "))
+    } else if let Some(start) = text.find("RSP SW Version: ") {
+        let version: String = text[start + 16..].chars().take_while(|c| c.is_alphanumeric() || *c == '.').collect();
+        let full = format!("Fast3D (RSP SW Version: {})", version);
+        ("Fast3D".to_string(), version, full)
+    } else {
+        return None;
+    };
+
+    let microcode = KNOWN_GFX_UCODES.iter()
+        .find(|&&(known_name, versions, _)| known_name == name && versions.contains(&&version[..]))
+        .and_then(|&(_, _, microcode)| microcode);
+
+    Some(UcodeInfo {
+        name: full,
+        handler: microcode.map(Handler::Gfx),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gfx_handler(version_string: &str) -> Option<Option<Handler>> {
+        let mut data = vec![0; 0x100];
+        data.extend_from_slice(version_string.as_bytes());
+        data.push(0);
+        identify(TASK_TYPE_GFX, &data).map(|info| info.handler)
+    }
+
+    #[test]
+    fn gfx_microcodes_are_identified_by_exact_version() {
+        assert_eq!(gfx_handler("RSP SW Version: 2.0D, 04-01-96"), Some(Some(Handler::Gfx(GfxMicrocode::Fast3d))));
+        assert_eq!(gfx_handler("RSP Gfx ucode F3DEX       1.23 Yoshitaka Yasumoto Nintendo."),
+                   Some(Some(Handler::Gfx(GfxMicrocode::F3dex))));
+        assert_eq!(gfx_handler("RSP Gfx ucode F3DEX       fifo 2.08  Yoshitaka Yasumoto 1999 Nintendo."),
+                   Some(Some(Handler::Gfx(GfxMicrocode::F3dex2))));
+        assert_eq!(gfx_handler("RSP Gfx ucode F3DZEX.NoN  fifo 2.08J Yoshitaka Yasumoto/Kawasedo 1999."),
+                   Some(Some(Handler::Gfx(GfxMicrocode::F3dex2))));
+
+        // Known, but without an HLE implementation
+        assert_eq!(gfx_handler("RSP Gfx ucode L3DEX       fifo 2.08  Yoshitaka Yasumoto 1999 Nintendo."), Some(None));
+        assert_eq!(gfx_handler("RSP Gfx ucode F3DLX.Rej   fifo 2.08  Yoshitaka Yasumoto 1999 Nintendo."), Some(None));
+        // Unknown versions and variants
+        assert_eq!(gfx_handler("RSP Gfx ucode F3DEX       fifo 2.99  Yoshitaka Yasumoto 1999 Nintendo."), Some(None));
+        assert_eq!(gfx_handler("RSP Gfx ucode F3DFOO      1.23 Yoshitaka Yasumoto Nintendo."), Some(None));
+        assert_eq!(gfx_handler("RSP SW Version: 2.0X, 04-01-96"), Some(None));
+
+        assert_eq!(gfx_handler("Not a graphics microcode"), None);
+    }
+}
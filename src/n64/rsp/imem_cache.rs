@@ -0,0 +1,79 @@
+use super::{Instruction, RspCopOpcode, RspOpcode, RspRegImmOpcode, RspSpecialOpcode};
+use super::{VectorLoadOpcode, VectorOpcode, VectorStoreOpcode};
+
+use n64::mem_map::SP_IMEM_LENGTH;
+
+const IMEM_INSTRUCTIONS: usize = (SP_IMEM_LENGTH / 4) as usize;
+
+// The opcode fields of an instruction, decoded down to the operation
+#[derive(Debug, Clone, Copy)]
+pub enum Op {
+    Special(RspSpecialOpcode),
+    RegImm(RspRegImmOpcode),
+    Cop0(RspCopOpcode),
+    Cop2(RspCopOpcode),
+    Vector(VectorOpcode),
+    VectorLoad(VectorLoadOpcode),
+    VectorStore(VectorStoreOpcode),
+    // Every other opcode doesn't have a secondary opcode field
+    Main(RspOpcode),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DecodedInstruction {
+    pub instr: Instruction,
+    pub op: Op,
+}
+
+impl DecodedInstruction {
+    // Panics on invalid opcodes, so only instructions which are actually
+    // executed are decoded
+    pub fn decode(instr: Instruction) -> DecodedInstruction {
+        let op = match instr.opcode() {
+            RspOpcode::Special => Op::Special(instr.special_op()),
+            RspOpcode::RegImm => Op::RegImm(instr.reg_imm_op()),
+            RspOpcode::Cop0 => Op::Cop0(instr.cop_op()),
+            RspOpcode::Cop2 if instr.is_vector_op() => Op::Vector(instr.vector_op()),
+            RspOpcode::Cop2 => Op::Cop2(instr.cop_op()),
+            RspOpcode::Lwc2 => Op::VectorLoad(instr.vector_load_op()),
+            RspOpcode::Swc2 => Op::VectorStore(instr.vector_store_op()),
+            opcode => Op::Main(opcode),
+        };
+
+        DecodedInstruction {
+            instr: instr,
+            op: op,
+        }
+    }
+}
+
+// Decoded instructions for every word of IMEM. Entries are filled when they
+// are first executed and dropped whenever the word is written to.
+#[derive(Debug)]
+pub struct ImemCache {
+    entries: Box<[Option<DecodedInstruction>]>,
+}
+
+impl ImemCache {
+    pub fn new() -> ImemCache {
+        ImemCache {
+            entries: vec![None; IMEM_INSTRUCTIONS].into_boxed_slice(),
+        }
+    }
+
+    pub fn get(&mut self, offset: u32, word: u32) -> DecodedInstruction {
+        let entry = &mut self.entries[(offset as usize >> 2) % IMEM_INSTRUCTIONS];
+        if let Some(decoded) = *entry {
+            return decoded;
+        }
+
+        let decoded = DecodedInstruction::decode(Instruction(word));
+        *entry = Some(decoded);
+        decoded
+    }
+
+    // Drops the entry holding the byte at `offset`
+    pub fn invalidate(&mut self, offset: u32) {
+        self.entries[(offset as usize >> 2) % IMEM_INSTRUCTIONS] = None;
+    }
+}
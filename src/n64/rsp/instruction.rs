@@ -1,6 +1,7 @@
 use std::fmt;
 
-use super::opcode::{RspOpcode, RspSpecialOpcode};
+use super::opcode::{RspCopOpcode, RspOpcode, RspRegImmOpcode, RspSpecialOpcode};
+use super::opcode::{VectorLoadOpcode, VectorOpcode, VectorStoreOpcode};
 
 use num::FromPrimitive;
 
@@ -63,6 +64,85 @@ impl Instruction {
             panic!("Unrecognized RSP special opcode: {:#010x} (op: {:#08b})", self.0, value)
         })
     }
+
+    #[inline(always)]
+    pub fn reg_imm_op(&self) -> RspRegImmOpcode {
+        let value = (self.0 >> 16) & 0b11111;
+        RspRegImmOpcode::from_u32(value).unwrap_or_else(|| {
+            panic!("Unrecognized RSP reg imm opcode: {:#010x} (op: {:#07b})", self.0, value)
+        })
+    }
+
+    #[inline(always)]
+    pub fn cop_op(&self) -> RspCopOpcode {
+        let value = self.rs() as u32;
+        RspCopOpcode::from_u32(value).unwrap_or_else(|| {
+            panic!("Unrecognized RSP cop opcode: {:#010x} (op: {:#07b})", self.0, value)
+        })
+    }
+
+    #[inline(always)]
+    pub fn vector_load_op(&self) -> VectorLoadOpcode {
+        let value = self.rd();
+        VectorLoadOpcode::from_u32(value).unwrap_or_else(|| {
+            panic!("Unrecognized RSP vector load opcode: {:#010x} (op: {:#07b})", self.0, value)
+        })
+    }
+
+    #[inline(always)]
+    pub fn vector_store_op(&self) -> VectorStoreOpcode {
+        let value = self.rd();
+        VectorStoreOpcode::from_u32(value).unwrap_or_else(|| {
+            panic!("Unrecognized RSP vector store opcode: {:#010x} (op: {:#07b})", self.0, value)
+        })
+    }
+
+    #[inline(always)]
+    pub fn is_vector_op(&self) -> bool {
+        (self.0 & (1 << 25)) != 0
+    }
+
+    #[inline(always)]
+    pub fn vector_op(&self) -> VectorOpcode {
+        let value = self.0 & 0b111111;
+        VectorOpcode::from_u32(value).unwrap_or_else(|| {
+            panic!("Unrecognized RSP vector opcode: {:#010x} (op: {:#08b})", self.0, value)
+        })
+    }
+
+    // Vector instructions reuse the rt, rd and sa fields for vt, vs and vd
+    #[inline(always)]
+    pub fn vt(&self) -> usize {
+        self.rt()
+    }
+
+    #[inline(always)]
+    pub fn vs(&self) -> usize {
+        self.rd() as usize
+    }
+
+    #[inline(always)]
+    pub fn vd(&self) -> usize {
+        self.sa() as usize
+    }
+
+    // Element selector of vt for computational instructions
+    #[inline(always)]
+    pub fn element(&self) -> usize {
+        ((self.0 >> 21) & 0b1111) as usize
+    }
+
+    // Byte element for vector loads/stores and COP2 moves
+    #[inline(always)]
+    pub fn byte_element(&self) -> u32 {
+        (self.0 >> 7) & 0b1111
+    }
+
+    // Signed 7-bit offset of vector loads/stores, not yet scaled by the access size
+    #[inline(always)]
+    pub fn vector_offset(&self) -> u32 {
+        (((self.0 & 0x7f) << 25) as i32 >> 25) as u32
+    }
 }
 
 impl fmt::Debug for Instruction {
@@ -1,9 +1,21 @@
+mod div_rom;
+mod hle;
+mod imem_cache;
 mod instruction;
 mod opcode;
 mod rsp;
+mod vector_simd;
+mod vector_unit;
 
+pub use self::hle::Hle;
 pub use self::instruction::Instruction;
+pub use self::opcode::RspCopOpcode;
 pub use self::opcode::RspOpcode;
+pub use self::opcode::RspRegImmOpcode;
 pub use self::opcode::RspSpecialOpcode;
+pub use self::opcode::VectorLoadOpcode;
+pub use self::opcode::VectorOpcode;
+pub use self::opcode::VectorStoreOpcode;
 pub use self::rsp::Rsp;
 pub use self::rsp::RspRegs;
+pub use self::vector_unit::VectorUnit;
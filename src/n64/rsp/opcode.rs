@@ -1,23 +1,41 @@
 enum_from_primitive! {
-    #[derive(Debug)]
+    #[derive(Debug, Clone, Copy)]
     pub enum RspOpcode {
         Special = 0b000000,
+        RegImm =  0b000001,
         J =       0b000010,
+        Jal =     0b000011,
+        Beq =     0b000100,
+        Bne =     0b000101,
+        Blez =    0b000110,
+        Bgtz =    0b000111,
         Addi =    0b001000,
         Addiu =   0b001001,
+        Slti =    0b001010,
+        Sltiu =   0b001011,
         Andi =    0b001100,
         Ori =     0b001101,
         Xori =    0b001110,
         Lui =     0b001111,
+        Cop0 =    0b010000,
+        Cop2 =    0b010010,
+        Lb =      0b100000,
+        Lh =      0b100001,
         Lw =      0b100011,
+        Lbu =     0b100100,
+        Lhu =     0b100101,
+        Lwu =     0b100111,
+        Sb =      0b101000,
         Sh =      0b101001,
         Sw =      0b101011,
+        Lwc2 =    0b110010,
+        Swc2 =    0b111010,
     }
 
 }
 
 enum_from_primitive! {
-    #[derive(Debug)]
+    #[derive(Debug, Clone, Copy)]
     pub enum RspSpecialOpcode {
         Sll =    0b000000,
         Srl =    0b000010,
@@ -25,6 +43,8 @@ enum_from_primitive! {
         Sllv =   0b000100,
         Srlv =   0b000110,
         Srav =   0b000111,
+        Jr =     0b001000,
+        Jalr =   0b001001,
         Break =  0b001101,
         Add =    0b100000,
         Addu =   0b100001,
@@ -34,5 +54,109 @@ enum_from_primitive! {
         Or =     0b100101,
         Xor =    0b100110,
         Nor =    0b100111,
+        Slt =    0b101010,
+        Sltu =   0b101011,
     }
-}
\ No newline at end of file
+}
+
+enum_from_primitive! {
+    #[derive(Debug, Clone, Copy)]
+    pub enum RspRegImmOpcode {
+        Bltz =   0b00000,
+        Bgez =   0b00001,
+        Bltzal = 0b10000,
+        Bgezal = 0b10001,
+    }
+}
+
+enum_from_primitive! {
+    #[derive(Debug, Clone, Copy)]
+    pub enum VectorOpcode {
+        Vmulf =  0b000000,
+        Vmulu =  0b000001,
+        Vmudl =  0b000100,
+        Vmudm =  0b000101,
+        Vmudn =  0b000110,
+        Vmudh =  0b000111,
+        Vmacf =  0b001000,
+        Vmacu =  0b001001,
+        Vmadl =  0b001100,
+        Vmadm =  0b001101,
+        Vmadn =  0b001110,
+        Vmadh =  0b001111,
+        Vadd =   0b010000,
+        Vsub =   0b010001,
+        Vabs =   0b010011,
+        Vaddc =  0b010100,
+        Vsubc =  0b010101,
+        Vsar =   0b011101,
+        Vlt =    0b100000,
+        Veq =    0b100001,
+        Vne =    0b100010,
+        Vge =    0b100011,
+        Vcl =    0b100100,
+        Vch =    0b100101,
+        Vcr =    0b100110,
+        Vmrg =   0b100111,
+        Vand =   0b101000,
+        Vnand =  0b101001,
+        Vor =    0b101010,
+        Vnor =   0b101011,
+        Vxor =   0b101100,
+        Vnxor =  0b101101,
+        Vrcp =   0b110000,
+        Vrcpl =  0b110001,
+        Vrcph =  0b110010,
+        Vmov =   0b110011,
+        Vrsq =   0b110100,
+        Vrsql =  0b110101,
+        Vrsqh =  0b110110,
+        Vnop =   0b110111,
+    }
+}
+
+// Move instructions shared by COP0 and COP2, encoded in the rs field
+enum_from_primitive! {
+    #[derive(Debug, Clone, Copy)]
+    pub enum RspCopOpcode {
+        Mf = 0b00000,
+        Cf = 0b00010,
+        Mt = 0b00100,
+        Ct = 0b00110,
+    }
+}
+
+enum_from_primitive! {
+    #[derive(Debug, Clone, Copy)]
+    pub enum VectorLoadOpcode {
+        Lbv = 0b00000,
+        Lsv = 0b00001,
+        Llv = 0b00010,
+        Ldv = 0b00011,
+        Lqv = 0b00100,
+        Lrv = 0b00101,
+        Lpv = 0b00110,
+        Luv = 0b00111,
+        Lhv = 0b01000,
+        Lfv = 0b01001,
+        Ltv = 0b01011,
+    }
+}
+
+enum_from_primitive! {
+    #[derive(Debug, Clone, Copy)]
+    pub enum VectorStoreOpcode {
+        Sbv = 0b00000,
+        Ssv = 0b00001,
+        Slv = 0b00010,
+        Sdv = 0b00011,
+        Sqv = 0b00100,
+        Srv = 0b00101,
+        Spv = 0b00110,
+        Suv = 0b00111,
+        Shv = 0b01000,
+        Sfv = 0b01001,
+        Swv = 0b01010,
+        Stv = 0b01011,
+    }
+}
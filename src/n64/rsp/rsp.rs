@@ -1,24 +1,30 @@
 use byteorder::{BigEndian, ByteOrder};
 
-use n64::mem_map::{SP_DMEM_START, SP_IMEM_START};
+use std::fmt;
+
+use n64::mem_map;
 use n64::mem_map::{SP_DMEM_LENGTH, SP_IMEM_LENGTH};
-use n64::dma::DMARequest;
-use n64::Interconnect;
+use n64::{Interconnect, Interrupt, MipsInterface};
 
-use super::Instruction;
+use super::{Hle, Instruction, VectorUnit};
+use super::imem_cache::{DecodedInstruction, ImemCache, Op};
+use super::RspCopOpcode::*;
 use super::RspOpcode::*;
+use super::RspRegImmOpcode::*;
 use super::RspSpecialOpcode::*;
 
+// Transfer rate used to time SP DMAs
+const DMA_BYTES_PER_CYCLE: u32 = 8;
+
 #[derive(Debug)]
 pub enum RspHleOperation {
-    Cicx105Ucode,
+    StartTask,
 }
 
 #[derive(Debug)]
 pub struct RspStatusReg {
     pub halt: bool,
     pub broke: bool,
-    pub interrupt_enable: bool,
     pub clear: bool,
     pub sstep: bool,
     pub interrupt_on_break: bool,
@@ -32,6 +38,28 @@ pub struct RspStatusReg {
     pub signal7: bool,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SpDmaDirection {
+    ToRsp,   // SP_RD_LEN_REG
+    ToRdram, // SP_WR_LEN_REG
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SpDma {
+    direction: SpDmaDirection,
+    mem_addr: u32, // Bit 12 selects IMEM
+    dram_addr: u32,
+    length: u32, // Per row
+    count: u32,
+    skip: u32,
+}
+
+impl SpDma {
+    fn total_length(&self) -> u32 {
+        self.length * self.count
+    }
+}
+
 #[derive(Debug)]
 pub struct RspRegs {
     pc: u32,
@@ -40,21 +68,35 @@ pub struct RspRegs {
 
     dram_addr: u32,
     mem_addr: u32,
-    dma_read: DMARequest,
+    rd_len: u32,
+    wr_len: u32,
+
+    semaphore: bool,
+
+    // The DMA engine holds the running transfer and one queued behind it
+    dma_current: Option<SpDma>,
+    dma_pending: Option<SpDma>,
+    dma_cycles: u32,
 
     // Memory
     dmem: Box<[u8]>,
     imem: Box<[u8]>,
+    imem_cache: ImemCache,
 
     // Pending HLE operation
     pub hle_operation: Option<RspHleOperation>,
 }
 
-#[derive(Debug)]
 pub struct Rsp {
     reg_gpr: [u32; 32],
+    vu: VectorUnit,
+    hle: Hle,
 
     delay_slot_pc: Option<u32>,
+
+    // Set when the last instruction polled a register which is busy, ends
+    // the current burst
+    waiting: bool,
 }
 
 impl RspRegs {
@@ -67,7 +109,6 @@ impl RspRegs {
             status: RspStatusReg {
                 halt: true,
                 broke: false,
-                interrupt_enable: false,
                 clear: false,
                 sstep: false,
                 interrupt_on_break: false,
@@ -82,16 +123,32 @@ impl RspRegs {
             },
 
             dram_addr: 0,
-            mem_addr: SP_DMEM_START,
-            dma_read: DMARequest::default(),
+            mem_addr: 0,
+            rd_len: 0,
+            wr_len: 0,
+
+            semaphore: false,
+
+            dma_current: None,
+            dma_pending: None,
+            dma_cycles: 0,
 
             dmem: vec![0; SP_DMEM_LENGTH as usize].into_boxed_slice(),
             imem: vec![0; SP_IMEM_LENGTH as usize].into_boxed_slice(),
+            imem_cache: ImemCache::new(),
 
             hle_operation: None,
         }
     }
 
+    pub fn pc(&self) -> u32 {
+        self.pc
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.status.halt || self.status.broke
+    }
+
     pub fn read_dmem(&self, offset: u32) -> u32 {
         BigEndian::read_u32(&self.dmem[offset as usize..])
     }
@@ -103,6 +160,16 @@ impl RspRegs {
     }
     pub fn write_imem(&mut self, offset: u32, value: u32) {
         BigEndian::write_u32(&mut self.imem[offset as usize..], value);
+        self.imem_cache.invalidate(offset);
+    }
+    pub fn imem(&self) -> &[u8] {
+        &self.imem
+    }
+    pub fn dmem(&self) -> &[u8] {
+        &self.dmem
+    }
+    pub fn dmem_mut(&mut self) -> &mut [u8] {
+        &mut self.dmem
     }
     pub fn read_dmem_byte(&self, offset: u32) -> u8 {
         self.dmem[offset as usize]
@@ -115,42 +182,140 @@ impl RspRegs {
     }
     pub fn write_imem_byte(&mut self, offset: u32, value: u8) {
         self.imem[offset as usize] = value;
+        self.imem_cache.invalidate(offset);
     }
 
+    fn fetch_instruction(&mut self, pc: u32) -> DecodedInstruction {
+        let word = self.read_imem(pc & 0x0ffc);
+        self.imem_cache.get(pc, word)
+    }
 
+    // Advances the DMA engine by one cycle. A transfer is carried out once
+    // enough cycles for its length have passed, after which the queued one
+    // is started.
+    pub fn step_dma(&mut self, rdram: &mut [u8]) {
+        let dma = match self.dma_current {
+            Some(dma) => dma,
+            None => return,
+        };
+
+        if self.dma_cycles > 0 {
+            self.dma_cycles -= 1;
+            return;
+        }
+
+        self.do_dma(dma, rdram);
+
+        self.dma_current = self.dma_pending.take();
+        if let Some(next) = self.dma_current {
+            self.dma_cycles = next.total_length() / DMA_BYTES_PER_CYCLE;
+        }
+    }
+
+    fn do_dma(&mut self, dma: SpDma, rdram: &mut [u8]) {
+        println!("[RSP][DMA] {:?}", dma);
+
+        let mut mem_addr = dma.mem_addr & 0x0ff8;
+        let mut dram_addr = dma.dram_addr & 0x00ff_fff8;
+        let to_imem = (dma.mem_addr & 0x1000) != 0;
+        let mem = if to_imem { &mut self.imem } else { &mut self.dmem };
+
+        for _ in 0..dma.count {
+            for i in 0..dma.length {
+                // Each row wraps around within the selected memory, RDRAM
+                // accesses past the installed memory are dropped
+                let mem_offset = ((mem_addr + i) & 0x0fff) as usize;
+                let dram_offset = (dram_addr + i) as usize;
+                match dma.direction {
+                    SpDmaDirection::ToRsp => {
+                        mem[mem_offset] = rdram.get(dram_offset).cloned().unwrap_or(0);
+                        if to_imem {
+                            self.imem_cache.invalidate(mem_offset as u32);
+                        }
+                    }
+                    SpDmaDirection::ToRdram => {
+                        if let Some(byte) = rdram.get_mut(dram_offset) {
+                            *byte = mem[mem_offset];
+                        }
+                    }
+                }
+            }
+            mem_addr = (mem_addr + dma.length) & 0x0fff;
+            dram_addr = (dram_addr + dma.length + dma.skip) & 0x00ff_fff8;
+        }
+
+        // The address registers point past the transfer when it is done,
+        // the length reads back as 0xff8 with a row count of zero
+        self.mem_addr = (dma.mem_addr & 0x1000) | mem_addr;
+        self.dram_addr = dram_addr;
+        let len = (dma.skip << 20) | 0x0ff8;
+        match dma.direction {
+            SpDmaDirection::ToRsp => self.rd_len = len,
+            SpDmaDirection::ToRdram => self.wr_len = len,
+        }
+    }
+
+    fn queue_dma(&mut self, direction: SpDmaDirection, value: u32) {
+        let dma = SpDma {
+            direction: direction,
+            mem_addr: self.mem_addr,
+            dram_addr: self.dram_addr,
+            length: ((value & 0x0fff) | 0b111) + 1,
+            count: ((value >> 12) & 0xff) + 1,
+            skip: (value >> 20) & 0x0fff,
+        };
+
+        if self.dma_current.is_none() {
+            self.dma_cycles = dma.total_length() / DMA_BYTES_PER_CYCLE;
+            self.dma_current = Some(dma);
+        } else if self.dma_pending.is_none() {
+            self.dma_pending = Some(dma);
+        } else {
+            println!("WARNING: Discarding RSP DMA while the queue is full: {:?}", dma);
+        }
+    }
 
-    pub fn get_dma_read_chunk(&mut self) -> DMARequest {
-        self.dma_read.get_chunk(8192) // Completely arbitrary chunk size...
+    pub fn read_mem_addr_reg(&self) -> u32 {
+        self.mem_addr
     }
 
     pub fn write_mem_addr_reg(&mut self, value: u32) {
-        self.mem_addr = value & 0x1fff + SP_DMEM_START;
+        self.mem_addr = value & 0x1ff8;
+    }
+
+    pub fn read_dram_addr_reg(&self) -> u32 {
+        self.dram_addr
     }
 
     pub fn write_dram_addr_reg(&mut self, value: u32) {
         self.dram_addr = value & 0x00ff_ffff;
     }
 
+    pub fn read_rd_len_reg(&self) -> u32 {
+        self.rd_len
+    }
+
     pub fn write_rd_len_reg(&mut self, value: u32) {
-        let count = (value >> 12) & 0xff;
-        let skip = (value >> 20);
+        self.rd_len = value;
+        self.queue_dma(SpDmaDirection::ToRsp, value);
+    }
 
-        if count != 0 { panic!("Multi-part DMAs not yet supported by RSP {} {}", count, skip); }
+    pub fn read_wr_len_reg(&self) -> u32 {
+        self.wr_len
+    }
 
-        self.dma_read = DMARequest {
-            from: self.dram_addr,
-            to: self.mem_addr,
-            length: (value & 0x0ffc) + 4,
-        };
-        println!("[RSP][DMA] from {:?}", self.dma_read);
+    pub fn write_wr_len_reg(&mut self, value: u32) {
+        self.wr_len = value;
+        self.queue_dma(SpDmaDirection::ToRdram, value);
     }
 
-    // TODO: Read dma/single-step regs
+    // IO full (bit 4) is never set, writes from the CPU are not queued
     pub fn read_status_reg(&self) -> u32 {
         (if self.status.halt               { 1 } else { 0 } <<  0) |
-        (if self.status.interrupt_enable   { 1 } else { 0 } <<  1) |
-        (if self.dma_read.is_pending()     { 1 } else { 0 } <<  2) |
-        // The following are still missing: [2] dma busy [3] dma full [4] io full [5] single step
+        (if self.status.broke              { 1 } else { 0 } <<  1) |
+        (if self.dma_current.is_some()     { 1 } else { 0 } <<  2) |
+        (if self.dma_pending.is_some()     { 1 } else { 0 } <<  3) |
+        (if self.status.sstep              { 1 } else { 0 } <<  5) |
         (if self.status.interrupt_on_break { 1 } else { 0 } <<  6) |
         (if self.status.signal0            { 1 } else { 0 } <<  7) |
         (if self.status.signal1            { 1 } else { 0 } <<  8) |
@@ -162,7 +327,7 @@ impl RspRegs {
         (if self.status.signal7            { 1 } else { 0 } << 14)
     }
 
-    pub fn write_status_reg(&mut self, value: u32) {
+    pub fn write_status_reg(&mut self, value: u32, mi: &mut MipsInterface) {
         let is_halted_or_broke = self.status.halt ||
                                  self.status.broke;
 
@@ -172,8 +337,8 @@ impl RspRegs {
 
         if (value & (1 <<  2)) != 0 { self.status.broke = false; }
 
-        if (value & (1 <<  3)) != 0 { self.status.interrupt_enable = false; }
-        if (value & (1 <<  4)) != 0 { self.status.interrupt_enable = true; }
+        if (value & (1 <<  3)) != 0 { mi.clear_interrupt(Interrupt::Sp); }
+        if (value & (1 <<  4)) != 0 { mi.raise_interrupt(Interrupt::Sp); }
 
         if (value & (1 <<  5)) != 0 { self.status.sstep = false; }
         if (value & (1 <<  6)) != 0 { self.status.sstep = true; }
@@ -209,32 +374,41 @@ impl RspRegs {
 
     pub fn try_hle_emulation(&mut self)
     {
-        self.hle_operation = None;
-
-        let task_ucode_boot_size = self.read_dmem(0xfcc);
-        if (task_ucode_boot_size > 1000)
-        {
-            let mut sum: u32 = 0;
-            for i in 0..44 {
-                sum += self.imem[i as usize] as u32;
-            }
-            if sum == 0x09e2 {
-                self.hle_operation = Some(RspHleOperation::Cicx105Ucode);
-            }
-        }
+        // The task header is looked at when the RSP steps next
+        self.hle_operation = Some(RspHleOperation::StartTask);
     }
 
     pub fn read_dma_busy_reg(&self) -> u32 {
-        println!("WARNING: Reading SP_DMA_BUSY_REG: {:#08X}", self.pc);
-        0
+        if self.dma_current.is_some() { 1 } else { 0 }
+    }
+
+    pub fn read_dma_full_reg(&self) -> u32 {
+        if self.dma_pending.is_some() { 1 } else { 0 }
+    }
+
+    // Signal 2 is the task done flag of the OS
+    pub fn set_task_done(&mut self) {
+        self.status.signal2 = true;
+    }
+
+    pub fn set_break(&mut self, mi: &mut MipsInterface) {
+        self.status.broke = true;
+        self.status.halt = true;
+        if self.status.interrupt_on_break {
+            mi.raise_interrupt(Interrupt::Sp);
+        }
     }
 
-    pub fn write_dma_busy_reg(&self, value: u32) {
-        panic!("Attempted write to SP_DMA_BUSY: {:#?}", value);
+    // Reading returns the current value and acquires the semaphore
+    pub fn read_semaphore_reg(&mut self) -> u32 {
+        let value = if self.semaphore { 1 } else { 0 };
+        self.semaphore = true;
+        value
     }
 
-    pub fn write_semaphore_reg(&self, value: u32) {
-        println!("WARNING: Writing to SP_SEMAPHORE_REG to {:#08X}", value);
+    // Any write releases it
+    pub fn write_semaphore_reg(&mut self, _: u32) {
+        self.semaphore = false;
     }
 
     pub fn read_pc_reg(&self) -> u32 {
@@ -252,76 +426,108 @@ impl Rsp {
     pub fn new() -> Rsp {
         Rsp {
             reg_gpr: [0; 32],
+            vu: VectorUnit::new(),
+            hle: Hle::new(),
             delay_slot_pc: None,
+            waiting: false,
         }
     }
 
-    pub fn step(&mut self, interconnect: &mut Interconnect) {
-        // TODO: Find out how halt and break work together
-        if interconnect.rsp().status.halt || interconnect.rsp().status.broke {
-            return;
-        }
+    pub fn hle(&self) -> &Hle {
+        &self.hle
+    }
 
-        if let Some(hle_op) = interconnect.rsp().hle_operation.take() {
-            match hle_op {
-                RspHleOperation::Cicx105Ucode => {
-                    println!("[RSP][HLE] CIC x105 uCode emulation");
+    pub fn vu(&self) -> &VectorUnit {
+        &self.vu
+    }
 
-                    // dma_read(0x1120, 0x1e8, 0x1e8)
-                    for i in 0..0x7C {
-                        let val = interconnect.read_word(0x01e8 + i * 4);
-                        interconnect.write_word(0x0400_1000 + 0x0120 + i * 4, val);
-                    }
+    // Address of the next instruction to execute
+    pub fn current_pc(&self, rsp: &RspRegs) -> u32 {
+        self.delay_slot_pc.unwrap_or(rsp.pc())
+    }
 
-                    /* dma_write(0x1120, 0x2fb1f0, 0xfe817000) */
-                    let mut dst_addr = 0x002f_b1f0;
-                    let mut src_imem_addr = 0x0120;
-                    for i in 0..24 {
-                        let val1 = interconnect.read_word(0x0400_1000 + src_imem_addr + 0);
-                        let val2 = interconnect.read_word(0x0400_1000 + src_imem_addr + 4);
-                        interconnect.write_word(dst_addr + 0, val1);
-                        interconnect.write_word(dst_addr + 4, val2);
-                        dst_addr += 0xff0;
-                        src_imem_addr += 0x8;
+    pub fn will_execute_from_delay_slot(&self) -> bool {
+        self.delay_slot_pc.is_some()
+    }
 
-                    }
+    // Runs up to `max_instructions` until the RSP halts or starts waiting on
+    // the semaphore or a DMA, or `stop` returns true for the address of the
+    // next instruction. The DMA engine advances by a cycle per instruction.
+    // Returns the number of instructions executed.
+    pub fn run<F>(&mut self, interconnect: &mut Interconnect, max_instructions: usize, mut stop: F) -> usize
+        where F: FnMut(u32) -> bool
+    {
+        for executed in 0..max_instructions {
+            if interconnect.rsp().is_halted() || stop(self.current_pc(interconnect.rsp_regs())) {
+                return executed;
+            }
 
-                    interconnect.rsp().status.broke = true;
-                    interconnect.rsp().status.halt = true;
+            self.step(interconnect);
+            interconnect.step_rsp_dma(1);
+            if self.waiting {
+                return executed + 1;
+            }
+        }
+        max_instructions
+    }
 
-                    return;
-                }
+    pub fn step(&mut self, interconnect: &mut Interconnect) {
+        // TODO: Find out how halt and break work together
+        if interconnect.rsp().status.halt || interconnect.rsp().status.broke {
+            return;
+        }
+
+        if let Some(RspHleOperation::StartTask) = interconnect.rsp().hle_operation.take() {
+            if self.hle.start_task(interconnect) {
+                return;
             }
+            // No HLE implementation, run the task on the RSP
         }
 
+        self.waiting = false;
+
         if let Some(pc) = self.delay_slot_pc {
-            let instr = self.read_instruction(interconnect, pc);
+            let decoded = interconnect.rsp().fetch_instruction(pc);
             self.delay_slot_pc = None;
 
-            self.execute_instruction(interconnect, instr);
+            self.execute_instruction(interconnect, decoded);
         } else {
             let reg_pc = interconnect.rsp().pc;
-            let instr = self.read_instruction(interconnect, reg_pc);
+            let decoded = interconnect.rsp().fetch_instruction(reg_pc);
 
             let new_pc = reg_pc + 4;
             interconnect.rsp().pc = new_pc & 0x0fff;
-            self.execute_instruction(interconnect, instr);
+            self.execute_instruction(interconnect, decoded);
         }
 
+        // In single-step mode the RSP halts again after every instruction
+        if interconnect.rsp().status.sstep {
+            interconnect.rsp().status.halt = true;
+        }
     }
 
-    pub fn execute_instruction(&mut self, interconnect: &mut Interconnect, instr: Instruction) {
-        match instr.opcode() {
-            Special => {
-                match instr.special_op() {
+    fn execute_instruction(&mut self, interconnect: &mut Interconnect, decoded: DecodedInstruction) {
+        let instr = decoded.instr;
+        match decoded.op {
+            Op::Special(op) => {
+                match op {
                     Sll => self.reg_instr(instr, |_, rt, sa| rt << sa),
                     Srl => self.reg_instr(instr, |_, rt, sa| rt >> sa),
                     Sra => self.reg_instr(instr, |_, rt, sa| ((rt as i32) >> sa) as u32),
                     Sllv => self.reg_instr(instr, |rs, rt, _| rt << (rs & 0b11111)),
                     Srlv => self.reg_instr(instr, |rs, rt, _| rt >> (rs & 0b11111)),
                     Srav => self.reg_instr(instr, |rs, rt, _| ((rt as i32) >> (rs & 0b11111)) as u32),
+                    Jr => {
+                        let jump_to = self.read_reg_gpr(instr.rs());
+                        self.jump(interconnect, jump_to);
+                    }
+                    Jalr => {
+                        let jump_to = self.read_reg_gpr(instr.rs());
+                        let link = self.jump(interconnect, jump_to);
+                        self.write_reg_gpr(instr.rd() as usize, link);
+                    }
                     Break => {
-                        interconnect.rsp().status.broke = true;
+                        interconnect.rsp_break();
                         interconnect.rsp().pc = 0;
                     }
                     Add => self.reg_instr(instr, |rs, rt, _| rs.wrapping_add(rt)), // Todo handle overflow exception (or is there none?)
@@ -332,48 +538,167 @@ impl Rsp {
                     Or => self.reg_instr(instr, |rs, rt, _| rs | rt),
                     Xor => self.reg_instr(instr, |rs, rt, _| rs ^ rt),
                     Nor => self.reg_instr(instr, |rs, rt, _| !(rs | rt)),
+                    Slt => self.reg_instr(instr, |rs, rt, _| ((rs as i32) < (rt as i32)) as u32),
+                    Sltu => self.reg_instr(instr, |rs, rt, _| (rs < rt) as u32),
                 };
             }
-            J => {
-                let delay_slot_pc = interconnect.rsp().pc;
-                let jump_to = (instr.target() << 2) & 0x0fff;
-                interconnect.rsp().pc = jump_to;
-                self.delay_slot_pc = Some(delay_slot_pc);
+            Op::RegImm(op) => {
+                match op {
+                    Bltz => { self.branch(interconnect, instr, |rs, _| (rs as i32) < 0); }
+                    Bgez => { self.branch(interconnect, instr, |rs, _| (rs as i32) >= 0); }
+                    Bltzal => {
+                        let link = self.branch(interconnect, instr, |rs, _| (rs as i32) < 0);
+                        self.write_reg_gpr(31, link);
+                    }
+                    Bgezal => {
+                        let link = self.branch(interconnect, instr, |rs, _| (rs as i32) >= 0);
+                        self.write_reg_gpr(31, link);
+                    }
+                }
+            }
+            Op::Cop0(op) => {
+                let addr = mem_map::rsp_cop0_reg_addr(instr.rd());
+                match op {
+                    Mf => {
+                        let value = interconnect.read_word(addr);
+                        self.write_reg_gpr(instr.rt(), value);
+                        self.waiting = is_busy(instr.rd(), value);
+                    }
+                    Mt => {
+                        let value = self.read_reg_gpr(instr.rt());
+                        interconnect.write_word(addr, value);
+                    }
+                    _ => panic!("Unrecognized RSP COP0 instruction: {:#010x}", instr.0),
+                }
             }
-            Addi => self.imm_instr(instr, |rs, _, imm_sign_extended| rs.wrapping_add(imm_sign_extended)),
-            Addiu => self.imm_instr(instr, |rs, _, imm_sign_extended| rs.wrapping_add(imm_sign_extended)),
-            Andi => self.imm_instr(instr, |rs, imm, _| rs & imm),
-            Ori => self.imm_instr(instr, |rs, imm, _| rs | imm),
-            Xori => self.imm_instr(instr, |rs, imm, _| rs ^ imm),
-            Lui => self.imm_instr(instr, |_, imm, _| imm << 16),
-            Lw => {
-                let base = self.read_reg_gpr(instr.rs());
-                let sign_extended_offset = instr.offset_sign_extended();
-                let dmem_addr = base.wrapping_add(sign_extended_offset) & 0x0fff;
-                let mem = interconnect.read_word(SP_DMEM_START + dmem_addr);
-                self.reg_gpr[instr.rt()] = mem;
+            Op::Vector(op) => self.vu.execute(op, instr),
+            Op::Cop2(op) => {
+                match op {
+                    Mf => {
+                        let value = self.vu.move_from(instr.vs(), instr.byte_element());
+                        self.write_reg_gpr(instr.rt(), value);
+                    }
+                    Cf => {
+                        let value = self.vu.read_control(instr.rd());
+                        self.write_reg_gpr(instr.rt(), value);
+                    }
+                    Mt => {
+                        let value = self.read_reg_gpr(instr.rt());
+                        self.vu.move_to(instr.vs(), instr.byte_element(), value);
+                    }
+                    Ct => {
+                        let value = self.read_reg_gpr(instr.rt());
+                        self.vu.write_control(instr.rd(), value);
+                    }
+                }
             }
-            Sh => {
+            Op::VectorLoad(op) => {
                 let base = self.read_reg_gpr(instr.rs());
-                let sign_extended_offset = instr.offset_sign_extended();
-                let dmem_addr = base.wrapping_add(sign_extended_offset) & 0x0fff;
-                let reg = self.read_reg_gpr(instr.rt()) as u16;
-                interconnect.write_byte(SP_DMEM_START + dmem_addr, (reg >> 8) as u8);
-                interconnect.write_byte(SP_DMEM_START + dmem_addr + 1, (reg & 0xff) as u8);
+                self.vu.load(op, instr, base, interconnect.rsp().dmem());
             }
-            Sw => {
+            Op::VectorStore(op) => {
                 let base = self.read_reg_gpr(instr.rs());
-                let sign_extended_offset = instr.offset_sign_extended();
-                let dmem_addr = base.wrapping_add(sign_extended_offset) & 0x0fff;
-                let reg = self.read_reg_gpr(instr.rt());
-                interconnect.write_word(SP_DMEM_START + dmem_addr, reg);
+                self.vu.store(op, instr, base, interconnect.rsp().dmem_mut());
+            }
+            Op::Main(opcode) => {
+                match opcode {
+                    J => {
+                        self.jump(interconnect, instr.target() << 2);
+                    }
+                    Jal => {
+                        let link = self.jump(interconnect, instr.target() << 2);
+                        self.write_reg_gpr(31, link);
+                    }
+                    Beq => { self.branch(interconnect, instr, |rs, rt| rs == rt); }
+                    Bne => { self.branch(interconnect, instr, |rs, rt| rs != rt); }
+                    Blez => { self.branch(interconnect, instr, |rs, _| (rs as i32) <= 0); }
+                    Bgtz => { self.branch(interconnect, instr, |rs, _| (rs as i32) > 0); }
+                    Addi => self.imm_instr(instr, |rs, _, imm_sign_extended| rs.wrapping_add(imm_sign_extended)),
+                    Addiu => self.imm_instr(instr, |rs, _, imm_sign_extended| rs.wrapping_add(imm_sign_extended)),
+                    Slti => self.imm_instr(instr, |rs, _, imm_sign_extended| ((rs as i32) < (imm_sign_extended as i32)) as u32),
+                    Sltiu => self.imm_instr(instr, |rs, _, imm_sign_extended| (rs < imm_sign_extended) as u32),
+                    Andi => self.imm_instr(instr, |rs, imm, _| rs & imm),
+                    Ori => self.imm_instr(instr, |rs, imm, _| rs | imm),
+                    Xori => self.imm_instr(instr, |rs, imm, _| rs ^ imm),
+                    Lui => self.imm_instr(instr, |_, imm, _| imm << 16),
+                    Lb => {
+                        let mem = self.load(interconnect, instr, 1);
+                        self.write_reg_gpr(instr.rt(), mem as i8 as u32);
+                    }
+                    Lh => {
+                        let mem = self.load(interconnect, instr, 2);
+                        self.write_reg_gpr(instr.rt(), mem as i16 as u32);
+                    }
+                    // The scalar registers are only 32 bits wide, so LWU behaves like LW
+                    Lw | Lwu => {
+                        let mem = self.load(interconnect, instr, 4);
+                        self.write_reg_gpr(instr.rt(), mem);
+                    }
+                    Lbu => {
+                        let mem = self.load(interconnect, instr, 1);
+                        self.write_reg_gpr(instr.rt(), mem);
+                    }
+                    Lhu => {
+                        let mem = self.load(interconnect, instr, 2);
+                        self.write_reg_gpr(instr.rt(), mem);
+                    }
+                    Sb => self.store(interconnect, instr, 1),
+                    Sh => self.store(interconnect, instr, 2),
+                    Sw => self.store(interconnect, instr, 4),
+                    Special | RegImm | Cop0 | Cop2 | Lwc2 | Swc2 => unreachable!(),
+                }
             }
         };
     }
 
-    pub fn read_instruction(&self, interconnect: &mut Interconnect, pc: u32) -> Instruction {
-        let word = interconnect.read_word(pc + SP_IMEM_START);
-        Instruction(word)
+    // Returns the link address. The program counter is only 12 bits wide, so
+    // both the target and the link address wrap around within IMEM.
+    fn jump(&mut self, interconnect: &mut Interconnect, jump_to: u32) -> u32 {
+        let delay_slot_pc = interconnect.rsp().pc;
+        interconnect.rsp().pc = jump_to & 0x0ffc;
+        self.delay_slot_pc = Some(delay_slot_pc);
+
+        (delay_slot_pc + 4) & 0x0fff
+    }
+
+    // Returns the link address, which is also needed when the branch is not taken
+    fn branch<F>(&mut self, interconnect: &mut Interconnect, instr: Instruction, f: F) -> u32
+        where F: FnOnce(u32, u32) -> bool
+    {
+        let rs = self.read_reg_gpr(instr.rs());
+        let rt = self.read_reg_gpr(instr.rt());
+        let delay_slot_pc = interconnect.rsp().pc;
+
+        if f(rs, rt) {
+            let offset = instr.offset_sign_extended() << 2;
+            self.jump(interconnect, delay_slot_pc.wrapping_add(offset))
+        } else {
+            (delay_slot_pc + 4) & 0x0fff
+        }
+    }
+
+    fn dmem_addr(&self, instr: Instruction) -> u32 {
+        let base = self.read_reg_gpr(instr.rs());
+        base.wrapping_add(instr.offset_sign_extended()) & 0x0fff
+    }
+
+    // Scalar accesses don't need to be aligned and wrap around at the end of DMEM
+    fn load(&self, interconnect: &mut Interconnect, instr: Instruction, size: u32) -> u32 {
+        let dmem_addr = self.dmem_addr(instr);
+        let rsp = interconnect.rsp();
+        (0..size).fold(0, |value, i| {
+            (value << 8) | rsp.read_dmem_byte((dmem_addr + i) & 0x0fff) as u32
+        })
+    }
+
+    fn store(&self, interconnect: &mut Interconnect, instr: Instruction, size: u32) {
+        let dmem_addr = self.dmem_addr(instr);
+        let value = self.read_reg_gpr(instr.rt());
+        let rsp = interconnect.rsp();
+        for i in 0..size {
+            let shift = (size - 1 - i) * 8;
+            rsp.write_dmem_byte((dmem_addr + i) & 0x0fff, (value >> shift) as u8);
+        }
     }
 
     fn imm_instr<F>(&mut self, instr: Instruction, f: F)
@@ -410,3 +735,286 @@ impl Rsp {
         }
     }
 }
+
+// Whether a value read from an SP/DP register by the RSP means that the
+// microcode is going to poll it until some other part of the system is done
+fn is_busy(reg: u32, value: u32) -> bool {
+    match reg {
+        4 => (value & 0b1100) != 0, // SP_STATUS, DMA busy or full
+        5 | 6 => value != 0,        // SP_DMA_FULL, SP_DMA_BUSY
+        7 => value != 0,            // SP_SEMAPHORE, already taken
+        _ => false,
+    }
+}
+
+impl fmt::Debug for Rsp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const REGS_PER_LINE: usize = 4;
+
+        write!(f, "RSP General Purpose Registers:")?;
+        for reg_num in 0..self.reg_gpr.len() {
+            if (reg_num % REGS_PER_LINE) == 0 {
+                writeln!(f, "")?;
+            }
+            write!(f, "gpr{:02}: {:#010X} ", reg_num, self.reg_gpr[reg_num])?;
+        }
+
+        if let Some(pc) = self.delay_slot_pc {
+            write!(f, "\n\nDelay slot: {:#05X}", pc)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Row length, row count and skip as written to SP_RD_LEN/SP_WR_LEN
+    fn len_reg(length: u32, count: u32, skip: u32) -> u32 {
+        (skip << 20) | ((count - 1) << 12) | (length - 1)
+    }
+
+    fn rdram() -> Vec<u8> {
+        (0..0x1000).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn finish_dma(regs: &mut RspRegs, rdram: &mut [u8]) {
+        while regs.read_dma_busy_reg() != 0 {
+            regs.step_dma(rdram);
+        }
+    }
+
+    #[test]
+    fn multi_row_dma_to_dmem_skips_between_rows_in_rdram() {
+        let mut regs = RspRegs::new();
+        let mut rdram = rdram();
+        regs.write_mem_addr_reg(0x10);
+        regs.write_dram_addr_reg(0x100);
+        regs.write_rd_len_reg(len_reg(8, 3, 8));
+        finish_dma(&mut regs, &mut rdram);
+
+        for row in 0..3 {
+            let dmem = 0x10 + row * 8;
+            let dram = 0x100 + row * 16;
+            assert_eq!(&regs.dmem()[dmem..dmem + 8], &rdram[dram..dram + 8], "row {}", row);
+        }
+        assert_eq!(regs.dmem()[0x28], 0);
+        assert_eq!(regs.read_mem_addr_reg(), 0x28);
+        assert_eq!(regs.read_dram_addr_reg(), 0x130);
+        assert_eq!(regs.read_rd_len_reg(), (8 << 20) | 0x0ff8);
+    }
+
+    #[test]
+    fn multi_row_dma_to_rdram_leaves_the_skipped_bytes() {
+        let mut regs = RspRegs::new();
+        let mut rdram = vec![0; 0x1000];
+        for i in 0..0x40 {
+            regs.write_dmem_byte(i, i as u8 + 1);
+        }
+        regs.write_mem_addr_reg(0x08);
+        regs.write_dram_addr_reg(0x200);
+        regs.write_wr_len_reg(len_reg(16, 2, 16));
+        finish_dma(&mut regs, &mut rdram);
+
+        assert_eq!(&rdram[0x200..0x210], &regs.dmem()[0x08..0x18]);
+        assert_eq!(&rdram[0x210..0x220], &[0; 16]);
+        assert_eq!(&rdram[0x220..0x230], &regs.dmem()[0x18..0x28]);
+        assert_eq!(rdram[0x230], 0);
+        assert_eq!(regs.read_dram_addr_reg(), 0x240);
+        assert_eq!(regs.read_wr_len_reg(), (16 << 20) | 0x0ff8);
+    }
+
+    #[test]
+    fn bit_12_of_the_address_selects_imem() {
+        let mut regs = RspRegs::new();
+        let mut rdram = rdram();
+        regs.write_mem_addr_reg(0x1000 | 0x20);
+        regs.write_dram_addr_reg(0x40);
+        regs.write_rd_len_reg(len_reg(16, 1, 0));
+        finish_dma(&mut regs, &mut rdram);
+
+        assert_eq!(&regs.imem()[0x20..0x30], &rdram[0x40..0x50]);
+        assert_eq!(&regs.dmem()[0x20..0x30], &[0; 16]);
+        assert_eq!(regs.read_mem_addr_reg(), 0x1000 | 0x30);
+    }
+
+    #[test]
+    fn second_dma_is_queued_and_a_third_is_dropped() {
+        let mut regs = RspRegs::new();
+        let mut rdram = rdram();
+        assert_eq!((regs.read_dma_busy_reg(), regs.read_dma_full_reg()), (0, 0));
+
+        let queue = |regs: &mut RspRegs, mem_addr: u32| {
+            regs.write_mem_addr_reg(mem_addr);
+            regs.write_dram_addr_reg(0x80);
+            regs.write_rd_len_reg(len_reg(0x40, 1, 0));
+        };
+        queue(&mut regs, 0x000);
+        queue(&mut regs, 0x100);
+        assert_eq!((regs.read_dma_busy_reg(), regs.read_dma_full_reg()), (1, 1));
+        assert_eq!(regs.read_status_reg() & 0b1100, 0b1100);
+        queue(&mut regs, 0x200);
+
+        // 0x40 bytes take 8 cycles, the transfer happens on the next one
+        for _ in 0..9 {
+            regs.step_dma(&mut rdram);
+        }
+        assert_eq!((regs.read_dma_busy_reg(), regs.read_dma_full_reg()), (1, 0));
+        assert_eq!(regs.read_status_reg() & 0b1100, 0b0100);
+        assert_eq!(&regs.dmem()[0x000..0x040], &rdram[0x80..0xc0]);
+        assert_eq!(&regs.dmem()[0x100..0x140], &[0; 0x40][..]);
+
+        for _ in 0..9 {
+            regs.step_dma(&mut rdram);
+        }
+        assert_eq!((regs.read_dma_busy_reg(), regs.read_dma_full_reg()), (0, 0));
+        assert_eq!(&regs.dmem()[0x100..0x140], &rdram[0x80..0xc0]);
+        assert_eq!(&regs.dmem()[0x200..0x240], &[0; 0x40][..]);
+    }
+
+    const SP_STATUS_REG: u32 = 0x0404_0010;
+    const MI_INTR_REG: u32 = 0x0430_0008;
+
+    const BREAK: u32 = 0x0000_000d;
+    const DELAY_SLOT: u32 = 0x2408_0005; // addiu $t0, $zero, 5
+
+    // Places `program` at the start of IMEM and starts the RSP with the
+    // SP_STATUS bits in `status` written as well
+    fn start(program: &[u32], status: u32) -> (Rsp, Interconnect) {
+        let mut interconnect = Interconnect::without_roms();
+        for (i, &word) in program.iter().enumerate() {
+            interconnect.rsp().write_imem(i as u32 * 4, word);
+        }
+        interconnect.write_word(SP_STATUS_REG, status | 1 << 0);
+        (Rsp::new(), interconnect)
+    }
+
+    // The same at `pc`, wrapping around the end of IMEM
+    fn start_at(pc: u32, program: &[u32]) -> (Rsp, Interconnect) {
+        let (rsp, mut interconnect) = start(&[], 0);
+        for (i, &word) in program.iter().enumerate() {
+            interconnect.rsp().write_imem((pc + i as u32 * 4) & 0x0ffc, word);
+        }
+        interconnect.rsp().pc = pc;
+        (rsp, interconnect)
+    }
+
+    fn step(rsp: &mut Rsp, interconnect: &mut Interconnect, count: usize) -> u32 {
+        for _ in 0..count {
+            rsp.step(interconnect);
+        }
+        rsp.current_pc(interconnect.rsp_regs())
+    }
+
+    #[test]
+    fn jal_links_past_the_delay_slot_and_wraps_around_imem() {
+        let (mut rsp, mut interconnect) = start_at(0xffc, &[
+            0x0c00_0040, // jal 0x100
+            DELAY_SLOT,
+        ]);
+        assert_eq!(step(&mut rsp, &mut interconnect, 1), 0x000);
+        assert!(rsp.will_execute_from_delay_slot());
+        assert_eq!(rsp.reg_gpr[31], 0x004);
+        assert_eq!(rsp.reg_gpr[8], 0);
+
+        assert_eq!(step(&mut rsp, &mut interconnect, 1), 0x100);
+        assert!(!rsp.will_execute_from_delay_slot());
+        assert_eq!(rsp.reg_gpr[8], 5);
+    }
+
+    #[test]
+    fn jump_targets_are_cut_to_12_bits() {
+        let (mut rsp, mut interconnect) = start_at(0x010, &[
+            0x0800_0440, // j 0x1100
+            DELAY_SLOT,
+        ]);
+        assert_eq!(step(&mut rsp, &mut interconnect, 2), 0x100);
+
+        let (mut rsp, mut interconnect) = start_at(0x004, &[
+            0x340a_1234, // ori $t2, $zero, 0x1234
+            0x0140_4809, // jalr $t1, $t2
+            DELAY_SLOT,
+        ]);
+        assert_eq!(step(&mut rsp, &mut interconnect, 3), 0x234);
+        assert_eq!(rsp.reg_gpr[9], 0x010);
+        assert_eq!(rsp.reg_gpr[8], 5);
+    }
+
+    #[test]
+    fn branches_backwards_wrap_around_imem() {
+        let (mut rsp, mut interconnect) = start_at(0x004, &[
+            0x1000_fffd, // b -3
+            DELAY_SLOT,
+        ]);
+        assert_eq!(step(&mut rsp, &mut interconnect, 1), 0x008);
+        assert_eq!(step(&mut rsp, &mut interconnect, 1), 0xffc);
+        assert_eq!(rsp.reg_gpr[8], 5);
+    }
+
+    #[test]
+    fn branches_and_link_link_whether_taken_or_not() {
+        let (mut rsp, mut interconnect) = start_at(0x010, &[
+            0x3408_0001, // ori $t0, $zero, 1
+            0x0510_0002, // bltzal $t0, 2
+        ]);
+        assert_eq!(step(&mut rsp, &mut interconnect, 2), 0x018);
+        assert!(!rsp.will_execute_from_delay_slot());
+        assert_eq!(rsp.reg_gpr[31], 0x01c);
+
+        let (mut rsp, mut interconnect) = start_at(0x010, &[
+            0x3408_0001, // ori $t0, $zero, 1
+            0x0511_0002, // bgezal $t0, 2
+        ]);
+        assert_eq!(step(&mut rsp, &mut interconnect, 2), 0x018);
+        assert!(rsp.will_execute_from_delay_slot());
+        assert_eq!(step(&mut rsp, &mut interconnect, 1), 0x020);
+        assert_eq!(rsp.reg_gpr[31], 0x01c);
+    }
+
+    #[test]
+    fn reading_the_semaphore_takes_it_and_writing_releases_it() {
+        let mut regs = RspRegs::new();
+        assert_eq!(regs.read_semaphore_reg(), 0);
+        assert_eq!(regs.read_semaphore_reg(), 1);
+        assert_eq!(regs.read_semaphore_reg(), 1);
+
+        // Whatever value is written
+        regs.write_semaphore_reg(0);
+        assert_eq!(regs.read_semaphore_reg(), 0);
+        regs.write_semaphore_reg(1);
+        assert_eq!(regs.read_semaphore_reg(), 0);
+    }
+
+    #[test]
+    fn break_raises_the_sp_interrupt_only_when_asked_to() {
+        for &interrupt_on_break in &[false, true] {
+            let status = if interrupt_on_break { 1 << 8 } else { 1 << 7 };
+            let (mut rsp, mut interconnect) = start(&[BREAK], status);
+            rsp.step(&mut interconnect);
+
+            assert!(interconnect.rsp_regs().is_halted());
+            assert_eq!(interconnect.read_word(SP_STATUS_REG) & 0b11, 0b11);
+            let sp_interrupt = interconnect.read_word(MI_INTR_REG) & 1;
+            assert_eq!(sp_interrupt, interrupt_on_break as u32, "interrupt on break: {}", interrupt_on_break);
+        }
+    }
+
+    #[test]
+    fn single_step_halts_after_one_instruction() {
+        // IMEM is all NOPs, set single-step mode
+        let (mut rsp, mut interconnect) = start(&[], 1 << 6);
+        rsp.step(&mut interconnect);
+        assert_eq!(interconnect.rsp_regs().pc(), 4);
+        assert!(interconnect.rsp_regs().is_halted());
+        assert_eq!(interconnect.read_word(SP_STATUS_REG) & 0b11, 0b01);
+
+        rsp.step(&mut interconnect);
+        assert_eq!(interconnect.rsp_regs().pc(), 4);
+
+        interconnect.write_word(SP_STATUS_REG, 1 << 0);
+        rsp.step(&mut interconnect);
+        assert_eq!(interconnect.rsp_regs().pc(), 8);
+        assert!(interconnect.rsp_regs().is_halted());
+    }
+}
@@ -0,0 +1,619 @@
+// Lane-wise operations of the vector unit. Every operation has a scalar
+// reference implementation, and on x86_64 an SSE2 one, which is what the
+// vector unit uses. Selecting lanes uses SSE4.1 blends when the CPU has them.
+//
+// The clip compares (VCL, VCH, VCR), VABS and the divide unit stay scalar in
+// the vector unit: their flag updates depend on each other lane by lane, and
+// the divide unit works on a single lane.
+
+pub type Lanes = [u16; 8];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogicalOp {
+    And,
+    Nand,
+    Or,
+    Nor,
+    Xor,
+    Nxor,
+}
+
+// The accumulating variants (VMACF, VMADL, ...) share the product and
+// clamping of their non-accumulating counterpart
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MultiplyOp {
+    Mulf,
+    Mulu,
+    Mudl,
+    Mudm,
+    Mudn,
+    Mudh,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Lt,
+    Eq,
+    Ne,
+    Ge,
+}
+
+#[cfg(target_arch = "x86_64")]
+pub use self::sse::{add, add_carry, compare, logical, merge, multiply, sub, sub_carry};
+
+#[cfg(not(target_arch = "x86_64"))]
+pub use self::scalar::{add, add_carry, compare, logical, merge, multiply, sub, sub_carry};
+
+// On x86_64 only the tests use these, as the reference for the SSE versions
+#[cfg(any(test, not(target_arch = "x86_64")))]
+pub mod scalar {
+    use super::{CompareOp, Lanes, LogicalOp, MultiplyOp};
+
+    pub fn logical(op: LogicalOp, vs: &Lanes, vt: &Lanes) -> Lanes {
+        let mut vd = [0; 8];
+        for n in 0..8 {
+            let (s, t) = (vs[n], vt[n]);
+            vd[n] = match op {
+                LogicalOp::And => s & t,
+                LogicalOp::Nand => !(s & t),
+                LogicalOp::Or => s | t,
+                LogicalOp::Nor => !(s | t),
+                LogicalOp::Xor => s ^ t,
+                LogicalOp::Nxor => !(s ^ t),
+            };
+        }
+        vd
+    }
+
+    // Returns the clamped sum and the low accumulator
+    pub fn add(vs: &Lanes, vt: &Lanes, carry: u8) -> (Lanes, Lanes) {
+        let (mut vd, mut acc) = ([0; 8], [0; 8]);
+        for n in 0..8 {
+            let result = vs[n] as i16 as i32 + vt[n] as i16 as i32 + ((carry >> n) & 1) as i32;
+            acc[n] = result as u16;
+            vd[n] = clamp_i16(result);
+        }
+        (vd, acc)
+    }
+
+    pub fn sub(vs: &Lanes, vt: &Lanes, carry: u8) -> (Lanes, Lanes) {
+        let (mut vd, mut acc) = ([0; 8], [0; 8]);
+        for n in 0..8 {
+            let result = vs[n] as i16 as i32 - vt[n] as i16 as i32 - ((carry >> n) & 1) as i32;
+            acc[n] = result as u16;
+            vd[n] = clamp_i16(result);
+        }
+        (vd, acc)
+    }
+
+    // Returns the wrapped sum and the new VCO
+    pub fn add_carry(vs: &Lanes, vt: &Lanes) -> (Lanes, u16) {
+        let mut vd = [0; 8];
+        let mut vco = 0;
+        for n in 0..8 {
+            let result = vs[n] as u32 + vt[n] as u32;
+            if result > 0xffff {
+                vco |= 1 << n;
+            }
+            vd[n] = result as u16;
+        }
+        (vd, vco)
+    }
+
+    pub fn sub_carry(vs: &Lanes, vt: &Lanes) -> (Lanes, u16) {
+        let mut vd = [0; 8];
+        let mut vco = 0;
+        for n in 0..8 {
+            let result = vs[n] as i32 - vt[n] as i32;
+            if result < 0 {
+                vco |= 1 << n;
+            }
+            if result != 0 {
+                vco |= 1 << (n + 8);
+            }
+            vd[n] = result as u16;
+        }
+        (vd, vco)
+    }
+
+    // Returns the selected lanes and the new VCC
+    pub fn compare(op: CompareOp, vs: &Lanes, vt: &Lanes, vco: u16) -> (Lanes, u16) {
+        let mut vd = [0; 8];
+        let mut vcc = 0;
+        for n in 0..8 {
+            let (s, t) = (vs[n] as i16, vt[n] as i16);
+            let carry = ((vco >> n) & 1) != 0;
+            let ne = ((vco >> (n + 8)) & 1) != 0;
+            let result = match op {
+                CompareOp::Lt => s < t || (s == t && carry && ne),
+                CompareOp::Eq => s == t && !ne,
+                CompareOp::Ne => s != t || ne,
+                CompareOp::Ge => s > t || (s == t && !(carry && ne)),
+            };
+            if result {
+                vcc |= 1 << n;
+            }
+            vd[n] = if result { vs[n] } else { vt[n] };
+        }
+        (vd, vcc)
+    }
+
+    pub fn merge(vs: &Lanes, vt: &Lanes, vcc: u16) -> Lanes {
+        let mut vd = [0; 8];
+        for n in 0..8 {
+            vd[n] = if ((vcc >> n) & 1) != 0 { vs[n] } else { vt[n] };
+        }
+        vd
+    }
+
+    pub fn multiply(op: MultiplyOp, accumulate: bool, vs: &Lanes, vt: &Lanes, acc: &mut [i64; 8]) -> Lanes {
+        let mut vd = [0; 8];
+        for n in 0..8 {
+            let (s, t) = (vs[n], vt[n]);
+            let product = match op {
+                MultiplyOp::Mulf | MultiplyOp::Mulu => {
+                    let round = if accumulate { 0 } else { 0x8000 };
+                    product_signed(s, t) * 2 + round
+                }
+                MultiplyOp::Mudl => (s as i64 * t as i64) >> 16,
+                MultiplyOp::Mudm => s as i16 as i64 * t as i64,
+                MultiplyOp::Mudn => s as i64 * t as i16 as i64,
+                MultiplyOp::Mudh => product_signed(s, t) << 16,
+            };
+
+            let base = if accumulate { acc[n] } else { 0 };
+            acc[n] = sign_extend_acc(base + product);
+            vd[n] = match op {
+                MultiplyOp::Mulf | MultiplyOp::Mudm | MultiplyOp::Mudh => clamp_signed(acc[n]),
+                MultiplyOp::Mulu => clamp_unsigned(acc[n]),
+                MultiplyOp::Mudl | MultiplyOp::Mudn => clamp_low(acc[n]),
+            };
+        }
+        vd
+    }
+
+    fn product_signed(s: u16, t: u16) -> i64 {
+        s as i16 as i64 * t as i16 as i64
+    }
+
+    fn sign_extend_acc(acc: i64) -> i64 {
+        (acc << 16) >> 16
+    }
+
+    fn clamp_i16(value: i32) -> u16 {
+        if value < -0x8000 {
+            0x8000
+        } else if value > 0x7fff {
+            0x7fff
+        } else {
+            value as u16
+        }
+    }
+
+    // Clamps bits 47-16 of the accumulator to a signed 16-bit value
+    fn clamp_signed(acc: i64) -> u16 {
+        clamp_i16((acc >> 16) as i32)
+    }
+
+    // Clamps bits 47-16 of the accumulator to an unsigned 16-bit value
+    fn clamp_unsigned(acc: i64) -> u16 {
+        let value = acc >> 16;
+        if value < 0 {
+            0
+        } else if value > 0x7fff {
+            0xffff
+        } else {
+            value as u16
+        }
+    }
+
+    // Returns bits 15-0 of the accumulator, saturated when bits 47-16 don't
+    // fit in a signed 16-bit value
+    fn clamp_low(acc: i64) -> u16 {
+        let value = acc >> 16;
+        if value < -0x8000 {
+            0
+        } else if value > 0x7fff {
+            0xffff
+        } else {
+            acc as u16
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+pub mod sse {
+    use std::arch::x86_64::*;
+
+    use super::{CompareOp, Lanes, LogicalOp, MultiplyOp};
+
+    // SSE2 is part of the x86_64 baseline, so these are always safe to call.
+    // SSE4.1 is only used once it has been detected.
+
+    pub fn logical(op: LogicalOp, vs: &Lanes, vt: &Lanes) -> Lanes {
+        unsafe {
+            let (s, t) = (load(vs), load(vt));
+            let ones = _mm_set1_epi16(-1);
+            store(match op {
+                LogicalOp::And => _mm_and_si128(s, t),
+                LogicalOp::Nand => _mm_xor_si128(_mm_and_si128(s, t), ones),
+                LogicalOp::Or => _mm_or_si128(s, t),
+                LogicalOp::Nor => _mm_xor_si128(_mm_or_si128(s, t), ones),
+                LogicalOp::Xor => _mm_xor_si128(s, t),
+                LogicalOp::Nxor => _mm_xor_si128(_mm_xor_si128(s, t), ones),
+            })
+        }
+    }
+
+    pub fn add(vs: &Lanes, vt: &Lanes, carry: u8) -> (Lanes, Lanes) {
+        unsafe {
+            let (s, t) = (load(vs), load(vt));
+            let c = _mm_and_si128(expand_mask(carry), _mm_set1_epi16(1));
+
+            // Work on 32-bit lanes so the carry can't saturate early
+            let lo = _mm_add_epi32(_mm_add_epi32(widen_lo(s), widen_lo(t)), widen_lo(c));
+            let hi = _mm_add_epi32(_mm_add_epi32(widen_hi(s), widen_hi(t)), widen_hi(c));
+
+            let acc = _mm_add_epi16(_mm_add_epi16(s, t), c);
+            (store(_mm_packs_epi32(lo, hi)), store(acc))
+        }
+    }
+
+    pub fn sub(vs: &Lanes, vt: &Lanes, carry: u8) -> (Lanes, Lanes) {
+        unsafe {
+            let (s, t) = (load(vs), load(vt));
+            let c = _mm_and_si128(expand_mask(carry), _mm_set1_epi16(1));
+
+            let lo = _mm_sub_epi32(_mm_sub_epi32(widen_lo(s), widen_lo(t)), widen_lo(c));
+            let hi = _mm_sub_epi32(_mm_sub_epi32(widen_hi(s), widen_hi(t)), widen_hi(c));
+
+            let acc = _mm_sub_epi16(_mm_sub_epi16(s, t), c);
+            (store(_mm_packs_epi32(lo, hi)), store(acc))
+        }
+    }
+
+    pub fn add_carry(vs: &Lanes, vt: &Lanes) -> (Lanes, u16) {
+        unsafe {
+            let (s, t) = (load(vs), load(vt));
+            let sum = _mm_add_epi16(s, t);
+            // The sum wrapped if it is below either input
+            let carry = cmplt_epu16(sum, s);
+            (store(sum), lane_bits(carry) as u16)
+        }
+    }
+
+    pub fn sub_carry(vs: &Lanes, vt: &Lanes) -> (Lanes, u16) {
+        unsafe {
+            let (s, t) = (load(vs), load(vt));
+            let diff = _mm_sub_epi16(s, t);
+            let borrow = cmplt_epu16(s, t);
+            let not_equal = _mm_xor_si128(_mm_cmpeq_epi16(s, t), _mm_set1_epi16(-1));
+            (store(diff), lane_bits(borrow) as u16 | (lane_bits(not_equal) as u16) << 8)
+        }
+    }
+
+    pub fn compare(op: CompareOp, vs: &Lanes, vt: &Lanes, vco: u16) -> (Lanes, u16) {
+        unsafe {
+            if is_x86_feature_detected!("sse4.1") {
+                compare_sse41(op, vs, vt, vco)
+            } else {
+                compare_with::<Sse2>(op, vs, vt, vco)
+            }
+        }
+    }
+
+    pub fn merge(vs: &Lanes, vt: &Lanes, vcc: u16) -> Lanes {
+        unsafe {
+            if is_x86_feature_detected!("sse4.1") {
+                merge_sse41(vs, vt, vcc)
+            } else {
+                merge_with::<Sse2>(vs, vt, vcc)
+            }
+        }
+    }
+
+    #[target_feature(enable = "sse4.1")]
+    unsafe fn compare_sse41(op: CompareOp, vs: &Lanes, vt: &Lanes, vco: u16) -> (Lanes, u16) {
+        compare_with::<Sse41>(op, vs, vt, vco)
+    }
+
+    #[target_feature(enable = "sse4.1")]
+    unsafe fn merge_sse41(vs: &Lanes, vt: &Lanes, vcc: u16) -> Lanes {
+        merge_with::<Sse41>(vs, vt, vcc)
+    }
+
+    // Inlined into the callers above, so the blend is compiled with the
+    // features of the caller
+    #[inline(always)]
+    pub(super) unsafe fn compare_with<B: Blend>(op: CompareOp, vs: &Lanes, vt: &Lanes, vco: u16) -> (Lanes, u16) {
+        let (s, t) = (load(vs), load(vt));
+        let carry = expand_mask(vco as u8);
+        let ne = expand_mask((vco >> 8) as u8);
+        let eq = _mm_cmpeq_epi16(s, t);
+
+        let result = match op {
+            CompareOp::Lt => _mm_or_si128(_mm_cmplt_epi16(s, t), _mm_and_si128(eq, _mm_and_si128(carry, ne))),
+            CompareOp::Eq => _mm_andnot_si128(ne, eq),
+            CompareOp::Ne => _mm_or_si128(_mm_xor_si128(eq, _mm_set1_epi16(-1)), ne),
+            CompareOp::Ge => _mm_or_si128(_mm_cmpgt_epi16(s, t), _mm_andnot_si128(_mm_and_si128(carry, ne), eq)),
+        };
+        (store(B::select(result, s, t)), lane_bits(result) as u16)
+    }
+
+    #[inline(always)]
+    pub(super) unsafe fn merge_with<B: Blend>(vs: &Lanes, vt: &Lanes, vcc: u16) -> Lanes {
+        store(B::select(expand_mask(vcc as u8), load(vs), load(vt)))
+    }
+
+    pub fn multiply(op: MultiplyOp, accumulate: bool, vs: &Lanes, vt: &Lanes, acc: &mut [i64; 8]) -> Lanes {
+        unsafe {
+            let (s, t) = (load(vs), load(vt));
+            let product_lo = _mm_mullo_epi16(s, t);
+
+            // 32-bit products, except for VMUDL which only keeps the high half
+            let (p0, p1) = match op {
+                MultiplyOp::Mudl => {
+                    let hi = _mm_mulhi_epu16(s, t);
+                    (_mm_unpacklo_epi16(hi, _mm_setzero_si128()), _mm_unpackhi_epi16(hi, _mm_setzero_si128()))
+                }
+                _ => {
+                    let product_hi = match op {
+                        // Signed times unsigned is the unsigned product,
+                        // minus the unsigned operand when the signed one is negative
+                        MultiplyOp::Mudm => _mm_sub_epi16(_mm_mulhi_epu16(s, t), _mm_and_si128(_mm_srai_epi16(s, 15), t)),
+                        MultiplyOp::Mudn => _mm_sub_epi16(_mm_mulhi_epu16(s, t), _mm_and_si128(_mm_srai_epi16(t, 15), s)),
+                        _ => _mm_mulhi_epi16(s, t),
+                    };
+                    (_mm_unpacklo_epi16(product_lo, product_hi), _mm_unpackhi_epi16(product_lo, product_hi))
+                }
+            };
+
+            let acc_ptr = acc.as_mut_ptr() as *mut __m128i;
+            let mut lanes = [widen_lo_epi32(p0), widen_hi_epi32(p0), widen_lo_epi32(p1), widen_hi_epi32(p1)];
+            for (i, lane) in lanes.iter_mut().enumerate() {
+                let mut value = match op {
+                    MultiplyOp::Mulf | MultiplyOp::Mulu => {
+                        let doubled = _mm_slli_epi64(*lane, 1);
+                        if accumulate { doubled } else { _mm_add_epi64(doubled, _mm_set1_epi64x(0x8000)) }
+                    }
+                    MultiplyOp::Mudh => _mm_slli_epi64(*lane, 16),
+                    _ => *lane,
+                };
+                if accumulate {
+                    value = _mm_add_epi64(value, _mm_loadu_si128(acc_ptr.offset(i as isize)));
+                }
+                *lane = sign_extend_48(value);
+                _mm_storeu_si128(acc_ptr.offset(i as isize), *lane);
+            }
+
+            // Bits 47-16 of each lane
+            let mid_lo = low_dwords(_mm_srli_epi64(lanes[0], 16), _mm_srli_epi64(lanes[1], 16));
+            let mid_hi = low_dwords(_mm_srli_epi64(lanes[2], 16), _mm_srli_epi64(lanes[3], 16));
+            let clamped = _mm_packs_epi32(mid_lo, mid_hi);
+
+            store(match op {
+                MultiplyOp::Mulf | MultiplyOp::Mudm | MultiplyOp::Mudh => clamped,
+                MultiplyOp::Mulu => {
+                    let negative = _mm_cmplt_epi16(clamped, _mm_setzero_si128());
+                    let over = packed_mask(_mm_cmpgt_epi32(mid_lo, _mm_set1_epi32(0x7fff)),
+                                           _mm_cmpgt_epi32(mid_hi, _mm_set1_epi32(0x7fff)));
+                    _mm_or_si128(_mm_andnot_si128(negative, clamped), over)
+                }
+                MultiplyOp::Mudl | MultiplyOp::Mudn => {
+                    let low = truncate_dwords(low_dwords(lanes[0], lanes[1]), low_dwords(lanes[2], lanes[3]));
+                    let under = packed_mask(_mm_cmplt_epi32(mid_lo, _mm_set1_epi32(-0x8000)),
+                                            _mm_cmplt_epi32(mid_hi, _mm_set1_epi32(-0x8000)));
+                    let over = packed_mask(_mm_cmpgt_epi32(mid_lo, _mm_set1_epi32(0x7fff)),
+                                           _mm_cmpgt_epi32(mid_hi, _mm_set1_epi32(0x7fff)));
+                    _mm_or_si128(_mm_andnot_si128(_mm_or_si128(under, over), low), over)
+                }
+            })
+        }
+    }
+
+    unsafe fn load(lanes: &Lanes) -> __m128i {
+        _mm_loadu_si128(lanes.as_ptr() as *const __m128i)
+    }
+
+    unsafe fn store(value: __m128i) -> Lanes {
+        let mut lanes = [0; 8];
+        _mm_storeu_si128(lanes.as_mut_ptr() as *mut __m128i, value);
+        lanes
+    }
+
+    // Bit n of the mask becomes all ones in lane n
+    unsafe fn expand_mask(mask: u8) -> __m128i {
+        let bits = _mm_set_epi16(0x80, 0x40, 0x20, 0x10, 0x08, 0x04, 0x02, 0x01);
+        _mm_cmpeq_epi16(_mm_and_si128(_mm_set1_epi16(mask as i16), bits), bits)
+    }
+
+    // The inverse of expand_mask
+    unsafe fn lane_bits(mask: __m128i) -> u8 {
+        _mm_movemask_epi8(_mm_packs_epi16(mask, _mm_setzero_si128())) as u8
+    }
+
+    unsafe fn cmplt_epu16(a: __m128i, b: __m128i) -> __m128i {
+        let bias = _mm_set1_epi16(-0x8000);
+        _mm_cmplt_epi16(_mm_xor_si128(a, bias), _mm_xor_si128(b, bias))
+    }
+
+    // Picks the lanes of a where the mask is set and of b elsewhere
+    pub(super) trait Blend {
+        unsafe fn select(mask: __m128i, a: __m128i, b: __m128i) -> __m128i;
+    }
+
+    pub(super) struct Sse2;
+    pub(super) struct Sse41;
+
+    impl Blend for Sse2 {
+        #[inline(always)]
+        unsafe fn select(mask: __m128i, a: __m128i, b: __m128i) -> __m128i {
+            _mm_or_si128(_mm_and_si128(mask, a), _mm_andnot_si128(mask, b))
+        }
+    }
+
+    impl Blend for Sse41 {
+        #[inline(always)]
+        unsafe fn select(mask: __m128i, a: __m128i, b: __m128i) -> __m128i {
+            _mm_blendv_epi8(b, a, mask)
+        }
+    }
+
+    // Sign extends 16-bit lanes 0-3 or 4-7 to 32 bits
+    unsafe fn widen_lo(value: __m128i) -> __m128i {
+        _mm_unpacklo_epi16(value, _mm_srai_epi16(value, 15))
+    }
+
+    unsafe fn widen_hi(value: __m128i) -> __m128i {
+        _mm_unpackhi_epi16(value, _mm_srai_epi16(value, 15))
+    }
+
+    // Sign extends 32-bit lanes 0-1 or 2-3 to 64 bits
+    unsafe fn widen_lo_epi32(value: __m128i) -> __m128i {
+        _mm_unpacklo_epi32(value, _mm_srai_epi32(value, 31))
+    }
+
+    unsafe fn widen_hi_epi32(value: __m128i) -> __m128i {
+        _mm_unpackhi_epi32(value, _mm_srai_epi32(value, 31))
+    }
+
+    // SSE2 has no 64-bit arithmetic shift, so the sign is extended by
+    // flipping bit 47 and subtracting it again
+    unsafe fn sign_extend_48(value: __m128i) -> __m128i {
+        let sign = _mm_set1_epi64x(1 << 47);
+        let masked = _mm_and_si128(value, _mm_set1_epi64x((1 << 48) - 1));
+        _mm_sub_epi64(_mm_xor_si128(masked, sign), sign)
+    }
+
+    // Gathers the low 32 bits of the four 64-bit lanes of a and b
+    unsafe fn low_dwords(a: __m128i, b: __m128i) -> __m128i {
+        _mm_unpacklo_epi64(_mm_shuffle_epi32(a, 0b10_00_10_00), _mm_shuffle_epi32(b, 0b10_00_10_00))
+    }
+
+    // Packs the low 16 bits of 32-bit lanes without saturating
+    unsafe fn truncate_dwords(lo: __m128i, hi: __m128i) -> __m128i {
+        let lo = _mm_srai_epi32(_mm_slli_epi32(lo, 16), 16);
+        let hi = _mm_srai_epi32(_mm_slli_epi32(hi, 16), 16);
+        _mm_packs_epi32(lo, hi)
+    }
+
+    unsafe fn packed_mask(lo: __m128i, hi: __m128i) -> __m128i {
+        _mm_packs_epi32(lo, hi)
+    }
+}
+
+// Both implementations of every operation are run on random inputs
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+
+    const ITERATIONS: u32 = 100_000;
+    const SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+    const LOGICAL_OPS: [LogicalOp; 6] = [LogicalOp::And, LogicalOp::Nand, LogicalOp::Or, LogicalOp::Nor, LogicalOp::Xor, LogicalOp::Nxor];
+    const MULTIPLY_OPS: [MultiplyOp; 6] = [MultiplyOp::Mulf, MultiplyOp::Mulu, MultiplyOp::Mudl, MultiplyOp::Mudm, MultiplyOp::Mudn, MultiplyOp::Mudh];
+    const COMPARE_OPS: [CompareOp; 4] = [CompareOp::Lt, CompareOp::Eq, CompareOp::Ne, CompareOp::Ge];
+
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        // Edge cases are picked often, since that's where clamping happens
+        fn lane(&mut self) -> u16 {
+            const EDGES: [u16; 8] = [0x0000, 0x0001, 0x7fff, 0x8000, 0x8001, 0xffff, 0x00ff, 0xff00];
+            let value = self.next();
+            if (value & 0b11) == 0 {
+                EDGES[((value >> 2) & 0b111) as usize]
+            } else {
+                (value >> 16) as u16
+            }
+        }
+
+        fn lanes(&mut self) -> Lanes {
+            let mut lanes = [0; 8];
+            for lane in &mut lanes {
+                *lane = self.lane();
+            }
+            lanes
+        }
+
+        // Sign extended 48-bit values, biased towards the clamping boundaries
+        fn accumulator(&mut self) -> [i64; 8] {
+            let mut acc = [0; 8];
+            for lane in &mut acc {
+                let value = (self.lane() as i64) << 32 | (self.lane() as i64) << 16 | self.lane() as i64;
+                *lane = (value << 16) >> 16;
+            }
+            acc
+        }
+    }
+
+    // Calls `check` with two random vectors and random flags per iteration
+    fn for_random_inputs<F: FnMut(&mut XorShift, &Lanes, &Lanes, u16)>(mut check: F) {
+        let mut rng = XorShift(SEED);
+        for _ in 0..ITERATIONS {
+            let (vs, vt) = (rng.lanes(), rng.lanes());
+            let flags = rng.next() as u16;
+            check(&mut rng, &vs, &vt, flags);
+        }
+    }
+
+    #[test]
+    fn logical() {
+        for_random_inputs(|_, vs, vt, _| {
+            for &op in &LOGICAL_OPS {
+                assert_eq!(sse::logical(op, vs, vt), scalar::logical(op, vs, vt), "{:?} {:04X?} {:04X?}", op, vs, vt);
+            }
+        });
+    }
+
+    #[test]
+    fn add_and_sub() {
+        for_random_inputs(|_, vs, vt, flags| {
+            let carry = flags as u8;
+            assert_eq!(sse::add(vs, vt, carry), scalar::add(vs, vt, carry), "{:04X?} {:04X?} {:#04X}", vs, vt, carry);
+            assert_eq!(sse::sub(vs, vt, carry), scalar::sub(vs, vt, carry), "{:04X?} {:04X?} {:#04X}", vs, vt, carry);
+            assert_eq!(sse::add_carry(vs, vt), scalar::add_carry(vs, vt), "{:04X?} {:04X?}", vs, vt);
+            assert_eq!(sse::sub_carry(vs, vt), scalar::sub_carry(vs, vt), "{:04X?} {:04X?}", vs, vt);
+        });
+    }
+
+    #[test]
+    fn compare_and_merge() {
+        for_random_inputs(|_, vs, vt, flags| {
+            for &op in &COMPARE_OPS {
+                let expected = scalar::compare(op, vs, vt, flags);
+                assert_eq!(sse::compare(op, vs, vt, flags), expected, "{:?} {:04X?} {:04X?} {:#06X}", op, vs, vt, flags);
+                let sse2 = unsafe { sse::compare_with::<sse::Sse2>(op, vs, vt, flags) };
+                assert_eq!(sse2, expected, "{:?} {:04X?} {:04X?} {:#06X}", op, vs, vt, flags);
+            }
+
+            let expected = scalar::merge(vs, vt, flags);
+            assert_eq!(sse::merge(vs, vt, flags), expected, "{:04X?} {:04X?} {:#06X}", vs, vt, flags);
+            let sse2 = unsafe { sse::merge_with::<sse::Sse2>(vs, vt, flags) };
+            assert_eq!(sse2, expected, "{:04X?} {:04X?} {:#06X}", vs, vt, flags);
+        });
+    }
+
+    #[test]
+    fn multiply() {
+        for_random_inputs(|rng, vs, vt, _| {
+            for &op in &MULTIPLY_OPS {
+                for &accumulate in &[false, true] {
+                    let acc = rng.accumulator();
+                    let (mut acc_scalar, mut acc_sse) = (acc, acc);
+                    let expected = scalar::multiply(op, accumulate, vs, vt, &mut acc_scalar);
+                    let result = sse::multiply(op, accumulate, vs, vt, &mut acc_sse);
+                    assert_eq!((result, acc_sse), (expected, acc_scalar),
+                               "{:?} (accumulate: {}) {:04X?} {:04X?} {:012X?}", op, accumulate, vs, vt, acc);
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,973 @@
+use std::cmp;
+use std::fmt;
+
+use super::div_rom::{RCP_ROM, RSQ_ROM};
+use super::vector_simd::{self, CompareOp, Lanes, LogicalOp, MultiplyOp};
+use super::{Instruction, VectorLoadOpcode, VectorOpcode, VectorStoreOpcode};
+use super::VectorLoadOpcode::*;
+use super::VectorOpcode::*;
+use super::VectorStoreOpcode::*;
+
+pub struct VectorUnit {
+    // 8 lanes of 16 bits, lane 0 being the most significant halfword in memory
+    reg_vpr: [[u16; 8]; 32],
+
+    // 48 bits per lane, kept sign extended
+    reg_acc: [i64; 8],
+
+    // Bit n of each flag register belongs to lane n
+    reg_vco: u16, // Carry (bits 0-7), not equal (bits 8-15)
+    reg_vcc: u16, // Compare (bits 0-7), clip (bits 8-15)
+    reg_vce: u8,
+
+    // State of the divide unit between VRCPH/VRSQH and VRCPL/VRSQL
+    div_in: u16,
+    div_out: u16,
+    div_dp: bool,
+}
+
+impl VectorUnit {
+    pub fn new() -> VectorUnit {
+        VectorUnit {
+            reg_vpr: [[0; 8]; 32],
+            reg_acc: [0; 8],
+
+            reg_vco: 0,
+            reg_vcc: 0,
+            reg_vce: 0,
+
+            div_in: 0,
+            div_out: 0,
+            div_dp: false,
+        }
+    }
+
+    pub fn execute(&mut self, op: VectorOpcode, instr: Instruction) {
+        let vs = self.reg_vpr[instr.vs()];
+        let vt = self.select_elements(instr.vt(), instr.element());
+
+        let vd = match op {
+            Vmulf => self.multiply(MultiplyOp::Mulf, false, &vs, &vt),
+            Vmulu => self.multiply(MultiplyOp::Mulu, false, &vs, &vt),
+            Vmudl => self.multiply(MultiplyOp::Mudl, false, &vs, &vt),
+            Vmudm => self.multiply(MultiplyOp::Mudm, false, &vs, &vt),
+            Vmudn => self.multiply(MultiplyOp::Mudn, false, &vs, &vt),
+            Vmudh => self.multiply(MultiplyOp::Mudh, false, &vs, &vt),
+            Vmacf => self.multiply(MultiplyOp::Mulf, true, &vs, &vt),
+            Vmacu => self.multiply(MultiplyOp::Mulu, true, &vs, &vt),
+            Vmadl => self.multiply(MultiplyOp::Mudl, true, &vs, &vt),
+            Vmadm => self.multiply(MultiplyOp::Mudm, true, &vs, &vt),
+            Vmadn => self.multiply(MultiplyOp::Mudn, true, &vs, &vt),
+            Vmadh => self.multiply(MultiplyOp::Mudh, true, &vs, &vt),
+
+            Vadd => self.add(&vs, &vt),
+            Vsub => self.sub(&vs, &vt),
+            Vabs => self.abs(&vs, &vt),
+            Vaddc => self.add_carry(&vs, &vt),
+            Vsubc => self.sub_carry(&vs, &vt),
+            Vsar => self.read_acc_slice(instr.element()),
+
+            Vlt => self.compare(CompareOp::Lt, &vs, &vt),
+            Veq => self.compare(CompareOp::Eq, &vs, &vt),
+            Vne => self.compare(CompareOp::Ne, &vs, &vt),
+            Vge => self.compare(CompareOp::Ge, &vs, &vt),
+            Vcl => self.clip_low(&vs, &vt),
+            Vch => self.clip_high(&vs, &vt),
+            Vcr => self.clip_reverse(&vs, &vt),
+            Vmrg => self.merge(&vs, &vt),
+
+            Vand => self.logical(LogicalOp::And, &vs, &vt),
+            Vnand => self.logical(LogicalOp::Nand, &vs, &vt),
+            Vor => self.logical(LogicalOp::Or, &vs, &vt),
+            Vnor => self.logical(LogicalOp::Nor, &vs, &vt),
+            Vxor => self.logical(LogicalOp::Xor, &vs, &vt),
+            Vnxor => self.logical(LogicalOp::Nxor, &vs, &vt),
+
+            Vrcp | Vrcpl | Vrcph | Vmov | Vrsq | Vrsql | Vrsqh => {
+                self.single_lane(op, instr, &vt);
+                return;
+            }
+            Vnop => return,
+        };
+
+        self.reg_vpr[instr.vd()] = vd;
+    }
+
+    // MFC2, the byte pair is sign extended
+    pub fn move_from(&self, vs: usize, element: u32) -> u32 {
+        let hi = self.read_byte(vs, element) as u16;
+        let lo = self.read_byte(vs, element + 1) as u16;
+        (hi << 8 | lo) as i16 as u32
+    }
+
+    // MTC2, the low byte is dropped when starting at the last byte
+    pub fn move_to(&mut self, vs: usize, element: u32, value: u32) {
+        self.write_byte(vs, element, (value >> 8) as u8);
+        if element != 15 {
+            self.write_byte(vs, element + 1, value as u8);
+        }
+    }
+
+    // CFC2
+    pub fn read_control(&self, index: u32) -> u32 {
+        match index & 0b11 {
+            0 => self.reg_vco as i16 as u32,
+            1 => self.reg_vcc as i16 as u32,
+            _ => self.reg_vce as u32,
+        }
+    }
+
+    // CTC2
+    pub fn write_control(&mut self, index: u32, value: u32) {
+        match index & 0b11 {
+            0 => self.reg_vco = value as u16,
+            1 => self.reg_vcc = value as u16,
+            _ => self.reg_vce = value as u8,
+        }
+    }
+
+    // Vector accesses wrap around at the end of DMEM. Accesses which cross
+    // a 16-byte boundary are either truncated (LQV/SQV) or continued at the
+    // start of the line (LRV/SRV and the packed/transposed variants).
+    pub fn load(&mut self, op: VectorLoadOpcode, instr: Instruction, base: u32, dmem: &[u8]) {
+        let vt = instr.vt();
+        let e = instr.byte_element();
+        let shift = match op {
+            Lbv => 0,
+            Lsv => 1,
+            Llv => 2,
+            Ldv | Lpv | Luv => 3,
+            Lqv | Lrv | Lhv | Lfv | Ltv => 4,
+        };
+        let addr = base.wrapping_add(instr.vector_offset() << shift);
+        let read = |addr: u32| dmem[(addr & 0x0fff) as usize];
+
+        match op {
+            Lbv | Lsv | Llv | Ldv => {
+                let end = cmp::min(e + (1 << shift), 16);
+                for offset in e..end {
+                    self.write_byte(vt, offset, read(addr.wrapping_add(offset - e)));
+                }
+            }
+            Lqv => {
+                let end = cmp::min(16 + e - (addr & 15), 16);
+                for offset in e..end {
+                    self.write_byte(vt, offset, read(addr.wrapping_add(offset - e)));
+                }
+            }
+            Lrv => {
+                let start = 16 + e - (addr & 15);
+                let line = addr & !15;
+                for offset in start..16 {
+                    self.write_byte(vt, offset, read(line + offset - start));
+                }
+            }
+            Lpv | Luv | Lhv => {
+                let (stride, shift) = match op {
+                    Lpv => (1, 8),
+                    Luv => (1, 7),
+                    _ => (2, 7),
+                };
+                let index = (addr & 7).wrapping_sub(e);
+                let line = addr & !7;
+                for n in 0..8 {
+                    let value = read(line + (index.wrapping_add(n * stride) & 15));
+                    self.reg_vpr[vt][n as usize] = (value as u16) << shift;
+                }
+            }
+            Lfv => {
+                let index = (addr & 7).wrapping_sub(e);
+                let line = addr & !7;
+                let mut unpacked = [0; 8];
+                for n in 0..4 {
+                    unpacked[n] = (read(line + (index.wrapping_add(n as u32 * 4) & 15)) as u16) << 7;
+                    unpacked[n + 4] = (read(line + (index.wrapping_add(n as u32 * 4 + 8) & 15)) as u16) << 7;
+                }
+                let end = cmp::min(e + 8, 16);
+                for offset in e..end {
+                    let element = unpacked[(offset >> 1) as usize];
+                    let value = if (offset & 1) == 0 { element >> 8 } else { element };
+                    self.write_byte(vt, offset, value as u8);
+                }
+            }
+            Ltv => {
+                // Element n goes to register n of the group, rotated by e / 2
+                let line = addr & !7;
+                let mut addr = line + ((e + (addr & 8)) & 15);
+                let group = vt & !7;
+                for n in 0..8 {
+                    let reg = group + ((e as usize >> 1) + n) % 8;
+                    for i in 0..2 {
+                        self.write_byte(reg, n as u32 * 2 + i, read(addr));
+                        addr += 1;
+                        if addr == line + 16 {
+                            addr = line;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn store(&self, op: VectorStoreOpcode, instr: Instruction, base: u32, dmem: &mut [u8]) {
+        let vt = instr.vt();
+        let e = instr.byte_element();
+        let shift = match op {
+            Sbv => 0,
+            Ssv => 1,
+            Slv => 2,
+            Sdv | Spv | Suv => 3,
+            Sqv | Srv | Shv | Sfv | Swv | Stv => 4,
+        };
+        let addr = base.wrapping_add(instr.vector_offset() << shift);
+        let mut write = |addr: u32, value: u8| dmem[(addr & 0x0fff) as usize] = value;
+
+        match op {
+            Sbv | Ssv | Slv | Sdv => {
+                for offset in e..e + (1 << shift) {
+                    write(addr.wrapping_add(offset - e), self.read_byte(vt, offset));
+                }
+            }
+            Sqv => {
+                for offset in e..e + 16 - (addr & 15) {
+                    write(addr.wrapping_add(offset - e), self.read_byte(vt, offset));
+                }
+            }
+            Srv => {
+                let rotate = 16 - (addr & 15);
+                let line = addr & !15;
+                for offset in e..e + (addr & 15) {
+                    write(line + offset - e, self.read_byte(vt, offset + rotate));
+                }
+            }
+            Spv | Suv => {
+                // SPV stores the high byte of the first four lanes as read,
+                // SUV the high byte of the 7-bit shifted values. Elements past
+                // the 8th byte swap the two behaviours.
+                let swapped = match op { Spv => false, _ => true };
+                for offset in e..e + 8 {
+                    let packed = ((offset & 15) < 8) != swapped;
+                    let value = if packed {
+                        self.read_byte(vt, (offset & 7) << 1)
+                    } else {
+                        (self.reg_vpr[vt][(offset & 7) as usize] >> 7) as u8
+                    };
+                    write(addr.wrapping_add(offset - e), value);
+                }
+            }
+            Shv => {
+                let index = addr & 7;
+                let line = addr & !7;
+                for n in 0..8 {
+                    let byte = e + n * 2;
+                    let value = (self.read_byte(vt, byte) << 1) | (self.read_byte(vt, byte + 1) >> 7);
+                    write(line + ((index + n * 2) & 15), value);
+                }
+            }
+            Sfv => {
+                let index = addr & 7;
+                let line = addr & !7;
+                let elements = match e {
+                    0 | 15 => Some([0, 1, 2, 3]),
+                    1 => Some([6, 7, 4, 5]),
+                    4 => Some([1, 2, 3, 0]),
+                    5 => Some([7, 4, 5, 6]),
+                    8 => Some([4, 5, 6, 7]),
+                    11 => Some([3, 0, 1, 2]),
+                    12 => Some([5, 6, 7, 4]),
+                    _ => None,
+                };
+                for n in 0..4 {
+                    let value = match elements {
+                        Some(elements) => (self.reg_vpr[vt][elements[n as usize]] >> 7) as u8,
+                        None => 0,
+                    };
+                    write(line + ((index + n * 4) & 15), value);
+                }
+            }
+            Swv => {
+                let index = addr & 7;
+                let line = addr & !7;
+                for offset in e..e + 16 {
+                    write(line + ((index + offset - e) & 15), self.read_byte(vt, offset));
+                }
+            }
+            Stv => {
+                // Lane n is taken from register n of the group, rotated by e / 2
+                let element = 16 - (e & !1);
+                let index = (addr & 7).wrapping_sub(e & !1);
+                let line = addr & !7;
+                let group = vt & !7;
+                for n in 0..8 {
+                    for i in 0..2 {
+                        let byte = n * 2 + i;
+                        let value = self.read_byte(group + n as usize, element + byte);
+                        write(line + (index.wrapping_add(byte) & 15), value);
+                    }
+                }
+            }
+        }
+    }
+
+    fn read_byte(&self, reg: usize, index: u32) -> u8 {
+        let index = index & 15;
+        let element = self.reg_vpr[reg][(index >> 1) as usize];
+        if (index & 1) == 0 { (element >> 8) as u8 } else { element as u8 }
+    }
+
+    fn write_byte(&mut self, reg: usize, index: u32, value: u8) {
+        let index = index & 15;
+        let element = &mut self.reg_vpr[reg][(index >> 1) as usize];
+        if (index & 1) == 0 {
+            *element = (*element & 0x00ff) | ((value as u16) << 8);
+        } else {
+            *element = (*element & 0xff00) | value as u16;
+        }
+    }
+
+    // Broadcasts the lanes of vt chosen by the element field:
+    // 0-1 whole vector, 2-3 quarters, 4-7 halves, 8-15 a single lane
+    fn select_elements(&self, index: usize, element: usize) -> [u16; 8] {
+        let reg = &self.reg_vpr[index];
+        let mut vt = [0; 8];
+        for n in 0..8 {
+            let lane = match element {
+                0..=1 => n,
+                2..=3 => (n & 0b110) | (element & 0b1),
+                4..=7 => (n & 0b100) | (element & 0b11),
+                _ => element & 0b111,
+            };
+            vt[n] = reg[lane];
+        }
+        vt
+    }
+
+    fn multiply(&mut self, op: MultiplyOp, accumulate: bool, vs: &Lanes, vt: &Lanes) -> Lanes {
+        vector_simd::multiply(op, accumulate, vs, vt, &mut self.reg_acc)
+    }
+
+    fn add(&mut self, vs: &Lanes, vt: &Lanes) -> Lanes {
+        let (vd, acc) = vector_simd::add(vs, vt, self.reg_vco as u8);
+        self.write_acc_lanes(&acc);
+        self.reg_vco = 0;
+        vd
+    }
+
+    fn sub(&mut self, vs: &Lanes, vt: &Lanes) -> Lanes {
+        let (vd, acc) = vector_simd::sub(vs, vt, self.reg_vco as u8);
+        self.write_acc_lanes(&acc);
+        self.reg_vco = 0;
+        vd
+    }
+
+    fn abs(&mut self, vs: &[u16; 8], vt: &[u16; 8]) -> [u16; 8] {
+        let mut vd = [0; 8];
+        for n in 0..8 {
+            let (s, t) = (vs[n] as i16, vt[n] as i16);
+            let (acc, result) = if s < 0 {
+                // -0x8000 can't be negated, the accumulator keeps the wrapped value
+                if t == -0x8000 { (0x8000, 0x7fff) } else { (t.wrapping_neg() as u16, t.wrapping_neg() as u16) }
+            } else if s == 0 {
+                (0, 0)
+            } else {
+                (vt[n], vt[n])
+            };
+            self.write_acc_low(n, acc);
+            vd[n] = result;
+        }
+        vd
+    }
+
+    fn add_carry(&mut self, vs: &Lanes, vt: &Lanes) -> Lanes {
+        let (vd, vco) = vector_simd::add_carry(vs, vt);
+        self.write_acc_lanes(&vd);
+        self.reg_vco = vco;
+        vd
+    }
+
+    fn sub_carry(&mut self, vs: &Lanes, vt: &Lanes) -> Lanes {
+        let (vd, vco) = vector_simd::sub_carry(vs, vt);
+        self.write_acc_lanes(&vd);
+        self.reg_vco = vco;
+        vd
+    }
+
+    fn read_acc_slice(&self, element: usize) -> [u16; 8] {
+        let mut vd = [0; 8];
+        let shift = match element {
+            8 => 32,
+            9 => 16,
+            10 => 0,
+            _ => return vd,
+        };
+        for n in 0..8 {
+            vd[n] = (self.reg_acc[n] >> shift) as u16;
+        }
+        vd
+    }
+
+    fn compare(&mut self, op: CompareOp, vs: &Lanes, vt: &Lanes) -> Lanes {
+        let (vd, vcc) = vector_simd::compare(op, vs, vt, self.reg_vco);
+        self.write_acc_lanes(&vd);
+        self.reg_vcc = vcc;
+        self.reg_vco = 0;
+        vd
+    }
+
+    fn clip_low(&mut self, vs: &[u16; 8], vt: &[u16; 8]) -> [u16; 8] {
+        let mut vd = [0; 8];
+        for n in 0..8 {
+            let (s, t) = (vs[n], vt[n]);
+            vd[n] = if self.carry(n) {
+                let le = if self.not_equal(n) {
+                    flag(self.reg_vcc, n)
+                } else {
+                    let sum = s as u32 + t as u32;
+                    let (zero, carry) = ((sum & 0xffff) == 0, sum > 0xffff);
+                    let le = if flag(self.reg_vce as u16, n) { zero || !carry } else { zero && !carry };
+                    set_flag(&mut self.reg_vcc, n, le);
+                    le
+                };
+                if le { t.wrapping_neg() } else { s }
+            } else {
+                let ge = if self.not_equal(n) {
+                    flag(self.reg_vcc, n + 8)
+                } else {
+                    let ge = s >= t;
+                    set_flag(&mut self.reg_vcc, n + 8, ge);
+                    ge
+                };
+                if ge { t } else { s }
+            };
+            self.write_acc_low(n, vd[n]);
+        }
+        self.reg_vco = 0;
+        self.reg_vce = 0;
+        vd
+    }
+
+    fn clip_high(&mut self, vs: &[u16; 8], vt: &[u16; 8]) -> [u16; 8] {
+        let mut vd = [0; 8];
+        let (mut vcc, mut vco, mut vce) = (0, 0, 0);
+        for n in 0..8 {
+            let (s, t) = (vs[n] as i16 as i32, vt[n] as i16 as i32);
+            let not_equal;
+            if (s ^ t) < 0 {
+                let result = s + t;
+                vd[n] = if result <= 0 { (-t) as u16 } else { s as u16 };
+                if result <= 0 { vcc |= 1 << n; }
+                if t < 0 { vcc |= 1 << (n + 8); }
+                vco |= 1 << n;
+                if result == -1 { vce |= 1 << n; }
+                not_equal = result != 0 && vs[n] != !vt[n];
+            } else {
+                let result = s - t;
+                vd[n] = if result >= 0 { t as u16 } else { s as u16 };
+                if t < 0 { vcc |= 1 << n; }
+                if result >= 0 { vcc |= 1 << (n + 8); }
+                not_equal = result != 0 && vs[n] != !vt[n];
+            }
+            if not_equal {
+                vco |= 1 << (n + 8);
+            }
+            self.write_acc_low(n, vd[n]);
+        }
+        self.reg_vcc = vcc;
+        self.reg_vco = vco;
+        self.reg_vce = vce;
+        vd
+    }
+
+    fn clip_reverse(&mut self, vs: &[u16; 8], vt: &[u16; 8]) -> [u16; 8] {
+        let mut vd = [0; 8];
+        let mut vcc = 0;
+        for n in 0..8 {
+            let (s, t) = (vs[n] as i16 as i32, vt[n] as i16 as i32);
+            if (s ^ t) < 0 {
+                let le = s + t + 1 <= 0;
+                if le { vcc |= 1 << n; }
+                if t < 0 { vcc |= 1 << (n + 8); }
+                vd[n] = if le { !vt[n] } else { vs[n] };
+            } else {
+                let ge = s - t >= 0;
+                if t < 0 { vcc |= 1 << n; }
+                if ge { vcc |= 1 << (n + 8); }
+                vd[n] = if ge { vt[n] } else { vs[n] };
+            }
+            self.write_acc_low(n, vd[n]);
+        }
+        self.reg_vcc = vcc;
+        self.reg_vco = 0;
+        self.reg_vce = 0;
+        vd
+    }
+
+    fn merge(&mut self, vs: &Lanes, vt: &Lanes) -> Lanes {
+        let vd = vector_simd::merge(vs, vt, self.reg_vcc);
+        self.write_acc_lanes(&vd);
+        self.reg_vco = 0;
+        vd
+    }
+
+    fn logical(&mut self, op: LogicalOp, vs: &Lanes, vt: &Lanes) -> Lanes {
+        let vd = vector_simd::logical(op, vs, vt);
+        self.write_acc_lanes(&vd);
+        vd
+    }
+
+    // The divide unit and VMOV only write a single lane of vd. The lane is
+    // encoded in the vs field, the source element of vt in the element field.
+    fn single_lane(&mut self, op: VectorOpcode, instr: Instruction, vt: &[u16; 8]) {
+        let dest = instr.vs() & 0b111;
+        let source = self.reg_vpr[instr.vt()][instr.element() & 0b111];
+
+        let result = match op {
+            Vmov => vt[dest],
+            Vrcph | Vrsqh => {
+                self.div_in = source;
+                self.div_dp = true;
+                self.div_out
+            }
+            op => {
+                let double_precision = match op { Vrcpl | Vrsql => self.div_dp, _ => false };
+                let input = if double_precision {
+                    ((self.div_in as u32) << 16 | source as u32) as i32
+                } else {
+                    source as i16 as i32
+                };
+                let square_root = match op { Vrsq | Vrsql => true, _ => false };
+
+                let result = reciprocal(input, square_root);
+                self.div_dp = false;
+                self.div_out = (result >> 16) as u16;
+                result as u16
+            }
+        };
+
+        self.write_acc_lanes(vt);
+        self.reg_vpr[instr.vd()][dest] = result;
+    }
+
+    fn carry(&self, lane: usize) -> bool {
+        flag(self.reg_vco, lane)
+    }
+
+    fn not_equal(&self, lane: usize) -> bool {
+        flag(self.reg_vco, lane + 8)
+    }
+
+    fn write_acc_low(&mut self, lane: usize, value: u16) {
+        self.reg_acc[lane] = (self.reg_acc[lane] & !0xffff) | value as i64;
+    }
+
+    fn write_acc_lanes(&mut self, values: &Lanes) {
+        for n in 0..8 {
+            self.write_acc_low(n, values[n]);
+        }
+    }
+}
+
+fn flag(reg: u16, bit: usize) -> bool {
+    (reg & (1 << bit)) != 0
+}
+
+fn set_flag(reg: &mut u16, bit: usize, value: bool) {
+    if value {
+        *reg |= 1 << bit;
+    } else {
+        *reg &= !(1 << bit);
+    }
+}
+
+// Looks up the mantissa in the reciprocal ROM and shifts it back into place.
+// Returns a 32-bit fixed point result, the high half of which ends up in
+// the divide unit's output register.
+fn reciprocal(input: i32, square_root: bool) -> u32 {
+    let mask = input >> 31;
+    let mut data = input ^ mask;
+    if input > -0x8000 {
+        data = data.wrapping_sub(mask);
+    }
+
+    if data == 0 {
+        0x7fff_ffff
+    } else if input == -0x8000 {
+        0xffff_0000
+    } else {
+        let shift = data.leading_zeros();
+        let index = (((data as u32) << shift) & 0x7fc0_0000) >> 22;
+
+        let (rom, shift) = if square_root {
+            (RSQ_ROM[((index & 0x1fe) | (shift & 1)) as usize], (31 - shift) >> 1)
+        } else {
+            (RCP_ROM[index as usize], 31 - shift)
+        };
+
+        let result = ((0x10000 | rom as i32) << 14) >> shift;
+        (result ^ mask) as u32
+    }
+}
+
+impl fmt::Debug for VectorUnit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RSP Vector Registers:")?;
+        for (reg_num, reg) in self.reg_vpr.iter().enumerate() {
+            write!(f, "\nv{:02}:", reg_num)?;
+            for lane in reg {
+                write!(f, " {:04X}", lane)?;
+            }
+        }
+
+        write!(f, "\n\nRSP Accumulator:")?;
+        for (name, shift) in [("acc_h", 32), ("acc_m", 16), ("acc_l", 0)].iter() {
+            write!(f, "\n{}:", name)?;
+            for acc in &self.reg_acc {
+                write!(f, " {:04X}", (acc >> shift) as u16)?;
+            }
+        }
+
+        writeln!(f, "\n\nvco: {:#06X} vcc: {:#06X} vce: {:#04X}", self.reg_vco, self.reg_vcc, self.reg_vce)?;
+        write!(f, "div_in: {:#06X} div_out: {:#06X} div_dp: {}", self.div_in, self.div_out, self.div_dp)
+    }
+}
+
+// Expected values follow the behaviour documented by the ares and cen64
+// RSP implementations
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn execute(vu: &mut VectorUnit, op: VectorOpcode, vd: u32, vs: u32, vt: u32, element: u32) {
+        let word = 0x4a00_0000 | element << 21 | vt << 16 | vs << 11 | vd << 6 | op as u32;
+        vu.execute(op, Instruction(word));
+    }
+
+    // Runs `op` on v1 and v2 into v3
+    fn run(vu: &mut VectorUnit, op: VectorOpcode, vs: Lanes, vt: Lanes) -> Lanes {
+        vu.reg_vpr[1] = vs;
+        vu.reg_vpr[2] = vt;
+        execute(vu, op, 3, 1, 2, 0);
+        vu.reg_vpr[3]
+    }
+
+    // The high, middle and low slices as read by VSAR
+    fn accumulator(vu: &mut VectorUnit) -> [Lanes; 3] {
+        let mut slices = [[0; 8]; 3];
+        for (slice, element) in slices.iter_mut().zip(8..11) {
+            execute(vu, Vsar, 31, 0, 0, element);
+            *slice = vu.reg_vpr[31];
+        }
+        slices
+    }
+
+    const MULTIPLY_VS: Lanes = [0x4000, 0x8000, 0xffff, 0x7fff, 0x8000, 0x0001, 0x1234, 0xc000];
+    const MULTIPLY_VT: Lanes = [0x4000, 0x8000, 0x0001, 0x7fff, 0x7fff, 0xffff, 0x5678, 0x4000];
+
+    #[test]
+    fn vmulf_rounds_and_clamps() {
+        let mut vu = VectorUnit::new();
+        let vd = run(&mut vu, Vmulf, MULTIPLY_VS, MULTIPLY_VT);
+        assert_eq!(vd, [0x2000, 0x7fff, 0x0000, 0x7ffe, 0x8001, 0x0000, 0x0c4c, 0xe000]);
+        assert_eq!(accumulator(&mut vu), [
+            [0x0000, 0x0000, 0x0000, 0x0000, 0xffff, 0x0000, 0x0000, 0xffff],
+            [0x2000, 0x8000, 0x0000, 0x7ffe, 0x8001, 0x0000, 0x0c4c, 0xe000],
+            [0x8000, 0x8000, 0x7ffe, 0x8002, 0x8000, 0x7ffe, 0x80c0, 0x8000],
+        ]);
+    }
+
+    #[test]
+    fn vmulu_clamps_to_unsigned() {
+        let mut vu = VectorUnit::new();
+        let vd = run(&mut vu, Vmulu, MULTIPLY_VS, MULTIPLY_VT);
+        assert_eq!(vd, [0x2000, 0xffff, 0x0000, 0x7ffe, 0x0000, 0x0000, 0x0c4c, 0x0000]);
+    }
+
+    #[test]
+    fn vmacf_accumulates_without_rounding_and_clamps() {
+        let mut vu = VectorUnit::new();
+        run(&mut vu, Vmulf, MULTIPLY_VS, MULTIPLY_VT);
+        run(&mut vu, Vmacf, MULTIPLY_VS, MULTIPLY_VT);
+        let vd = run(&mut vu, Vmacf, MULTIPLY_VS, MULTIPLY_VT);
+        assert_eq!(vd, [0x6000, 0x7fff, 0x0000, 0x7fff, 0x8000, 0x0000, 0x24e4, 0xa000]);
+        assert_eq!(accumulator(&mut vu), [
+            [0x0000, 0x0001, 0x0000, 0x0001, 0xfffe, 0x0000, 0x0000, 0xffff],
+            [0x6000, 0x8000, 0x0000, 0x7ffa, 0x8003, 0x0000, 0x24e4, 0xa000],
+            [0x8000, 0x8000, 0x7ffa, 0x8006, 0x8000, 0x7ffa, 0x8240, 0x8000],
+        ]);
+    }
+
+    #[test]
+    fn vmadh_wraps_the_accumulator_at_48_bits() {
+        let mut vu = VectorUnit::new();
+        run(&mut vu, Vmudh, MULTIPLY_VS, MULTIPLY_VT);
+        run(&mut vu, Vmadh, MULTIPLY_VS, MULTIPLY_VT);
+        let vd = run(&mut vu, Vmadh, MULTIPLY_VS, MULTIPLY_VT);
+        assert_eq!(vd, [0x7fff, 0x8000, 0xfffd, 0x8000, 0x7fff, 0xfffd, 0x7fff, 0x8000]);
+        assert_eq!(accumulator(&mut vu), [
+            [0x3000, 0xc000, 0xffff, 0xbffd, 0x4001, 0xffff, 0x1272, 0xd000],
+            [0x0000, 0x0000, 0xfffd, 0x0003, 0x8000, 0xfffd, 0x0120, 0x0000],
+            [0x0000; 8],
+        ]);
+    }
+
+    #[test]
+    fn elements_broadcast_lanes_of_vt() {
+        let mut vu = VectorUnit::new();
+        vu.reg_vpr[2] = [0, 1, 2, 3, 4, 5, 6, 7];
+        let cases: [(u32, Lanes); 4] = [
+            (0, [0, 1, 2, 3, 4, 5, 6, 7]),
+            (2, [0, 0, 2, 2, 4, 4, 6, 6]),
+            (5, [1, 1, 1, 1, 5, 5, 5, 5]),
+            (11, [3; 8]),
+        ];
+        for &(element, expected) in &cases {
+            execute(&mut vu, Vor, 3, 0, 2, element);
+            assert_eq!(vu.reg_vpr[3], expected, "element {}", element);
+        }
+    }
+
+    #[test]
+    fn vadd_and_vsub_use_the_carry_and_clamp() {
+        let mut vu = VectorUnit::new();
+        vu.write_control(0, 0xff05);
+        let vd = run(&mut vu, Vadd, [0x7fff, 0x8000, 1, 0xffff, 0, 0, 0, 0], [1, 0xffff, 2, 1, 0, 0, 0, 0]);
+        assert_eq!(vd, [0x7fff, 0x8000, 4, 0, 0, 0, 0, 0]);
+        assert_eq!(accumulator(&mut vu)[2], [0x8001, 0x7fff, 4, 0, 0, 0, 0, 0]);
+        assert_eq!(vu.read_control(0), 0);
+
+        vu.write_control(0, 0x0004);
+        let vd = run(&mut vu, Vsub, [0x8000, 0x7fff, 5, 0, 0, 0, 0, 0], [1, 0xffff, 2, 0, 0, 0, 0, 0]);
+        assert_eq!(vd, [0x8000, 0x7fff, 2, 0, 0, 0, 0, 0]);
+        assert_eq!(accumulator(&mut vu)[2], [0x7fff, 0x8000, 2, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn vaddc_and_vsubc_set_carry_and_not_equal() {
+        let mut vu = VectorUnit::new();
+        let vd = run(&mut vu, Vaddc, [0xffff, 1, 0, 0, 0, 0, 0, 0], [1, 2, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(vd, [0, 3, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(vu.read_control(0), 0x0001);
+
+        let vd = run(&mut vu, Vsubc, [1, 5, 5, 0, 0, 0, 0, 0], [2, 5, 3, 0xffff, 0, 0, 0, 0]);
+        assert_eq!(vd, [0xffff, 0, 2, 1, 0, 0, 0, 0]);
+        assert_eq!(vu.read_control(0), 0x0d09);
+    }
+
+    #[test]
+    fn compares_take_equal_lanes_from_the_flags() {
+        let vs = [1, 2, 3, 3, 0, 0, 0, 0];
+        let vt = [2, 2, 1, 3, 0, 0, 0, 0];
+
+        let mut vu = VectorUnit::new();
+        vu.write_control(0, 0x0808);
+        assert_eq!(run(&mut vu, Vlt, vs, vt), [1, 2, 1, 3, 0, 0, 0, 0]);
+        assert_eq!(vu.read_control(1), 0x0009);
+        assert_eq!(vu.read_control(0), 0);
+
+        vu.write_control(0, 0x0200);
+        run(&mut vu, Veq, vs, vt);
+        assert_eq!(vu.read_control(1), 0x00f8);
+
+        vu.write_control(0, 0x0808);
+        run(&mut vu, Vge, vs, vt);
+        assert_eq!(vu.read_control(1), 0x00f6);
+
+        vu.write_control(1, 0x00aa);
+        let vd = run(&mut vu, Vmrg, [1; 8], [2; 8]);
+        assert_eq!(vd, [2, 1, 2, 1, 2, 1, 2, 1]);
+    }
+
+    #[test]
+    fn vch_sets_all_three_flag_registers() {
+        let mut vu = VectorUnit::new();
+        let vd = run(&mut vu, Vch, [1, 5, 0xfffb, 0xfffc, 3, 2, 0, 0], [2, 0xfffd, 3, 3, 2, 2, 0, 0]);
+        assert_eq!(vd, [1, 5, 0xfffd, 0xfffd, 2, 2, 0, 0]);
+        assert_eq!(vu.read_control(0), 0x170e);
+        assert_eq!(vu.read_control(1), 0xffff_f20c);
+        assert_eq!(vu.read_control(2), 0x08);
+    }
+
+    #[test]
+    fn vcl_continues_from_the_flags_of_vch() {
+        let mut vu = VectorUnit::new();
+        vu.write_control(0, 0xc07c);
+        vu.write_control(1, 0x0040);
+        vu.write_control(2, 0x30);
+        let vd = run(&mut vu, Vcl, [5, 2, 0xfffd, 0, 0xfffe, 1, 0, 9], [3, 3, 3, 0, 3, 3, 7, 4]);
+        assert_eq!(vd, [3, 2, 0xfffd, 0, 0xfffe, 0xfffd, 0xfff9, 9]);
+        assert_eq!(vu.read_control(1), 0x0168);
+        assert_eq!((vu.read_control(0), vu.read_control(2)), (0, 0));
+    }
+
+    #[test]
+    fn vcr_clips_to_the_ones_complement() {
+        let mut vu = VectorUnit::new();
+        vu.write_control(0, 0xffff);
+        let vd = run(&mut vu, Vcr, [5, 0xfffb, 1, 3, 0, 0, 0, 0], [0xfffd, 3, 2, 2, 0, 0, 0, 0]);
+        assert_eq!(vd, [5, 0xfffc, 1, 2, 0, 0, 0, 0]);
+        assert_eq!(vu.read_control(1), 0xffff_f902);
+        assert_eq!(vu.read_control(0), 0);
+    }
+
+    #[test]
+    fn control_registers_read_back_sign_extended() {
+        let mut vu = VectorUnit::new();
+        vu.write_control(0, 0x1_8001);
+        vu.write_control(1, 0x7fff);
+        vu.write_control(2, 0x1ff);
+        assert_eq!(vu.read_control(0), 0xffff_8001);
+        assert_eq!(vu.read_control(1), 0x7fff);
+        assert_eq!(vu.read_control(2), 0xff);
+    }
+
+    // Runs a divide op on lane 0 of v1 into lane `dest` of v3, returns the
+    // lane and what a following VRCPH reads out
+    fn divide(vu: &mut VectorUnit, op: VectorOpcode, input: u16) -> (u16, u16) {
+        vu.reg_vpr[1][0] = input;
+        execute(vu, op, 3, 5, 1, 8);
+        let result = vu.reg_vpr[3][5];
+        execute(vu, Vrcph, 4, 0, 0, 8);
+        (result, vu.reg_vpr[4][0])
+    }
+
+    #[test]
+    fn reciprocals_come_from_the_rom() {
+        let mut vu = VectorUnit::new();
+        assert_eq!(divide(&mut vu, Vrcp, 3), (0xa000, 0x2aaa));
+        assert_eq!(divide(&mut vu, Vrcp, 0xfffd), (0x5fff, 0xd555));
+        assert_eq!(divide(&mut vu, Vrcp, 0), (0xffff, 0x7fff));
+        assert_eq!(divide(&mut vu, Vrcp, 0x8000), (0x0000, 0xffff));
+        assert_eq!(divide(&mut vu, Vrsq, 2), (0x4000, 0x5a82));
+        assert_eq!(divide(&mut vu, Vrsq, 4), (0xe000, 0x3fff));
+    }
+
+    #[test]
+    fn vrcpl_after_vrcph_takes_a_32_bit_input() {
+        let mut vu = VectorUnit::new();
+        vu.reg_vpr[1] = [0x0001, 0x0000, 0, 0, 0, 0, 0, 0];
+        execute(&mut vu, Vrcph, 3, 0, 1, 8);
+        execute(&mut vu, Vrcpl, 3, 1, 1, 9);
+        assert_eq!(vu.reg_vpr[3][1], 0x7fff);
+        assert_eq!(vu.div_out, 0x0000);
+        assert!(!vu.div_dp);
+
+        // Without VRCPH the input is the sign extended lane
+        execute(&mut vu, Vrcpl, 3, 1, 1, 9);
+        assert_eq!(vu.reg_vpr[3][1], 0xffff);
+        assert_eq!(vu.div_out, 0x7fff);
+    }
+
+    // DMEM holding the low byte of each address
+    fn dmem() -> Vec<u8> {
+        (0..0x1000).map(|addr| addr as u8).collect()
+    }
+
+    fn load(vu: &mut VectorUnit, op: VectorLoadOpcode, vt: u32, element: u32, addr: u32, dmem: &[u8]) {
+        let word = 0xc800_0000 | vt << 16 | (op as u32) << 11 | element << 7;
+        vu.load(op, Instruction(word), addr, dmem);
+    }
+
+    fn store(vu: &VectorUnit, op: VectorStoreOpcode, vt: u32, element: u32, addr: u32, dmem: &mut [u8]) {
+        let word = 0xe800_0000 | vt << 16 | (op as u32) << 11 | element << 7;
+        vu.store(op, Instruction(word), addr, dmem);
+    }
+
+    const BYTES: Lanes = [0x0102, 0x0304, 0x0506, 0x0708, 0x090a, 0x0b0c, 0x0d0e, 0x0f10];
+
+    #[test]
+    fn lqv_and_lrv_load_an_unaligned_vector() {
+        let mut vu = VectorUnit::new();
+        let dmem = dmem();
+        vu.reg_vpr[1] = [0xeeee; 8];
+        load(&mut vu, Lqv, 1, 0, 0x13, &dmem);
+        assert_eq!(vu.reg_vpr[1], [0x1314, 0x1516, 0x1718, 0x191a, 0x1b1c, 0x1d1e, 0x1fee, 0xeeee]);
+        load(&mut vu, Lrv, 1, 0, 0x23, &dmem);
+        assert_eq!(vu.reg_vpr[1], [0x1314, 0x1516, 0x1718, 0x191a, 0x1b1c, 0x1d1e, 0x1f20, 0x2122]);
+
+        // From an element, up to the end of the register
+        vu.reg_vpr[2] = [0xeeee; 8];
+        load(&mut vu, Lqv, 2, 4, 0x40, &dmem);
+        assert_eq!(vu.reg_vpr[2], [0xeeee, 0xeeee, 0x4041, 0x4243, 0x4445, 0x4647, 0x4849, 0x4a4b]);
+    }
+
+    #[test]
+    fn sqv_and_srv_store_an_unaligned_vector() {
+        let mut vu = VectorUnit::new();
+        let mut dmem = vec![0; 0x1000];
+        vu.reg_vpr[1] = BYTES;
+        store(&vu, Sqv, 1, 0, 0x13, &mut dmem);
+        assert_eq!(&dmem[0x12..0x21], &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 0]);
+        store(&vu, Srv, 1, 0, 0x23, &mut dmem);
+        assert_eq!(&dmem[0x1f..0x24], &[13, 14, 15, 16, 0]);
+
+        // Elements past the end of the register wrap around
+        store(&vu, Sqv, 1, 8, 0x40, &mut dmem);
+        assert_eq!(&dmem[0x40..0x50], &[9, 10, 11, 12, 13, 14, 15, 16, 1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn short_accesses_stop_at_the_register_and_wrap_around_dmem() {
+        let mut vu = VectorUnit::new();
+        let mut dmem = dmem();
+        vu.reg_vpr[1] = [0xeeee; 8];
+        load(&mut vu, Ldv, 1, 12, 0x30, &dmem);
+        assert_eq!(vu.reg_vpr[1], [0xeeee, 0xeeee, 0xeeee, 0xeeee, 0xeeee, 0xeeee, 0x3031, 0x3233]);
+
+        vu.reg_vpr[2] = BYTES;
+        store(&vu, Ssv, 2, 2, 0xfff, &mut dmem);
+        assert_eq!((dmem[0xfff], dmem[0x000], dmem[0x001]), (3, 4, 1));
+        load(&mut vu, Lsv, 3, 0, 0xfff, &dmem);
+        assert_eq!(vu.reg_vpr[3][0], 0x0304);
+    }
+
+    #[test]
+    fn packed_loads_and_stores_shift_bytes_into_lanes() {
+        let mut vu = VectorUnit::new();
+        let mut dmem = dmem();
+        load(&mut vu, Lpv, 1, 0, 0x101, &dmem);
+        assert_eq!(vu.reg_vpr[1], [0x0100, 0x0200, 0x0300, 0x0400, 0x0500, 0x0600, 0x0700, 0x0800]);
+        load(&mut vu, Luv, 2, 0, 0x100, &dmem);
+        assert_eq!(vu.reg_vpr[2], [0x0000, 0x0080, 0x0100, 0x0180, 0x0200, 0x0280, 0x0300, 0x0380]);
+
+        vu.reg_vpr[3] = [0x1234, 0x8000, 0x7fff, 0x00ff, 0x0100, 0xffff, 0x4080, 0x0000];
+        store(&vu, Spv, 3, 0, 0x200, &mut dmem);
+        assert_eq!(&dmem[0x200..0x208], &[0x12, 0x80, 0x7f, 0x00, 0x01, 0xff, 0x40, 0x00]);
+        store(&vu, Suv, 3, 0, 0x200, &mut dmem);
+        assert_eq!(&dmem[0x200..0x208], &[0x24, 0x00, 0xff, 0x01, 0x02, 0xff, 0x81, 0x00]);
+    }
+
+    #[test]
+    fn transposed_accesses_rotate_through_the_register_group() {
+        let mut vu = VectorUnit::new();
+        let mut dmem = dmem();
+        load(&mut vu, Ltv, 8, 2, 0x300, &dmem);
+        for n in 0..8 {
+            let reg = 8 + (n + 1) % 8;
+            let byte = (2 + n as u16 * 2) & 15;
+            assert_eq!(vu.reg_vpr[reg][n], byte << 8 | (byte + 1), "lane {}", n);
+        }
+
+        // Lane n of register n of the group, the diagonal
+        for n in 0..8 {
+            vu.reg_vpr[16 + n][n] = 0x1111 * n as u16;
+        }
+        store(&vu, Stv, 16, 0, 0x400, &mut dmem);
+        for n in 0..8 {
+            let value = (dmem[0x400 + n * 2] as u16) << 8 | dmem[0x401 + n * 2] as u16;
+            assert_eq!(value, 0x1111 * n as u16, "lane {}", n);
+        }
+    }
+
+    #[test]
+    fn moves_sign_extend_and_wrap_within_the_register() {
+        let mut vu = VectorUnit::new();
+        vu.reg_vpr[1] = [0x8081, 0, 0, 0, 0, 0, 0, 0x1234];
+        assert_eq!(vu.move_from(1, 0), 0xffff_8081);
+        assert_eq!(vu.move_from(1, 14), 0x1234);
+        assert_eq!(vu.move_from(1, 15), 0x3480);
+
+        vu.move_to(1, 15, 0xabcd);
+        assert_eq!(vu.reg_vpr[1][7], 0x12ab);
+        assert_eq!(vu.reg_vpr[1][0], 0x8081);
+    }
+}
@@ -1,3 +1,19 @@
+use super::cpu;
+
+// The VI is stepped once per CPU cycle and runs on the video clock of the
+// console's TV system
+const NTSC_VI_CLOCK_HZ: u32 = 48_681_812;
+const PAL_VI_CLOCK_HZ: u32 = 49_656_530;
+
+// Fields cycle through the bits of the PAL leap pattern
+const LEAP_PATTERN_LENGTH: u32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TvSystem {
+    Ntsc,
+    Pal,
+}
+
 #[derive(Debug, Clone)]
 pub enum FramebufferFormat {
     Blank,
@@ -33,37 +49,58 @@ impl From<u32> for RegTiming {
 }
 
 pub struct VideoInterface {
+    clock_hz: u32,
     framebuffer: FramebufferDescription,
-    current_line: u32,
-    tick_counter: u32,
+    // Serrated vertical sync, the fields of interlaced video
+    serrate: bool,
+
+    // The half-line being scanned out, as read from VI_CURRENT. Fields of
+    // interlaced video alternately start on an even and an odd half-line,
+    // which leaves the field in bit 0.
+    half_line: u32,
+    field_count: u32,
+    // VI clocks into the current line
+    line_clock: u32,
+    // CPU cycles not making up a whole VI clock yet, in units of 1/clock_hz
+    clock_fraction: u32,
 
     interrupt_half_line: u32,
     timing_reg: RegTiming,
 
-    v_sync_reg: u32, // [9:0] Lines per field
+    v_sync_reg: u32, // [9:0] Half-lines per field, minus one
     h_sync_reg: u32, // [20:16] 5-bit pal pattern, [11:0] duration of line in 1/4 pixels
+    h_sync_leap_reg: u32, // [27:16] Leap A, [11:0] leap B line durations
 
     active_video_start: u32,
     active_video_end: u32,
 }
 
 impl VideoInterface {
-    pub fn new() -> VideoInterface {
+    pub fn new(tv_system: TvSystem) -> VideoInterface {
         VideoInterface {
+            clock_hz: match tv_system {
+                TvSystem::Ntsc => NTSC_VI_CLOCK_HZ,
+                TvSystem::Pal => PAL_VI_CLOCK_HZ,
+            },
             framebuffer: FramebufferDescription {
                 format: FramebufferFormat::Blank,
                 origin: 0,
                 width: 0,
                 height: 0,
             },
-            current_line: 0,
-            tick_counter: 0,
+            serrate: false,
+
+            half_line: 0,
+            field_count: 0,
+            line_clock: 0,
+            clock_fraction: 0,
 
             interrupt_half_line: 0,
             timing_reg: RegTiming::default(),
 
             v_sync_reg: 0,
             h_sync_reg: 0,
+            h_sync_leap_reg: 0,
 
             active_video_start: 0,
             active_video_end: 0,
@@ -72,21 +109,50 @@ impl VideoInterface {
 
     // Does a step, and returns whether the frame buffer can be scanned out
     pub fn step(&mut self) -> bool {
-        self.tick_counter += 1;
-        if self.tick_counter == 548 {
-            self.current_line += 1;
-            self.tick_counter = 0;
-
-            let lines = self.v_sync_reg & 0b11_1111_1111;
-            if lines > 0 && self.current_line == lines - 1 {
-                return true;
-            }
-            if self.current_line >= lines {
-                self.current_line = 0;
-            }
+        self.clock_fraction += self.clock_hz;
+        if self.clock_fraction < cpu::CLOCK_HZ {
+            return false;
         }
+        self.clock_fraction -= cpu::CLOCK_HZ;
 
-        false
+        self.line_clock += 1;
+        if self.line_clock < self.line_duration() {
+            return false;
+        }
+        self.line_clock = 0;
+
+        // A field ends once all of its half-lines are scanned out. V_SYNC
+        // holds their count minus one, which is even for interlaced modes.
+        let half_lines = (self.v_sync_reg & 0b11_1111_1111) + 1;
+        self.half_line += 2;
+        if self.half_line < half_lines {
+            return false;
+        }
+        self.half_line = if self.serrate {
+            self.half_line % half_lines
+        } else {
+            0
+        };
+        self.field_count = self.field_count.wrapping_add(1);
+        self.v_sync_reg != 0
+    }
+
+    // In VI clocks, which are quarter pixels. PAL consoles make up for their
+    // video clock with lines of the leap durations during vertical sync,
+    // picked by the leap pattern.
+    fn line_duration(&self) -> u32 {
+        let pattern = (self.h_sync_reg >> 16) & 0b1_1111;
+        let vertical_sync = self.half_line < self.timing_reg.vsync_width as u32;
+        let duration = if pattern != 0 && vertical_sync {
+            if (pattern >> (self.field_count % LEAP_PATTERN_LENGTH)) & 1 != 0 {
+                self.h_sync_leap_reg
+            } else {
+                self.h_sync_leap_reg >> 16
+            }
+        } else {
+            self.h_sync_reg
+        };
+        (duration & 0x0fff) + 1
     }
 
     pub fn framebuffer_description(&self) -> Option<FramebufferDescription> {
@@ -107,6 +173,7 @@ impl VideoInterface {
             3 => self.framebuffer.format = FramebufferFormat::RGBA32Bit,
             _ => {}
         };
+        self.serrate = (value & (1 << 6)) != 0;
         // NOTE: There is much more to this register...
         println!(
             "WARNING: Stub for write VI_STATUS_REG {:08X} (fb_format: {:?})",
@@ -133,8 +200,7 @@ impl VideoInterface {
     }
 
     pub fn read_current_reg(&self) -> u32 {
-        // TODO: Proper impl
-        self.current_line
+        self.half_line
     }
 
     pub fn write_current_reg(&mut self, value: u32) {
@@ -156,7 +222,7 @@ impl VideoInterface {
     }
 
     pub fn write_h_sync_leap_reg(&mut self, value: u32) {
-        println!("WARNING: Stub for write VI_H_SYNC_LEAP_REG {:08X}", value);
+        self.h_sync_leap_reg = value & 0x0fff_0fff;
     }
 
     pub fn read_h_start_reg(&self) -> u32 {
@@ -184,3 +250,80 @@ impl VideoInterface {
         println!("WARNING: Stub for write VI_Y_SCALE_REG {:08X}", value);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Lines of 100 VI clocks, without PAL leap lines
+    const LINE_DURATION: u32 = 100;
+
+    fn video_interface(tv_system: TvSystem, v_sync: u32, serrate: bool) -> VideoInterface {
+        let mut vi = VideoInterface::new(tv_system);
+        vi.write_h_sync_reg(LINE_DURATION - 1);
+        vi.write_v_sync_reg(v_sync);
+        vi.write_status_reg(if serrate { 1 << 6 } else { 0 });
+        vi
+    }
+
+    // VI_CURRENT after every line of `lines`, and whether a field ended
+    // there
+    fn step_lines(vi: &mut VideoInterface, lines: u32) -> Vec<(u32, bool)> {
+        let mut currents = Vec::new();
+        for _ in 0..lines {
+            let start = vi.read_current_reg();
+            let mut field_ended = false;
+            while vi.read_current_reg() == start && !field_ended {
+                field_ended = vi.step();
+            }
+            currents.push((vi.read_current_reg(), field_ended));
+        }
+        currents
+    }
+
+    #[test]
+    fn interlaced_fields_start_on_alternate_half_lines() {
+        // 525 half-lines, as libultra sets up interlaced NTSC
+        let mut vi = video_interface(TvSystem::Ntsc, 524, true);
+        let currents = step_lines(&mut vi, 525);
+
+        // 263 lines in the first field, 262 in the second
+        let mut expected: Vec<(u32, bool)> = (1..263).map(|line| (line * 2, false)).collect();
+        expected.push((1, true));
+        expected.extend((1..262).map(|line| (line * 2 + 1, false)));
+        expected.push((0, true));
+        assert_eq!(currents.len(), expected.len());
+        for (line, (current, expected)) in currents.iter().zip(&expected).enumerate() {
+            assert_eq!(current, expected, "after line {}", line + 1);
+        }
+    }
+
+    #[test]
+    fn progressive_fields_start_on_half_line_zero() {
+        // 526 half-lines, as libultra sets up progressive NTSC
+        let mut vi = video_interface(TvSystem::Ntsc, 525, false);
+        let currents = step_lines(&mut vi, 263 * 2);
+
+        let mut field: Vec<(u32, bool)> = (1..263).map(|line| (line * 2, false)).collect();
+        field.push((0, true));
+        assert!(currents[..263] == field[..]);
+        assert!(currents[263..] == field[..]);
+    }
+
+    #[test]
+    fn field_duration_follows_the_tv_system_clock() {
+        for &(tv_system, clock_hz) in &[(TvSystem::Ntsc, 48_681_812u64), (TvSystem::Pal, 49_656_530)] {
+            let mut vi = video_interface(tv_system, 525, false);
+            let mut cycles = 1u64;
+            while !vi.step() {
+                cycles += 1;
+            }
+
+            // The field ends on the cycle its last VI clock completes
+            let vi_clocks = 263 * LINE_DURATION as u64;
+            let cpu_clock_hz = cpu::CLOCK_HZ as u64;
+            let expected = (vi_clocks * cpu_clock_hz + clock_hz - 1) / clock_hz;
+            assert_eq!(cycles, expected, "{:?}", tv_system);
+        }
+    }
+}